@@ -0,0 +1,154 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::sync::Mutex;
+
+use serde::Serialize;
+use tracing::error;
+
+use crate::error::Error;
+
+/// The audit log destination that writes records to stdout rather than a file.
+const STDOUT_DESTINATION: &str = "stdout";
+
+/// Records requests that modify files, such as uploads, renames, and removals, so that operators
+/// have a trail of who changed what. Each record is written as a line of JSON.
+pub struct AuditLog {
+    writer: Mutex<Box<dyn Write + Send>>,
+}
+
+/// A request that modified, or attempted to modify, files. The result is `success` or the error
+/// returned to the client.
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct AuditRecord {
+    pub user: String,
+    pub op: &'static str,
+    pub paths: Vec<String>,
+    pub timestamp: String,
+    pub result: String,
+}
+
+impl AuditLog {
+    pub fn new(writer: Box<dyn Write + Send>) -> AuditLog {
+        AuditLog {
+            writer: Mutex::new(writer),
+        }
+    }
+
+    /// Opens the destination configured with `DRAY_AUDIT_LOG`, which is either `stdout` or the
+    /// path of a file that records are appended to.
+    pub fn open(destination: &str) -> Result<AuditLog, Error> {
+        if destination == STDOUT_DESTINATION {
+            return Ok(AuditLog::new(Box::new(std::io::stdout())));
+        }
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(destination)
+            .map_err(|err| {
+                Error::Configuration(format!("Failed to open audit log {}: {}", destination, err))
+            })?;
+
+        Ok(AuditLog::new(Box::new(file)))
+    }
+
+    /// Writes a record. A failure to write is logged rather than failing the request that was
+    /// audited, since the request has already been processed.
+    pub fn record(&self, record: &AuditRecord) {
+        let mut line = match serde_json::to_vec(record) {
+            Ok(line) => line,
+            Err(err) => {
+                error!("Failed to serialize audit record: {}", err);
+                return;
+            }
+        };
+        line.push(b'\n');
+
+        let mut writer = self.writer.lock().unwrap_or_else(|err| err.into_inner());
+
+        if let Err(err) = writer.write_all(&line).and_then(|_| writer.flush()) {
+            error!("Failed to write audit record: {}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::sync::Arc;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_record_writes_json_line() {
+        let writer = TestWriter::default();
+        let audit_log = AuditLog::new(Box::new(writer.clone()));
+
+        audit_log.record(&create_record("mkdir"));
+        audit_log.record(&create_record("rmdir"));
+
+        let output = String::from_utf8(writer.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+
+        assert_eq!(2, lines.len());
+        assert_eq!(
+            r#"{"user":"test","op":"mkdir","paths":["/home/test/dir"],"timestamp":"2024-01-01T00:00:00+00:00","result":"success"}"#,
+            lines[0]
+        );
+        assert!(lines[1].contains(r#""op":"rmdir""#));
+    }
+
+    #[test]
+    fn test_open_appends_to_file() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("audit.log");
+        std::fs::write(&path, "existing\n").unwrap();
+
+        let audit_log = AuditLog::open(path.to_str().unwrap()).unwrap();
+        audit_log.record(&create_record("mkdir"));
+
+        let output = std::fs::read_to_string(&path).unwrap();
+
+        assert!(output.starts_with("existing\n{"));
+        assert_eq!(2, output.lines().count());
+    }
+
+    #[test]
+    fn test_open_with_stdout() {
+        assert!(AuditLog::open("stdout").is_ok());
+    }
+
+    #[test]
+    fn test_open_with_missing_directory() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("missing/audit.log");
+
+        assert!(matches!(
+            AuditLog::open(path.to_str().unwrap()),
+            Err(Error::Configuration(_))
+        ));
+    }
+
+    #[derive(Clone, Default)]
+    struct TestWriter(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for TestWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn create_record(op: &'static str) -> AuditRecord {
+        AuditRecord {
+            user: String::from("test"),
+            op,
+            paths: vec![String::from("/home/test/dir")],
+            timestamp: String::from("2024-01-01T00:00:00+00:00"),
+            result: String::from("success"),
+        }
+    }
+}
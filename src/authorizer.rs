@@ -0,0 +1,191 @@
+use crate::error::Error;
+use crate::protocol::request::path::normalize_path;
+use crate::sftp_session::{get_home, SftpSessionConfig};
+
+/// An operation that a client requests on a path.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operation {
+    /// Reading a file's data or attributes, listing a directory, or reading a symbolic link.
+    Read,
+    /// Creating a file or opening it for writing.
+    Write,
+    /// Changing a file's attributes.
+    SetAttributes,
+    /// Changing the attributes of a file through an open handle, which is authorized for the path
+    /// the handle was opened with.
+    SetHandleAttributes,
+    Remove,
+    CreateDir,
+    RemoveDir,
+    /// Renaming a file, which is authorized for both the old and new paths.
+    Rename,
+    /// Creating a symbolic or hard link. Hard links are authorized for both the existing and new
+    /// paths, and symbolic links for the link with a read of the target.
+    CreateLink,
+}
+
+/// Decides whether a user may perform an operation on a path. Paths are absolute, but may not be
+/// normalized, so implementations must resolve `..` components before comparing paths.
+pub trait Authorizer: Send + Sync {
+    fn authorize(&self, user: &str, operation: Operation, path: &str) -> Result<(), Error>;
+}
+
+/// Allows users to modify files within their home directory, and to read files within their home
+/// and the read-only prefixes. Read-only users may not modify any files.
+pub struct HomeDirAuthorizer {
+    home_template: String,
+    read_only_prefixes: Vec<String>,
+    read_only_users: Vec<String>,
+}
+
+impl From<&SftpSessionConfig> for HomeDirAuthorizer {
+    fn from(session_config: &SftpSessionConfig) -> Self {
+        HomeDirAuthorizer {
+            home_template: session_config.home_template.clone(),
+            read_only_prefixes: session_config.read_only_prefixes.clone(),
+            read_only_users: session_config.read_only_users.clone(),
+        }
+    }
+}
+
+impl Authorizer for HomeDirAuthorizer {
+    fn authorize(&self, user: &str, operation: Operation, path: &str) -> Result<(), Error> {
+        let normalized_path = normalize_path(path);
+        let is_home_path = is_path_within(&normalized_path, &get_home(&self.home_template, user)?);
+
+        let is_allowed = match operation {
+            Operation::Read => {
+                is_home_path
+                    || self
+                        .read_only_prefixes
+                        .iter()
+                        .any(|prefix| is_path_within(&normalized_path, prefix))
+            }
+            _ => !self.is_read_only_user(user) && is_home_path,
+        };
+
+        match is_allowed {
+            true => Ok(()),
+            false => Err(Error::PermissionDenied),
+        }
+    }
+}
+
+impl HomeDirAuthorizer {
+    fn is_read_only_user(&self, user: &str) -> bool {
+        self.read_only_users
+            .iter()
+            .any(|read_only| read_only == user)
+    }
+}
+
+/// Checks if a path is a prefix or is contained in a prefix, respecting directory boundaries.
+fn is_path_within(path: &str, prefix: &str) -> bool {
+    let prefix = prefix.trim_end_matches('/');
+
+    match path.strip_prefix(prefix) {
+        Some(remainder) => remainder.is_empty() || remainder.starts_with('/'),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_is_path_within() {
+        assert!(is_path_within("/home/test", "/home/test"));
+        assert!(is_path_within("/home/test/", "/home/test"));
+        assert!(is_path_within("/home/test/file.txt", "/home/test"));
+        assert!(is_path_within("/shared/file.txt", "/shared/"));
+        assert!(!is_path_within("/home/test2", "/home/test"));
+        assert!(!is_path_within("/home/other", "/home/test"));
+    }
+
+    #[test]
+    fn test_home_dir_authorizer_allows_home() {
+        let authorizer = HomeDirAuthorizer::from(&SftpSessionConfig::default());
+
+        for operation in [
+            Operation::Read,
+            Operation::Write,
+            Operation::SetAttributes,
+            Operation::SetHandleAttributes,
+            Operation::Remove,
+            Operation::CreateDir,
+            Operation::RemoveDir,
+            Operation::Rename,
+            Operation::CreateLink,
+        ] {
+            assert_eq!(
+                Ok(()),
+                authorizer.authorize("test", operation, "/home/test/file"),
+                "{:?}",
+                operation
+            );
+            assert_eq!(
+                Err(Error::PermissionDenied),
+                authorizer.authorize("test", operation, "/home/other/file"),
+                "{:?}",
+                operation
+            );
+        }
+    }
+
+    #[test]
+    fn test_home_dir_authorizer_allows_reads_of_read_only_prefixes() {
+        let authorizer = HomeDirAuthorizer::from(&SftpSessionConfig {
+            read_only_prefixes: vec![String::from("/shared")],
+            ..Default::default()
+        });
+
+        assert_eq!(
+            Ok(()),
+            authorizer.authorize("test", Operation::Read, "/shared/file")
+        );
+        assert_eq!(
+            Err(Error::PermissionDenied),
+            authorizer.authorize("test", Operation::Write, "/shared/file")
+        );
+        assert_eq!(
+            Err(Error::PermissionDenied),
+            authorizer.authorize("test", Operation::Read, "/shared/../home/other")
+        );
+    }
+
+    #[test]
+    fn test_home_dir_authorizer_denies_modifications_by_read_only_users() {
+        let authorizer = HomeDirAuthorizer::from(&SftpSessionConfig {
+            read_only_users: vec![String::from("test")],
+            ..Default::default()
+        });
+
+        assert_eq!(
+            Ok(()),
+            authorizer.authorize("test", Operation::Read, "/home/test/file")
+        );
+        assert_eq!(
+            Err(Error::PermissionDenied),
+            authorizer.authorize("test", Operation::Write, "/home/test/file")
+        );
+        assert_eq!(
+            Err(Error::PermissionDenied),
+            authorizer.authorize("test", Operation::SetHandleAttributes, "/home/test/file")
+        );
+        assert_eq!(
+            Ok(()),
+            authorizer.authorize("other", Operation::Write, "/home/other/file")
+        );
+    }
+
+    #[test]
+    fn test_home_dir_authorizer_rejects_invalid_user() {
+        let authorizer = HomeDirAuthorizer::from(&SftpSessionConfig::default());
+
+        assert_eq!(
+            Err(Error::PermissionDenied),
+            authorizer.authorize("..", Operation::Read, "/home/file")
+        );
+    }
+}
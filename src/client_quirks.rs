@@ -0,0 +1,57 @@
+/// Adjustments to the behavior of a session for specific SFTP clients, which are detected from the
+/// SSH version string that a client sends when it connects, such as
+/// `SSH-2.0-WinSCP_release_6.3.3`. Client-specific behavior is kept here rather than scattered
+/// through the request handlers.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ClientQuirks {
+    /// Realpath responses include the attributes of the path when it exists, rather than
+    /// placeholder attributes. WinSCP reads the attributes of the home directory from the realpath
+    /// response when it opens a session.
+    pub realpath_attributes: bool,
+}
+
+impl ClientQuirks {
+    pub fn from_ssh_id(ssh_id: &str) -> ClientQuirks {
+        // The software version follows the protocol version, as in `SSH-2.0-softwareversion`, and
+        // may be followed by a space and comments.
+        let software_version = ssh_id.trim_end().splitn(3, '-').nth(2).unwrap_or_default();
+
+        ClientQuirks {
+            realpath_attributes: software_version.starts_with("WinSCP_"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_from_ssh_id_with_winscp() {
+        assert_eq!(
+            ClientQuirks {
+                realpath_attributes: true
+            },
+            ClientQuirks::from_ssh_id("SSH-2.0-WinSCP_release_6.3.3\r\n")
+        );
+    }
+
+    #[test]
+    fn test_from_ssh_id_with_other_clients() {
+        for ssh_id in [
+            "SSH-2.0-OpenSSH_9.6",
+            "SSH-2.0-OpenSSH_for_Windows_8.1",
+            "SSH-2.0-FileZilla_3.66.4",
+            "SSH-2.0-OpenSSH_9.6 WinSCP_release",
+            "WinSCP_release_6.3.3",
+            "",
+        ] {
+            assert_eq!(
+                ClientQuirks::default(),
+                ClientQuirks::from_ssh_id(ssh_id),
+                "{}",
+                ssh_id
+            );
+        }
+    }
+}
@@ -1,11 +1,37 @@
-use std::{net::SocketAddr, path::Path};
+use std::{
+    fmt::Display,
+    net::{SocketAddr, ToSocketAddrs},
+    path::Path,
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
 
 use russh_keys::key;
-use serde::Deserialize;
-use tracing::info;
+use serde::{Deserialize, Deserializer};
+use tracing::{info, warn};
 
 use crate::error::Error;
+use crate::sftp_session::{DEFAULT_HOME_TEMPLATE, DEFAULT_MAX_PATH_DEPTH, DEFAULT_MAX_READ_LENGTH};
+pub use crate::storage::filesystem::FilesystemConfig;
+use crate::storage::filesystem::FilesystemStorageFactory;
+pub use crate::storage::gcs::GcsConfig;
+use crate::storage::gcs::GcsStorageFactory;
 pub use crate::storage::s3::S3Config;
+use crate::storage::s3::S3StorageFactory;
+use crate::storage::{
+    DefaultModes, StorageFactory, DEFAULT_DIR_MODE, DEFAULT_FILE_MODE, DEFAULT_MAX_HANDLES,
+    DEFAULT_MAX_WRITE_HANDLES,
+};
+
+const DEFAULT_SHUTDOWN_TIMEOUT: u64 = 10;
+const DEFAULT_AUTHORIZED_KEYS_TTL: u64 = 60;
+const DEFAULT_LISTEN_BACKLOG: i32 = 1024;
+const DEFAULT_SSH_WINDOW_SIZE: u32 = 16777216;
+const DEFAULT_SSH_MAX_PACKET: u32 = 32768;
+
+/// SSH packets are limited to what fits in a TCP packet.
+const MAX_SSH_MAX_PACKET: u32 = 65535;
 
 #[derive(Clone, Deserialize, Debug)]
 pub struct DrayConfig {
@@ -13,8 +39,139 @@ pub struct DrayConfig {
 
     pub ssh_key_paths: String,
 
+    #[serde(default = "get_default_max_handles")]
+    pub max_handles: usize,
+
+    /// The maximum number of files a session may have open for writing at once. Each buffers
+    /// data before it is uploaded, so this bounds the memory a session can use for uploads.
+    #[serde(default = "get_default_max_write_handles")]
+    pub max_write_handles: usize,
+
+    #[serde(default = "get_default_max_read_length")]
+    pub max_read_length: u32,
+
+    #[serde(default = "get_default_home_template")]
+    pub home_template: String,
+
+    #[serde(default)]
+    pub read_only_prefixes: Vec<String>,
+
+    /// Creates a user's home directory when they authenticate, if it does not exist.
+    #[serde(default)]
+    pub auto_create_home: bool,
+
+    /// Users that may read and list files, but may not modify them.
+    #[serde(default)]
+    pub read_only_users: Vec<String>,
+
+    /// The only users that may authenticate. Any user with authorized keys may authenticate if
+    /// this is not set.
+    #[serde(default)]
+    pub allowed_users: Option<Vec<String>>,
+
+    #[serde(default)]
+    pub storage_backend: StorageBackend,
+
+    #[serde(default)]
+    pub user_quota_bytes: Option<u64>,
+
+    #[serde(default)]
+    pub max_upload_bytes: Option<u64>,
+
+    /// The maximum number of directories and file names in a path that a client creates.
+    #[serde(default = "get_default_max_path_depth")]
+    pub max_path_depth: usize,
+
+    #[serde(default)]
+    pub metrics_addr: Option<String>,
+
+    #[serde(default)]
+    pub health_addr: Option<String>,
+
+    #[serde(default = "get_default_shutdown_timeout")]
+    pub shutdown_timeout: u64,
+
+    #[serde(default = "get_default_authorized_keys_ttl")]
+    pub authorized_keys_ttl: u64,
+
+    #[serde(default)]
+    pub max_sessions: Option<usize>,
+
+    /// The maximum number of concurrent connections from a single IP address.
+    #[serde(default)]
+    pub max_conn_per_ip: Option<usize>,
+
+    #[serde(default)]
+    pub session_rps: Option<u32>,
+
+    #[serde(default)]
+    pub idle_timeout_secs: Option<u64>,
+
+    /// The number of seconds a request may wait on storage before it fails.
+    #[serde(default)]
+    pub storage_op_timeout: Option<u64>,
+
+    /// The number of connections that may wait to be accepted on each listen address.
+    #[serde(default = "get_default_listen_backlog")]
+    pub listen_backlog: i32,
+
+    #[serde(default)]
+    pub tcp_keepalive_secs: Option<u64>,
+
+    /// The number of bytes a client may send on a channel before waiting for the server to
+    /// acknowledge them.
+    #[serde(default = "get_default_ssh_window_size")]
+    pub ssh_window_size: u32,
+
+    /// The maximum number of bytes of data in a single SSH packet.
+    #[serde(default = "get_default_ssh_max_packet")]
+    pub ssh_max_packet: u32,
+
+    /// Where audit records of requests that modify files are written, either `stdout` or the path
+    /// of a file. Audit logging is disabled if this is not set.
+    #[serde(default)]
+    pub audit_log: Option<String>,
+
+    #[serde(default)]
+    pub check_config: bool,
+
+    /// A banner shown to clients before they authenticate, such as a legal notice. This is the
+    /// path of a file containing the banner, or the banner's text.
+    #[serde(default)]
+    pub login_banner: Option<String>,
+
+    /// The permissions of files without stored permissions, as an octal string such as `644`.
+    #[serde(
+        default = "get_default_file_mode",
+        deserialize_with = "deserialize_mode"
+    )]
+    pub default_file_mode: u32,
+
+    /// The permissions of directories without stored permissions, as an octal string such as
+    /// `755`.
+    #[serde(
+        default = "get_default_dir_mode",
+        deserialize_with = "deserialize_mode"
+    )]
+    pub default_dir_mode: u32,
+
     #[serde(flatten)]
-    pub s3: S3Config,
+    pub s3: Option<S3Config>,
+
+    #[serde(flatten)]
+    pub filesystem: Option<FilesystemConfig>,
+
+    #[serde(flatten)]
+    pub gcs: Option<GcsConfig>,
+}
+
+#[derive(Clone, Copy, Default, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    #[default]
+    S3,
+    Filesystem,
+    Gcs,
 }
 
 impl DrayConfig {
@@ -27,44 +184,437 @@ impl DrayConfig {
         dray_config.get_ssh_keys()?;
 
         // Validate Host
-        dray_config.get_host_socket_addr()?;
+        dray_config.get_host_socket_addrs()?;
+
+        // Validate Metrics Address
+        dray_config.get_metrics_socket_addr()?;
+
+        // Validate Health Address
+        dray_config.get_health_socket_addr()?;
+
+        // Validate Rate Limits
+        dray_config.validate_rate_limits()?;
+
+        // Validate Timeouts
+        dray_config.get_idle_timeout()?;
+        dray_config.get_storage_op_timeout()?;
+
+        // Validate Login Banner
+        dray_config.get_login_banner()?;
+
+        // Validate Listener Options
+        dray_config.get_listen_backlog()?;
+        dray_config.get_tcp_keepalive()?;
+
+        // Validate SSH Channel Sizes
+        dray_config.validate_ssh_channel_sizes()?;
+
+        // Validate Storage Backend
+        match dray_config.storage_backend {
+            StorageBackend::S3 => {
+                let s3_config = dray_config.get_s3_config()?;
+                s3_config.validate_tls()?;
+                s3_config.get_storage_class()?;
+                s3_config.get_server_side_encryption()?;
+            }
+            StorageBackend::Filesystem => dray_config.get_filesystem_config().map(|_| ())?,
+            StorageBackend::Gcs => dray_config.get_gcs_config().map(|_| ())?,
+        };
 
         info!("Successfully loaded configuration");
 
         Ok(dray_config)
     }
 
-    pub fn get_host_socket_addr(&self) -> Result<SocketAddr, Error> {
-        self.host.parse::<SocketAddr>().map_err(Error::from)
+    /// Validates the configuration against the environment it will run in, rather than only
+    /// parsing it. The SSH keys must be readable and the storage backend must be reachable with
+    /// the configured credentials.
+    pub async fn validate_full(&self) -> Result<(), Error> {
+        info!("Validating configuration against the environment");
+
+        for key_path in self.get_ssh_key_paths() {
+            std::fs::File::open(key_path).map_err(|err| {
+                Error::Configuration(format!("Failed to read SSH key {}: {}", key_path, err))
+            })?;
+        }
+
+        self.get_ssh_keys()?;
+
+        self.create_storage_factory()
+            .await?
+            .create_storage()
+            .health_check()
+            .await?;
+
+        info!("Successfully validated configuration");
+
+        Ok(())
+    }
+
+    pub async fn create_storage_factory(&self) -> Result<Arc<dyn StorageFactory>, Error> {
+        Ok(match self.storage_backend {
+            StorageBackend::S3 => Arc::new(
+                S3StorageFactory::new(
+                    self.get_s3_config()?,
+                    self.max_handles,
+                    self.get_authorized_keys_ttl(),
+                )
+                .await?
+                .with_default_modes(self.get_default_modes())
+                .with_max_write_handles(self.max_write_handles),
+            ),
+            StorageBackend::Filesystem => Arc::new(
+                FilesystemStorageFactory::new(self.get_filesystem_config()?, self.max_handles)
+                    .with_max_write_handles(self.max_write_handles),
+            ),
+            StorageBackend::Gcs => Arc::new(
+                GcsStorageFactory::new(
+                    self.get_gcs_config()?,
+                    self.max_handles,
+                    self.get_authorized_keys_ttl(),
+                )
+                .with_default_modes(self.get_default_modes())
+                .with_max_write_handles(self.max_write_handles),
+            ),
+        })
+    }
+
+    /// The host is a comma separated list of addresses to listen on, such as
+    /// `0.0.0.0:22,[::]:22` to accept both IPv4 and IPv6 connections.
+    pub fn get_host_socket_addrs(&self) -> Result<Vec<SocketAddr>, Error> {
+        let addrs = self
+            .host
+            .split(',')
+            .map(str::trim)
+            .filter(|host| !host.is_empty())
+            .map(resolve_host)
+            .collect::<Result<Vec<SocketAddr>, Error>>()?;
+
+        match addrs.is_empty() {
+            true => Err(Error::Configuration(String::from(
+                "At least one host must be configured",
+            ))),
+            false => Ok(addrs),
+        }
+    }
+
+    pub fn get_metrics_socket_addr(&self) -> Result<Option<SocketAddr>, Error> {
+        self.metrics_addr
+            .as_ref()
+            .map(|metrics_addr| metrics_addr.parse::<SocketAddr>().map_err(Error::from))
+            .transpose()
+    }
+
+    pub fn get_health_socket_addr(&self) -> Result<Option<SocketAddr>, Error> {
+        self.health_addr
+            .as_ref()
+            .map(|health_addr| health_addr.parse::<SocketAddr>().map_err(Error::from))
+            .transpose()
+    }
+
+    pub fn get_shutdown_timeout(&self) -> Duration {
+        Duration::from_secs(self.shutdown_timeout)
+    }
+
+    pub fn get_authorized_keys_ttl(&self) -> Duration {
+        Duration::from_secs(self.authorized_keys_ttl)
+    }
+
+    pub fn get_default_modes(&self) -> DefaultModes {
+        DefaultModes {
+            file_mode: self.default_file_mode,
+            dir_mode: self.default_dir_mode,
+        }
+    }
+
+    pub fn get_idle_timeout(&self) -> Result<Option<Duration>, Error> {
+        match self.idle_timeout_secs {
+            Some(0) => Err(Error::Configuration(
+                "DRAY_IDLE_TIMEOUT_SECS must be greater than 0.".to_string(),
+            )),
+            idle_timeout_secs => Ok(idle_timeout_secs.map(Duration::from_secs)),
+        }
+    }
+
+    pub fn get_storage_op_timeout(&self) -> Result<Option<Duration>, Error> {
+        match self.storage_op_timeout {
+            Some(0) => Err(Error::Configuration(
+                "DRAY_STORAGE_OP_TIMEOUT must be greater than 0.".to_string(),
+            )),
+            storage_op_timeout => Ok(storage_op_timeout.map(Duration::from_secs)),
+        }
+    }
+
+    pub fn is_user_allowed(&self, user: &str) -> bool {
+        match &self.allowed_users {
+            Some(allowed_users) => allowed_users
+                .iter()
+                .any(|allowed_user| allowed_user == user),
+            None => true,
+        }
+    }
+
+    /// Gets the login banner, which is read from a file if `DRAY_LOGIN_BANNER` is the path of a
+    /// file, and is otherwise the text of the banner.
+    pub fn get_login_banner(&self) -> Result<Option<String>, Error> {
+        let login_banner = match &self.login_banner {
+            Some(login_banner) => login_banner,
+            None => return Ok(None),
+        };
+
+        if !Path::new(login_banner).is_file() {
+            return Ok(Some(login_banner.clone()));
+        }
+
+        std::fs::read_to_string(login_banner)
+            .map(Some)
+            .map_err(|err| {
+                Error::Configuration(format!(
+                    "Failed to read DRAY_LOGIN_BANNER {}: {}",
+                    login_banner, err
+                ))
+            })
+    }
+
+    pub fn get_listen_backlog(&self) -> Result<i32, Error> {
+        match self.listen_backlog {
+            listen_backlog if listen_backlog <= 0 => Err(Error::Configuration(
+                "DRAY_LISTEN_BACKLOG must be greater than 0.".to_string(),
+            )),
+            listen_backlog => Ok(listen_backlog),
+        }
+    }
+
+    /// The idle time before TCP keepalive probes are sent, so that connections to peers that have
+    /// gone away are closed.
+    pub fn get_tcp_keepalive(&self) -> Result<Option<Duration>, Error> {
+        match self.tcp_keepalive_secs {
+            Some(0) => Err(Error::Configuration(
+                "DRAY_TCP_KEEPALIVE_SECS must be greater than 0.".to_string(),
+            )),
+            tcp_keepalive_secs => Ok(tcp_keepalive_secs.map(Duration::from_secs)),
+        }
+    }
+
+    /// Validates the SSH window and packet sizes. The window must hold at least one packet, so
+    /// that a full packet can always be sent.
+    pub fn validate_ssh_channel_sizes(&self) -> Result<(), Error> {
+        if self.ssh_max_packet == 0 || self.ssh_max_packet > MAX_SSH_MAX_PACKET {
+            return Err(Error::Configuration(format!(
+                "DRAY_SSH_MAX_PACKET must be between 1 and {}.",
+                MAX_SSH_MAX_PACKET
+            )));
+        }
+
+        if self.ssh_window_size < self.ssh_max_packet {
+            return Err(Error::Configuration(
+                "DRAY_SSH_WINDOW_SIZE must be at least DRAY_SSH_MAX_PACKET.".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub fn validate_rate_limits(&self) -> Result<(), Error> {
+        if self.max_sessions == Some(0) {
+            return Err(Error::Configuration(
+                "DRAY_MAX_SESSIONS must be greater than 0.".to_string(),
+            ));
+        }
+
+        if self.max_conn_per_ip == Some(0) {
+            return Err(Error::Configuration(
+                "DRAY_MAX_CONN_PER_IP must be greater than 0.".to_string(),
+            ));
+        }
+
+        if self.session_rps == Some(0) {
+            return Err(Error::Configuration(
+                "DRAY_SESSION_RPS must be greater than 0.".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub fn get_s3_config(&self) -> Result<&S3Config, Error> {
+        self.s3.as_ref().ok_or_else(|| {
+            Error::Configuration("The S3 storage backend requires DRAY_S3_BUCKET.".to_string())
+        })
+    }
+
+    pub fn get_filesystem_config(&self) -> Result<&FilesystemConfig, Error> {
+        self.filesystem.as_ref().ok_or_else(|| {
+            Error::Configuration(
+                "The filesystem storage backend requires DRAY_FILESYSTEM_ROOT.".to_string(),
+            )
+        })
+    }
+
+    pub fn get_gcs_config(&self) -> Result<&GcsConfig, Error> {
+        self.gcs.as_ref().ok_or_else(|| {
+            Error::Configuration(
+                "The GCS storage backend requires DRAY_GCS_BUCKET, DRAY_GCS_HMAC_ACCESS_ID, and DRAY_GCS_HMAC_SECRET."
+                    .to_string(),
+            )
+        })
     }
 
     pub fn get_ssh_keys(&self) -> Result<Vec<key::KeyPair>, Error> {
         info!("Loading SSH keys");
 
-        let keys: Result<Vec<key::KeyPair>, _> = self
-            .ssh_key_paths
-            .split(',')
-            .map(|key_path| key_path.trim())
+        let keys: Result<Vec<key::KeyPair>, Error> = self
+            .get_ssh_key_paths()
             .map(|key_path| {
                 info!("Loading SSH key from {}", key_path);
 
-                russh_keys::load_secret_key(Path::new(key_path), None).map_err(|err| {
-                    let error_message = format!("Failed to load SSH key {}: {}", key_path, err);
-                    Error::Configuration(error_message)
-                })
+                let key =
+                    russh_keys::load_secret_key(Path::new(key_path), None).map_err(|err| {
+                        let error_message = format!("Failed to load SSH key {}: {}", key_path, err);
+                        Error::Configuration(error_message)
+                    })?;
+
+                info!("Loaded {} SSH key from {}", key.name(), key_path);
+
+                Ok(key)
             })
             .collect();
 
-        info!("Successfully loaded SSH keys");
-
         let keys = keys?;
+
+        if keys.is_empty() {
+            return Err(Error::Configuration(
+                "DRAY_SSH_KEY_PATHS must contain at least one SSH key.".to_string(),
+            ));
+        }
+
+        // RSA keys may be needed for legacy clients, but modern clients should be offered an
+        // ed25519 key.
+        if !keys
+            .iter()
+            .any(|key| matches!(key, key::KeyPair::Ed25519(_)))
+        {
+            warn!("No ed25519 SSH key is configured");
+        }
+
+        info!(
+            "Successfully loaded SSH keys with algorithms {}",
+            get_ssh_key_algorithms(&keys).join(", ")
+        );
+
         Ok(keys)
     }
+
+    fn get_ssh_key_paths(&self) -> impl Iterator<Item = &str> {
+        self.ssh_key_paths
+            .split(',')
+            .map(|key_path| key_path.trim())
+            .filter(|key_path| !key_path.is_empty())
+    }
+}
+
+/// Resolves an address or a hostname with a port, such as `localhost:22`. Hostnames are resolved
+/// when the configuration is loaded, and the server listens on the first address.
+fn resolve_host(host: &str) -> Result<SocketAddr, Error> {
+    if let Ok(addr) = host.parse::<SocketAddr>() {
+        return Ok(addr);
+    }
+
+    let mut addrs = host
+        .to_socket_addrs()
+        .map_err(|err| Error::Configuration(format!("Failed to resolve host {}: {}", host, err)))?;
+
+    addrs
+        .next()
+        .ok_or_else(|| Error::Configuration(format!("Host {} did not resolve to an address", host)))
+}
+
+/// Lists the algorithm of each SSH host key, in the order that the keys are offered to clients.
+pub fn get_ssh_key_algorithms(keys: &[key::KeyPair]) -> Vec<&'static str> {
+    keys.iter().map(|key| key.name()).collect()
+}
+
+/// Deserializes a value from its string representation. Environment variables in flattened
+/// configuration are always provided as strings, so numeric values must be parsed explicitly.
+pub(crate) fn deserialize_from_str<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: Display,
+{
+    String::deserialize(deserializer)?
+        .parse::<T>()
+        .map_err(serde::de::Error::custom)
+}
+
+/// Deserializes permissions from an octal string, such as `644` or `0755`.
+fn deserialize_mode<'de, D>(deserializer: D) -> Result<u32, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let mode = String::deserialize(deserializer)?;
+
+    match u32::from_str_radix(&mode, 8) {
+        Ok(mode) if mode <= 0o7777 => Ok(mode),
+        _ => Err(serde::de::Error::custom(format!(
+            "{} is not a valid octal file mode",
+            mode
+        ))),
+    }
+}
+
+fn get_default_max_handles() -> usize {
+    DEFAULT_MAX_HANDLES
+}
+
+fn get_default_max_write_handles() -> usize {
+    DEFAULT_MAX_WRITE_HANDLES
+}
+
+fn get_default_max_read_length() -> u32 {
+    DEFAULT_MAX_READ_LENGTH
+}
+
+fn get_default_max_path_depth() -> usize {
+    DEFAULT_MAX_PATH_DEPTH
+}
+
+fn get_default_home_template() -> String {
+    DEFAULT_HOME_TEMPLATE.to_string()
+}
+
+fn get_default_file_mode() -> u32 {
+    DEFAULT_FILE_MODE
+}
+
+fn get_default_dir_mode() -> u32 {
+    DEFAULT_DIR_MODE
+}
+
+fn get_default_shutdown_timeout() -> u64 {
+    DEFAULT_SHUTDOWN_TIMEOUT
+}
+
+fn get_default_authorized_keys_ttl() -> u64 {
+    DEFAULT_AUTHORIZED_KEYS_TTL
+}
+
+fn get_default_listen_backlog() -> i32 {
+    DEFAULT_LISTEN_BACKLOG
+}
+
+fn get_default_ssh_window_size() -> u32 {
+    DEFAULT_SSH_WINDOW_SIZE
+}
+
+fn get_default_ssh_max_packet() -> u32 {
+    DEFAULT_SSH_MAX_PACKET
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::storage::s3::ForcePathStyle;
     use std::{env, fs::File, io::Write};
 
     #[test]
@@ -82,6 +632,45 @@ mod test {
         assert_eq!(2, config.get_ssh_keys().unwrap().len())
     }
 
+    #[test]
+    fn test_get_ssh_keys_with_multiple_algorithms() {
+        let rsa_key = tempfile::NamedTempFile::new().unwrap();
+        russh_keys::encode_pkcs8_pem(
+            &key::KeyPair::generate_rsa(2048, key::SignatureHash::SHA2_256).unwrap(),
+            rsa_key.as_file(),
+        )
+        .unwrap();
+
+        let config = create_config(format!(
+            "{}, {}",
+            create_temp_key(),
+            rsa_key.path().to_str().unwrap()
+        ));
+
+        let algorithms = get_ssh_key_algorithms(&config.get_ssh_keys().unwrap());
+
+        assert_eq!(2, algorithms.len());
+        assert_eq!("ssh-ed25519", algorithms[0]);
+        assert!(algorithms[1].starts_with("rsa-sha2-"));
+    }
+
+    #[test]
+    fn test_get_ssh_keys_with_trailing_separator() {
+        let config = create_config(format!("{},", create_temp_key()));
+
+        assert_eq!(1, config.get_ssh_keys().unwrap().len())
+    }
+
+    #[test]
+    fn test_get_ssh_keys_with_no_keys() {
+        let config = create_config(String::from(" , "));
+
+        assert!(matches!(
+            config.get_ssh_keys(),
+            Err(Error::Configuration(_))
+        ));
+    }
+
     #[test]
     #[should_panic]
     fn test_get_ssh_keys_with_invalid_key() {
@@ -90,16 +679,78 @@ mod test {
         config.get_ssh_keys().unwrap();
     }
 
+    #[tokio::test]
+    async fn test_validate_full() {
+        let root = tempfile::TempDir::new().unwrap();
+        let config = create_filesystem_config(root.path().to_str().unwrap());
+
+        assert_eq!(Ok(()), config.validate_full().await);
+    }
+
+    #[tokio::test]
+    async fn test_validate_full_with_missing_key_file() {
+        let root = tempfile::TempDir::new().unwrap();
+        let config = DrayConfig {
+            ssh_key_paths: format!("{},/missing/id_ed25519", create_temp_key()),
+            ..create_filesystem_config(root.path().to_str().unwrap())
+        };
+
+        match config.validate_full().await {
+            Err(Error::Configuration(message)) => {
+                assert!(message.starts_with("Failed to read SSH key /missing/id_ed25519"))
+            }
+            result => panic!("Expected a configuration error, but received {:?}", result),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_validate_full_with_unreachable_storage() {
+        let root = tempfile::TempDir::new().unwrap();
+        let config = create_filesystem_config(root.path().join("missing").to_str().unwrap());
+
+        assert!(matches!(
+            config.validate_full().await,
+            Err(Error::Storage(_))
+        ));
+    }
+
     #[test]
     fn test_get_host_socket_addr() {
         let config = create_config(create_temp_key());
 
         assert_eq!(
-            "0.0.0.0:22".parse::<SocketAddr>().unwrap(),
-            config.get_host_socket_addr().unwrap()
+            vec!["0.0.0.0:22".parse::<SocketAddr>().unwrap()],
+            config.get_host_socket_addrs().unwrap()
         );
     }
 
+    #[test]
+    fn test_get_host_socket_addr_with_hostname() {
+        let config = DrayConfig {
+            host: String::from("localhost:2222"),
+            ..create_config(create_temp_key())
+        };
+
+        let addrs = config.get_host_socket_addrs().unwrap();
+
+        assert_eq!(1, addrs.len());
+        assert!(addrs[0].ip().is_loopback());
+        assert_eq!(2222, addrs[0].port());
+    }
+
+    #[test]
+    fn test_get_host_socket_addr_with_unresolvable_hostname() {
+        let config = DrayConfig {
+            host: String::from("missing.invalid:22"),
+            ..create_config(create_temp_key())
+        };
+
+        assert!(matches!(
+            config.get_host_socket_addrs(),
+            Err(Error::Configuration(message)) if message.starts_with("Failed to resolve host missing.invalid:22")
+        ));
+    }
+
     #[test]
     #[should_panic]
     fn test_get_host_socket_addr_with_invalid_host() {
@@ -108,18 +759,572 @@ mod test {
             ..create_config(create_temp_key())
         };
 
-        config.get_host_socket_addr().unwrap();
+        config.get_host_socket_addrs().unwrap();
     }
 
-    fn create_config(key_paths: String) -> DrayConfig {
-        DrayConfig {
-            host: String::from("0.0.0.0:22"),
-            ssh_key_paths: key_paths,
-            s3: S3Config {
-                endpoint_name: None,
-                endpoint_region: String::from("us-east-1"),
-                bucket: String::from("bucket"),
-            },
+    #[test]
+    fn test_get_host_socket_addrs_with_multiple_hosts() {
+        let config = DrayConfig {
+            host: String::from("0.0.0.0:22, [::]:22,"),
+            ..create_config(create_temp_key())
+        };
+
+        assert_eq!(
+            vec![
+                "0.0.0.0:22".parse::<SocketAddr>().unwrap(),
+                "[::]:22".parse::<SocketAddr>().unwrap()
+            ],
+            config.get_host_socket_addrs().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_get_host_socket_addrs_without_hosts() {
+        let config = DrayConfig {
+            host: String::from(","),
+            ..create_config(create_temp_key())
+        };
+
+        assert!(config.get_host_socket_addrs().is_err());
+    }
+
+    #[test]
+    fn test_get_metrics_socket_addr() {
+        let config = DrayConfig {
+            metrics_addr: Some(String::from("127.0.0.1:9090")),
+            ..create_config(create_temp_key())
+        };
+
+        assert_eq!(
+            Some("127.0.0.1:9090".parse::<SocketAddr>().unwrap()),
+            config.get_metrics_socket_addr().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_get_metrics_socket_addr_with_missing_addr() {
+        let config = create_config(create_temp_key());
+
+        assert_eq!(None, config.get_metrics_socket_addr().unwrap());
+    }
+
+    #[test]
+    fn test_get_metrics_socket_addr_with_invalid_addr() {
+        let config = DrayConfig {
+            metrics_addr: Some(String::from("missingport")),
+            ..create_config(create_temp_key())
+        };
+
+        assert!(config.get_metrics_socket_addr().is_err());
+    }
+
+    #[test]
+    fn test_get_health_socket_addr() {
+        let config = DrayConfig {
+            health_addr: Some(String::from("0.0.0.0:8080")),
+            ..create_config(create_temp_key())
+        };
+
+        assert_eq!(
+            Some("0.0.0.0:8080".parse::<SocketAddr>().unwrap()),
+            config.get_health_socket_addr().unwrap()
+        );
+        assert_eq!(
+            None,
+            create_config(create_temp_key())
+                .get_health_socket_addr()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_get_health_socket_addr_with_invalid_addr() {
+        let config = DrayConfig {
+            health_addr: Some(String::from("missingport")),
+            ..create_config(create_temp_key())
+        };
+
+        assert!(config.get_health_socket_addr().is_err());
+    }
+
+    #[test]
+    fn test_get_shutdown_timeout() {
+        let config = DrayConfig {
+            shutdown_timeout: 30,
+            ..create_config(create_temp_key())
+        };
+
+        assert_eq!(Duration::from_secs(30), config.get_shutdown_timeout());
+    }
+
+    #[test]
+    fn test_get_authorized_keys_ttl() {
+        let config = DrayConfig {
+            authorized_keys_ttl: 300,
+            ..create_config(create_temp_key())
+        };
+
+        assert_eq!(Duration::from_secs(300), config.get_authorized_keys_ttl());
+    }
+
+    #[test]
+    fn test_get_idle_timeout() {
+        let config = DrayConfig {
+            idle_timeout_secs: Some(300),
+            ..create_config(create_temp_key())
+        };
+
+        assert_eq!(
+            Ok(Some(Duration::from_secs(300))),
+            config.get_idle_timeout()
+        );
+        assert_eq!(
+            Ok(None),
+            create_config(create_temp_key()).get_idle_timeout()
+        );
+    }
+
+    #[test]
+    fn test_get_idle_timeout_with_zero_timeout() {
+        let config = DrayConfig {
+            idle_timeout_secs: Some(0),
+            ..create_config(create_temp_key())
+        };
+
+        assert!(config.get_idle_timeout().is_err());
+    }
+
+    #[test]
+    fn test_get_storage_op_timeout() {
+        let config = DrayConfig {
+            storage_op_timeout: Some(30),
+            ..create_config(create_temp_key())
+        };
+
+        assert_eq!(
+            Ok(Some(Duration::from_secs(30))),
+            config.get_storage_op_timeout()
+        );
+        assert_eq!(
+            Ok(None),
+            create_config(create_temp_key()).get_storage_op_timeout()
+        );
+    }
+
+    #[test]
+    fn test_get_storage_op_timeout_with_zero_timeout() {
+        let config = DrayConfig {
+            storage_op_timeout: Some(0),
+            ..create_config(create_temp_key())
+        };
+
+        assert!(config.get_storage_op_timeout().is_err());
+    }
+
+    #[test]
+    fn test_is_user_allowed() {
+        let config = DrayConfig {
+            allowed_users: Some(vec![String::from("alice"), String::from("bob")]),
+            ..create_config(create_temp_key())
+        };
+
+        assert!(config.is_user_allowed("alice"));
+        assert!(config.is_user_allowed("bob"));
+        assert!(!config.is_user_allowed("mallory"));
+    }
+
+    #[test]
+    fn test_is_user_allowed_without_allowed_users() {
+        assert!(create_config(create_temp_key()).is_user_allowed("mallory"));
+    }
+
+    #[test]
+    fn test_get_login_banner_with_text() {
+        let config = DrayConfig {
+            login_banner: Some(String::from("Authorized use only.")),
+            ..create_config(create_temp_key())
+        };
+
+        assert_eq!(
+            Ok(Some(String::from("Authorized use only."))),
+            config.get_login_banner()
+        );
+        assert_eq!(
+            Ok(None),
+            create_config(create_temp_key()).get_login_banner()
+        );
+    }
+
+    #[test]
+    fn test_get_login_banner_with_file() {
+        let mut banner_file = tempfile::NamedTempFile::new().unwrap();
+        banner_file.write_all(b"Authorized use only.\n").unwrap();
+
+        let config = DrayConfig {
+            login_banner: Some(banner_file.path().to_str().unwrap().to_string()),
+            ..create_config(create_temp_key())
+        };
+
+        assert_eq!(
+            Ok(Some(String::from("Authorized use only.\n"))),
+            config.get_login_banner()
+        );
+    }
+
+    #[test]
+    fn test_get_listen_backlog() {
+        let config = DrayConfig {
+            listen_backlog: 128,
+            ..create_config(create_temp_key())
+        };
+
+        assert_eq!(Ok(128), config.get_listen_backlog());
+        assert_eq!(
+            Ok(1024),
+            create_config(create_temp_key()).get_listen_backlog()
+        );
+    }
+
+    #[test]
+    fn test_get_listen_backlog_with_zero_backlog() {
+        let config = DrayConfig {
+            listen_backlog: 0,
+            ..create_config(create_temp_key())
+        };
+
+        assert!(config.get_listen_backlog().is_err());
+    }
+
+    #[test]
+    fn test_validate_ssh_channel_sizes() {
+        assert_eq!(
+            Ok(()),
+            create_config(create_temp_key()).validate_ssh_channel_sizes()
+        );
+
+        let config = DrayConfig {
+            ssh_window_size: 65535,
+            ssh_max_packet: 65535,
+            ..create_config(create_temp_key())
+        };
+
+        assert_eq!(Ok(()), config.validate_ssh_channel_sizes());
+    }
+
+    #[test]
+    fn test_validate_ssh_channel_sizes_with_invalid_max_packet() {
+        for ssh_max_packet in [0, 65536] {
+            let config = DrayConfig {
+                ssh_max_packet,
+                ..create_config(create_temp_key())
+            };
+
+            assert!(matches!(
+                config.validate_ssh_channel_sizes(),
+                Err(Error::Configuration(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_validate_ssh_channel_sizes_with_window_smaller_than_packet() {
+        let config = DrayConfig {
+            ssh_window_size: 16384,
+            ..create_config(create_temp_key())
+        };
+
+        assert!(matches!(
+            config.validate_ssh_channel_sizes(),
+            Err(Error::Configuration(_))
+        ));
+    }
+
+    #[test]
+    fn test_get_default_ssh_channel_sizes() {
+        assert_eq!(16777216, get_default_ssh_window_size());
+        assert_eq!(32768, get_default_ssh_max_packet());
+    }
+
+    #[test]
+    fn test_get_tcp_keepalive() {
+        let config = DrayConfig {
+            tcp_keepalive_secs: Some(60),
+            ..create_config(create_temp_key())
+        };
+
+        assert_eq!(
+            Ok(Some(Duration::from_secs(60))),
+            config.get_tcp_keepalive()
+        );
+        assert_eq!(
+            Ok(None),
+            create_config(create_temp_key()).get_tcp_keepalive()
+        );
+    }
+
+    #[test]
+    fn test_get_tcp_keepalive_with_zero_keepalive() {
+        let config = DrayConfig {
+            tcp_keepalive_secs: Some(0),
+            ..create_config(create_temp_key())
+        };
+
+        assert!(config.get_tcp_keepalive().is_err());
+    }
+
+    #[test]
+    fn test_validate_rate_limits() {
+        let config = DrayConfig {
+            max_sessions: Some(10),
+            max_conn_per_ip: Some(5),
+            session_rps: Some(100),
+            ..create_config(create_temp_key())
+        };
+
+        assert!(config.validate_rate_limits().is_ok());
+        assert!(create_config(create_temp_key())
+            .validate_rate_limits()
+            .is_ok());
+    }
+
+    #[test]
+    fn test_validate_rate_limits_with_zero_limits() {
+        let config = DrayConfig {
+            max_sessions: Some(0),
+            ..create_config(create_temp_key())
+        };
+
+        assert!(config.validate_rate_limits().is_err());
+
+        let config = DrayConfig {
+            max_conn_per_ip: Some(0),
+            ..create_config(create_temp_key())
+        };
+
+        assert!(config.validate_rate_limits().is_err());
+
+        let config = DrayConfig {
+            session_rps: Some(0),
+            ..create_config(create_temp_key())
+        };
+
+        assert!(config.validate_rate_limits().is_err());
+    }
+
+    #[test]
+    fn test_get_s3_config() {
+        let config = create_config(create_temp_key());
+
+        assert_eq!("bucket", config.get_s3_config().unwrap().bucket);
+    }
+
+    #[test]
+    fn test_get_s3_config_with_missing_config() {
+        let config = DrayConfig {
+            s3: None,
+            ..create_config(create_temp_key())
+        };
+
+        assert!(config.get_s3_config().is_err());
+    }
+
+    #[test]
+    fn test_get_filesystem_config() {
+        let config = DrayConfig {
+            storage_backend: StorageBackend::Filesystem,
+            filesystem: Some(FilesystemConfig {
+                root: String::from("/srv/dray"),
+            }),
+            ..create_config(create_temp_key())
+        };
+
+        assert_eq!("/srv/dray", config.get_filesystem_config().unwrap().root);
+    }
+
+    #[test]
+    fn test_get_filesystem_config_with_missing_config() {
+        let config = create_config(create_temp_key());
+
+        assert!(config.get_filesystem_config().is_err());
+    }
+
+    #[test]
+    fn test_get_gcs_config() {
+        let config = DrayConfig {
+            storage_backend: StorageBackend::Gcs,
+            gcs: Some(GcsConfig {
+                endpoint_name: String::from("https://storage.googleapis.com"),
+                bucket: String::from("gcs-bucket"),
+                hmac_access_id: String::from("id"),
+                hmac_secret: String::from("secret"),
+            }),
+            ..create_config(create_temp_key())
+        };
+
+        assert_eq!("gcs-bucket", config.get_gcs_config().unwrap().bucket);
+    }
+
+    #[test]
+    fn test_get_gcs_config_with_missing_config() {
+        let config = create_config(create_temp_key());
+
+        assert!(config.get_gcs_config().is_err());
+    }
+
+    #[test]
+    fn test_deserialize_from_str() {
+        let value: usize = deserialize_from_str(serde::de::value::StrDeserializer::<
+            serde::de::value::Error,
+        >::new("123"))
+        .unwrap();
+
+        assert_eq!(123, value);
+    }
+
+    #[test]
+    fn test_deserialize_from_str_with_invalid_value() {
+        let value: Result<usize, _> = deserialize_from_str(serde::de::value::StrDeserializer::<
+            serde::de::value::Error,
+        >::new("abc"));
+
+        assert!(value.is_err());
+    }
+
+    #[test]
+    fn test_deserialize_mode() {
+        let mode = deserialize_mode(
+            serde::de::value::StrDeserializer::<serde::de::value::Error>::new("0750"),
+        )
+        .unwrap();
+
+        assert_eq!(0o750, mode);
+    }
+
+    #[test]
+    fn test_deserialize_mode_with_invalid_mode() {
+        for mode in ["rw-r--r--", "888", "17777"] {
+            let result = deserialize_mode(serde::de::value::StrDeserializer::<
+                serde::de::value::Error,
+            >::new(mode));
+
+            assert!(result.is_err());
+        }
+    }
+
+    #[test]
+    fn test_get_default_modes() {
+        let config = DrayConfig {
+            default_file_mode: 0o600,
+            default_dir_mode: 0o700,
+            ..create_config(create_temp_key())
+        };
+
+        assert_eq!(
+            DefaultModes {
+                file_mode: 0o600,
+                dir_mode: 0o700,
+            },
+            config.get_default_modes()
+        );
+    }
+
+    #[test]
+    fn test_get_default_modes_with_defaults() {
+        assert_eq!(0o644, get_default_file_mode());
+        assert_eq!(0o755, get_default_dir_mode());
+    }
+
+    #[test]
+    fn test_get_default_max_handles() {
+        assert_eq!(64, get_default_max_handles());
+    }
+
+    #[test]
+    fn test_get_default_max_write_handles() {
+        assert_eq!(16, get_default_max_write_handles());
+    }
+
+    #[test]
+    fn test_get_default_home_template() {
+        assert_eq!("/home/{user}", get_default_home_template());
+    }
+
+    #[test]
+    fn test_get_default_max_read_length() {
+        assert_eq!(261120, get_default_max_read_length());
+    }
+
+    #[test]
+    fn test_get_default_max_path_depth() {
+        assert_eq!(64, get_default_max_path_depth());
+    }
+
+    fn create_config(key_paths: String) -> DrayConfig {
+        DrayConfig {
+            host: String::from("0.0.0.0:22"),
+            ssh_key_paths: key_paths,
+            max_handles: DEFAULT_MAX_HANDLES,
+            max_write_handles: DEFAULT_MAX_WRITE_HANDLES,
+            max_read_length: DEFAULT_MAX_READ_LENGTH,
+            home_template: DEFAULT_HOME_TEMPLATE.to_string(),
+            read_only_prefixes: vec![],
+            auto_create_home: false,
+            read_only_users: vec![],
+            allowed_users: None,
+            storage_backend: StorageBackend::S3,
+            user_quota_bytes: None,
+            max_upload_bytes: None,
+            max_path_depth: DEFAULT_MAX_PATH_DEPTH,
+            metrics_addr: None,
+            health_addr: None,
+            shutdown_timeout: DEFAULT_SHUTDOWN_TIMEOUT,
+            authorized_keys_ttl: DEFAULT_AUTHORIZED_KEYS_TTL,
+            max_sessions: None,
+            max_conn_per_ip: None,
+            session_rps: None,
+            idle_timeout_secs: None,
+            storage_op_timeout: None,
+            listen_backlog: DEFAULT_LISTEN_BACKLOG,
+            tcp_keepalive_secs: None,
+            ssh_window_size: DEFAULT_SSH_WINDOW_SIZE,
+            ssh_max_packet: DEFAULT_SSH_MAX_PACKET,
+            audit_log: None,
+            check_config: false,
+            login_banner: None,
+            default_file_mode: DEFAULT_FILE_MODE,
+            default_dir_mode: DEFAULT_DIR_MODE,
+            s3: Some(S3Config {
+                endpoint_name: None,
+                endpoint_region: Some(String::from("us-east-1")),
+                aws_profile: None,
+                aws_region: None,
+                bucket: String::from("bucket"),
+                part_size: 10000000,
+                multipart_threshold: 10000000,
+                read_ahead_size: 1048576,
+                ca_bundle: None,
+                tls_insecure: false,
+                tls_insecure_dev_override: false,
+                force_path_style: ForcePathStyle::Auto,
+                verify_uploads: false,
+                storage_class: None,
+                sse: None,
+                sse_kms_key_id: None,
+                map_chmod_to_acl: false,
+            }),
+            filesystem: None,
+            gcs: None,
+        }
+    }
+
+    fn create_filesystem_config(root: &str) -> DrayConfig {
+        DrayConfig {
+            storage_backend: StorageBackend::Filesystem,
+            s3: None,
+            filesystem: Some(FilesystemConfig {
+                root: root.to_string(),
+            }),
+            ..create_config(create_temp_key())
         }
     }
 
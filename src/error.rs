@@ -2,8 +2,13 @@ use std::io::ErrorKind;
 
 use thiserror::Error;
 
+/// Errors are compared by value, so errors converted from other types keep the source's kind or
+/// message rather than the source itself.
 #[derive(Error, Debug, PartialEq, Eq)]
 pub enum Error {
+    #[error("The file already exists.")]
+    AlreadyExists,
+
     #[error("Bad message received from client.")]
     BadMessage,
 
@@ -16,15 +21,36 @@ pub enum Error {
     #[error("{}", .0)]
     Failure(String),
 
+    #[error("The file exceeds the maximum upload size.")]
+    FileTooLarge,
+
+    #[error("The handle does not exist.")]
+    InvalidHandle,
+
     #[error("IO Error: {}", .0)]
     IOError(std::io::ErrorKind),
 
+    #[error("The file is a directory.")]
+    IsADirectory,
+
     #[error("File not found.")]
     NoSuchFile,
 
+    #[error("The path is not a directory.")]
+    NotADirectory,
+
+    #[error("The path exceeds the maximum length or depth.")]
+    PathTooLong,
+
     #[error("Permission denied.")]
     PermissionDenied,
 
+    #[error("The storage quota has been exceeded.")]
+    QuotaExceeded,
+
+    #[error("Too many levels of symbolic links.")]
+    SymlinkLoop,
+
     #[error("An error occurred with the storage backend: {}", .0)]
     Storage(String),
 
@@ -32,6 +58,32 @@ pub enum Error {
     Unimplemented,
 }
 
+impl Error {
+    /// A stable, machine-readable name for the kind of error, for integrators that need to handle
+    /// errors without matching on their messages.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::AlreadyExists => "already_exists",
+            Error::BadMessage => "bad_message",
+            Error::Configuration(_) => "configuration",
+            Error::EndOfFile => "end_of_file",
+            Error::Failure(_) => "failure",
+            Error::FileTooLarge => "file_too_large",
+            Error::InvalidHandle => "invalid_handle",
+            Error::IOError(_) => "io_error",
+            Error::IsADirectory => "is_a_directory",
+            Error::NoSuchFile => "no_such_file",
+            Error::NotADirectory => "not_a_directory",
+            Error::PathTooLong => "path_too_long",
+            Error::PermissionDenied => "permission_denied",
+            Error::QuotaExceeded => "quota_exceeded",
+            Error::SymlinkLoop => "symlink_loop",
+            Error::Storage(_) => "storage",
+            Error::Unimplemented => "unimplemented",
+        }
+    }
+}
+
 impl From<envy::Error> for Error {
     fn from(envy_error: envy::Error) -> Self {
         Error::Configuration(envy_error.to_string())
@@ -64,3 +116,126 @@ impl From<russh_keys::Error> for Error {
         Error::Failure(russh_error.to_string())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_error_display_and_code() {
+        let errors = vec![
+            (
+                Error::AlreadyExists,
+                "The file already exists.",
+                "already_exists",
+            ),
+            (
+                Error::BadMessage,
+                "Bad message received from client.",
+                "bad_message",
+            ),
+            (
+                Error::Configuration(String::from("Missing host.")),
+                "Configuration error: Missing host.",
+                "configuration",
+            ),
+            (Error::EndOfFile, "End of file.", "end_of_file"),
+            (
+                Error::Failure(String::from("Failed.")),
+                "Failed.",
+                "failure",
+            ),
+            (
+                Error::FileTooLarge,
+                "The file exceeds the maximum upload size.",
+                "file_too_large",
+            ),
+            (
+                Error::InvalidHandle,
+                "The handle does not exist.",
+                "invalid_handle",
+            ),
+            (
+                Error::IOError(ErrorKind::BrokenPipe),
+                "IO Error: broken pipe",
+                "io_error",
+            ),
+            (
+                Error::IsADirectory,
+                "The file is a directory.",
+                "is_a_directory",
+            ),
+            (Error::NoSuchFile, "File not found.", "no_such_file"),
+            (
+                Error::NotADirectory,
+                "The path is not a directory.",
+                "not_a_directory",
+            ),
+            (
+                Error::PathTooLong,
+                "The path exceeds the maximum length or depth.",
+                "path_too_long",
+            ),
+            (
+                Error::PermissionDenied,
+                "Permission denied.",
+                "permission_denied",
+            ),
+            (
+                Error::QuotaExceeded,
+                "The storage quota has been exceeded.",
+                "quota_exceeded",
+            ),
+            (
+                Error::SymlinkLoop,
+                "Too many levels of symbolic links.",
+                "symlink_loop",
+            ),
+            (
+                Error::Storage(String::from("Unavailable.")),
+                "An error occurred with the storage backend: Unavailable.",
+                "storage",
+            ),
+            (
+                Error::Unimplemented,
+                "SFTP request not implemented.",
+                "unimplemented",
+            ),
+        ];
+
+        for (error, display, code) in errors {
+            assert_eq!(display, error.to_string());
+            assert_eq!(code, error.code());
+        }
+    }
+
+    #[test]
+    fn test_error_implements_std_error() {
+        let error: Box<dyn std::error::Error + Send + Sync> = Box::new(Error::NoSuchFile);
+
+        assert_eq!("File not found.", error.to_string());
+        assert!(error.source().is_none());
+    }
+
+    #[test]
+    fn test_from_io_error_keeps_kind() {
+        assert_eq!(
+            Error::IOError(ErrorKind::PermissionDenied),
+            Error::from(std::io::Error::from(ErrorKind::PermissionDenied))
+        );
+        assert_eq!(
+            Error::EndOfFile,
+            Error::from(std::io::Error::from(ErrorKind::UnexpectedEof))
+        );
+    }
+
+    #[test]
+    fn test_from_addr_parse_error_keeps_message() {
+        let addr_parse_error = "host".parse::<std::net::SocketAddr>().unwrap_err();
+
+        assert_eq!(
+            Error::Configuration(addr_parse_error.to_string()),
+            Error::from(addr_parse_error)
+        );
+    }
+}
@@ -0,0 +1,78 @@
+/// Checks if a file name matches a glob pattern, where `*` matches any sequence of characters,
+/// including an empty one, and `?` matches exactly one character. Every other character matches
+/// itself.
+pub fn is_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+
+    let mut pattern_index = 0;
+    let mut name_index = 0;
+
+    // The position of the last `*` and the name position it was matched against, so that the
+    // `*` can be retried against a longer sequence when a later part of the pattern fails.
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while name_index < name.len() {
+        match pattern.get(pattern_index) {
+            Some('*') => {
+                backtrack = Some((pattern_index, name_index));
+                pattern_index += 1;
+            }
+            Some('?') => {
+                pattern_index += 1;
+                name_index += 1;
+            }
+            Some(c) if *c == name[name_index] => {
+                pattern_index += 1;
+                name_index += 1;
+            }
+            _ => match backtrack {
+                Some((star_index, star_name_index)) => {
+                    backtrack = Some((star_index, star_name_index + 1));
+                    pattern_index = star_index + 1;
+                    name_index = star_name_index + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[pattern_index..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_is_match_with_literal() {
+        assert!(is_match("file.txt", "file.txt"));
+        assert!(!is_match("file.txt", "file.csv"));
+        assert!(!is_match("file.txt", "file.txt.bak"));
+        assert!(!is_match("file", ""));
+        assert!(is_match("", ""));
+    }
+
+    #[test]
+    fn test_is_match_with_star() {
+        assert!(is_match("*", ""));
+        assert!(is_match("*", "file.txt"));
+        assert!(is_match("*.txt", "file.txt"));
+        assert!(is_match("*.txt", ".txt"));
+        assert!(!is_match("*.txt", "file.csv"));
+        assert!(is_match("report-*.csv", "report-2024-01.csv"));
+        assert!(is_match("a*b*c", "aXbYbZc"));
+        assert!(!is_match("a*b*c", "aXbYbZ"));
+        assert!(is_match("**", "file"));
+    }
+
+    #[test]
+    fn test_is_match_with_question_mark() {
+        assert!(is_match("file?.txt", "file1.txt"));
+        assert!(!is_match("file?.txt", "file.txt"));
+        assert!(!is_match("file?.txt", "file12.txt"));
+        assert!(is_match("?*", "a"));
+        assert!(!is_match("?*", ""));
+        assert!(is_match("???", "äöü"));
+    }
+}
@@ -0,0 +1,220 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{error, info};
+
+use crate::error::Error;
+use crate::storage::Storage;
+
+/// The maximum length of a request's line and headers. Probes send small requests, so anything
+/// larger is rejected.
+const MAX_REQUEST_LENGTH: usize = 8 * 1024;
+
+/// Serves liveness and readiness probes over HTTP for container orchestrators. `/livez` succeeds
+/// while the process is running, and `/readyz` succeeds while the storage backend is reachable.
+pub struct HealthServer {
+    listener: TcpListener,
+    object_storage: Arc<dyn Storage>,
+}
+
+impl HealthServer {
+    pub async fn bind(
+        health_addr: SocketAddr,
+        object_storage: Arc<dyn Storage>,
+    ) -> Result<HealthServer, Error> {
+        let listener = TcpListener::bind(health_addr).await?;
+
+        info!("Serving health checks on {}", health_addr);
+
+        Ok(HealthServer {
+            listener,
+            object_storage,
+        })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, Error> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    pub async fn run(self) {
+        loop {
+            let stream = match self.listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(error) => {
+                    error!("Failed to accept health check connection: {}", error);
+                    continue;
+                }
+            };
+
+            let object_storage = self.object_storage.clone();
+
+            tokio::spawn(async move {
+                if let Err(error) = handle_connection(stream, object_storage).await {
+                    error!("Failed to handle health check: {}", error);
+                }
+            });
+        }
+    }
+}
+
+async fn handle_connection(
+    mut stream: TcpStream,
+    object_storage: Arc<dyn Storage>,
+) -> Result<(), Error> {
+    let response = match read_request_line(&mut stream).await? {
+        Some(request_line) => route_request(&request_line, object_storage.as_ref()).await,
+        None => build_response(400, "Bad Request"),
+    };
+
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+
+    Ok(())
+}
+
+/// Reads the request until the end of its headers, returning the request line. `None` is
+/// returned if the request is too large or the client disconnects early.
+async fn read_request_line(stream: &mut TcpStream) -> Result<Option<String>, Error> {
+    let mut request = Vec::new();
+    let mut buffer = [0; 1024];
+
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        let len = stream.read(&mut buffer).await?;
+
+        if len == 0 || request.len() + len > MAX_REQUEST_LENGTH {
+            return Ok(None);
+        }
+
+        request.extend_from_slice(&buffer[..len]);
+    }
+
+    let request = String::from_utf8_lossy(&request);
+
+    Ok(request.lines().next().map(|line| line.to_string()))
+}
+
+async fn route_request(request_line: &str, object_storage: &dyn Storage) -> String {
+    let mut request_parts = request_line.split_whitespace();
+
+    match (request_parts.next(), request_parts.next()) {
+        (Some("GET"), Some("/livez")) => build_response(200, "OK"),
+        (Some("GET"), Some("/readyz")) => match object_storage.health_check().await {
+            Ok(_) => build_response(200, "OK"),
+            Err(error) => {
+                error!("Readiness check failed: {}", error);
+                build_response(503, "Service Unavailable")
+            }
+        },
+        (Some("GET"), Some(_)) => build_response(404, "Not Found"),
+        _ => build_response(405, "Method Not Allowed"),
+    }
+}
+
+fn build_response(status_code: u16, reason: &str) -> String {
+    format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}\n",
+        status_code,
+        reason,
+        reason.len() + 1,
+        reason
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::storage::{filesystem::FilesystemStorage, DEFAULT_MAX_HANDLES};
+    use std::path::PathBuf;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_livez() {
+        let root = TempDir::new().unwrap();
+        let health_addr = start_health_server(root.path().to_path_buf()).await;
+
+        let response = send_request(health_addr, "GET /livez HTTP/1.1").await;
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_livez_with_failing_storage() {
+        let root = TempDir::new().unwrap();
+        let health_addr = start_health_server(root.path().join("missing")).await;
+
+        let response = send_request(health_addr, "GET /livez HTTP/1.1").await;
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_readyz() {
+        let root = TempDir::new().unwrap();
+        let health_addr = start_health_server(root.path().to_path_buf()).await;
+
+        let response = send_request(health_addr, "GET /readyz HTTP/1.1").await;
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\nOK\n"));
+    }
+
+    #[tokio::test]
+    async fn test_readyz_with_failing_storage() {
+        let root = TempDir::new().unwrap();
+        let health_addr = start_health_server(root.path().join("missing")).await;
+
+        let response = send_request(health_addr, "GET /readyz HTTP/1.1").await;
+
+        assert!(response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_unknown_path() {
+        let root = TempDir::new().unwrap();
+        let health_addr = start_health_server(root.path().to_path_buf()).await;
+
+        let response = send_request(health_addr, "GET /unknown HTTP/1.1").await;
+
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_unsupported_method() {
+        let root = TempDir::new().unwrap();
+        let health_addr = start_health_server(root.path().to_path_buf()).await;
+
+        let response = send_request(health_addr, "POST /readyz HTTP/1.1").await;
+
+        assert!(response.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+    }
+
+    async fn start_health_server(root: PathBuf) -> SocketAddr {
+        let object_storage = Arc::new(FilesystemStorage::new(root, DEFAULT_MAX_HANDLES));
+
+        let health_server = HealthServer::bind("127.0.0.1:0".parse().unwrap(), object_storage)
+            .await
+            .unwrap();
+        let health_addr = health_server.local_addr().unwrap();
+
+        tokio::spawn(health_server.run());
+
+        health_addr
+    }
+
+    async fn send_request(health_addr: SocketAddr, request_line: &str) -> String {
+        let mut stream = TcpStream::connect(health_addr).await.unwrap();
+
+        stream
+            .write_all(format!("{}\r\nHost: localhost\r\n\r\n", request_line).as_bytes())
+            .await
+            .unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+
+        response
+    }
+}
@@ -1,8 +1,16 @@
+pub mod audit;
+pub mod authorizer;
+pub mod client_quirks;
 pub mod config;
 pub mod error;
-mod protocol;
-mod sftp_session;
-mod sftp_stream;
+mod glob;
+pub mod health;
+pub mod metrics;
+pub mod observability;
+pub mod protocol;
+mod rate_limiter;
+pub mod sftp_session;
+pub mod sftp_stream;
 mod ssh_keys;
 pub mod ssh_server;
 pub mod storage;
@@ -2,59 +2,74 @@ use dotenv::dotenv;
 use std::time::Duration;
 use tokio::runtime::Runtime;
 use tokio::signal;
-use tracing::{info, Level};
+use tracing::{error, info};
 
-use dray::{config::DrayConfig, ssh_server::DraySshServer};
-
-use tracing_subscriber::{filter::EnvFilter, fmt, layer::SubscriberExt, util::SubscriberInitExt};
+use dray::{
+    config::DrayConfig,
+    health::HealthServer,
+    metrics,
+    observability::{self, ObservabilityConfig},
+    ssh_server::DraySshServer,
+};
 
 fn main() {
     dotenv().ok();
 
     let runtime = Runtime::new().expect("Tokio Runtime should initialize");
 
-    runtime.spawn(init_tracer());
+    let observability_config = ObservabilityConfig::new().unwrap();
+
+    runtime.spawn(observability::init_observability(
+        observability_config.log_format,
+    ));
 
     info!("Starting Dray");
 
     let dray_config = DrayConfig::new().unwrap();
-    let dray_server = runtime.block_on(DraySshServer::new(dray_config));
+
+    if dray_config.check_config {
+        match runtime.block_on(dray_config.validate_full()) {
+            Ok(_) => std::process::exit(0),
+            Err(error) => {
+                error!("Configuration check failed: {}", error);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if let Some(metrics_addr) = dray_config.get_metrics_socket_addr().unwrap() {
+        let _runtime_guard = runtime.enter();
+        metrics::init_metrics(metrics_addr).unwrap();
+    }
+
+    let shutdown_timeout = dray_config.get_shutdown_timeout();
+    let health_addr = dray_config.get_health_socket_addr().unwrap();
+
+    let dray_server = runtime.block_on(DraySshServer::new(dray_config)).unwrap();
+    let shutdown_handle = dray_server.get_shutdown_handle();
 
     runtime.block_on(dray_server.health_check()).unwrap();
+
+    if let Some(health_addr) = health_addr {
+        let health_server = runtime
+            .block_on(HealthServer::bind(
+                health_addr,
+                dray_server.get_object_storage(),
+            ))
+            .unwrap();
+        runtime.spawn(health_server.run());
+    }
+
     runtime.spawn(dray_server.run_server());
 
     runtime.block_on(signal::ctrl_c()).unwrap();
 
     info!("Received SIGINT - Shutting Down Dray");
 
-    runtime.shutdown_timeout(Duration::from_secs(10))
-}
+    match runtime.block_on(shutdown_handle.shutdown(shutdown_timeout)) {
+        Ok(_) => info!("Drained all sftp sessions"),
+        Err(error) => error!("Failed to drain sftp sessions: {}", error),
+    };
 
-async fn init_tracer() {
-    let tracer = opentelemetry_otlp::new_pipeline()
-        .tracing()
-        .with_exporter(opentelemetry_otlp::new_exporter().tonic())
-        .with_trace_config(opentelemetry_sdk::trace::config().with_resource(
-            opentelemetry_sdk::Resource::new(vec![opentelemetry::KeyValue::new(
-                "service.name",
-                "dray",
-            )]),
-        ))
-        .install_batch(opentelemetry_sdk::runtime::Tokio)
-        .expect("Tokio runtime should be configured");
-
-    // Create a tracing layer with the configured tracer
-    let opentelemetry = tracing_opentelemetry::layer().with_tracer(tracer);
-
-    let env_filter = EnvFilter::builder()
-        .with_default_directive(Level::INFO.into())
-        .from_env()
-        .unwrap();
-
-    tracing_subscriber::registry()
-        .with(env_filter)
-        .with(opentelemetry)
-        .with(fmt::Layer::default())
-        .try_init()
-        .unwrap();
+    runtime.shutdown_timeout(Duration::from_secs(1))
 }
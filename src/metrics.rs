@@ -0,0 +1,131 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use metrics_exporter_prometheus::PrometheusBuilder;
+
+use crate::error::Error;
+
+const REQUESTS_TOTAL: &str = "dray_requests_total";
+const REQUEST_DURATION_SECONDS: &str = "dray_request_duration_seconds";
+const OPEN_HANDLES: &str = "dray_open_handles";
+const ACTIVE_SESSIONS: &str = "dray_active_sessions";
+const REJECTED_SESSIONS_TOTAL: &str = "dray_rejected_sessions_total";
+const REJECTED_CONNECTIONS_TOTAL: &str = "dray_rejected_connections_total";
+
+pub const READ_HANDLE: &str = "read";
+pub const WRITE_HANDLE: &str = "write";
+pub const DIR_HANDLE: &str = "dir";
+
+/// Installs the global metrics recorder and serves metrics in the Prometheus text format on
+/// the given address. This must be called from within a Tokio runtime.
+pub fn init_metrics(metrics_addr: SocketAddr) -> Result<(), Error> {
+    PrometheusBuilder::new()
+        .with_http_listener(metrics_addr)
+        .install()
+        .map_err(|error| Error::Configuration(error.to_string()))
+}
+
+/// Records a handled request and how long it took to handle.
+pub fn record_request(request_name: &'static str, duration: Duration) {
+    ::metrics::counter!(REQUESTS_TOTAL, "request" => request_name).increment(1);
+    ::metrics::histogram!(REQUEST_DURATION_SECONDS, "request" => request_name)
+        .record(duration.as_secs_f64());
+}
+
+pub fn increment_open_handles(handle_type: &'static str) {
+    ::metrics::gauge!(OPEN_HANDLES, "type" => handle_type).increment(1.0);
+}
+
+pub fn decrement_open_handles(handle_type: &'static str, count: usize) {
+    if count > 0 {
+        ::metrics::gauge!(OPEN_HANDLES, "type" => handle_type).decrement(count as f64);
+    }
+}
+
+pub fn increment_active_sessions() {
+    ::metrics::gauge!(ACTIVE_SESSIONS).increment(1.0);
+}
+
+pub fn decrement_active_sessions() {
+    ::metrics::gauge!(ACTIVE_SESSIONS).decrement(1.0);
+}
+
+/// Records a session that was rejected because the maximum number of sessions were active.
+pub fn record_rejected_session() {
+    ::metrics::counter!(REJECTED_SESSIONS_TOTAL).increment(1);
+}
+
+/// Records a connection that was rejected because its IP address had the maximum number of
+/// connections open.
+pub fn record_rejected_connection() {
+    ::metrics::counter!(REJECTED_CONNECTIONS_TOTAL).increment(1);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::protocol::request::{self, Request};
+    use crate::sftp_session::{SftpSession, SftpSessionConfig};
+    use crate::storage::{filesystem::FilesystemStorage, DEFAULT_MAX_HANDLES};
+
+    use std::sync::Arc;
+    use tempfile::TempDir;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+
+    #[tokio::test]
+    async fn test_init_metrics_serves_request_metrics() {
+        let metrics_addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        init_metrics(metrics_addr).unwrap();
+
+        let root = TempDir::new().unwrap();
+        std::fs::create_dir_all(root.path().join("home/test")).unwrap();
+
+        let object_storage = Arc::new(FilesystemStorage::new(
+            root.path().to_path_buf(),
+            DEFAULT_MAX_HANDLES,
+        ));
+        let sftp_session = SftpSession::new(
+            object_storage,
+            String::from("test"),
+            &SftpSessionConfig::default(),
+        )
+        .unwrap();
+
+        sftp_session
+            .handle_request(Request::Init(request::init::Init { version: 3 }))
+            .await;
+        sftp_session
+            .handle_request(Request::Opendir(request::path::Path {
+                id: 1,
+                path: String::from("/home/test"),
+            }))
+            .await;
+
+        let metrics = scrape_metrics(metrics_addr).await;
+
+        assert!(metrics.contains("dray_requests_total{request=\"init\"}"));
+        assert!(metrics.contains("dray_requests_total{request=\"opendir\"}"));
+        assert!(metrics.contains("dray_request_duration_seconds"));
+        assert!(metrics.contains("dray_open_handles{type=\"dir\"}"));
+    }
+
+    async fn scrape_metrics(metrics_addr: SocketAddr) -> String {
+        let mut stream = TcpStream::connect(metrics_addr).await.unwrap();
+
+        stream
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+
+        response
+    }
+}
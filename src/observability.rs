@@ -0,0 +1,145 @@
+use serde::Deserialize;
+use tracing::{Level, Subscriber};
+use tracing_subscriber::{
+    filter::EnvFilter,
+    fmt::{self, MakeWriter},
+    layer::SubscriberExt,
+    registry::LookupSpan,
+    util::SubscriberInitExt,
+    Layer,
+};
+
+use crate::error::Error;
+
+#[derive(Clone, Copy, Default, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Pretty,
+    Json,
+}
+
+/// Logging is initialized before the rest of the configuration is loaded, so that configuration
+/// errors are logged, and its settings are loaded separately.
+#[derive(Clone, Deserialize, Debug)]
+pub struct ObservabilityConfig {
+    #[serde(default)]
+    pub log_format: LogFormat,
+}
+
+impl ObservabilityConfig {
+    pub fn new() -> Result<ObservabilityConfig, Error> {
+        Ok(envy::prefixed("DRAY_").from_env::<ObservabilityConfig>()?)
+    }
+}
+
+/// Installs the global tracing subscriber, which exports spans with OpenTelemetry and writes log
+/// lines to stdout in the configured format. This must be called from within a Tokio runtime.
+pub async fn init_observability(log_format: LogFormat) {
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(opentelemetry_otlp::new_exporter().tonic())
+        .with_trace_config(opentelemetry_sdk::trace::config().with_resource(
+            opentelemetry_sdk::Resource::new(vec![opentelemetry::KeyValue::new(
+                "service.name",
+                "dray",
+            )]),
+        ))
+        .install_batch(opentelemetry_sdk::runtime::Tokio)
+        .expect("Tokio runtime should be configured");
+
+    // Create a tracing layer with the configured tracer
+    let opentelemetry = tracing_opentelemetry::layer().with_tracer(tracer);
+
+    let env_filter = EnvFilter::builder()
+        .with_default_directive(Level::INFO.into())
+        .from_env()
+        .unwrap();
+
+    tracing_subscriber::registry()
+        .with(env_filter)
+        .with(opentelemetry)
+        .with(build_fmt_layer(log_format, std::io::stdout))
+        .try_init()
+        .unwrap();
+}
+
+/// Builds the layer that writes log lines. JSON lines include the fields of the current span and
+/// its parents, such as the request being handled, so that they can be queried by log shippers.
+fn build_fmt_layer<S, W>(log_format: LogFormat, make_writer: W) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    match log_format {
+        LogFormat::Pretty => fmt::layer().with_writer(make_writer).boxed(),
+        LogFormat::Json => fmt::layer()
+            .json()
+            .with_current_span(true)
+            .with_span_list(true)
+            .with_writer(make_writer)
+            .boxed(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+    use tracing::{info, info_span};
+
+    #[test]
+    fn test_build_fmt_layer_with_pretty_format() {
+        let output = write_test_event(LogFormat::Pretty);
+
+        assert!(!output.starts_with('{'));
+        assert!(output.contains("handle_request"));
+        assert!(output.contains("Handled request"));
+    }
+
+    #[test]
+    fn test_build_fmt_layer_with_json_format() {
+        let output = write_test_event(LogFormat::Json);
+
+        assert!(output.starts_with('{'));
+        assert!(output.trim_end().ends_with('}'));
+        assert_eq!(1, output.lines().count());
+        assert!(output.contains("\"message\":\"Handled request\""));
+        assert!(output.contains("\"id\":1"));
+        assert!(output.contains("\"name\":\"handle_request\""));
+        assert!(output.contains("\"user\":\"test\""));
+    }
+
+    #[derive(Clone, Default)]
+    struct TestWriter(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for TestWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn write_test_event(log_format: LogFormat) -> String {
+        let writer = TestWriter::default();
+        let make_writer = writer.clone();
+
+        let subscriber = tracing_subscriber::registry()
+            .with(build_fmt_layer(log_format, move || make_writer.clone()));
+
+        tracing::subscriber::with_default(subscriber, || {
+            let span = info_span!("handle_request", user = "test");
+            let _entered = span.enter();
+
+            info!(id = 1, "Handled request");
+        });
+
+        let output = writer.0.lock().unwrap().clone();
+        String::from_utf8(output).unwrap()
+    }
+}
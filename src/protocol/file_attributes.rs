@@ -10,6 +10,12 @@ const UIDGID: u32 = 0x00000002;
 const PERMISSIONS: u32 = 0x00000004;
 const ACMODTIME: u32 = 0x00000008;
 
+/// The bits of the permissions that hold the file type, as in `st_mode`.
+const S_IFMT: u32 = 0o170000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFREG: u32 = 0o100000;
+const S_IFLNK: u32 = 0o120000;
+
 #[derive(Debug, Default, PartialEq, Eq)]
 pub struct FileAttributes {
     pub size: Option<u64>,
@@ -22,8 +28,22 @@ pub struct FileAttributes {
 
 impl FileAttributes {
     pub fn is_dir(&self) -> bool {
+        self.has_file_type(S_IFDIR)
+    }
+
+    pub fn is_regular(&self) -> bool {
+        self.has_file_type(S_IFREG)
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.has_file_type(S_IFLNK)
+    }
+
+    /// Compares the whole file type, since other types, such as sockets and block devices, share
+    /// some of their bits with directories and regular files.
+    fn has_file_type(&self, file_type: u32) -> bool {
         match self.permissions {
-            Some(permissions) => (permissions >> 14) & 0x1 == 0x01,
+            Some(permissions) => permissions & S_IFMT == file_type,
             None => false,
         }
     }
@@ -274,4 +294,81 @@ mod tests {
         }
         .is_dir());
     }
+
+    #[test]
+    fn test_is_dir_returns_false_with_symlink_file_type() {
+        assert!(!FileAttributes {
+            permissions: Some(0o120777),
+            ..Default::default()
+        }
+        .is_dir());
+    }
+
+    #[test]
+    fn test_is_dir_returns_false_with_other_file_types() {
+        // Sockets and block devices have the directory bit set as part of their file type.
+        for permissions in [0o140777, 0o060644, 0o020644, 0o010644] {
+            assert!(!FileAttributes {
+                permissions: Some(permissions),
+                ..Default::default()
+            }
+            .is_dir());
+        }
+    }
+
+    #[test]
+    fn test_is_regular_returns_true_with_regular_file_type() {
+        assert!(FileAttributes {
+            permissions: Some(0o100644),
+            ..Default::default()
+        }
+        .is_regular());
+    }
+
+    #[test]
+    fn test_is_regular_returns_false_with_other_file_types() {
+        for permissions in [0o40755, 0o120777, 0o140777, 0o00644] {
+            assert!(!FileAttributes {
+                permissions: Some(permissions),
+                ..Default::default()
+            }
+            .is_regular());
+        }
+    }
+
+    #[test]
+    fn test_is_regular_returns_false_with_missing_permissions() {
+        assert!(!FileAttributes {
+            permissions: None,
+            ..Default::default()
+        }
+        .is_regular());
+    }
+
+    #[test]
+    fn test_is_symlink_returns_true_with_symlink_file_type() {
+        assert!(FileAttributes {
+            permissions: Some(0o120777),
+            ..Default::default()
+        }
+        .is_symlink());
+    }
+
+    #[test]
+    fn test_is_symlink_returns_false_with_regular_file_type() {
+        assert!(!FileAttributes {
+            permissions: Some(0o100777),
+            ..Default::default()
+        }
+        .is_symlink());
+    }
+
+    #[test]
+    fn test_is_symlink_returns_false_with_missing_permissions() {
+        assert!(!FileAttributes {
+            permissions: None,
+            ..Default::default()
+        }
+        .is_symlink());
+    }
 }
@@ -0,0 +1,331 @@
+use crate::error::Error;
+use crate::try_buf::TryBuf;
+
+use bytes::Bytes;
+use std::convert::TryFrom;
+
+use super::RequestId;
+
+pub const FSYNC: &str = "fsync@openssh.com";
+pub const HARDLINK: &str = "hardlink@openssh.com";
+pub const LIMITS: &str = "limits@openssh.com";
+pub const LIST_GLOB: &str = "list-glob@dray";
+pub const POSIX_RENAME: &str = "posix-rename@openssh.com";
+pub const STATVFS: &str = "statvfs@openssh.com";
+
+/// The extensions advertised to clients in the version response, with the version of each.
+pub const SUPPORTED_EXTENSIONS: &[(&str, &str)] = &[
+    (FSYNC, "1"),
+    (HARDLINK, "1"),
+    (LIMITS, "1"),
+    (LIST_GLOB, "2"),
+    (POSIX_RENAME, "1"),
+    (STATVFS, "2"),
+];
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Extended {
+    pub id: u32,
+    pub extended_request: ExtendedRequest,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ExtendedRequest {
+    Fsync {
+        handle: String,
+    },
+    Hardlink {
+        old_path: String,
+        new_path: String,
+    },
+    /// Requests the server's limits on the size of requests and the number of open handles.
+    Limits,
+    /// Opens a directory for reading the files with names that match a glob pattern.
+    ListGlob {
+        path: String,
+        pattern: String,
+    },
+    PosixRename {
+        old_path: String,
+        new_path: String,
+    },
+    Statvfs {
+        path: String,
+    },
+    Unsupported {
+        name: String,
+    },
+}
+
+impl RequestId for Extended {
+    fn get_request_id(&self) -> u32 {
+        self.id
+    }
+}
+
+impl TryFrom<&mut Bytes> for Extended {
+    type Error = Error;
+
+    #[tracing::instrument]
+    fn try_from(extended_bytes: &mut Bytes) -> Result<Self, Self::Error> {
+        let id = extended_bytes.try_get_u32()?;
+        let name = extended_bytes.try_get_string()?;
+
+        // The data following the name depends on the extension, so the data of an unsupported
+        // extension is ignored.
+        let extended_request = match name.as_str() {
+            FSYNC => ExtendedRequest::Fsync {
+                handle: extended_bytes.try_get_string()?,
+            },
+            HARDLINK => ExtendedRequest::Hardlink {
+                old_path: extended_bytes.try_get_string()?,
+                new_path: extended_bytes.try_get_string()?,
+            },
+            LIMITS => ExtendedRequest::Limits,
+            LIST_GLOB => ExtendedRequest::ListGlob {
+                path: extended_bytes.try_get_string()?,
+                pattern: extended_bytes.try_get_string()?,
+            },
+            POSIX_RENAME => ExtendedRequest::PosixRename {
+                old_path: extended_bytes.try_get_string()?,
+                new_path: extended_bytes.try_get_string()?,
+            },
+            STATVFS => ExtendedRequest::Statvfs {
+                path: extended_bytes.try_get_string()?,
+            },
+            _ => ExtendedRequest::Unsupported { name },
+        };
+
+        Ok(Extended {
+            id,
+            extended_request,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    use crate::try_buf::TryBufMut;
+
+    use bytes::{BufMut, BytesMut};
+
+    #[test]
+    fn test_parse_fsync() {
+        let mut extended_bytes = BytesMut::new();
+
+        extended_bytes.put_u32(0x01); // id
+        extended_bytes.try_put_str(FSYNC).unwrap(); // extended request
+        extended_bytes.try_put_str("HANDLE").unwrap(); // handle
+
+        assert_eq!(
+            Extended::try_from(&mut extended_bytes.freeze()),
+            Ok(Extended {
+                id: 0x01,
+                extended_request: ExtendedRequest::Fsync {
+                    handle: String::from("HANDLE")
+                },
+            })
+        )
+    }
+
+    #[test]
+    fn test_parse_fsync_with_invalid_handle() {
+        let mut extended_bytes = BytesMut::new();
+
+        extended_bytes.put_u32(0x01); // id
+        extended_bytes.try_put_str(FSYNC).unwrap(); // extended request
+        extended_bytes.put_u32(0x01); // bad handle length
+
+        assert_eq!(
+            Extended::try_from(&mut extended_bytes.freeze()),
+            Err(Error::BadMessage)
+        )
+    }
+
+    #[test]
+    fn test_parse_hardlink() {
+        let mut extended_bytes = BytesMut::new();
+
+        extended_bytes.put_u32(0x01); // id
+        extended_bytes.try_put_str(HARDLINK).unwrap(); // extended request
+        extended_bytes.try_put_str("old").unwrap(); // old path
+        extended_bytes.try_put_str("new").unwrap(); // new path
+
+        assert_eq!(
+            Extended::try_from(&mut extended_bytes.freeze()),
+            Ok(Extended {
+                id: 0x01,
+                extended_request: ExtendedRequest::Hardlink {
+                    old_path: String::from("old"),
+                    new_path: String::from("new"),
+                },
+            })
+        )
+    }
+
+    #[test]
+    fn test_parse_list_glob() {
+        let mut extended_bytes = BytesMut::new();
+
+        extended_bytes.put_u32(0x01); // id
+        extended_bytes.try_put_str(LIST_GLOB).unwrap(); // extended request
+        extended_bytes.try_put_str("/path").unwrap(); // path
+        extended_bytes.try_put_str("*.txt").unwrap(); // pattern
+
+        assert_eq!(
+            Extended::try_from(&mut extended_bytes.freeze()),
+            Ok(Extended {
+                id: 0x01,
+                extended_request: ExtendedRequest::ListGlob {
+                    path: String::from("/path"),
+                    pattern: String::from("*.txt"),
+                },
+            })
+        )
+    }
+
+    #[test]
+    fn test_parse_list_glob_with_invalid_pattern() {
+        let mut extended_bytes = BytesMut::new();
+
+        extended_bytes.put_u32(0x01); // id
+        extended_bytes.try_put_str(LIST_GLOB).unwrap(); // extended request
+        extended_bytes.try_put_str("/path").unwrap(); // path
+        extended_bytes.put_u32(0x01); // bad pattern length
+
+        assert_eq!(
+            Extended::try_from(&mut extended_bytes.freeze()),
+            Err(Error::BadMessage)
+        )
+    }
+
+    #[test]
+    fn test_parse_posix_rename() {
+        let mut extended_bytes = BytesMut::new();
+
+        extended_bytes.put_u32(0x01); // id
+        extended_bytes.try_put_str(POSIX_RENAME).unwrap(); // extended request
+        extended_bytes.try_put_str("old").unwrap(); // old path
+        extended_bytes.try_put_str("new").unwrap(); // new path
+
+        assert_eq!(
+            Extended::try_from(&mut extended_bytes.freeze()),
+            Ok(Extended {
+                id: 0x01,
+                extended_request: ExtendedRequest::PosixRename {
+                    old_path: String::from("old"),
+                    new_path: String::from("new"),
+                },
+            })
+        )
+    }
+
+    #[test]
+    fn test_parse_posix_rename_with_invalid_new_path() {
+        let mut extended_bytes = BytesMut::new();
+
+        extended_bytes.put_u32(0x01); // id
+        extended_bytes.try_put_str(POSIX_RENAME).unwrap(); // extended request
+        extended_bytes.try_put_str("old").unwrap(); // old path
+        extended_bytes.put_u32(0x01); // bad new path length
+
+        assert_eq!(
+            Extended::try_from(&mut extended_bytes.freeze()),
+            Err(Error::BadMessage)
+        )
+    }
+
+    #[test]
+    fn test_parse_limits() {
+        let mut extended_bytes = BytesMut::new();
+
+        extended_bytes.put_u32(0x01); // id
+        extended_bytes.try_put_str(LIMITS).unwrap(); // extended request
+
+        assert_eq!(
+            Extended::try_from(&mut extended_bytes.freeze()),
+            Ok(Extended {
+                id: 0x01,
+                extended_request: ExtendedRequest::Limits,
+            })
+        )
+    }
+
+    #[test]
+    fn test_parse_statvfs() {
+        let mut extended_bytes = BytesMut::new();
+
+        extended_bytes.put_u32(0x01); // id
+        extended_bytes.try_put_str(STATVFS).unwrap(); // extended request
+        extended_bytes.try_put_str("/path").unwrap(); // path
+
+        assert_eq!(
+            Extended::try_from(&mut extended_bytes.freeze()),
+            Ok(Extended {
+                id: 0x01,
+                extended_request: ExtendedRequest::Statvfs {
+                    path: String::from("/path"),
+                },
+            })
+        )
+    }
+
+    #[test]
+    fn test_parse_unsupported_extension() {
+        let mut extended_bytes = BytesMut::new();
+
+        extended_bytes.put_u32(0x01); // id
+        extended_bytes.try_put_str("unknown@example.com").unwrap(); // extended request
+        extended_bytes.put_u32(0x02); // extension data
+
+        assert_eq!(
+            Extended::try_from(&mut extended_bytes.freeze()),
+            Ok(Extended {
+                id: 0x01,
+                extended_request: ExtendedRequest::Unsupported {
+                    name: String::from("unknown@example.com")
+                },
+            })
+        )
+    }
+
+    #[test]
+    fn test_parse_extended_with_invalid_id() {
+        let mut extended_bytes = BytesMut::new();
+
+        extended_bytes.put_u8(0x01); // bad id
+
+        assert_eq!(
+            Extended::try_from(&mut extended_bytes.freeze()),
+            Err(Error::BadMessage)
+        )
+    }
+
+    #[test]
+    fn test_parse_extended_with_invalid_name() {
+        let mut extended_bytes = BytesMut::new();
+
+        extended_bytes.put_u32(0x01); // id
+        extended_bytes.put_u32(0x01); // bad extended request length
+
+        assert_eq!(
+            Extended::try_from(&mut extended_bytes.freeze()),
+            Err(Error::BadMessage)
+        )
+    }
+
+    #[test]
+    fn test_get_request_id() {
+        let extended = Extended {
+            id: 1000,
+            extended_request: ExtendedRequest::Fsync {
+                handle: String::from("handle"),
+            },
+        };
+
+        assert_eq!(1000, extended.get_request_id());
+    }
+}
@@ -8,7 +8,7 @@ use super::RequestId;
 
 #[derive(Debug, PartialEq, Eq)]
 pub struct Init {
-    pub version: u8,
+    pub version: u32,
 }
 
 impl RequestId for Init {
@@ -23,7 +23,7 @@ impl TryFrom<&mut Bytes> for Init {
     #[tracing::instrument]
     fn try_from(init_bytes: &mut Bytes) -> Result<Self, Self::Error> {
         Ok(Init {
-            version: init_bytes.try_get_u8()?,
+            version: init_bytes.try_get_u32()?,
         })
     }
 }
@@ -38,7 +38,7 @@ mod tests {
     fn test_parse_init_message() {
         let mut init_bytes = BytesMut::new();
 
-        init_bytes.put_u8(0x03);
+        init_bytes.put_u32(0x03);
 
         assert_eq!(
             Init::try_from(&mut init_bytes.freeze()),
@@ -46,6 +46,18 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_parse_init_message_with_truncated_version() {
+        let mut init_bytes = BytesMut::new();
+
+        init_bytes.put_u8(0x03);
+
+        assert_eq!(
+            Init::try_from(&mut init_bytes.freeze()),
+            Err(Error::BadMessage)
+        );
+    }
+
     #[test]
     fn test_parse_invalid_message() {
         let init_bytes = BytesMut::new();
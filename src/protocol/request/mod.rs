@@ -1,11 +1,12 @@
-use std::convert::TryFrom;
+use std::convert::{TryFrom, TryInto};
 
 use bytes::Bytes;
-use tracing::Level;
+use tracing::{warn, Level};
 
 use crate::error::Error;
 use crate::try_buf::TryBuf;
 
+pub mod extended;
 pub mod handle;
 pub mod handle_attributes;
 pub mod init;
@@ -13,12 +14,17 @@ pub mod open;
 pub mod path;
 pub mod path_attributes;
 pub mod read;
+pub mod realpath;
 pub mod rename;
 pub mod symlink;
+pub mod unsupported;
 pub mod write;
 
 const DATA_TYPE_LENGTH: u32 = 1;
 
+/// The init request has a protocol version where other requests have an id.
+const INIT_DATA_TYPE: u8 = 1;
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum Request {
     Init(init::Init),
@@ -35,11 +41,13 @@ pub enum Request {
     Remove(path::Path),
     Mkdir(path_attributes::PathAttributes),
     Rmdir(path::Path),
-    Realpath(path::Path),
+    Realpath(realpath::Realpath),
     Stat(path::Path),
     Rename(rename::Rename),
     Readlink(path::Path),
     Symlink(symlink::Symlink),
+    Extended(extended::Extended),
+    Unsupported(unsupported::Unsupported),
 }
 
 pub trait RequestId {
@@ -68,6 +76,37 @@ impl RequestId for Request {
             Request::Rename(rename) => rename.get_request_id(),
             Request::Readlink(readlink) => readlink.get_request_id(),
             Request::Symlink(symlink) => symlink.get_request_id(),
+            Request::Extended(extended) => extended.get_request_id(),
+            Request::Unsupported(unsupported) => unsupported.get_request_id(),
+        }
+    }
+}
+
+impl Request {
+    /// Retrieves the name of the request type, such as `open` or `readdir`.
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            Request::Init(_) => "init",
+            Request::Open(_) => "open",
+            Request::Close(_) => "close",
+            Request::Read(_) => "read",
+            Request::Write(_) => "write",
+            Request::Lstat(_) => "lstat",
+            Request::Fstat(_) => "fstat",
+            Request::Setstat(_) => "setstat",
+            Request::Fsetstat(_) => "fsetstat",
+            Request::Opendir(_) => "opendir",
+            Request::Readdir(_) => "readdir",
+            Request::Remove(_) => "remove",
+            Request::Mkdir(_) => "mkdir",
+            Request::Rmdir(_) => "rmdir",
+            Request::Realpath(_) => "realpath",
+            Request::Stat(_) => "stat",
+            Request::Rename(_) => "rename",
+            Request::Readlink(_) => "readlink",
+            Request::Symlink(_) => "symlink",
+            Request::Extended(_) => "extended",
+            Request::Unsupported(_) => "unsupported",
         }
     }
 }
@@ -82,7 +121,7 @@ impl TryFrom<&mut Bytes> for Request {
         let data_payload = &mut request_bytes.try_get_bytes(data_length - DATA_TYPE_LENGTH)?;
 
         let message = match data_type {
-            1 => Request::Init(init::Init::try_from(data_payload)?),
+            INIT_DATA_TYPE => Request::Init(init::Init::try_from(data_payload)?),
             3 => Request::Open(open::Open::try_from(data_payload)?),
             4 => Request::Close(handle::Handle::try_from(data_payload)?),
             5 => Request::Read(read::Read::try_from(data_payload)?),
@@ -96,18 +135,39 @@ impl TryFrom<&mut Bytes> for Request {
             13 => Request::Remove(path::Path::try_from(data_payload)?),
             14 => Request::Mkdir(path_attributes::PathAttributes::try_from(data_payload)?),
             15 => Request::Rmdir(path::Path::try_from(data_payload)?),
-            16 => Request::Realpath(path::Path::try_from(data_payload)?),
+            16 => Request::Realpath(realpath::Realpath::try_from(data_payload)?),
             17 => Request::Stat(path::Path::try_from(data_payload)?),
             18 => Request::Rename(rename::Rename::try_from(data_payload)?),
             19 => Request::Readlink(path::Path::try_from(data_payload)?),
             20 => Request::Symlink(symlink::Symlink::try_from(data_payload)?),
-            _ => return Err(Error::BadMessage),
+            // SSH_FXP_LINK, SSH_FXP_BLOCK, and SSH_FXP_UNBLOCK were added in version 6.
+            21..=23 => {
+                Request::Unsupported(unsupported::Unsupported::parse(data_type, data_payload)?)
+            }
+            200 => Request::Extended(extended::Extended::try_from(data_payload)?),
+            _ => {
+                warn!("Received unknown request type {}", data_type);
+                return Err(Error::BadMessage);
+            }
         };
 
         Ok(message)
     }
 }
 
+/// Reads the id of a request without parsing the rest of it, so that a request with an invalid
+/// payload can still be answered with its id. `None` is returned for init requests, which do not
+/// have an id, and for frames too short to contain one.
+pub fn peek_request_id(request_bytes: &Bytes) -> Option<u32> {
+    let data_type = *request_bytes.get(4)?;
+    let id_bytes = request_bytes.get(5..9)?;
+
+    match data_type {
+        INIT_DATA_TYPE => None,
+        _ => Some(u32::from_be_bytes(id_bytes.try_into().ok()?)),
+    }
+}
+
 impl TryFrom<&[u8]> for Request {
     type Error = Error;
 
@@ -148,7 +208,7 @@ mod tests {
     #[test]
     fn test_parse_init_message() {
         let mut init_payload = BytesMut::new();
-        init_payload.put_u8(3); // Protocol Version 3
+        init_payload.put_u32(3); // Protocol Version 3
 
         assert_eq!(
             Request::try_from(&mut build_message(1, init_payload)),
@@ -459,9 +519,31 @@ mod tests {
 
         assert_eq!(
             Request::try_from(&mut build_message(16, realpath_payload)),
-            Ok(Request::Realpath(path::Path {
+            Ok(Request::Realpath(realpath::Realpath {
+                id: 1,
+                path: String::from("path"),
+                control_byte: None,
+                compose_paths: vec![],
+            }))
+        );
+    }
+
+    #[test]
+    fn test_parse_realpath_message_with_control_byte() {
+        let mut realpath_payload = BytesMut::new();
+
+        realpath_payload.put_u32(1); // Id
+        realpath_payload.try_put_str("path").unwrap(); // Path
+        realpath_payload.put_u8(3); // Control Byte
+        realpath_payload.try_put_str("file").unwrap(); // Compose Path
+
+        assert_eq!(
+            Request::try_from(&mut build_message(16, realpath_payload)),
+            Ok(Request::Realpath(realpath::Realpath {
                 id: 1,
                 path: String::from("path"),
+                control_byte: Some(realpath::ControlByte::StatAlways),
+                compose_paths: vec![String::from("file")],
             }))
         );
     }
@@ -559,6 +641,30 @@ mod tests {
         assert_invalid_message(20);
     }
 
+    #[test]
+    fn test_parse_extended_message() {
+        let mut extended_payload = BytesMut::new();
+
+        extended_payload.put_u32(1);
+        extended_payload.try_put_str("fsync@openssh.com").unwrap();
+        extended_payload.try_put_str("handle").unwrap();
+
+        assert_eq!(
+            Request::try_from(&mut build_message(200, extended_payload)),
+            Ok(Request::Extended(extended::Extended {
+                id: 1,
+                extended_request: extended::ExtendedRequest::Fsync {
+                    handle: String::from("handle"),
+                },
+            }))
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid_extended_message() {
+        assert_invalid_message(200);
+    }
+
     #[test]
     fn test_init_get_request_id() {
         let init_request = Request::Init(super::init::Init { version: 3 });
@@ -707,9 +813,11 @@ mod tests {
 
     #[test]
     fn test_realpath_get_request_id() {
-        let realpath_request = Request::Realpath(super::path::Path {
+        let realpath_request = Request::Realpath(super::realpath::Realpath {
             id: 1000,
             path: String::from("path"),
+            control_byte: None,
+            compose_paths: vec![],
         });
 
         assert_eq!(1000, realpath_request.get_request_id());
@@ -757,6 +865,100 @@ mod tests {
         assert_eq!(1000, symlink_request.get_request_id());
     }
 
+    #[test]
+    fn test_extended_get_request_id() {
+        let extended_request = Request::Extended(super::extended::Extended {
+            id: 1000,
+            extended_request: super::extended::ExtendedRequest::Unsupported {
+                name: String::from("unknown@example.com"),
+            },
+        });
+
+        assert_eq!(1000, extended_request.get_request_id());
+    }
+
+    #[test]
+    fn test_get_name() {
+        assert_eq!("init", Request::Init(init::Init { version: 3 }).get_name());
+        assert_eq!(
+            "readdir",
+            Request::Readdir(handle::Handle {
+                id: 1000,
+                handle: String::from("handle"),
+            })
+            .get_name()
+        );
+        assert_eq!(
+            "symlink",
+            Request::Symlink(symlink::Symlink {
+                id: 1000,
+                link_path: String::from("link"),
+                target_path: String::from("target"),
+            })
+            .get_name()
+        );
+    }
+
+    #[test]
+    fn test_parse_unsupported_messages() {
+        for message_type in 21..=23 {
+            let mut unsupported_payload = BytesMut::new();
+            unsupported_payload.put_u32(0x01); // Id
+            unsupported_payload.try_put_str("handle").unwrap();
+
+            assert_eq!(
+                Request::try_from(&mut build_message(message_type, unsupported_payload)),
+                Ok(Request::Unsupported(unsupported::Unsupported {
+                    id: 0x01,
+                    data_type: message_type,
+                }))
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_unknown_message() {
+        let mut unknown_payload = BytesMut::new();
+        unknown_payload.put_u32(0x01); // Id
+
+        assert_eq!(
+            Request::try_from(&mut build_message(100, unknown_payload)),
+            Err(Error::BadMessage)
+        );
+    }
+
+    #[test]
+    fn test_peek_request_id() {
+        let mut open_payload = BytesMut::new();
+        open_payload.put_u32(0x2a); // Id
+        open_payload.put_u8(0xff); // Invalid filename
+
+        let message = build_message(3, open_payload);
+
+        assert_eq!(Some(0x2a), peek_request_id(&message));
+        assert_eq!(
+            Err(Error::BadMessage),
+            Request::try_from(&mut message.clone())
+        );
+    }
+
+    #[test]
+    fn test_peek_request_id_with_init_message() {
+        let mut init_payload = BytesMut::new();
+        init_payload.put_u32(3); // Protocol Version 3
+
+        assert_eq!(None, peek_request_id(&build_message(1, init_payload)));
+    }
+
+    #[test]
+    fn test_peek_request_id_with_short_message() {
+        let mut short_payload = BytesMut::new();
+        short_payload.put_u16(0x01);
+
+        assert_eq!(None, peek_request_id(&build_message(3, short_payload)));
+        assert_eq!(None, peek_request_id(&Bytes::new()));
+    }
+
     fn assert_invalid_message(message_type: u8) {
         let payload = BytesMut::new();
 
@@ -14,31 +14,37 @@ pub struct Path {
 
 impl Path {
     pub fn to_normalized_path(&self) -> String {
-        let mut normalized_components: Vec<&str> = vec![];
-        let mut components_to_skip: usize = 0;
-
-        for path_component in self.path.rsplit('/') {
-            match path_component {
-                "" => {}
-                "." => {}
-                ".." => components_to_skip += 1,
-                _ => {
-                    if components_to_skip > 0 {
-                        components_to_skip -= 1;
-                    } else {
-                        normalized_components.push(path_component);
-                    }
+        normalize_path(&self.path)
+    }
+}
+
+/// Converts a path to an absolute path without `.` or `..` components, so the path can be
+/// compared against other paths.
+pub fn normalize_path(path: &str) -> String {
+    let mut normalized_components: Vec<&str> = vec![];
+    let mut components_to_skip: usize = 0;
+
+    for path_component in path.rsplit('/') {
+        match path_component {
+            "" => {}
+            "." => {}
+            ".." => components_to_skip += 1,
+            _ => {
+                if components_to_skip > 0 {
+                    components_to_skip -= 1;
+                } else {
+                    normalized_components.push(path_component);
                 }
             }
         }
+    }
 
-        if !normalized_components.is_empty() {
-            normalized_components.push("");
-            normalized_components.reverse();
-            normalized_components.join("/")
-        } else {
-            "/".to_owned()
-        }
+    if !normalized_components.is_empty() {
+        normalized_components.push("");
+        normalized_components.reverse();
+        normalized_components.join("/")
+    } else {
+        "/".to_owned()
     }
 }
 
@@ -159,6 +165,15 @@ mod test {
         assert_eq!("/sample/path", path.to_normalized_path());
     }
 
+    #[test]
+    fn test_normalize_path_with_escaping_double_dots() {
+        assert_eq!(
+            "/home/other/secret",
+            normalize_path("/home/test/../other/secret")
+        );
+        assert_eq!("/etc", normalize_path("/home/test/../../../etc"));
+    }
+
     #[test]
     fn test_get_request_id() {
         let path = Path {
@@ -0,0 +1,225 @@
+use crate::error::Error;
+use crate::try_buf::TryBuf;
+
+use bytes::{Buf, Bytes};
+use std::convert::TryFrom;
+
+use super::RequestId;
+
+const SSH_FXP_REALPATH_NO_CHECK: u8 = 0x01;
+const SSH_FXP_REALPATH_STAT_IF: u8 = 0x02;
+const SSH_FXP_REALPATH_STAT_ALWAYS: u8 = 0x03;
+
+/// Whether the server checks that a path exists when it is canonicalized, which version 6 clients
+/// may send after the path.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ControlByte {
+    /// The path is canonicalized without checking that it exists.
+    NoCheck,
+    /// The path's attributes are returned if it exists.
+    StatIf,
+    /// The path's attributes are returned, and the request fails if it does not exist.
+    StatAlways,
+}
+
+impl TryFrom<u8> for ControlByte {
+    type Error = Error;
+
+    fn try_from(control_byte: u8) -> Result<Self, Self::Error> {
+        match control_byte {
+            SSH_FXP_REALPATH_NO_CHECK => Ok(ControlByte::NoCheck),
+            SSH_FXP_REALPATH_STAT_IF => Ok(ControlByte::StatIf),
+            SSH_FXP_REALPATH_STAT_ALWAYS => Ok(ControlByte::StatAlways),
+            _ => Err(Error::BadMessage),
+        }
+    }
+}
+
+/// A request to canonicalize a path. Version 6 clients may follow the path with a control byte
+/// and paths that are composed onto it, which version 3 clients do not send.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Realpath {
+    pub id: u32,
+    pub path: String,
+    pub control_byte: Option<ControlByte>,
+    pub compose_paths: Vec<String>,
+}
+
+impl Realpath {
+    /// Composes the compose paths onto the path, in order. An absolute compose path replaces the
+    /// path composed so far, and a relative one is appended to it.
+    pub fn to_composed_path(&self) -> String {
+        self.compose_paths
+            .iter()
+            .fold(self.path.clone(), |path, compose_path| {
+                match compose_path.starts_with('/') {
+                    true => compose_path.clone(),
+                    false => format!("{}/{}", path, compose_path),
+                }
+            })
+    }
+}
+
+impl RequestId for Realpath {
+    fn get_request_id(&self) -> u32 {
+        self.id
+    }
+}
+
+impl TryFrom<&mut Bytes> for Realpath {
+    type Error = Error;
+
+    #[tracing::instrument]
+    fn try_from(realpath_bytes: &mut Bytes) -> Result<Self, Self::Error> {
+        let id = realpath_bytes.try_get_u32()?;
+        let path = realpath_bytes.try_get_string()?;
+
+        let control_byte = match realpath_bytes.has_remaining() {
+            true => Some(ControlByte::try_from(realpath_bytes.try_get_u8()?)?),
+            false => None,
+        };
+
+        let mut compose_paths = vec![];
+
+        while realpath_bytes.has_remaining() {
+            compose_paths.push(realpath_bytes.try_get_string()?);
+        }
+
+        Ok(Realpath {
+            id,
+            path,
+            control_byte,
+            compose_paths,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    use crate::try_buf::TryBufMut;
+
+    use bytes::{BufMut, BytesMut};
+
+    #[test]
+    fn test_parse_realpath_without_control_byte() {
+        let mut realpath_bytes = BytesMut::new();
+
+        realpath_bytes.put_u32(0x01); // id
+        realpath_bytes.try_put_str("/path").unwrap(); // path
+
+        assert_eq!(
+            Realpath::try_from(&mut realpath_bytes.freeze()),
+            Ok(Realpath {
+                id: 0x01,
+                path: String::from("/path"),
+                control_byte: None,
+                compose_paths: vec![],
+            })
+        )
+    }
+
+    #[test]
+    fn test_parse_realpath_with_control_byte_and_compose_paths() {
+        let mut realpath_bytes = BytesMut::new();
+
+        realpath_bytes.put_u32(0x01); // id
+        realpath_bytes.try_put_str("/path").unwrap(); // path
+        realpath_bytes.put_u8(SSH_FXP_REALPATH_STAT_IF); // control byte
+        realpath_bytes.try_put_str("dir").unwrap(); // compose path
+        realpath_bytes.try_put_str("file").unwrap(); // compose path
+
+        assert_eq!(
+            Realpath::try_from(&mut realpath_bytes.freeze()),
+            Ok(Realpath {
+                id: 0x01,
+                path: String::from("/path"),
+                control_byte: Some(ControlByte::StatIf),
+                compose_paths: vec![String::from("dir"), String::from("file")],
+            })
+        )
+    }
+
+    #[test]
+    fn test_parse_realpath_with_control_byte() {
+        for (control_byte, expected) in [
+            (SSH_FXP_REALPATH_NO_CHECK, ControlByte::NoCheck),
+            (SSH_FXP_REALPATH_STAT_IF, ControlByte::StatIf),
+            (SSH_FXP_REALPATH_STAT_ALWAYS, ControlByte::StatAlways),
+        ] {
+            let mut realpath_bytes = BytesMut::new();
+
+            realpath_bytes.put_u32(0x01); // id
+            realpath_bytes.try_put_str("/path").unwrap(); // path
+            realpath_bytes.put_u8(control_byte); // control byte
+
+            assert_eq!(
+                Realpath::try_from(&mut realpath_bytes.freeze())
+                    .map(|realpath| realpath.control_byte),
+                Ok(Some(expected))
+            )
+        }
+    }
+
+    #[test]
+    fn test_parse_realpath_with_invalid_control_byte() {
+        let mut realpath_bytes = BytesMut::new();
+
+        realpath_bytes.put_u32(0x01); // id
+        realpath_bytes.try_put_str("/path").unwrap(); // path
+        realpath_bytes.put_u8(0x04); // bad control byte
+
+        assert_eq!(
+            Realpath::try_from(&mut realpath_bytes.freeze()),
+            Err(Error::BadMessage)
+        )
+    }
+
+    #[test]
+    fn test_parse_realpath_with_invalid_compose_path() {
+        let mut realpath_bytes = BytesMut::new();
+
+        realpath_bytes.put_u32(0x01); // id
+        realpath_bytes.try_put_str("/path").unwrap(); // path
+        realpath_bytes.put_u8(SSH_FXP_REALPATH_NO_CHECK); // control byte
+        realpath_bytes.put_u32(0x10); // bad compose path length
+
+        assert_eq!(
+            Realpath::try_from(&mut realpath_bytes.freeze()),
+            Err(Error::BadMessage)
+        )
+    }
+
+    #[test]
+    fn test_to_composed_path() {
+        let realpath = Realpath {
+            id: 0x01,
+            path: String::from("/home/test"),
+            control_byte: Some(ControlByte::NoCheck),
+            compose_paths: vec![String::from("dir"), String::from("file")],
+        };
+
+        assert_eq!("/home/test/dir/file", realpath.to_composed_path());
+
+        let realpath = Realpath {
+            compose_paths: vec![String::from("dir"), String::from("/other")],
+            ..realpath
+        };
+
+        assert_eq!("/other", realpath.to_composed_path());
+    }
+
+    #[test]
+    fn test_get_request_id() {
+        let realpath = Realpath {
+            id: 1000,
+            path: String::from("/path"),
+            control_byte: None,
+            compose_paths: vec![],
+        };
+
+        assert_eq!(1000, realpath.get_request_id());
+    }
+}
@@ -0,0 +1,64 @@
+use crate::error::Error;
+use crate::try_buf::TryBuf;
+
+use bytes::Bytes;
+
+use super::RequestId;
+
+/// A request type from a newer version of the protocol, such as `SSH_FXP_BLOCK` from version 6.
+/// Only the id is parsed, so that the request can be answered as unsupported.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Unsupported {
+    pub id: u32,
+    pub data_type: u8,
+}
+
+impl RequestId for Unsupported {
+    fn get_request_id(&self) -> u32 {
+        self.id
+    }
+}
+
+impl Unsupported {
+    #[tracing::instrument]
+    pub fn parse(data_type: u8, unsupported_bytes: &mut Bytes) -> Result<Self, Error> {
+        let id = unsupported_bytes.try_get_u32()?;
+
+        Ok(Unsupported { id, data_type })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use bytes::{BufMut, BytesMut};
+
+    #[test]
+    fn test_parse_unsupported() {
+        let mut unsupported_bytes = BytesMut::new();
+
+        unsupported_bytes.put_u32(0x01); // id
+        unsupported_bytes.put_u64(0x02); // remaining fields are ignored
+
+        assert_eq!(
+            Unsupported::parse(22, &mut unsupported_bytes.freeze()),
+            Ok(Unsupported {
+                id: 0x01,
+                data_type: 22,
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_unsupported_with_invalid_id() {
+        let mut unsupported_bytes = BytesMut::new();
+
+        unsupported_bytes.put_u8(0x01);
+
+        assert_eq!(
+            Unsupported::parse(22, &mut unsupported_bytes.freeze()),
+            Err(Error::BadMessage)
+        );
+    }
+}
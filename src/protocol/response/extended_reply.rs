@@ -0,0 +1,138 @@
+use bytes::{BufMut, Bytes, BytesMut};
+use std::convert::From;
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ExtendedReply {
+    pub id: u32,
+    pub extended_reply: ExtendedReplyData,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ExtendedReplyData {
+    Limits(Limits),
+    Statvfs(Statvfs),
+}
+
+/// The server's limits in the layout of the `limits@openssh.com` reply. Clients size their reads
+/// and writes, and how many files they open at once, to fit these limits.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Limits {
+    pub max_packet_length: u64,
+    pub max_read_length: u64,
+    pub max_write_length: u64,
+    pub max_open_handles: u64,
+}
+
+/// Filesystem statistics in the layout of the `statvfs@openssh.com` reply.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Statvfs {
+    pub block_size: u64,
+    pub fragment_size: u64,
+    pub blocks: u64,
+    pub free_blocks: u64,
+    pub available_blocks: u64,
+    pub files: u64,
+    pub free_files: u64,
+    pub available_files: u64,
+    pub filesystem_id: u64,
+    pub flags: u64,
+    pub max_name_length: u64,
+}
+
+impl From<&ExtendedReply> for Bytes {
+    #[tracing::instrument]
+    fn from(extended_reply: &ExtendedReply) -> Self {
+        let mut extended_reply_bytes = BytesMut::new();
+
+        extended_reply_bytes.put_u32(extended_reply.id);
+
+        match &extended_reply.extended_reply {
+            ExtendedReplyData::Limits(limits) => {
+                extended_reply_bytes.put_u64(limits.max_packet_length);
+                extended_reply_bytes.put_u64(limits.max_read_length);
+                extended_reply_bytes.put_u64(limits.max_write_length);
+                extended_reply_bytes.put_u64(limits.max_open_handles);
+            }
+            ExtendedReplyData::Statvfs(statvfs) => {
+                extended_reply_bytes.put_u64(statvfs.block_size);
+                extended_reply_bytes.put_u64(statvfs.fragment_size);
+                extended_reply_bytes.put_u64(statvfs.blocks);
+                extended_reply_bytes.put_u64(statvfs.free_blocks);
+                extended_reply_bytes.put_u64(statvfs.available_blocks);
+                extended_reply_bytes.put_u64(statvfs.files);
+                extended_reply_bytes.put_u64(statvfs.free_files);
+                extended_reply_bytes.put_u64(statvfs.available_files);
+                extended_reply_bytes.put_u64(statvfs.filesystem_id);
+                extended_reply_bytes.put_u64(statvfs.flags);
+                extended_reply_bytes.put_u64(statvfs.max_name_length);
+            }
+        }
+
+        extended_reply_bytes.freeze()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use bytes::Buf;
+
+    #[test]
+    fn test_from_creates_limits_bytes() {
+        let extended_reply = ExtendedReply {
+            id: 0x01,
+            extended_reply: ExtendedReplyData::Limits(Limits {
+                max_packet_length: 0x02,
+                max_read_length: 0x03,
+                max_write_length: 0x04,
+                max_open_handles: 0x05,
+            }),
+        };
+
+        let extended_reply_bytes = &mut Bytes::from(&extended_reply);
+
+        assert_eq!(0x01, extended_reply_bytes.get_u32()); // id
+        assert_eq!(0x02, extended_reply_bytes.get_u64()); // max-packet-length
+        assert_eq!(0x03, extended_reply_bytes.get_u64()); // max-read-length
+        assert_eq!(0x04, extended_reply_bytes.get_u64()); // max-write-length
+        assert_eq!(0x05, extended_reply_bytes.get_u64()); // max-open-handles
+        assert!(!extended_reply_bytes.has_remaining());
+    }
+
+    #[test]
+    fn test_from_creates_statvfs_bytes() {
+        let extended_reply = ExtendedReply {
+            id: 0x01,
+            extended_reply: ExtendedReplyData::Statvfs(Statvfs {
+                block_size: 0x02,
+                fragment_size: 0x03,
+                blocks: 0x04,
+                free_blocks: 0x05,
+                available_blocks: 0x06,
+                files: 0x07,
+                free_files: 0x08,
+                available_files: 0x09,
+                filesystem_id: 0x0A,
+                flags: 0x0B,
+                max_name_length: 0x0C,
+            }),
+        };
+
+        let extended_reply_bytes = &mut Bytes::from(&extended_reply);
+
+        assert_eq!(0x01, extended_reply_bytes.get_u32()); // id
+        assert_eq!(0x02, extended_reply_bytes.get_u64()); // f_bsize
+        assert_eq!(0x03, extended_reply_bytes.get_u64()); // f_frsize
+        assert_eq!(0x04, extended_reply_bytes.get_u64()); // f_blocks
+        assert_eq!(0x05, extended_reply_bytes.get_u64()); // f_bfree
+        assert_eq!(0x06, extended_reply_bytes.get_u64()); // f_bavail
+        assert_eq!(0x07, extended_reply_bytes.get_u64()); // f_files
+        assert_eq!(0x08, extended_reply_bytes.get_u64()); // f_ffree
+        assert_eq!(0x09, extended_reply_bytes.get_u64()); // f_favail
+        assert_eq!(0x0A, extended_reply_bytes.get_u64()); // f_fsid
+        assert_eq!(0x0B, extended_reply_bytes.get_u64()); // f_flag
+        assert_eq!(0x0C, extended_reply_bytes.get_u64()); // f_namemax
+        assert!(!extended_reply_bytes.has_remaining());
+    }
+}
@@ -1,5 +1,6 @@
 pub mod attrs;
 pub mod data;
+pub mod extended_reply;
 pub mod handle;
 pub mod name;
 pub mod status;
@@ -21,11 +22,22 @@ pub enum Response {
     Data(data::Data),
     Name(name::Name),
     Attrs(attrs::Attrs),
+    ExtendedReply(extended_reply::ExtendedReply),
 }
 
 impl Response {
     pub fn build_error_response(id: u32, error: Error) -> Self {
         match error {
+            Error::AlreadyExists
+            | Error::FileTooLarge
+            | Error::InvalidHandle
+            | Error::IsADirectory
+            | Error::NotADirectory
+            | Error::PathTooLong
+            | Error::QuotaExceeded
+            | Error::SymlinkLoop => {
+                Response::build_status(id, status::StatusCode::Failure, &error.to_string())
+            }
             Error::BadMessage => Response::build_status(
                 id,
                 status::StatusCode::BadMessage,
@@ -73,6 +85,7 @@ impl From<&Response> for Bytes {
             Response::Data(_) => 103,
             Response::Name(_) => 104,
             Response::Attrs(_) => 105,
+            Response::ExtendedReply(_) => 201,
         };
 
         let data_payload: Bytes = match response {
@@ -82,6 +95,7 @@ impl From<&Response> for Bytes {
             Response::Data(data) => data.into(),
             Response::Name(name) => name.into(),
             Response::Attrs(attrs) => attrs.into(),
+            Response::ExtendedReply(extended_reply) => extended_reply.into(),
         };
 
         let data_length = DATA_TYPE_LENGTH + u32::try_from(data_payload.remaining()).unwrap();
@@ -106,7 +120,10 @@ mod test {
 
     #[test]
     fn test_from_creates_version_bytes() {
-        let version = Response::Version(version::Version { version: 0x01 });
+        let version = Response::Version(version::Version {
+            version: 0x01,
+            extensions: vec![],
+        });
 
         let version_bytes = &mut Bytes::from(&version);
 
@@ -138,6 +155,33 @@ mod test {
         ) // OK bytes
     }
 
+    #[test]
+    fn test_from_creates_extended_reply_bytes() {
+        let extended_reply = Response::ExtendedReply(extended_reply::ExtendedReply {
+            id: 0x01,
+            extended_reply: extended_reply::ExtendedReplyData::Statvfs(extended_reply::Statvfs {
+                block_size: 0x02,
+                fragment_size: 0x02,
+                blocks: 0x03,
+                free_blocks: 0x03,
+                available_blocks: 0x03,
+                files: 0x00,
+                free_files: 0x00,
+                available_files: 0x00,
+                filesystem_id: 0x00,
+                flags: 0x00,
+                max_name_length: 0xFF,
+            }),
+        });
+
+        let extended_reply_bytes = &mut Bytes::from(&extended_reply);
+
+        assert_eq!(93, extended_reply_bytes.get_u32());
+        assert_eq!(201, extended_reply_bytes.get_u8());
+        assert_eq!(0x01, extended_reply_bytes.get_u32());
+        assert_eq!(88, extended_reply_bytes.remaining()); // 11 statvfs fields
+    }
+
     #[test]
     fn test_from_creates_handle_bytes() {
         let handle = Response::Handle(handle::Handle {
@@ -188,14 +232,14 @@ mod test {
 
         let name_bytes = &mut Bytes::from(&name);
 
-        assert_eq!(74, name_bytes.get_u32());
+        assert_eq!(92, name_bytes.get_u32());
         assert_eq!(104, name_bytes.get_u8());
         assert_eq!(0x01, name_bytes.get_u32());
         assert_eq!(0x01, name_bytes.get_u32());
         assert_eq!(0x04, name_bytes.get_u32()); // file length
         assert_eq!(&[0x66, 0x69, 0x6C, 0x65], &name_bytes.copy_to_bytes(4)[..]); // file
 
-        let long = "---------- 0 2 3 0 Jan 01 1970 00:00 file";
+        let long = "----------   0 2        3               0 Jan  1  1970 file";
         assert_eq!(long.len() as u32, name_bytes.get_u32()); // long length
         assert_eq!(long.as_bytes(), &name_bytes.copy_to_bytes(long.len())[..]); // long
         assert_eq!(file_attributes_bytes, &name_bytes[..]);
@@ -277,6 +321,37 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_map_error_response_maps_failures_with_messages() {
+        let errors = [
+            (Error::AlreadyExists, "The file already exists."),
+            (
+                Error::FileTooLarge,
+                "The file exceeds the maximum upload size.",
+            ),
+            (Error::InvalidHandle, "The handle does not exist."),
+            (Error::IsADirectory, "The file is a directory."),
+            (Error::NotADirectory, "The path is not a directory."),
+            (
+                Error::PathTooLong,
+                "The path exceeds the maximum length or depth.",
+            ),
+            (Error::QuotaExceeded, "The storage quota has been exceeded."),
+            (Error::SymlinkLoop, "Too many levels of symbolic links."),
+        ];
+
+        for (error, error_message) in errors {
+            assert_eq!(
+                Response::Status(status::Status {
+                    id: 1000,
+                    status_code: status::StatusCode::Failure,
+                    error_message: String::from(error_message),
+                }),
+                Response::build_error_response(1000, error)
+            );
+        }
+    }
+
     #[test]
     fn test_map_error_response_maps_other_error() {
         let expected_status = Response::Status(status::Status {
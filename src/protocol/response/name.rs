@@ -2,11 +2,15 @@ use crate::protocol::file_attributes::FileAttributes;
 
 use bytes::{BufMut, Bytes, BytesMut};
 
-use chrono::DateTime;
+use chrono::{DateTime, Utc};
 
 use std::convert::From;
 use std::convert::TryInto;
 
+/// Files modified more recently than this are listed with the time of day instead of the year,
+/// matching OpenSSH.
+const SIX_MONTHS_SECS: i64 = 365 * 24 * 60 * 60 / 2;
+
 #[derive(Debug, PartialEq, Eq)]
 pub struct Name {
     pub id: u32,
@@ -36,18 +40,27 @@ pub struct File {
 
 impl File {
     pub fn get_long_name(&self) -> String {
+        self.format_long_name(Utc::now().timestamp())
+    }
+
+    /// Formats the long name in the same columns as OpenSSH, so that clients that print it
+    /// directly show an aligned `ls -l` listing.
+    fn format_long_name(&self, now: i64) -> String {
         let permissions = self.decode_permissions();
         let size = self.file_attributes.size.unwrap_or(0);
         let uid = self.file_attributes.uid.unwrap_or(0);
         let gid = self.file_attributes.gid.unwrap_or(0);
 
-        let datetime = DateTime::from_timestamp(self.file_attributes.mtime.unwrap_or(0) as i64, 0)
-            .unwrap_or_default();
-        let datetime = datetime.format("%b %d %Y %H:%M");
+        let mtime = self.file_attributes.mtime.unwrap_or(0) as i64;
+        let datetime = DateTime::from_timestamp(mtime, 0).unwrap_or_default();
+        let datetime = match now - SIX_MONTHS_SECS < mtime && mtime <= now {
+            true => datetime.format("%b %e %H:%M"),
+            false => datetime.format("%b %e  %Y"),
+        };
 
         format!(
-            "{} 0 {} {} {} {} {}",
-            permissions, uid, gid, size, datetime, self.file_name
+            "{} {:>3} {:<8} {:<8} {:>8} {} {}",
+            permissions, 0, uid, gid, size, datetime, self.file_name
         )
     }
 
@@ -58,13 +71,15 @@ impl File {
         let group = File::decode_permission((permissions >> 3) & 0x7);
         let other = File::decode_permission(permissions & 0x7);
 
-        let directory = if (permissions >> 14) & 0x1 == 0x01 {
+        let file_type = if self.file_attributes.is_symlink() {
+            "l"
+        } else if self.file_attributes.is_dir() {
             "d"
         } else {
             "-"
         };
 
-        format!("{}{}{}{}", directory, owner, group, other)
+        format!("{}{}{}{}", file_type, owner, group, other)
     }
 
     fn decode_permission(permission: u32) -> String {
@@ -115,7 +130,7 @@ mod test {
         };
 
         assert_eq!(
-            "---------- 0 0 0 0 Jan 01 1970 00:00 file",
+            "----------   0 0        0               0 Jan  1  1970 file",
             file.get_long_name()
         );
     }
@@ -131,7 +146,7 @@ mod test {
         };
 
         assert_eq!(
-            "-rwx------ 0 0 0 0 Jan 01 1970 00:00 file",
+            "-rwx------   0 0        0               0 Jan  1  1970 file",
             file.get_long_name()
         );
     }
@@ -147,7 +162,7 @@ mod test {
         };
 
         assert_eq!(
-            "----rwx--- 0 0 0 0 Jan 01 1970 00:00 file",
+            "----rwx---   0 0        0               0 Jan  1  1970 file",
             file.get_long_name()
         );
     }
@@ -163,7 +178,7 @@ mod test {
         };
 
         assert_eq!(
-            "-------rwx 0 0 0 0 Jan 01 1970 00:00 file",
+            "-------rwx   0 0        0               0 Jan  1  1970 file",
             file.get_long_name()
         );
     }
@@ -179,7 +194,7 @@ mod test {
         };
 
         assert_eq!(
-            "-r-x------ 0 0 0 0 Jan 01 1970 00:00 file",
+            "-r-x------   0 0        0               0 Jan  1  1970 file",
             file.get_long_name()
         );
     }
@@ -195,7 +210,23 @@ mod test {
         };
 
         assert_eq!(
-            "drwxrwxrwx 0 0 0 0 Jan 01 1970 00:00 file",
+            "drwxrwxrwx   0 0        0               0 Jan  1  1970 file",
+            file.get_long_name()
+        );
+    }
+
+    #[test]
+    fn test_get_long_name_creates_long_name_with_777_symlink() {
+        let file = File {
+            file_name: String::from("link"),
+            file_attributes: FileAttributes {
+                permissions: Some(0o120777),
+                ..Default::default()
+            },
+        };
+
+        assert_eq!(
+            "lrwxrwxrwx   0 0        0               0 Jan  1  1970 link",
             file.get_long_name()
         );
     }
@@ -211,7 +242,7 @@ mod test {
         };
 
         assert_eq!(
-            "---------- 0 0 0 1000 Jan 01 1970 00:00 file",
+            "----------   0 0        0            1000 Jan  1  1970 file",
             file.get_long_name()
         );
     }
@@ -228,7 +259,7 @@ mod test {
         };
 
         assert_eq!(
-            "---------- 0 1000 2000 0 Jan 01 1970 00:00 file",
+            "----------   0 1000     2000            0 Jan  1  1970 file",
             file.get_long_name()
         );
     }
@@ -244,11 +275,76 @@ mod test {
         };
 
         assert_eq!(
-            "---------- 0 0 0 0 Sep 09 2001 01:46 file",
+            "----------   0 0        0               0 Sep  9  2001 file",
+            file.get_long_name()
+        );
+    }
+
+    #[test]
+    fn test_get_long_name_creates_long_name_with_large_filesize() {
+        let file = File {
+            file_name: String::from("file"),
+            file_attributes: FileAttributes {
+                size: Some(12345678901),
+                uid: Some(1234567890),
+                ..Default::default()
+            },
+        };
+
+        assert_eq!(
+            "----------   0 1234567890 0        12345678901 Jan  1  1970 file",
             file.get_long_name()
         );
     }
 
+    #[test]
+    fn test_format_long_name_with_recent_mtime() {
+        let file = File {
+            file_name: String::from("file"),
+            file_attributes: FileAttributes {
+                mtime: Some(1000000000),
+                ..Default::default()
+            },
+        };
+
+        assert_eq!(
+            "----------   0 0        0               0 Sep  9 01:46 file",
+            file.format_long_name(1000000000 + 24 * 60 * 60)
+        );
+    }
+
+    #[test]
+    fn test_format_long_name_with_old_mtime() {
+        let file = File {
+            file_name: String::from("file"),
+            file_attributes: FileAttributes {
+                mtime: Some(1000000000),
+                ..Default::default()
+            },
+        };
+
+        assert_eq!(
+            "----------   0 0        0               0 Sep  9  2001 file",
+            file.format_long_name(1000000000 + SIX_MONTHS_SECS)
+        );
+    }
+
+    #[test]
+    fn test_format_long_name_with_future_mtime() {
+        let file = File {
+            file_name: String::from("file"),
+            file_attributes: FileAttributes {
+                mtime: Some(1000000000),
+                ..Default::default()
+            },
+        };
+
+        assert_eq!(
+            "----------   0 0        0               0 Sep  9  2001 file",
+            file.format_long_name(1000000000 - 60)
+        );
+    }
+
     #[test]
     fn test_from_creates_file_bytes() {
         let file = File {
@@ -262,7 +358,7 @@ mod test {
 
         assert_eq!(0x04, file_bytes.get_u32());
         assert_eq!(&[0x66, 0x69, 0x6C, 0x65], &file_bytes.copy_to_bytes(4)[..]);
-        let long_name = "---------- 0 0 0 0 Jan 01 1970 00:00 file";
+        let long_name = "----------   0 0        0               0 Jan  1  1970 file";
         assert_eq!(long_name.len() as u32, file_bytes.get_u32());
         assert_eq!(
             long_name.as_bytes(),
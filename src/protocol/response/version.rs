@@ -1,19 +1,29 @@
 use bytes::{BufMut, Bytes, BytesMut};
 use std::convert::From;
+use std::convert::TryInto;
 
 #[derive(Debug, PartialEq, Eq)]
 pub struct Version {
     pub version: u32,
+    /// Extension name and data pairs that tell the client which extended requests are supported.
+    pub extensions: Vec<(String, String)>,
 }
 
 impl From<&Version> for Bytes {
     #[tracing::instrument]
-    fn from(status: &Version) -> Self {
-        let mut status_bytes = BytesMut::new();
+    fn from(version: &Version) -> Self {
+        let mut version_bytes = BytesMut::new();
 
-        status_bytes.put_u32(status.version);
+        version_bytes.put_u32(version.version);
 
-        status_bytes.freeze()
+        for (name, data) in &version.extensions {
+            version_bytes.put_u32(name.len().try_into().unwrap());
+            version_bytes.put_slice(name.as_bytes());
+            version_bytes.put_u32(data.len().try_into().unwrap());
+            version_bytes.put_slice(data.as_bytes());
+        }
+
+        version_bytes.freeze()
     }
 }
 
@@ -25,10 +35,37 @@ mod test {
 
     #[test]
     fn test_from_creates_version_bytes() {
-        let version = Version { version: 0x03 };
+        let version = Version {
+            version: 0x03,
+            extensions: vec![],
+        };
+
+        let version_bytes = &mut Bytes::from(&version);
+
+        assert_eq!(0x03, version_bytes.get_u32());
+        assert!(!version_bytes.has_remaining());
+    }
+
+    #[test]
+    fn test_from_creates_version_bytes_with_extensions() {
+        let version = Version {
+            version: 0x03,
+            extensions: vec![
+                (String::from("fsync@openssh.com"), String::from("1")),
+                (String::from("a"), String::from("")),
+            ],
+        };
 
         let version_bytes = &mut Bytes::from(&version);
 
         assert_eq!(0x03, version_bytes.get_u32());
+        assert_eq!(17, version_bytes.get_u32()); // Extension name length
+        assert_eq!(b"fsync@openssh.com", &version_bytes.copy_to_bytes(17)[..]); // Extension name
+        assert_eq!(1, version_bytes.get_u32()); // Extension data length
+        assert_eq!(b"1", &version_bytes.copy_to_bytes(1)[..]); // Extension data
+        assert_eq!(1, version_bytes.get_u32()); // Extension name length
+        assert_eq!(b"a", &version_bytes.copy_to_bytes(1)[..]); // Extension name
+        assert_eq!(0, version_bytes.get_u32()); // Extension data length
+        assert!(!version_bytes.has_remaining());
     }
 }
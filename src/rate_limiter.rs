@@ -0,0 +1,106 @@
+use std::time::{Duration, Instant};
+
+/// Limits how often requests are processed with a token bucket. A client may send a burst of up
+/// to one second's worth of requests, after which requests are delayed to the configured rate.
+pub struct RateLimiter {
+    requests_per_second: f64,
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(requests_per_second: u32) -> RateLimiter {
+        RateLimiter {
+            requests_per_second: requests_per_second as f64,
+            tokens: requests_per_second as f64,
+            updated_at: Instant::now(),
+        }
+    }
+
+    /// Waits until another request may be processed.
+    pub async fn acquire(&mut self) {
+        let delay = self.reserve(Instant::now());
+
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    /// Takes a token from the bucket, returning how long the caller must wait for it to become
+    /// available. Tokens may be taken before they are available, so that callers are delayed in
+    /// the order that they arrive.
+    fn reserve(&mut self, now: Instant) -> Duration {
+        if now > self.updated_at {
+            let elapsed = now.duration_since(self.updated_at).as_secs_f64();
+
+            self.tokens =
+                (self.tokens + elapsed * self.requests_per_second).min(self.requests_per_second);
+            self.updated_at = now;
+        }
+
+        self.tokens -= 1.0;
+
+        match self.tokens >= 0.0 {
+            true => Duration::ZERO,
+            false => Duration::from_secs_f64(-self.tokens / self.requests_per_second),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_reserve_allows_burst() {
+        let mut rate_limiter = RateLimiter::new(10);
+        let now = rate_limiter.updated_at;
+
+        for _ in 0..10 {
+            assert_eq!(Duration::ZERO, rate_limiter.reserve(now));
+        }
+    }
+
+    #[test]
+    fn test_reserve_delays_requests_over_limit() {
+        let mut rate_limiter = RateLimiter::new(10);
+        let now = rate_limiter.updated_at;
+
+        for _ in 0..10 {
+            rate_limiter.reserve(now);
+        }
+
+        assert_eq!(Duration::from_millis(100), rate_limiter.reserve(now));
+        assert_eq!(Duration::from_millis(200), rate_limiter.reserve(now));
+    }
+
+    #[test]
+    fn test_reserve_refills_over_time() {
+        let mut rate_limiter = RateLimiter::new(10);
+        let now = rate_limiter.updated_at;
+
+        for _ in 0..10 {
+            rate_limiter.reserve(now);
+        }
+
+        let later = now + Duration::from_millis(500);
+
+        for _ in 0..5 {
+            assert_eq!(Duration::ZERO, rate_limiter.reserve(later));
+        }
+
+        assert_eq!(Duration::from_millis(100), rate_limiter.reserve(later));
+    }
+
+    #[test]
+    fn test_reserve_does_not_refill_past_burst() {
+        let mut rate_limiter = RateLimiter::new(10);
+        let later = rate_limiter.updated_at + Duration::from_secs(60);
+
+        for _ in 0..10 {
+            assert_eq!(Duration::ZERO, rate_limiter.reserve(later));
+        }
+
+        assert_eq!(Duration::from_millis(100), rate_limiter.reserve(later));
+    }
+}
@@ -1,71 +1,439 @@
-use crate::storage::Storage;
+use crate::audit::{AuditLog, AuditRecord};
+use crate::authorizer::{Authorizer, HomeDirAuthorizer, Operation};
+use crate::client_quirks::ClientQuirks;
+use crate::config::DrayConfig;
+use crate::sftp_stream::MAX_REQUEST_LENGTH;
+use crate::storage::{self, Storage, DEFAULT_MAX_HANDLES};
 use crate::{
     error::Error,
+    glob, metrics,
     protocol::{
         file_attributes::FileAttributes,
-        request::{self, Request, RequestId},
+        request::{self, path::normalize_path, Request, RequestId},
         response::{self, Response},
     },
 };
-use tracing::error;
+use chrono::Utc;
 use tracing::Level;
+use tracing::{error, info};
 
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
 
+/// The highest SFTP protocol version implemented by Dray.
+pub const MAX_SFTP_VERSION: u32 = 3;
+
+/// The default maximum number of bytes returned for a single read request, which matches the
+/// limit used by OpenSSH.
+pub const DEFAULT_MAX_READ_LENGTH: u32 = 261120;
+
+/// The default template used to calculate a user's home directory.
+pub const DEFAULT_HOME_TEMPLATE: &str = "/home/{user}";
+
+const HOME_TEMPLATE_USER: &str = "{user}";
+
+/// The block size reported to clients in filesystem statistics.
+const STATVFS_BLOCK_SIZE: u64 = 4096;
+
+/// Object storage does not have a fixed capacity, so 1 PiB is reported as an effectively
+/// unlimited capacity when a quota is not configured. Clients multiply the block count by the
+/// block size, so the capacity must be far below `u64::MAX`.
+const UNLIMITED_CAPACITY: u64 = 1 << 50;
+
+/// Object storage does not limit the number of files, so a large file count is reported.
+const UNLIMITED_FILES: u64 = u32::MAX as u64;
+
+const MAX_NAME_LENGTH: u64 = 255;
+
+/// The space reserved in a write request for everything other than the data, so that writes of
+/// the advertised maximum length fit within a single request.
+const WRITE_REQUEST_OVERHEAD: usize = 1024;
+
+/// The default maximum number of directories and file names in a path.
+pub const DEFAULT_MAX_PATH_DEPTH: usize = 64;
+
+/// S3 limits keys to 1024 bytes. A path's key is the path without its leading slash, and storage
+/// appends up to 10 bytes to some keys, such as the `/_$folder$` marker of a directory, so longer
+/// paths are rejected before they reach storage.
+const MAX_PATH_LENGTH: usize = 1024 + 1 - 10;
+
+/// The maximum number of symbolic links followed when resolving a path, which stops links that
+/// refer to themselves from being followed forever.
+const MAX_SYMLINK_DEPTH: usize = 32;
+
+/// Settings that control the behavior of an SFTP session.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SftpSessionConfig {
+    pub max_read_length: u32,
+    pub max_handles: usize,
+    pub home_template: String,
+    pub read_only_prefixes: Vec<String>,
+    pub read_only_users: Vec<String>,
+    pub user_quota_bytes: Option<u64>,
+    pub max_upload_bytes: Option<u64>,
+    pub max_path_depth: usize,
+    pub storage_op_timeout: Option<Duration>,
+}
+
+impl Default for SftpSessionConfig {
+    fn default() -> Self {
+        SftpSessionConfig {
+            max_read_length: DEFAULT_MAX_READ_LENGTH,
+            max_handles: DEFAULT_MAX_HANDLES,
+            home_template: DEFAULT_HOME_TEMPLATE.to_string(),
+            read_only_prefixes: vec![],
+            read_only_users: vec![],
+            user_quota_bytes: None,
+            max_upload_bytes: None,
+            max_path_depth: DEFAULT_MAX_PATH_DEPTH,
+            storage_op_timeout: None,
+        }
+    }
+}
+
+impl From<&DrayConfig> for SftpSessionConfig {
+    fn from(dray_config: &DrayConfig) -> Self {
+        SftpSessionConfig {
+            max_read_length: dray_config.max_read_length,
+            max_handles: dray_config.max_handles,
+            home_template: dray_config.home_template.clone(),
+            read_only_prefixes: dray_config.read_only_prefixes.clone(),
+            read_only_users: dray_config.read_only_users.clone(),
+            user_quota_bytes: dray_config.user_quota_bytes,
+            max_upload_bytes: dray_config.max_upload_bytes,
+            max_path_depth: dray_config.max_path_depth,
+            storage_op_timeout: dray_config.storage_op_timeout.map(Duration::from_secs),
+        }
+    }
+}
+
+/// Handles the SFTP requests of a single user. Requests are parsed from the protocol types in
+/// [`crate::protocol`], so a session can be driven by any transport, or by calling
+/// [`SftpSession::handle_request`] directly, without the SSH server.
 pub struct SftpSession {
     object_storage: Arc<dyn Storage>,
     user: String,
     user_home: String,
+    authorizer: Arc<dyn Authorizer>,
+    client_quirks: ClientQuirks,
+    max_read_length: u32,
+    max_handles: usize,
+    user_quota_bytes: Option<u64>,
+    /// The number of bytes stored by the user, which is only calculated when a quota is
+    /// configured. Storage is listed on the first write, and the count is updated as data is
+    /// written. Requests that free space clear the count, so that storage is listed again. The
+    /// count is kept per session, so concurrent sessions of a user may together exceed the quota.
+    used_bytes: Mutex<Option<u64>>,
+    max_upload_bytes: Option<u64>,
+    /// The number of bytes written to each open handle, which is only tracked when a maximum
+    /// upload size is configured.
+    upload_bytes: Mutex<HashMap<String, u64>>,
+    max_path_depth: usize,
+    /// How long a request may wait on storage before it fails.
+    storage_op_timeout: Option<Duration>,
+    audit_log: Option<Arc<AuditLog>>,
+    /// The path of each handle opened for writing, which is only tracked when audit logging is
+    /// enabled, so that closing the handle can be audited with the path.
+    audit_handles: Mutex<HashMap<String, String>>,
+    /// The resolved path of each open file handle, so that requests on the handle are authorized
+    /// against the file it was opened for.
+    handle_paths: Mutex<HashMap<String, String>>,
+    /// The pattern for each directory handle opened by a list-glob request, so that reading the
+    /// handle only returns matching files.
+    glob_patterns: Mutex<HashMap<String, String>>,
+    /// Whether the client has sent its init request, which must be the first and only init
+    /// request in a session.
+    initialized: AtomicBool,
+    version: AtomicU32,
+    /// The number of bytes the client has written to files.
+    bytes_in: AtomicU64,
+    /// The number of bytes the client has read from files.
+    bytes_out: AtomicU64,
 }
 
 impl SftpSession {
-    pub fn new(object_storage: Arc<dyn Storage>, user: String) -> Self {
-        let user_home = object_storage.get_home(&user);
+    pub fn new(
+        object_storage: Arc<dyn Storage>,
+        user: String,
+        session_config: &SftpSessionConfig,
+    ) -> Result<Self, Error> {
+        let user_home = get_home(&session_config.home_template, &user)?;
 
-        SftpSession {
+        Ok(SftpSession {
             object_storage,
             user,
             user_home,
-        }
+            authorizer: Arc::new(HomeDirAuthorizer::from(session_config)),
+            client_quirks: ClientQuirks::default(),
+            max_read_length: session_config.max_read_length,
+            max_handles: session_config.max_handles,
+            user_quota_bytes: session_config.user_quota_bytes,
+            used_bytes: Mutex::new(None),
+            max_upload_bytes: session_config.max_upload_bytes,
+            upload_bytes: Mutex::new(HashMap::new()),
+            max_path_depth: session_config.max_path_depth,
+            storage_op_timeout: session_config.storage_op_timeout,
+            audit_log: None,
+            audit_handles: Mutex::new(HashMap::new()),
+            handle_paths: Mutex::new(HashMap::new()),
+            glob_patterns: Mutex::new(HashMap::new()),
+            initialized: AtomicBool::new(false),
+            version: AtomicU32::new(MAX_SFTP_VERSION),
+            bytes_in: AtomicU64::new(0),
+            bytes_out: AtomicU64::new(0),
+        })
     }
 
-    #[tracing::instrument(skip(self), level = Level::DEBUG)]
+    pub fn with_audit_log(mut self, audit_log: Option<Arc<AuditLog>>) -> Self {
+        self.audit_log = audit_log;
+        self
+    }
+
+    /// Replaces the default authorizer, which limits users to their home and the read-only
+    /// prefixes, with custom access rules.
+    pub fn with_authorizer(mut self, authorizer: Arc<dyn Authorizer>) -> Self {
+        self.authorizer = authorizer;
+        self
+    }
+
+    pub fn with_client_quirks(mut self, client_quirks: ClientQuirks) -> Self {
+        self.client_quirks = client_quirks;
+        self
+    }
+
+    pub fn get_user(&self) -> &str {
+        &self.user
+    }
+
+    /// Gets the number of bytes the client has written to files in this session.
+    pub fn get_bytes_in(&self) -> u64 {
+        self.bytes_in.load(Ordering::Relaxed)
+    }
+
+    /// Gets the number of bytes the client has read from files in this session.
+    pub fn get_bytes_out(&self) -> u64 {
+        self.bytes_out.load(Ordering::Relaxed)
+    }
+
+    #[tracing::instrument(skip(self), fields(user = %self.user), level = Level::DEBUG)]
     pub async fn handle_request(&self, request: Request) -> Response {
         let request_id = request.get_request_id();
+        let request_name = request.get_name();
+        let start = Instant::now();
+        let audit_event = self.get_audit_event(&request).await;
 
+        // Writes reserve bytes before calling storage, so the timeout is applied to their storage
+        // calls instead of the whole request, which lets the reservations be released.
         let response = match request {
+            Request::Write(_) => self.dispatch_request(request).await,
+            _ => {
+                self.with_storage_op_timeout(self.dispatch_request(request))
+                    .await
+            }
+        };
+
+        metrics::record_request(request_name, start.elapsed());
+
+        if let Some((op, paths)) = audit_event {
+            self.record_audit_event(op, paths, &response).await;
+        }
+
+        match response {
+            Ok(response) => response,
+            Err(error) => {
+                error!("Received error while processing request: {}", error);
+                Response::build_error_response(request_id, error)
+            }
+        }
+    }
+
+    /// Fails a request with a storage error if it is not handled within the storage operation
+    /// timeout, so that a stuck call to storage does not hold the session forever. The request's
+    /// storage calls are cancelled when the timeout expires.
+    async fn with_storage_op_timeout<F, T>(&self, response: F) -> Result<T, Error>
+    where
+        F: Future<Output = Result<T, Error>>,
+    {
+        match self.storage_op_timeout {
+            Some(storage_op_timeout) => tokio::time::timeout(storage_op_timeout, response)
+                .await
+                .unwrap_or_else(|_| Err(build_storage_op_timeout_error())),
+            None => response.await,
+        }
+    }
+
+    async fn dispatch_request(&self, request: Request) -> Result<Response, Error> {
+        match request {
             Request::Init(init_request) => self.handle_init_request(init_request),
+            // The protocol version is negotiated by the init request, so other requests cannot be
+            // handled before it.
+            _ if !self.initialized.load(Ordering::SeqCst) => {
+                error!("Received {} request before init", request.get_name());
+                Err(Error::BadMessage)
+            }
             Request::Open(open_request) => self.handle_open_request(open_request).await,
             Request::Close(close_request) => self.handle_close_request(close_request).await,
             Request::Read(read_request) => self.handle_read_request(read_request).await,
             Request::Write(write_request) => self.handle_write_request(write_request).await,
             Request::Lstat(lstat_request) => self.handle_lstat_request(lstat_request).await,
             Request::Fstat(fstat_request) => self.handle_fstat_request(fstat_request).await,
-            Request::Setstat(setstat_request) => self.handle_setstat_request(setstat_request),
-            Request::Fsetstat(fsetstat_request) => self.handle_fsetstat_request(fsetstat_request),
+            Request::Setstat(setstat_request) => self.handle_setstat_request(setstat_request).await,
+            Request::Fsetstat(fsetstat_request) => {
+                self.handle_fsetstat_request(fsetstat_request).await
+            }
             Request::Opendir(opendir_request) => self.handle_opendir_request(opendir_request).await,
             Request::Readdir(readdir_request) => self.handle_readdir_request(readdir_request).await,
             Request::Remove(remove_request) => self.handle_remove_request(remove_request).await,
             Request::Mkdir(mkdir_request) => self.handle_mkdir_request(mkdir_request).await,
             Request::Rmdir(rmdir_request) => self.handle_rmdir_request(rmdir_request).await,
-            Request::Realpath(realpath_request) => self.handle_realpath_request(realpath_request),
+            Request::Realpath(realpath_request) => {
+                self.handle_realpath_request(realpath_request).await
+            }
             Request::Stat(stat_request) => self.handle_stat_request(stat_request).await,
             Request::Rename(rename_request) => self.handle_rename_request(rename_request).await,
-            Request::Readlink(readlink_request) => self.handle_readlink_request(readlink_request),
-            Request::Symlink(symlink_request) => self.handle_symlink_request(symlink_request),
+            Request::Readlink(readlink_request) => {
+                self.handle_readlink_request(readlink_request).await
+            }
+            Request::Symlink(symlink_request) => self.handle_symlink_request(symlink_request).await,
+            Request::Extended(extended_request) => {
+                self.handle_extended_request(extended_request).await
+            }
+            Request::Unsupported(unsupported_request) => {
+                info!(
+                    "Received unsupported request type {}",
+                    unsupported_request.data_type
+                );
+                Err(Error::Unimplemented)
+            }
+        }
+    }
+
+    /// Describes a request that modifies files for the audit log, as the operation and the paths
+    /// it affects. Other requests, and all requests when audit logging is disabled, return None.
+    async fn get_audit_event(&self, request: &Request) -> Option<(&'static str, Vec<String>)> {
+        self.audit_log.as_ref()?;
+
+        match request {
+            Request::Open(open_request)
+                if open_request.open_options.write || open_request.open_options.create =>
+            {
+                Some(("open", vec![open_request.filename.clone()]))
+            }
+            Request::Close(close_request) => self
+                .audit_handles
+                .lock()
+                .await
+                .remove(&close_request.handle)
+                .map(|path| ("close", vec![path])),
+            Request::Setstat(setstat_request) => {
+                Some(("setstat", vec![setstat_request.path.clone()]))
+            }
+            Request::Fsetstat(fsetstat_request) => {
+                let path = self
+                    .audit_handles
+                    .lock()
+                    .await
+                    .get(&fsetstat_request.handle)
+                    .cloned()
+                    .unwrap_or_else(|| fsetstat_request.handle.clone());
+
+                Some(("fsetstat", vec![path]))
+            }
+            Request::Remove(remove_request) => Some(("remove", vec![remove_request.path.clone()])),
+            Request::Mkdir(mkdir_request) => Some(("mkdir", vec![mkdir_request.path.clone()])),
+            Request::Rmdir(rmdir_request) => Some(("rmdir", vec![rmdir_request.path.clone()])),
+            Request::Rename(rename_request) => Some((
+                "rename",
+                vec![
+                    rename_request.old_path.clone(),
+                    rename_request.new_path.clone(),
+                ],
+            )),
+            // The paths are sent in the OpenSSH order, with the link path in the target field.
+            Request::Symlink(symlink_request) => Some((
+                "symlink",
+                vec![
+                    symlink_request.target_path.clone(),
+                    symlink_request.link_path.clone(),
+                ],
+            )),
+            Request::Extended(extended_request) => match &extended_request.extended_request {
+                request::extended::ExtendedRequest::Hardlink { old_path, new_path } => {
+                    Some(("hardlink", vec![old_path.clone(), new_path.clone()]))
+                }
+                request::extended::ExtendedRequest::PosixRename { old_path, new_path } => {
+                    Some(("rename", vec![old_path.clone(), new_path.clone()]))
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    async fn record_audit_event(
+        &self,
+        op: &'static str,
+        paths: Vec<String>,
+        response: &Result<Response, Error>,
+    ) {
+        let audit_log = match &self.audit_log {
+            Some(audit_log) => audit_log,
+            None => return,
         };
 
-        match response {
-            Ok(response) => response,
-            Err(error) => {
-                error!("Received error while processing request: {}", error);
-                Response::build_error_response(request_id, error)
+        let result = match response {
+            Ok(Response::Status(status))
+                if status.status_code != response::status::StatusCode::Ok =>
+            {
+                status.error_message.clone()
             }
+            Ok(_) => String::from("success"),
+            Err(error) => error.to_string(),
+        };
+
+        // Closing the handle is audited with the path that it was opened with.
+        if let Ok(Response::Handle(handle)) = response {
+            self.audit_handles
+                .lock()
+                .await
+                .insert(handle.handle.clone(), paths[0].clone());
         }
+
+        audit_log.record(&AuditRecord {
+            user: self.user.clone(),
+            op,
+            paths,
+            timestamp: Utc::now().to_rfc3339(),
+            result,
+        });
     }
 
-    fn handle_init_request(&self, _init_request: request::init::Init) -> Result<Response, Error> {
-        Ok(Response::Version(response::version::Version { version: 3 }))
+    #[tracing::instrument(skip(self))]
+    fn handle_init_request(&self, init_request: request::init::Init) -> Result<Response, Error> {
+        // A second init would renegotiate the version mid-session, which can desynchronize a
+        // client that is expecting responses to its other requests.
+        if self.initialized.swap(true, Ordering::SeqCst) {
+            error!("Received duplicate init request");
+            return Err(Error::BadMessage);
+        }
+
+        let version = init_request.version.min(MAX_SFTP_VERSION);
+
+        self.version.store(version, Ordering::SeqCst);
+
+        let extensions = request::extended::SUPPORTED_EXTENSIONS
+            .iter()
+            .map(|(name, data)| (name.to_string(), data.to_string()))
+            .collect();
+
+        Ok(Response::Version(response::version::Version {
+            version,
+            extensions,
+        }))
     }
 
     #[tracing::instrument(skip(self))]
@@ -73,15 +441,53 @@ impl SftpSession {
         &self,
         open_request: request::open::Open,
     ) -> Result<Response, Error> {
-        self.check_permission(&open_request.filename)?;
+        let open_options = &open_request.open_options;
+        let filename = self.resolve_path(&open_request.filename);
 
-        let handle = if open_request.open_options.create {
+        validate_filename(&filename, self.max_path_depth)?;
+
+        match open_options.create || open_options.write {
+            true => self.authorize(Operation::Write, &filename)?,
+            false => self.authorize(Operation::Read, &filename)?,
+        };
+
+        // Exclusive creates are used for lock files, so an existing file must not be replaced.
+        // Storage does not support conditional creates, so another client could still create the
+        // file between the check and the upload.
+        if open_options.create && open_options.create_new_only {
+            match self
+                .object_storage
+                .get_file_metadata(filename.clone())
+                .await
+            {
+                Ok(_) => return Err(Error::AlreadyExists),
+                Err(Error::NoSuchFile) => {}
+                Err(error) => return Err(error),
+            }
+        }
+
+        // Truncating or replacing a file frees the space it used.
+        let replaces_file = !open_options.append && (open_options.create || open_options.truncate);
+
+        let handle = if open_options.append {
+            self.object_storage
+                .open_append_handle(filename.clone(), open_options.create)
+                .await?
+        } else if open_options.create {
+            self.object_storage
+                .open_write_handle(filename.clone())
+                .await?
+        } else if open_options.read && open_options.write {
+            self.object_storage
+                .open_read_write_handle(filename.clone(), open_options.truncate)
+                .await?
+        } else if open_options.write {
             self.object_storage
-                .open_write_handle(open_request.filename)
+                .open_existing_write_handle(filename.clone(), open_options.truncate)
                 .await?
-        } else if open_request.open_options.read {
+        } else if open_options.read {
             self.object_storage
-                .open_read_handle(open_request.filename)
+                .open_read_handle(filename.clone())
                 .await?
         } else {
             return Ok(Response::Status(response::status::Status {
@@ -91,6 +497,15 @@ impl SftpSession {
             }));
         };
 
+        if replaces_file {
+            self.invalidate_used_bytes().await;
+        }
+
+        self.handle_paths
+            .lock()
+            .await
+            .insert(handle.clone(), filename);
+
         Ok(Response::Handle(response::handle::Handle {
             id: open_request.id,
             handle,
@@ -102,6 +517,13 @@ impl SftpSession {
         &self,
         close_request: request::handle::Handle,
     ) -> Result<Response, Error> {
+        self.upload_bytes.lock().await.remove(&close_request.handle);
+        self.handle_paths.lock().await.remove(&close_request.handle);
+        self.glob_patterns
+            .lock()
+            .await
+            .remove(&close_request.handle);
+
         self.object_storage
             .close_handle(&close_request.handle)
             .await?;
@@ -114,9 +536,24 @@ impl SftpSession {
         &self,
         read_request: request::read::Read,
     ) -> Result<Response, Error> {
+        // A zero-length read is not the end of the file, so it succeeds with no data and without
+        // reading from storage, as long as the handle is open.
+        if read_request.len == 0 {
+            self.handle_path(&read_request.handle).await?;
+
+            return Ok(Response::Data(response::data::Data {
+                id: read_request.id,
+                data: vec![],
+            }));
+        }
+
+        // The length is controlled by the client, so it is capped to bound the memory used by a
+        // single read. Clients must handle reads that return less data than requested.
+        let len = read_request.len.min(self.max_read_length);
+
         let data = self
             .object_storage
-            .read_data(&read_request.handle, read_request.len)
+            .read_data(&read_request.handle, read_request.offset, len)
             .await?;
 
         if data.is_empty() {
@@ -126,6 +563,9 @@ impl SftpSession {
                 error_message: String::from("End of file."),
             }))
         } else {
+            self.bytes_out
+                .fetch_add(data.len() as u64, Ordering::Relaxed);
+
             Ok(Response::Data(response::data::Data {
                 id: read_request.id,
                 data,
@@ -138,10 +578,47 @@ impl SftpSession {
         &self,
         write_request: request::write::Write,
     ) -> Result<Response, Error> {
-        self.object_storage
-            .write_data(&write_request.handle, write_request.data)
+        if write_request.data.is_empty() {
+            self.handle_path(&write_request.handle).await?;
+
+            return Ok(SftpSession::build_successful_response(write_request.id));
+        }
+
+        let len = write_request.data.len() as u64;
+
+        self.with_storage_op_timeout(self.reserve_upload_bytes(&write_request.handle, len))
             .await?;
 
+        if let Err(error) = self
+            .with_storage_op_timeout(self.reserve_quota(&write_request.handle, len))
+            .await
+        {
+            self.release_upload_bytes(&write_request.handle, len).await;
+            return Err(error);
+        }
+
+        if let Err(error) = self
+            .with_storage_op_timeout(self.object_storage.write_data(
+                &write_request.handle,
+                write_request.offset,
+                write_request.data,
+            ))
+            .await
+        {
+            self.release_upload_bytes(&write_request.handle, len).await;
+            self.release_quota(len).await;
+
+            // A write that timed out may have been partly applied to the handle, so the handle
+            // is aborted rather than completing a file with data the client was told failed.
+            if error == build_storage_op_timeout_error() {
+                self.abort_timed_out_write(&write_request.handle).await;
+            }
+
+            return Err(error);
+        }
+
+        self.bytes_in.fetch_add(len, Ordering::Relaxed);
+
         Ok(SftpSession::build_successful_response(write_request.id))
     }
 
@@ -150,11 +627,20 @@ impl SftpSession {
         &self,
         lstat_request: request::path::Path,
     ) -> Result<Response, Error> {
-        self.check_permission(&lstat_request.path)?;
+        let path = self.resolve_path(&lstat_request.path);
+
+        self.authorize(Operation::Read, &path)?;
+
+        let file_attributes = self
+            .object_storage
+            .get_file_metadata(path)
+            .await?
+            .file_attributes;
 
-        // Object storage does not have symbolic links, so stat and lstat will operate the same
-        // because there are no symbolic links to follow.
-        self.handle_stat_request(lstat_request).await
+        Ok(Response::Attrs(response::attrs::Attrs {
+            id: lstat_request.id,
+            file_attributes,
+        }))
     }
 
     #[tracing::instrument(skip(self))]
@@ -175,23 +661,47 @@ impl SftpSession {
     }
 
     #[tracing::instrument(skip(self))]
-    fn handle_setstat_request(
+    async fn handle_setstat_request(
         &self,
         setstat_request: request::path_attributes::PathAttributes,
     ) -> Result<Response, Error> {
-        Ok(SftpSession::build_not_supported_response(
-            setstat_request.id,
-        ))
+        let path = self.resolve_path(&setstat_request.path);
+
+        self.authorize(Operation::SetAttributes, &path)?;
+
+        let resizes_file = setstat_request.file_attributes.size.is_some();
+
+        self.object_storage
+            .set_file_metadata(path, setstat_request.file_attributes)
+            .await?;
+
+        if resizes_file {
+            self.invalidate_used_bytes().await;
+        }
+
+        Ok(SftpSession::build_successful_response(setstat_request.id))
     }
 
     #[tracing::instrument(skip(self))]
-    fn handle_fsetstat_request(
+    async fn handle_fsetstat_request(
         &self,
         fsetstat_request: request::handle_attributes::HandleAttributes,
     ) -> Result<Response, Error> {
-        Ok(SftpSession::build_not_supported_response(
-            fsetstat_request.id,
-        ))
+        let path = self.handle_path(&fsetstat_request.handle).await?;
+
+        self.authorize(Operation::SetHandleAttributes, &path)?;
+
+        let resizes_file = fsetstat_request.file_attributes.size.is_some();
+
+        self.object_storage
+            .set_handle_metadata(&fsetstat_request.handle, fsetstat_request.file_attributes)
+            .await?;
+
+        if resizes_file {
+            self.invalidate_used_bytes().await;
+        }
+
+        Ok(SftpSession::build_successful_response(fsetstat_request.id))
     }
 
     #[tracing::instrument(skip(self))]
@@ -199,12 +709,11 @@ impl SftpSession {
         &self,
         opendir_request: request::path::Path,
     ) -> Result<Response, Error> {
-        self.check_permission(&opendir_request.path)?;
+        let path = self.resolve_path(&opendir_request.path);
 
-        let handle = self
-            .object_storage
-            .open_dir_handle(opendir_request.path)
-            .await?;
+        self.authorize(Operation::Read, &path)?;
+
+        let handle = self.object_storage.open_dir_handle(path).await?;
 
         Ok(Response::Handle(response::handle::Handle {
             id: opendir_request.id,
@@ -217,11 +726,27 @@ impl SftpSession {
         &self,
         readdir_request: request::handle::Handle,
     ) -> Result<Response, Error> {
-        let files = self
-            .object_storage
-            .read_dir(&readdir_request.handle)
-            .await?;
+        let pattern = self
+            .glob_patterns
+            .lock()
+            .await
+            .get(&readdir_request.handle)
+            .cloned();
+
+        let files = match pattern {
+            Some(pattern) => {
+                self.read_matching_files(&readdir_request.handle, &pattern)
+                    .await?
+            }
+            None => {
+                self.object_storage
+                    .read_dir(&readdir_request.handle)
+                    .await?
+            }
+        };
 
+        // SFTP v3 cannot mark a listing's last page, so clients read until they receive EOF.
+        // Storage only returns an empty batch once the listing is complete.
         match files.is_empty() {
             true => Ok(Response::Status(response::status::Status {
                 id: readdir_request.id,
@@ -240,9 +765,12 @@ impl SftpSession {
         &self,
         remove_request: request::path::Path,
     ) -> Result<Response, Error> {
-        self.check_permission(&remove_request.path)?;
+        let path = self.resolve_path(&remove_request.path);
+
+        self.authorize(Operation::Remove, &path)?;
 
-        self.object_storage.remove_file(remove_request.path).await?;
+        self.object_storage.remove_file(path).await?;
+        self.invalidate_used_bytes().await;
 
         Ok(SftpSession::build_successful_response(remove_request.id))
     }
@@ -252,9 +780,12 @@ impl SftpSession {
         &self,
         mkdir_request: request::path_attributes::PathAttributes,
     ) -> Result<Response, Error> {
-        self.check_permission(&mkdir_request.path)?;
+        let path = self.resolve_path(&mkdir_request.path);
 
-        self.object_storage.create_dir(mkdir_request.path).await?;
+        validate_filename(&path, self.max_path_depth)?;
+        self.authorize(Operation::CreateDir, &path)?;
+
+        self.object_storage.create_dir(path).await?;
 
         Ok(SftpSession::build_successful_response(mkdir_request.id))
     }
@@ -264,36 +795,65 @@ impl SftpSession {
         &self,
         rmdir_request: request::path::Path,
     ) -> Result<Response, Error> {
-        self.check_permission(&rmdir_request.path)?;
+        let path = self.resolve_path(&rmdir_request.path);
+
+        self.authorize(Operation::RemoveDir, &path)?;
 
-        self.object_storage.remove_dir(rmdir_request.path).await?;
+        self.object_storage.remove_dir(path).await?;
+        self.invalidate_used_bytes().await;
 
         Ok(SftpSession::build_successful_response(rmdir_request.id))
     }
 
     #[tracing::instrument(skip(self))]
-    fn handle_realpath_request(
+    async fn handle_realpath_request(
         &self,
-        realpath_request: request::path::Path,
+        mut realpath_request: request::realpath::Realpath,
     ) -> Result<Response, Error> {
-        let path = if realpath_request.path == "." {
-            self.object_storage.get_home(&self.user)
-        } else {
-            realpath_request.to_normalized_path()
+        // Relative paths, including "." and the empty path, are resolved from the home directory
+        // before the compose paths are applied.
+        realpath_request.path = self.resolve_path(&realpath_request.path);
+
+        let path = normalize_path(&realpath_request.to_composed_path());
+
+        // Version 3 clients do not send a control byte, and most only use the name of the
+        // response.
+        let placeholder_attributes = FileAttributes {
+            permissions: Some(0o40777),
+            size: None,
+            uid: None,
+            gid: None,
+            atime: None,
+            mtime: None,
+        };
+
+        let file_attributes = match realpath_request.control_byte {
+            // Clients that use the attributes still resolve paths they cannot stat, such as the
+            // parent of their home directory.
+            None if self.client_quirks.realpath_attributes => {
+                match self.stat_path(path.clone()).await {
+                    Err(Error::NoSuchFile | Error::PermissionDenied) => placeholder_attributes,
+                    file_attributes => file_attributes?,
+                }
+            }
+            None => placeholder_attributes,
+            Some(request::realpath::ControlByte::NoCheck) => FileAttributes::default(),
+            Some(request::realpath::ControlByte::StatIf) => {
+                match self.stat_path(path.clone()).await {
+                    Err(Error::NoSuchFile) => FileAttributes::default(),
+                    file_attributes => file_attributes?,
+                }
+            }
+            Some(request::realpath::ControlByte::StatAlways) => {
+                self.stat_path(path.clone()).await?
+            }
         };
 
         Ok(Response::Name(response::name::Name {
             id: realpath_request.id,
             files: vec![response::name::File {
                 file_name: path,
-                file_attributes: FileAttributes {
-                    permissions: Some(0o40777),
-                    size: None,
-                    uid: None,
-                    gid: None,
-                    atime: None,
-                    mtime: None,
-                },
+                file_attributes,
             }],
         }))
     }
@@ -303,86 +863,3583 @@ impl SftpSession {
         &self,
         stat_request: request::path::Path,
     ) -> Result<Response, Error> {
-        self.check_permission(&stat_request.path)?;
-
-        let file_attributes = self
-            .object_storage
-            .get_file_metadata(stat_request.path.clone())
-            .await?
-            .file_attributes;
-
         Ok(Response::Attrs(response::attrs::Attrs {
             id: stat_request.id,
-            file_attributes,
+            file_attributes: self
+                .stat_path(self.resolve_path(&stat_request.path))
+                .await?,
         }))
     }
 
+    /// Retrieves the attributes of a path, following links to the attributes of their targets.
+    async fn stat_path(&self, mut path: String) -> Result<FileAttributes, Error> {
+        // Each target must be readable, so a link cannot reveal the attributes of files that the
+        // user cannot access.
+        for _ in 0..=MAX_SYMLINK_DEPTH {
+            self.authorize(Operation::Read, &path)?;
+
+            let file_attributes = self
+                .object_storage
+                .get_file_metadata(path.clone())
+                .await?
+                .file_attributes;
+
+            if !file_attributes.is_symlink() {
+                return Ok(file_attributes);
+            }
+
+            let target_path = self.object_storage.read_symlink(path.clone()).await?;
+            path = resolve_symlink_target(&path, &target_path);
+        }
+
+        Err(Error::SymlinkLoop)
+    }
+
     #[tracing::instrument(skip(self))]
     async fn handle_rename_request(
         &self,
         rename_request: request::rename::Rename,
     ) -> Result<Response, Error> {
-        self.check_permission(&rename_request.new_path)?;
-        self.check_permission(&rename_request.old_path)?;
+        let old_path = self.resolve_path(&rename_request.old_path);
+        let new_path = self.resolve_path(&rename_request.new_path);
 
-        self.object_storage
-            .rename(rename_request.old_path, rename_request.new_path)
-            .await?;
+        validate_filename(&new_path, self.max_path_depth)?;
+        check_not_symlink_sentinel(&old_path)?;
+        self.authorize(Operation::Rename, &new_path)?;
+        self.authorize(Operation::Rename, &old_path)?;
+
+        self.object_storage.rename(old_path, new_path).await?;
+        self.invalidate_used_bytes().await;
 
         Ok(SftpSession::build_successful_response(rename_request.id))
     }
 
     #[tracing::instrument(skip(self))]
-    fn handle_readlink_request(
+    async fn handle_readlink_request(
         &self,
         readlink_request: request::path::Path,
     ) -> Result<Response, Error> {
-        Ok(SftpSession::build_not_supported_response(
-            readlink_request.id,
-        ))
+        let path = self.resolve_path(&readlink_request.path);
+
+        self.authorize(Operation::Read, &path)?;
+
+        let target = self.object_storage.read_symlink(path).await?;
+
+        Ok(Response::Name(response::name::Name {
+            id: readlink_request.id,
+            files: vec![response::name::File {
+                file_name: target,
+                file_attributes: FileAttributes::default(),
+            }],
+        }))
     }
 
     #[tracing::instrument(skip(self))]
-    fn handle_symlink_request(
+    async fn handle_symlink_request(
         &self,
         symlink_request: request::symlink::Symlink,
     ) -> Result<Response, Error> {
-        Ok(SftpSession::build_not_supported_response(
-            symlink_request.id,
-        ))
-    }
+        // OpenSSH sends the target path before the link path, which is the reverse of the order
+        // in the SFTP draft. The OpenSSH order is used because it is what clients implement.
+        let target_path = symlink_request.link_path;
+        let link_path = self.resolve_path(&symlink_request.target_path);
 
-    #[tracing::instrument]
-    fn build_successful_response(id: u32) -> Response {
-        Response::Status(response::status::Status {
-            id,
-            status_code: response::status::StatusCode::Ok,
-            error_message: String::from(""),
-        })
+        let resolved_target_path = resolve_symlink_target(&link_path, &target_path);
+
+        validate_filename(&link_path, self.max_path_depth)?;
+        self.authorize(Operation::CreateLink, &link_path)?;
+        self.authorize(Operation::Read, &resolved_target_path)?;
+
+        self.object_storage
+            .create_symlink(link_path, target_path)
+            .await?;
+
+        Ok(SftpSession::build_successful_response(symlink_request.id))
     }
 
-    #[tracing::instrument]
-    pub fn build_invalid_request_message_response() -> Response {
-        Response::Status(response::status::Status {
-            id: 0,
-            status_code: response::status::StatusCode::BadMessage,
-            error_message: String::from("The request message is invalid."),
-        })
+    #[tracing::instrument(skip(self))]
+    async fn handle_extended_request(
+        &self,
+        extended_request: request::extended::Extended,
+    ) -> Result<Response, Error> {
+        match extended_request.extended_request {
+            request::extended::ExtendedRequest::Fsync { handle } => {
+                self.object_storage.fsync(&handle).await?;
+
+                Ok(SftpSession::build_successful_response(extended_request.id))
+            }
+            request::extended::ExtendedRequest::Hardlink { old_path, new_path } => {
+                let old_path = self.resolve_path(&old_path);
+                let new_path = self.resolve_path(&new_path);
+
+                validate_filename(&new_path, self.max_path_depth)?;
+                self.authorize(Operation::CreateLink, &new_path)?;
+                self.authorize(Operation::CreateLink, &old_path)?;
+
+                self.create_hardlink(old_path, new_path).await?;
+
+                Ok(SftpSession::build_successful_response(extended_request.id))
+            }
+            request::extended::ExtendedRequest::Limits => Ok(Response::ExtendedReply(
+                response::extended_reply::ExtendedReply {
+                    id: extended_request.id,
+                    extended_reply: response::extended_reply::ExtendedReplyData::Limits(
+                        self.build_limits(),
+                    ),
+                },
+            )),
+            request::extended::ExtendedRequest::ListGlob { path, pattern } => {
+                let path = self.resolve_path(&path);
+
+                self.authorize(Operation::Read, &path)?;
+
+                let handle = self.object_storage.open_dir_handle(path).await?;
+                self.glob_patterns
+                    .lock()
+                    .await
+                    .insert(handle.clone(), pattern);
+
+                Ok(Response::Handle(response::handle::Handle {
+                    id: extended_request.id,
+                    handle,
+                }))
+            }
+            request::extended::ExtendedRequest::PosixRename { old_path, new_path } => {
+                let old_path = self.resolve_path(&old_path);
+                let new_path = self.resolve_path(&new_path);
+
+                validate_filename(&new_path, self.max_path_depth)?;
+                check_not_symlink_sentinel(&old_path)?;
+                self.authorize(Operation::Rename, &new_path)?;
+                self.authorize(Operation::Rename, &old_path)?;
+
+                self.object_storage.posix_rename(old_path, new_path).await?;
+                self.invalidate_used_bytes().await;
+
+                Ok(SftpSession::build_successful_response(extended_request.id))
+            }
+            request::extended::ExtendedRequest::Statvfs { path } => {
+                self.authorize(Operation::Read, &self.resolve_path(&path))?;
+
+                Ok(Response::ExtendedReply(
+                    response::extended_reply::ExtendedReply {
+                        id: extended_request.id,
+                        extended_reply: response::extended_reply::ExtendedReplyData::Statvfs(
+                            self.build_statvfs().await?,
+                        ),
+                    },
+                ))
+            }
+            request::extended::ExtendedRequest::Unsupported { name } => {
+                info!("Received unsupported extended request {}", name);
+
+                Ok(SftpSession::build_unsupported_extension_response(
+                    extended_request.id,
+                    &name,
+                ))
+            }
+        }
     }
 
-    #[tracing::instrument]
-    fn build_not_supported_response(id: u32) -> Response {
-        Response::Status(response::status::Status {
-            id,
-            status_code: response::status::StatusCode::OperationUnsupported,
-            error_message: String::from("Operation Unsupported!"),
-        })
+    /// Reads the next page of a directory with files that match a glob pattern. An empty page ends
+    /// the client's listing, so pages without matches are skipped until files are found or the
+    /// listing is complete.
+    async fn read_matching_files(
+        &self,
+        handle: &str,
+        pattern: &str,
+    ) -> Result<Vec<response::name::File>, Error> {
+        loop {
+            let files = self.object_storage.read_dir(handle).await?;
+
+            if files.is_empty() {
+                return Ok(files);
+            }
+
+            let matching_files: Vec<response::name::File> = files
+                .into_iter()
+                .filter(|file| glob::is_match(pattern, &file.file_name))
+                .collect();
+
+            if !matching_files.is_empty() {
+                return Ok(matching_files);
+            }
+        }
     }
 
-    fn check_permission(&self, path: &str) -> Result<(), Error> {
-        match path.starts_with(&self.user_home) {
-            true => Ok(()),
-            false => Err(Error::PermissionDenied),
+    /// Emulates a hard link by copying the file, since storage cannot share data between paths.
+    /// Like link(2), an existing destination is not replaced.
+    async fn create_hardlink(&self, old_path: String, new_path: String) -> Result<(), Error> {
+        let file = self
+            .object_storage
+            .get_file_metadata(old_path.clone())
+            .await?;
+
+        if file.file_attributes.is_dir() || file.file_attributes.is_symlink() {
+            return Err(Error::Failure(String::from(
+                "Hard links are only supported for regular files.",
+            )));
+        }
+
+        match self
+            .object_storage
+            .get_file_metadata(new_path.clone())
+            .await
+        {
+            Ok(_) => return Err(Error::AlreadyExists),
+            Err(Error::NoSuchFile) => {}
+            Err(err) => return Err(err),
+        };
+
+        // The copy stores the file's data again, so it is counted against the user's quota.
+        let size = file.file_attributes.size.unwrap_or_default();
+        self.reserve_quota_bytes(size).await?;
+
+        if let Err(err) = self.object_storage.copy_file(old_path, new_path).await {
+            self.release_quota(size).await;
+            return Err(err);
+        }
+
+        Ok(())
+    }
+
+    /// Counts written data against the user's quota. When a write would exceed the quota, the
+    /// file being written is aborted so that an incomplete file is not stored.
+    async fn reserve_quota(&self, handle: &str, len: u64) -> Result<(), Error> {
+        match self.reserve_quota_bytes(len).await {
+            Err(Error::QuotaExceeded) => {
+                info!(
+                    "Aborting write to handle {} because the quota was exceeded",
+                    handle
+                );
+                self.object_storage.abort_write_handle(handle).await?;
+
+                Err(Error::QuotaExceeded)
+            }
+            result => result,
+        }
+    }
+
+    /// Counts bytes written to a handle, aborting the write if the file would exceed the maximum
+    /// upload size. Unlike the quota, this limits the size of each file.
+    async fn reserve_upload_bytes(&self, handle: &str, len: u64) -> Result<(), Error> {
+        let max_upload_bytes = match self.max_upload_bytes {
+            Some(max_upload_bytes) => max_upload_bytes,
+            None => return Ok(()),
+        };
+
+        let mut upload_bytes = self.upload_bytes.lock().await;
+        let written = upload_bytes.get(handle).copied().unwrap_or_default();
+
+        if written.saturating_add(len) <= max_upload_bytes {
+            upload_bytes.insert(handle.to_string(), written + len);
+            return Ok(());
+        }
+
+        upload_bytes.remove(handle);
+        drop(upload_bytes);
+
+        info!(
+            "Aborting write to handle {} because the file exceeds the maximum upload size",
+            handle
+        );
+        self.object_storage.abort_write_handle(handle).await?;
+
+        Err(Error::FileTooLarge)
+    }
+
+    /// Aborts a write handle after a write to it timed out. The abort is also limited by the
+    /// timeout, and a failure to abort is only logged, since the write has already failed.
+    async fn abort_timed_out_write(&self, handle: &str) {
+        info!(
+            "Aborting write to handle {} because a write timed out",
+            handle
+        );
+
+        if let Err(error) = self
+            .with_storage_op_timeout(self.object_storage.abort_write_handle(handle))
+            .await
+        {
+            error!("Failed to abort write to handle {}: {}", handle, error);
+        }
+    }
+
+    /// Returns bytes reserved by a write that failed to the handle's upload size.
+    async fn release_upload_bytes(&self, handle: &str, len: u64) {
+        let mut upload_bytes = self.upload_bytes.lock().await;
+
+        if let Some(written) = upload_bytes.get_mut(handle) {
+            *written = written.saturating_sub(len);
+
+            if *written == 0 {
+                upload_bytes.remove(handle);
+            }
+        }
+    }
+
+    /// Counts bytes against the user's quota, failing if they would exceed it.
+    async fn reserve_quota_bytes(&self, len: u64) -> Result<(), Error> {
+        let user_quota_bytes = match self.user_quota_bytes {
+            Some(user_quota_bytes) => user_quota_bytes,
+            None => return Ok(()),
+        };
+
+        let mut used_bytes = self.used_bytes.lock().await;
+
+        let used = match *used_bytes {
+            Some(used) => used,
+            None => {
+                self.object_storage
+                    .get_dir_size(self.user_home.clone())
+                    .await?
+            }
+        };
+
+        *used_bytes = Some(used);
+
+        if used.saturating_add(len) > user_quota_bytes {
+            return Err(Error::QuotaExceeded);
+        }
+
+        *used_bytes = Some(used + len);
+
+        Ok(())
+    }
+
+    /// Returns bytes reserved by a write that failed to the user's quota.
+    async fn release_quota(&self, len: u64) {
+        if let Some(used) = self.used_bytes.lock().await.as_mut() {
+            *used = used.saturating_sub(len);
+        }
+    }
+
+    /// Returns the path an open file handle was opened for, or an invalid handle error if the
+    /// handle is not open in this session.
+    async fn handle_path(&self, handle: &str) -> Result<String, Error> {
+        match self.handle_paths.lock().await.get(handle) {
+            Some(path) => Ok(path.clone()),
+            None => Err(Error::InvalidHandle),
+        }
+    }
+
+    /// Clears the number of bytes stored by the user after a request that may have freed space,
+    /// such as a removal or a rename that replaced a file, so that storage is listed again on
+    /// the next write.
+    async fn invalidate_used_bytes(&self) {
+        *self.used_bytes.lock().await = None;
+    }
+
+    /// Builds the limits advertised to clients, which match the limits enforced on requests, so
+    /// that clients can send the largest requests that will be accepted.
+    fn build_limits(&self) -> response::extended_reply::Limits {
+        response::extended_reply::Limits {
+            max_packet_length: MAX_REQUEST_LENGTH as u64,
+            max_read_length: self.max_read_length as u64,
+            max_write_length: (MAX_REQUEST_LENGTH - WRITE_REQUEST_OVERHEAD) as u64,
+            max_open_handles: self.max_handles as u64,
+        }
+    }
+
+    /// Builds filesystem statistics for the user. When a quota is configured, the capacity is
+    /// the quota and the free space is what remains of the quota after the user's home.
+    async fn build_statvfs(&self) -> Result<response::extended_reply::Statvfs, Error> {
+        let (capacity, free) = match self.user_quota_bytes {
+            Some(user_quota_bytes) => {
+                let used = self
+                    .object_storage
+                    .get_dir_size(self.user_home.clone())
+                    .await?;
+
+                (user_quota_bytes, user_quota_bytes.saturating_sub(used))
+            }
+            None => (UNLIMITED_CAPACITY, UNLIMITED_CAPACITY),
+        };
+
+        Ok(response::extended_reply::Statvfs {
+            block_size: STATVFS_BLOCK_SIZE,
+            fragment_size: STATVFS_BLOCK_SIZE,
+            blocks: capacity / STATVFS_BLOCK_SIZE,
+            free_blocks: free / STATVFS_BLOCK_SIZE,
+            available_blocks: free / STATVFS_BLOCK_SIZE,
+            files: UNLIMITED_FILES,
+            free_files: UNLIMITED_FILES,
+            available_files: UNLIMITED_FILES,
+            filesystem_id: 0,
+            flags: 0,
+            max_name_length: MAX_NAME_LENGTH,
+        })
+    }
+
+    #[tracing::instrument]
+    fn build_successful_response(id: u32) -> Response {
+        Response::Status(response::status::Status {
+            id,
+            status_code: response::status::StatusCode::Ok,
+            error_message: String::from(""),
+        })
+    }
+
+    #[tracing::instrument]
+    /// Clients disable an extension when the server reports it as unsupported, rather than
+    /// treating the request as a failure.
+    fn build_unsupported_extension_response(id: u32, name: &str) -> Response {
+        Response::Status(response::status::Status {
+            id,
+            status_code: response::status::StatusCode::OperationUnsupported,
+            error_message: format!("The extension {} is unsupported.", name),
+        })
+    }
+
+    /// Discards any files the client is still writing. This is used when the session is
+    /// interrupted before the client has closed its handles.
+    #[tracing::instrument(skip(self))]
+    pub async fn abort_write_handles(&self) -> Result<(), Error> {
+        self.object_storage.abort_write_handles().await
+    }
+
+    /// Builds the response to a request that could not be parsed. The request's id is echoed when
+    /// it could be read, so that the client can match the error to its request.
+    #[tracing::instrument]
+    pub fn build_invalid_request_message_response(id: u32) -> Response {
+        Response::Status(response::status::Status {
+            id,
+            status_code: response::status::StatusCode::BadMessage,
+            error_message: String::from("The request message is invalid."),
+        })
+    }
+
+    /// Resolves a path from a request to the path that is both authorized and passed to storage,
+    /// so that `..` cannot make storage use a different path than the one checked. Relative
+    /// paths, including `.`, are resolved from the user's home, which is the working directory
+    /// reported by realpath.
+    fn resolve_path(&self, path: &str) -> String {
+        match path.starts_with('/') {
+            true => normalize_path(path),
+            false => normalize_path(&format!("{}/{}", self.user_home, path)),
+        }
+    }
+
+    /// Checks with the authorizer that the user may perform an operation on a path.
+    fn authorize(&self, operation: Operation, path: &str) -> Result<(), Error> {
+        self.authorizer.authorize(&self.user, operation, path)
+    }
+}
+
+fn build_storage_op_timeout_error() -> Error {
+    Error::Storage(String::from("Operation timed out."))
+}
+
+/// Resolves the target of a symbolic link to an absolute path. Relative targets are resolved from
+/// the directory containing the link.
+fn resolve_symlink_target(link_path: &str, target_path: &str) -> String {
+    let target_path = match target_path.starts_with('/') {
+        true => target_path.to_string(),
+        false => match link_path.rsplit_once('/') {
+            Some((link_dir, _)) => format!("{}/{}", link_dir, target_path),
+            None => target_path.to_string(),
+        },
+    };
+
+    normalize_path(&target_path)
+}
+
+/// Calculates a user's home directory from a template by substituting `{user}` with the user.
+/// Users that could change the directory structure of the home directory are rejected.
+pub(crate) fn get_home(home_template: &str, user: &str) -> Result<String, Error> {
+    if user.is_empty()
+        || user == "."
+        || user == ".."
+        || user.contains('/')
+        || user.chars().any(char::is_control)
+    {
+        return Err(Error::PermissionDenied);
+    }
+
+    Ok(home_template.replace(HOME_TEMPLATE_USER, user))
+}
+
+/// Checks that a path a client is creating can be safely used as a storage key. Control
+/// characters, such as NUL and newlines, and names longer than the advertised maximum name length
+/// are rejected. Paths that are too long for a storage key, or that are nested deeper than the
+/// maximum depth, are rejected with an error that explains the limit. Names of symbolic link
+/// sentinels are rejected, since a file with one would become a link.
+fn validate_filename(path: &str, max_path_depth: usize) -> Result<(), Error> {
+    let has_control_characters = path.chars().any(char::is_control);
+    let has_long_name = path
+        .split('/')
+        .any(|name| name.len() as u64 > MAX_NAME_LENGTH);
+
+    if has_control_characters || has_long_name {
+        return Err(Error::BadMessage);
+    }
+
+    let normalized_path = normalize_path(path);
+    check_not_symlink_sentinel(&normalized_path)?;
+
+    let depth = normalized_path
+        .split('/')
+        .filter(|name| !name.is_empty())
+        .count();
+
+    match normalized_path.len() > MAX_PATH_LENGTH || depth > max_path_depth {
+        true => Err(Error::PathTooLong),
+        false => Ok(()),
+    }
+}
+
+/// Sentinel files for symbolic links are managed with their links, so clients may not create,
+/// rename, or replace files with the sentinel's suffix.
+fn check_not_symlink_sentinel(path: &str) -> Result<(), Error> {
+    match storage::is_symlink_name(&normalize_path(path)) {
+        true => Err(Error::PermissionDenied),
+        false => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::storage::{filesystem::FilesystemStorage, DEFAULT_MAX_HANDLES};
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_handle_init_request_with_newer_client_version() {
+        let root = TempDir::new().unwrap();
+        let sftp_session = create_uninitialized_sftp_session(&root, &SftpSessionConfig::default());
+
+        assert_eq!(
+            Response::Version(response::version::Version {
+                version: 3,
+                extensions: vec![
+                    (String::from("fsync@openssh.com"), String::from("1")),
+                    (String::from("hardlink@openssh.com"), String::from("1")),
+                    (String::from("limits@openssh.com"), String::from("1")),
+                    (String::from("list-glob@dray"), String::from("2")),
+                    (String::from("posix-rename@openssh.com"), String::from("1")),
+                    (String::from("statvfs@openssh.com"), String::from("2")),
+                ],
+            }),
+            sftp_session
+                .handle_request(Request::Init(request::init::Init { version: 6 }))
+                .await
+        );
+        assert_eq!(3, sftp_session.version.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn test_handle_init_request_with_older_client_version() {
+        let root = TempDir::new().unwrap();
+        let sftp_session = create_uninitialized_sftp_session(&root, &SftpSessionConfig::default());
+
+        assert_eq!(
+            Response::Version(response::version::Version {
+                version: 2,
+                extensions: vec![
+                    (String::from("fsync@openssh.com"), String::from("1")),
+                    (String::from("hardlink@openssh.com"), String::from("1")),
+                    (String::from("limits@openssh.com"), String::from("1")),
+                    (String::from("list-glob@dray"), String::from("2")),
+                    (String::from("posix-rename@openssh.com"), String::from("1")),
+                    (String::from("statvfs@openssh.com"), String::from("2")),
+                ],
+            }),
+            sftp_session
+                .handle_request(Request::Init(request::init::Init { version: 2 }))
+                .await
+        );
+        assert_eq!(2, sftp_session.version.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn test_handle_init_request_twice() {
+        let root = TempDir::new().unwrap();
+        let sftp_session = create_uninitialized_sftp_session(&root, &SftpSessionConfig::default());
+
+        sftp_session
+            .handle_request(Request::Init(request::init::Init { version: 3 }))
+            .await;
+
+        assert_eq!(
+            Response::build_error_response(0, Error::BadMessage),
+            sftp_session
+                .handle_request(Request::Init(request::init::Init { version: 2 }))
+                .await
+        );
+        assert_eq!(3, sftp_session.version.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn test_handle_read_request_before_init() {
+        let root = TempDir::new().unwrap();
+        let sftp_session = create_uninitialized_sftp_session(&root, &SftpSessionConfig::default());
+
+        assert_eq!(
+            Response::build_error_response(1, Error::BadMessage),
+            sftp_session
+                .handle_request(Request::Read(request::read::Read {
+                    id: 1,
+                    handle: String::from("handle"),
+                    offset: 0,
+                    len: 4,
+                }))
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_read_request_with_absurd_length_returns_available_data() {
+        let root = TempDir::new().unwrap();
+        std::fs::create_dir_all(root.path().join("home/test")).unwrap();
+        std::fs::write(root.path().join("home/test/file.txt"), b"data").unwrap();
+
+        let sftp_session = create_sftp_session(&root, &SftpSessionConfig::default());
+        let handle = open_read_handle(&sftp_session, "/home/test/file.txt").await;
+
+        assert_eq!(
+            Response::Data(response::data::Data {
+                id: 2,
+                data: b"data".to_vec(),
+            }),
+            sftp_session
+                .handle_request(Request::Read(request::read::Read {
+                    id: 2,
+                    handle: handle.clone(),
+                    offset: 0,
+                    len: u32::MAX,
+                }))
+                .await
+        );
+
+        assert_eq!(
+            Response::Status(response::status::Status {
+                id: 3,
+                status_code: response::status::StatusCode::Eof,
+                error_message: String::from("End of file."),
+            }),
+            sftp_session
+                .handle_request(Request::Read(request::read::Read {
+                    id: 3,
+                    handle,
+                    offset: 4,
+                    len: u32::MAX,
+                }))
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_read_request_with_offset() {
+        let root = TempDir::new().unwrap();
+        std::fs::create_dir_all(root.path().join("home/test")).unwrap();
+        std::fs::write(root.path().join("home/test/file.txt"), b"hello world").unwrap();
+
+        let sftp_session = create_sftp_session(&root, &SftpSessionConfig::default());
+        let handle = open_read_handle(&sftp_session, "/home/test/file.txt").await;
+
+        assert_eq!(
+            Response::Data(response::data::Data {
+                id: 2,
+                data: b"world".to_vec(),
+            }),
+            sftp_session
+                .handle_request(Request::Read(request::read::Read {
+                    id: 2,
+                    handle: handle.clone(),
+                    offset: 6,
+                    len: 100,
+                }))
+                .await
+        );
+
+        assert_eq!(
+            Response::Data(response::data::Data {
+                id: 3,
+                data: b"hello".to_vec(),
+            }),
+            sftp_session
+                .handle_request(Request::Read(request::read::Read {
+                    id: 3,
+                    handle,
+                    offset: 0,
+                    len: 5,
+                }))
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_read_request_after_end_of_file() {
+        let root = TempDir::new().unwrap();
+        std::fs::create_dir_all(root.path().join("home/test")).unwrap();
+        std::fs::write(root.path().join("home/test/file.txt"), b"data").unwrap();
+
+        let sftp_session = create_sftp_session(&root, &SftpSessionConfig::default());
+        let handle = open_read_handle(&sftp_session, "/home/test/file.txt").await;
+
+        sftp_session
+            .handle_request(Request::Read(request::read::Read {
+                id: 2,
+                handle: handle.clone(),
+                offset: 0,
+                len: 100,
+            }))
+            .await;
+
+        for id in [3, 4] {
+            assert_eq!(
+                Response::Status(response::status::Status {
+                    id,
+                    status_code: response::status::StatusCode::Eof,
+                    error_message: String::from("End of file."),
+                }),
+                sftp_session
+                    .handle_request(Request::Read(request::read::Read {
+                        id,
+                        handle: handle.clone(),
+                        offset: 4,
+                        len: 100,
+                    }))
+                    .await
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handle_read_request_with_zero_length() {
+        let root = TempDir::new().unwrap();
+        std::fs::create_dir_all(root.path().join("home/test")).unwrap();
+        std::fs::write(root.path().join("home/test/file.txt"), "data").unwrap();
+
+        let sftp_session = create_sftp_session(&root, &SftpSessionConfig::default());
+
+        let handle = match sftp_session
+            .handle_request(build_open_request("/home/test/file.txt", false))
+            .await
+        {
+            Response::Handle(handle) => handle.handle,
+            response => panic!("Unexpected response {:?}", response),
+        };
+
+        // Reading at the end of the file would return an EOF status if storage were called.
+        assert_eq!(
+            Response::Data(response::data::Data {
+                id: 2,
+                data: vec![],
+            }),
+            sftp_session
+                .handle_request(Request::Read(request::read::Read {
+                    id: 2,
+                    handle,
+                    offset: 4,
+                    len: 0,
+                }))
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_read_request_with_zero_length_and_invalid_handle() {
+        let root = TempDir::new().unwrap();
+        let sftp_session = create_sftp_session(&root, &SftpSessionConfig::default());
+
+        assert_eq!(
+            Response::build_error_response(2, Error::InvalidHandle),
+            sftp_session
+                .handle_request(Request::Read(request::read::Read {
+                    id: 2,
+                    handle: String::from("missing"),
+                    offset: 0,
+                    len: 0,
+                }))
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_read_request_caps_length_at_max_read_length() {
+        let root = TempDir::new().unwrap();
+        std::fs::create_dir_all(root.path().join("home/test")).unwrap();
+        std::fs::write(root.path().join("home/test/file.txt"), vec![0; 4096]).unwrap();
+
+        let sftp_session = create_sftp_session(
+            &root,
+            &SftpSessionConfig {
+                max_read_length: 1024,
+                ..Default::default()
+            },
+        );
+        let handle = open_read_handle(&sftp_session, "/home/test/file.txt").await;
+
+        let response = sftp_session
+            .handle_request(Request::Read(request::read::Read {
+                id: 2,
+                handle,
+                offset: 0,
+                len: u32::MAX,
+            }))
+            .await;
+
+        match response {
+            Response::Data(data) => assert_eq!(1024, data.data.len()),
+            _ => panic!("Expected a data response, but received {:?}", response),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handle_request_records_user_on_events() {
+        let root = TempDir::new().unwrap();
+        let sftp_session = create_sftp_session(&root, &SftpSessionConfig::default());
+
+        let log_writer = LogWriter::default();
+        let make_writer = log_writer.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(Level::DEBUG)
+            .with_ansi(false)
+            .with_writer(move || make_writer.clone())
+            .finish();
+
+        let _default_guard = tracing::subscriber::set_default(subscriber);
+
+        sftp_session
+            .handle_request(Request::Read(request::read::Read {
+                id: 1,
+                handle: String::from("missing"),
+                offset: 0,
+                len: 100,
+            }))
+            .await;
+
+        let logs = String::from_utf8(log_writer.0.lock().unwrap().clone()).unwrap();
+        let error_event = logs
+            .lines()
+            .find(|line| line.contains("Received error while processing request"))
+            .unwrap();
+
+        assert!(error_event.contains("handle_request{"));
+        assert!(error_event.contains("user=test}"));
+    }
+
+    #[tokio::test]
+    async fn test_handle_open_request_with_missing_file_for_read() {
+        let root = TempDir::new().unwrap();
+        std::fs::create_dir_all(root.path().join("home/test")).unwrap();
+
+        let sftp_session = create_sftp_session(&root, &SftpSessionConfig::default());
+
+        assert_eq!(
+            Response::Status(response::status::Status {
+                id: 1,
+                status_code: response::status::StatusCode::NoSuchFile,
+                error_message: String::from("The requested file was not found."),
+            }),
+            sftp_session
+                .handle_request(Request::Open(request::open::Open {
+                    id: 1,
+                    filename: String::from("/home/test/missing.txt"),
+                    file_attributes: FileAttributes::default(),
+                    open_options: request::open::OpenOptions {
+                        read: true,
+                        write: false,
+                        create: false,
+                        create_new_only: false,
+                        append: false,
+                        truncate: false,
+                    },
+                }))
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_open_request_for_write_to_existing_file() {
+        let root = TempDir::new().unwrap();
+        std::fs::create_dir_all(root.path().join("home/test")).unwrap();
+        std::fs::write(root.path().join("home/test/file.txt"), b"hello world").unwrap();
+
+        let sftp_session = create_sftp_session(&root, &SftpSessionConfig::default());
+        let handle = open_existing_handle(&sftp_session, "/home/test/file.txt", false).await;
+
+        write_and_close(&sftp_session, handle, 6, "there").await;
+
+        assert_eq!(
+            "hello there",
+            std::fs::read_to_string(root.path().join("home/test/file.txt")).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_open_request_for_write_to_missing_file() {
+        let root = TempDir::new().unwrap();
+        std::fs::create_dir_all(root.path().join("home/test")).unwrap();
+
+        let sftp_session = create_sftp_session(&root, &SftpSessionConfig::default());
+
+        assert_eq!(
+            Response::build_error_response(1, Error::NoSuchFile),
+            sftp_session
+                .handle_request(Request::Open(request::open::Open {
+                    id: 1,
+                    filename: String::from("/home/test/missing.txt"),
+                    file_attributes: FileAttributes::default(),
+                    open_options: request::open::OpenOptions {
+                        read: false,
+                        write: true,
+                        create: false,
+                        create_new_only: false,
+                        append: false,
+                        truncate: false,
+                    },
+                }))
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_open_request_for_exclusive_create_of_existing_file() {
+        let root = TempDir::new().unwrap();
+        std::fs::create_dir_all(root.path().join("home/test")).unwrap();
+        std::fs::write(root.path().join("home/test/file.lock"), b"owner").unwrap();
+
+        let sftp_session = create_sftp_session(&root, &SftpSessionConfig::default());
+
+        assert_eq!(
+            Response::build_error_response(1, Error::AlreadyExists),
+            sftp_session
+                .handle_request(build_exclusive_create_request("/home/test/file.lock"))
+                .await
+        );
+        assert_eq!(
+            "owner",
+            std::fs::read_to_string(root.path().join("home/test/file.lock")).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_open_request_for_exclusive_create_of_missing_file() {
+        let root = TempDir::new().unwrap();
+        std::fs::create_dir_all(root.path().join("home/test")).unwrap();
+
+        let sftp_session = create_sftp_session(&root, &SftpSessionConfig::default());
+
+        let response = sftp_session
+            .handle_request(build_exclusive_create_request("/home/test/file.lock"))
+            .await;
+
+        let handle = match response {
+            Response::Handle(handle) => handle.handle,
+            _ => panic!("Expected a handle response, but received {:?}", response),
+        };
+
+        write_and_close(&sftp_session, handle, 0, "owner").await;
+
+        assert_eq!(
+            "owner",
+            std::fs::read_to_string(root.path().join("home/test/file.lock")).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_open_request_for_append() {
+        let root = TempDir::new().unwrap();
+        std::fs::create_dir_all(root.path().join("home/test")).unwrap();
+        std::fs::write(root.path().join("home/test/file.txt"), b"hello").unwrap();
+
+        let sftp_session = create_sftp_session(&root, &SftpSessionConfig::default());
+
+        let response = sftp_session
+            .handle_request(Request::Open(request::open::Open {
+                id: 1,
+                filename: String::from("/home/test/file.txt"),
+                file_attributes: FileAttributes::default(),
+                open_options: request::open::OpenOptions {
+                    read: false,
+                    write: true,
+                    create: false,
+                    create_new_only: false,
+                    append: true,
+                    truncate: false,
+                },
+            }))
+            .await;
+
+        let handle = match response {
+            Response::Handle(handle) => handle.handle,
+            _ => panic!("Expected a handle response, but received {:?}", response),
+        };
+
+        write_and_close(&sftp_session, handle, 0, " world").await;
+
+        assert_eq!(
+            "hello world",
+            std::fs::read_to_string(root.path().join("home/test/file.txt")).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_open_request_for_read_and_write() {
+        let root = TempDir::new().unwrap();
+        std::fs::create_dir_all(root.path().join("home/test")).unwrap();
+        std::fs::write(root.path().join("home/test/file.txt"), b"hello world").unwrap();
+
+        let sftp_session = create_sftp_session(&root, &SftpSessionConfig::default());
+        let handle = open_existing_handle(&sftp_session, "/home/test/file.txt", true).await;
+
+        assert_eq!(
+            Response::Data(response::data::Data {
+                id: 2,
+                data: b"hello world".to_vec(),
+            }),
+            sftp_session
+                .handle_request(Request::Read(request::read::Read {
+                    id: 2,
+                    handle: handle.clone(),
+                    offset: 0,
+                    len: 100,
+                }))
+                .await
+        );
+
+        write_and_close(&sftp_session, handle, 0, "HELLO").await;
+
+        assert_eq!(
+            "HELLO world",
+            std::fs::read_to_string(root.path().join("home/test/file.txt")).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_open_request_for_write_outside_home() {
+        let root = TempDir::new().unwrap();
+        std::fs::create_dir_all(root.path().join("home/other")).unwrap();
+        std::fs::write(root.path().join("home/other/file.txt"), b"hello").unwrap();
+
+        let sftp_session = create_sftp_session(&root, &SftpSessionConfig::default());
+
+        assert_eq!(
+            Response::build_error_response(1, Error::PermissionDenied),
+            sftp_session
+                .handle_request(Request::Open(request::open::Open {
+                    id: 1,
+                    filename: String::from("/home/other/file.txt"),
+                    file_attributes: FileAttributes::default(),
+                    open_options: request::open::OpenOptions {
+                        read: true,
+                        write: true,
+                        create: false,
+                        create_new_only: false,
+                        append: false,
+                        truncate: false,
+                    },
+                }))
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_symlink_and_readlink_requests() {
+        let root = TempDir::new().unwrap();
+        std::fs::create_dir_all(root.path().join("home/test")).unwrap();
+
+        let sftp_session = create_sftp_session(&root, &SftpSessionConfig::default());
+
+        assert_eq!(
+            SftpSession::build_successful_response(1),
+            sftp_session
+                .handle_request(Request::Symlink(request::symlink::Symlink {
+                    id: 1,
+                    link_path: String::from("target.txt"),
+                    target_path: String::from("/home/test/link"),
+                }))
+                .await
+        );
+
+        assert_eq!(
+            Response::Name(response::name::Name {
+                id: 2,
+                files: vec![response::name::File {
+                    file_name: String::from("target.txt"),
+                    file_attributes: FileAttributes::default(),
+                }],
+            }),
+            sftp_session
+                .handle_request(Request::Readlink(request::path::Path {
+                    id: 2,
+                    path: String::from("/home/test/link"),
+                }))
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_stat_request_follows_symlink() {
+        let root = TempDir::new().unwrap();
+        std::fs::create_dir_all(root.path().join("home/test/dir")).unwrap();
+        std::fs::write(root.path().join("home/test/target.txt"), b"data").unwrap();
+        std::fs::write(root.path().join("home/test/link.draylink"), "dir/link").unwrap();
+        std::fs::write(
+            root.path().join("home/test/dir/link.draylink"),
+            "../target.txt",
+        )
+        .unwrap();
+
+        let sftp_session = create_sftp_session(&root, &SftpSessionConfig::default());
+
+        let response = sftp_session
+            .handle_request(Request::Stat(request::path::Path {
+                id: 1,
+                path: String::from("/home/test/link"),
+            }))
+            .await;
+
+        match response {
+            Response::Attrs(attrs) => {
+                assert_eq!(Some(4), attrs.file_attributes.size);
+                assert!(!attrs.file_attributes.is_symlink());
+            }
+            _ => panic!("Expected an attrs response, but received {:?}", response),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handle_lstat_request_does_not_follow_symlink() {
+        let root = TempDir::new().unwrap();
+        std::fs::create_dir_all(root.path().join("home/test")).unwrap();
+        std::fs::write(root.path().join("home/test/target.txt"), b"data").unwrap();
+        std::fs::write(root.path().join("home/test/link.draylink"), "target.txt").unwrap();
+
+        let sftp_session = create_sftp_session(&root, &SftpSessionConfig::default());
+
+        let response = sftp_session
+            .handle_request(Request::Lstat(request::path::Path {
+                id: 1,
+                path: String::from("/home/test/link"),
+            }))
+            .await;
+
+        match response {
+            Response::Attrs(attrs) => assert!(attrs.file_attributes.is_symlink()),
+            _ => panic!("Expected an attrs response, but received {:?}", response),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handle_stat_request_with_symlink_loop() {
+        let root = TempDir::new().unwrap();
+        std::fs::create_dir_all(root.path().join("home/test")).unwrap();
+        std::fs::write(root.path().join("home/test/link.draylink"), "link").unwrap();
+
+        let sftp_session = create_sftp_session(&root, &SftpSessionConfig::default());
+
+        assert_eq!(
+            Response::build_error_response(1, Error::SymlinkLoop),
+            sftp_session
+                .handle_request(Request::Stat(request::path::Path {
+                    id: 1,
+                    path: String::from("/home/test/link"),
+                }))
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_stat_request_with_symlink_outside_home() {
+        let root = TempDir::new().unwrap();
+        std::fs::create_dir_all(root.path().join("home/test")).unwrap();
+        std::fs::create_dir_all(root.path().join("home/other")).unwrap();
+        std::fs::write(root.path().join("home/other/secret.txt"), b"data").unwrap();
+        std::fs::write(
+            root.path().join("home/test/link.draylink"),
+            "/home/other/secret.txt",
+        )
+        .unwrap();
+
+        let sftp_session = create_sftp_session(&root, &SftpSessionConfig::default());
+
+        assert_eq!(
+            Response::build_error_response(1, Error::PermissionDenied),
+            sftp_session
+                .handle_request(Request::Stat(request::path::Path {
+                    id: 1,
+                    path: String::from("/home/test/link"),
+                }))
+                .await
+        );
+    }
+
+    #[test]
+    fn test_resolve_symlink_target() {
+        assert_eq!(
+            "/home/test/target.txt",
+            resolve_symlink_target("/home/test/link", "target.txt")
+        );
+        assert_eq!(
+            "/home/target.txt",
+            resolve_symlink_target("/home/test/link", "../target.txt")
+        );
+        assert_eq!(
+            "/srv/target.txt",
+            resolve_symlink_target("/home/test/link", "/srv/target.txt")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_symlink_request_with_target_outside_home() {
+        let root = TempDir::new().unwrap();
+        std::fs::create_dir_all(root.path().join("home/test")).unwrap();
+
+        let sftp_session = create_sftp_session(&root, &SftpSessionConfig::default());
+
+        let response = sftp_session
+            .handle_request(Request::Symlink(request::symlink::Symlink {
+                id: 1,
+                link_path: String::from("/home/other/target.txt"),
+                target_path: String::from("/home/test/link"),
+            }))
+            .await;
+
+        match response {
+            Response::Status(status) => assert_eq!(
+                response::status::StatusCode::PermissionDenied,
+                status.status_code
+            ),
+            _ => panic!("Expected a status response, but received {:?}", response),
+        }
+        assert!(!root.path().join("home/test/link.draylink").exists());
+    }
+
+    #[test]
+    fn test_get_home_substitutes_user() {
+        assert_eq!(
+            Ok(String::from("/tenants/test/files")),
+            get_home("/tenants/{user}/files", "test")
+        );
+    }
+
+    #[test]
+    fn test_get_home_with_default_template() {
+        assert_eq!(
+            Ok(String::from("/home/test")),
+            get_home(DEFAULT_HOME_TEMPLATE, "test")
+        );
+    }
+
+    #[test]
+    fn test_get_home_rejects_unsafe_users() {
+        for user in ["", ".", "..", "../other", "test/../other", "te\0st"] {
+            assert_eq!(
+                Err(Error::PermissionDenied),
+                get_home(DEFAULT_HOME_TEMPLATE, user),
+                "{}",
+                user
+            );
+        }
+    }
+
+    #[test]
+    fn test_check_permission_with_read_only_prefix() {
+        let root = TempDir::new().unwrap();
+        let sftp_session = create_sftp_session(
+            &root,
+            &SftpSessionConfig {
+                home_template: String::from("/tenants/{user}"),
+                read_only_prefixes: vec![String::from("/shared")],
+                ..Default::default()
+            },
+        );
+
+        assert_eq!(
+            Ok(()),
+            sftp_session.authorize(Operation::Write, "/tenants/test/file")
+        );
+        assert_eq!(
+            Ok(()),
+            sftp_session.authorize(Operation::Read, "/tenants/test/file")
+        );
+        assert_eq!(
+            Ok(()),
+            sftp_session.authorize(Operation::Read, "/shared/file")
+        );
+        assert_eq!(
+            Err(Error::PermissionDenied),
+            sftp_session.authorize(Operation::Write, "/shared/file")
+        );
+        assert_eq!(
+            Err(Error::PermissionDenied),
+            sftp_session.authorize(Operation::Read, "/tenants/other/file")
+        );
+        assert_eq!(
+            Err(Error::PermissionDenied),
+            sftp_session.authorize(Operation::Read, "/home/test/file")
+        );
+    }
+
+    #[test]
+    fn test_check_permission_with_read_only_user() {
+        let root = TempDir::new().unwrap();
+        let sftp_session = create_sftp_session(
+            &root,
+            &SftpSessionConfig {
+                read_only_users: vec![String::from("test")],
+                ..Default::default()
+            },
+        );
+
+        assert_eq!(
+            Ok(()),
+            sftp_session.authorize(Operation::Read, "/home/test/file")
+        );
+        assert_eq!(
+            Err(Error::PermissionDenied),
+            sftp_session.authorize(Operation::Write, "/home/test/file")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_request_with_read_only_user() {
+        let root = TempDir::new().unwrap();
+        std::fs::create_dir_all(root.path().join("home/test/dir")).unwrap();
+        std::fs::write(root.path().join("home/test/file.txt"), "data").unwrap();
+
+        let sftp_session = create_sftp_session(
+            &root,
+            &SftpSessionConfig {
+                read_only_users: vec![String::from("other"), String::from("test")],
+                ..Default::default()
+            },
+        );
+
+        let handle = match sftp_session
+            .handle_request(Request::Opendir(request::path::Path {
+                id: 1,
+                path: String::from("/home/test"),
+            }))
+            .await
+        {
+            Response::Handle(handle) => handle.handle,
+            response => panic!("Unexpected response {:?}", response),
+        };
+
+        match sftp_session
+            .handle_request(Request::Readdir(request::handle::Handle { id: 2, handle }))
+            .await
+        {
+            Response::Name(name) => assert_eq!(2, name.files.len()),
+            response => panic!("Unexpected response {:?}", response),
+        };
+
+        let handle = match sftp_session
+            .handle_request(build_open_request("/home/test/file.txt", false))
+            .await
+        {
+            Response::Handle(handle) => handle.handle,
+            response => panic!("Unexpected response {:?}", response),
+        };
+
+        match sftp_session
+            .handle_request(Request::Read(request::read::Read {
+                id: 3,
+                handle: handle.clone(),
+                offset: 0,
+                len: 4,
+            }))
+            .await
+        {
+            Response::Data(data) => assert_eq!(b"data", &data.data[..]),
+            response => panic!("Unexpected response {:?}", response),
+        };
+
+        let denied_requests = vec![
+            build_open_request("/home/test/new.txt", true),
+            Request::Remove(request::path::Path {
+                id: 1,
+                path: String::from("/home/test/file.txt"),
+            }),
+            Request::Mkdir(request::path_attributes::PathAttributes {
+                id: 1,
+                path: String::from("/home/test/new"),
+                file_attributes: FileAttributes::default(),
+            }),
+            Request::Rmdir(request::path::Path {
+                id: 1,
+                path: String::from("/home/test/dir"),
+            }),
+            Request::Rename(request::rename::Rename {
+                id: 1,
+                old_path: String::from("/home/test/file.txt"),
+                new_path: String::from("/home/test/renamed.txt"),
+            }),
+            Request::Setstat(request::path_attributes::PathAttributes {
+                id: 1,
+                path: String::from("/home/test/file.txt"),
+                file_attributes: FileAttributes::default(),
+            }),
+            Request::Fsetstat(request::handle_attributes::HandleAttributes {
+                id: 1,
+                handle,
+                file_attributes: FileAttributes::default(),
+            }),
+        ];
+
+        for request in denied_requests {
+            assert_eq!(
+                Response::build_error_response(1, Error::PermissionDenied),
+                sftp_session.handle_request(request).await
+            );
+        }
+
+        assert!(root.path().join("home/test/file.txt").exists());
+        assert!(!root.path().join("home/test/new.txt").exists());
+        assert!(root.path().join("home/test/dir").exists());
+    }
+
+    #[tokio::test]
+    async fn test_handle_fsetstat_request_authorizes_handle_path() {
+        let root = TempDir::new().unwrap();
+        std::fs::create_dir_all(root.path().join("home/test")).unwrap();
+        std::fs::create_dir_all(root.path().join("shared")).unwrap();
+        std::fs::write(root.path().join("home/test/file.txt"), "data").unwrap();
+        std::fs::write(root.path().join("shared/file.txt"), "data").unwrap();
+
+        let sftp_session = create_sftp_session(
+            &root,
+            &SftpSessionConfig {
+                read_only_prefixes: vec![String::from("/shared")],
+                ..Default::default()
+            },
+        );
+
+        for (path, expected_response) in [
+            (
+                "/home/test/file.txt",
+                SftpSession::build_successful_response(2),
+            ),
+            (
+                "/shared/file.txt",
+                Response::build_error_response(2, Error::PermissionDenied),
+            ),
+        ] {
+            let handle = match sftp_session
+                .handle_request(build_open_request(path, false))
+                .await
+            {
+                Response::Handle(handle) => handle.handle,
+                response => panic!("Unexpected response {:?}", response),
+            };
+
+            assert_eq!(
+                expected_response,
+                sftp_session
+                    .handle_request(Request::Fsetstat(
+                        request::handle_attributes::HandleAttributes {
+                            id: 2,
+                            handle,
+                            file_attributes: FileAttributes::default(),
+                        }
+                    ))
+                    .await
+            );
+        }
+
+        assert_eq!(
+            Response::build_error_response(3, Error::InvalidHandle),
+            sftp_session
+                .handle_request(Request::Fsetstat(
+                    request::handle_attributes::HandleAttributes {
+                        id: 3,
+                        handle: String::from("missing"),
+                        file_attributes: FileAttributes::default(),
+                    }
+                ))
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_request_with_custom_authorizer() {
+        struct DenyRemoveAuthorizer;
+
+        impl Authorizer for DenyRemoveAuthorizer {
+            fn authorize(
+                &self,
+                _user: &str,
+                operation: Operation,
+                _path: &str,
+            ) -> Result<(), Error> {
+                match operation {
+                    Operation::Remove => Err(Error::PermissionDenied),
+                    _ => Ok(()),
+                }
+            }
+        }
+
+        let root = TempDir::new().unwrap();
+        std::fs::create_dir_all(root.path().join("shared")).unwrap();
+        std::fs::write(root.path().join("shared/file.txt"), "data").unwrap();
+
+        let sftp_session = create_sftp_session(&root, &SftpSessionConfig::default())
+            .with_authorizer(Arc::new(DenyRemoveAuthorizer));
+
+        assert_eq!(
+            Response::build_error_response(1, Error::PermissionDenied),
+            sftp_session
+                .handle_request(Request::Remove(request::path::Path {
+                    id: 1,
+                    path: String::from("/shared/file.txt"),
+                }))
+                .await
+        );
+
+        assert_eq!(
+            SftpSession::build_successful_response(2),
+            sftp_session
+                .handle_request(Request::Mkdir(request::path_attributes::PathAttributes {
+                    id: 2,
+                    path: String::from("/shared/dir"),
+                    file_attributes: FileAttributes::default(),
+                }))
+                .await
+        );
+
+        assert!(root.path().join("shared/file.txt").exists());
+        assert!(root.path().join("shared/dir").exists());
+    }
+
+    fn build_open_request(filename: &str, write: bool) -> Request {
+        Request::Open(request::open::Open {
+            id: 1,
+            filename: String::from(filename),
+            file_attributes: FileAttributes::default(),
+            open_options: request::open::OpenOptions {
+                read: !write,
+                write,
+                append: false,
+                create: write,
+                truncate: write,
+                create_new_only: false,
+            },
+        })
+    }
+
+    #[test]
+    fn test_check_permission_rejects_path_traversal() {
+        let root = TempDir::new().unwrap();
+        let sftp_session = create_sftp_session(
+            &root,
+            &SftpSessionConfig {
+                read_only_prefixes: vec![String::from("/shared")],
+                ..Default::default()
+            },
+        );
+
+        for path in [
+            "/home/test/../other/secret",
+            "/home/test/../../etc/passwd",
+            "/home/test/./../test2",
+            "/shared/../home/other",
+        ] {
+            assert_eq!(
+                Err(Error::PermissionDenied),
+                sftp_session.authorize(Operation::Write, path),
+                "{}",
+                path
+            );
+            assert_eq!(
+                Err(Error::PermissionDenied),
+                sftp_session.authorize(Operation::Read, path),
+                "{}",
+                path
+            );
+        }
+
+        assert_eq!(
+            Ok(()),
+            sftp_session.authorize(Operation::Write, "/home/test/dir/../file")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_request_rejects_path_traversal() {
+        let root = TempDir::new().unwrap();
+        std::fs::create_dir_all(root.path().join("home/other")).unwrap();
+        std::fs::write(root.path().join("home/other/secret"), b"secret").unwrap();
+
+        let sftp_session = create_sftp_session(&root, &SftpSessionConfig::default());
+        let path = String::from("/home/test/../other/secret");
+
+        let requests = vec![
+            Request::Open(request::open::Open {
+                id: 1,
+                filename: path.clone(),
+                file_attributes: FileAttributes::default(),
+                open_options: request::open::OpenOptions {
+                    read: true,
+                    write: false,
+                    create: false,
+                    create_new_only: false,
+                    append: false,
+                    truncate: false,
+                },
+            }),
+            Request::Stat(request::path::Path {
+                id: 1,
+                path: path.clone(),
+            }),
+            Request::Lstat(request::path::Path {
+                id: 1,
+                path: path.clone(),
+            }),
+            Request::Remove(request::path::Path {
+                id: 1,
+                path: path.clone(),
+            }),
+            Request::Mkdir(request::path_attributes::PathAttributes {
+                id: 1,
+                path: path.clone(),
+                file_attributes: FileAttributes::default(),
+            }),
+            Request::Rmdir(request::path::Path {
+                id: 1,
+                path: path.clone(),
+            }),
+            Request::Opendir(request::path::Path {
+                id: 1,
+                path: String::from("/home/test/../other"),
+            }),
+            Request::Rename(request::rename::Rename {
+                id: 1,
+                old_path: path.clone(),
+                new_path: String::from("/home/test/secret"),
+            }),
+        ];
+
+        for request in requests {
+            assert_eq!(
+                Response::Status(response::status::Status {
+                    id: 1,
+                    status_code: response::status::StatusCode::PermissionDenied,
+                    error_message: String::from(
+                        "The client has insufficient privileges to perform the requested operation.",
+                    ),
+                }),
+                sftp_session.handle_request(request).await
+            );
+        }
+
+        assert!(root.path().join("home/other/secret").exists());
+    }
+
+    #[tokio::test]
+    async fn test_handle_request_with_invalid_filenames() {
+        let root = TempDir::new().unwrap();
+        let sftp_session = create_sftp_session(&root, &SftpSessionConfig::default());
+
+        let invalid_paths = vec![
+            String::from("/home/test/file\0.txt"),
+            String::from("/home/test/file\n.txt"),
+            format!("/home/test/{}", "a".repeat(256)),
+        ];
+
+        for path in invalid_paths {
+            let requests = vec![
+                Request::Open(request::open::Open {
+                    id: 1,
+                    filename: path.clone(),
+                    open_options: request::open::OpenOptions {
+                        read: false,
+                        write: true,
+                        create: true,
+                        create_new_only: false,
+                        append: false,
+                        truncate: true,
+                    },
+                    file_attributes: FileAttributes::default(),
+                }),
+                Request::Mkdir(request::path_attributes::PathAttributes {
+                    id: 1,
+                    path: path.clone(),
+                    file_attributes: FileAttributes::default(),
+                }),
+                Request::Rename(request::rename::Rename {
+                    id: 1,
+                    old_path: String::from("/home/test/file.txt"),
+                    new_path: path.clone(),
+                }),
+            ];
+
+            for request in requests {
+                assert_eq!(
+                    Response::Status(response::status::Status {
+                        id: 1,
+                        status_code: response::status::StatusCode::BadMessage,
+                        error_message: String::from("The client sent a bad message."),
+                    }),
+                    sftp_session.handle_request(request).await
+                );
+            }
+        }
+
+        assert!(!root.path().join("home/test").exists());
+    }
+
+    #[test]
+    fn test_validate_filename_at_max_path_length() {
+        let prefix = "/home/test/";
+        let names = "a/".repeat((MAX_PATH_LENGTH - prefix.len()) / 2);
+        let path = format!("{}{}", prefix, names.trim_end_matches('/'));
+        let path = format!("{}{}", path, "b".repeat(MAX_PATH_LENGTH - path.len()));
+
+        assert_eq!(MAX_PATH_LENGTH, path.len());
+        assert_eq!(Ok(()), validate_filename(&path, usize::MAX));
+        assert_eq!(
+            Err(Error::PathTooLong),
+            validate_filename(&format!("{}b", path), usize::MAX)
+        );
+    }
+
+    #[test]
+    fn test_validate_filename_at_max_path_depth() {
+        assert_eq!(Ok(()), validate_filename("/home/test/a/b", 4));
+        assert_eq!(Ok(()), validate_filename("/home/test/a/b/../c", 4));
+        assert_eq!(
+            Err(Error::PathTooLong),
+            validate_filename("/home/test/a/b/c", 4)
+        );
+    }
+
+    #[test]
+    fn test_validate_filename_with_symlink_suffix() {
+        assert_eq!(
+            Err(Error::PermissionDenied),
+            validate_filename("/home/test/foo.draylink", usize::MAX)
+        );
+        assert_eq!(
+            Err(Error::PermissionDenied),
+            validate_filename("/home/test/foo.draylink/.", usize::MAX)
+        );
+        assert_eq!(
+            Ok(()),
+            validate_filename("/home/test/foo.draylink.txt", usize::MAX)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_open_request_with_symlink_suffix() {
+        let root = TempDir::new().unwrap();
+        std::fs::create_dir_all(root.path().join("home/test")).unwrap();
+
+        let sftp_session = create_sftp_session(&root, &SftpSessionConfig::default());
+
+        let response = sftp_session
+            .handle_request(Request::Open(request::open::Open {
+                id: 1,
+                filename: String::from("/home/test/foo.draylink"),
+                file_attributes: FileAttributes::default(),
+                open_options: request::open::OpenOptions {
+                    read: false,
+                    write: true,
+                    create: true,
+                    create_new_only: false,
+                    append: false,
+                    truncate: true,
+                },
+            }))
+            .await;
+
+        assert_eq!(
+            Response::build_error_response(1, Error::PermissionDenied),
+            response
+        );
+        assert!(!root.path().join("home/test/foo.draylink").exists());
+    }
+
+    #[tokio::test]
+    async fn test_handle_extended_request_posix_rename_with_symlink_suffix() {
+        let root = TempDir::new().unwrap();
+        std::fs::create_dir_all(root.path().join("home/test")).unwrap();
+        std::fs::write(root.path().join("home/test/file.txt"), "/home/test/secret").unwrap();
+        std::fs::write(root.path().join("home/test/link.draylink"), "/home/test").unwrap();
+
+        let sftp_session = create_sftp_session(&root, &SftpSessionConfig::default());
+
+        for (old_path, new_path) in [
+            ("/home/test/file.txt", "/home/test/foo.draylink"),
+            ("/home/test/link.draylink", "/home/test/file2.txt"),
+        ] {
+            assert_eq!(
+                Response::build_error_response(1, Error::PermissionDenied),
+                sftp_session
+                    .handle_request(Request::Extended(request::extended::Extended {
+                        id: 1,
+                        extended_request: request::extended::ExtendedRequest::PosixRename {
+                            old_path: String::from(old_path),
+                            new_path: String::from(new_path),
+                        },
+                    }))
+                    .await
+            );
+        }
+
+        assert!(root.path().join("home/test/file.txt").exists());
+        assert!(root.path().join("home/test/link.draylink").exists());
+    }
+
+    #[tokio::test]
+    async fn test_handle_mkdir_request_exceeding_max_path_depth() {
+        let root = TempDir::new().unwrap();
+        let sftp_session = create_sftp_session(
+            &root,
+            &SftpSessionConfig {
+                max_path_depth: 3,
+                ..Default::default()
+            },
+        );
+
+        assert_eq!(
+            SftpSession::build_successful_response(1),
+            sftp_session
+                .handle_request(Request::Mkdir(request::path_attributes::PathAttributes {
+                    id: 1,
+                    path: String::from("/home/test/dir"),
+                    file_attributes: FileAttributes::default(),
+                }))
+                .await
+        );
+
+        assert_eq!(
+            Response::Status(response::status::Status {
+                id: 2,
+                status_code: response::status::StatusCode::Failure,
+                error_message: String::from("The path exceeds the maximum length or depth."),
+            }),
+            sftp_session
+                .handle_request(Request::Mkdir(request::path_attributes::PathAttributes {
+                    id: 2,
+                    path: String::from("/home/test/dir/nested"),
+                    file_attributes: FileAttributes::default(),
+                }))
+                .await
+        );
+
+        assert!(!root.path().join("home/test/dir/nested").exists());
+    }
+
+    #[tokio::test]
+    async fn test_handle_mkdir_request_with_unicode_filename() {
+        let root = TempDir::new().unwrap();
+        let sftp_session = create_sftp_session(&root, &SftpSessionConfig::default());
+
+        assert_eq!(
+            SftpSession::build_successful_response(1),
+            sftp_session
+                .handle_request(Request::Mkdir(request::path_attributes::PathAttributes {
+                    id: 1,
+                    path: String::from("/home/test/my folder ünïcødé 📁"),
+                    file_attributes: FileAttributes::default(),
+                }))
+                .await
+        );
+
+        assert!(root.path().join("home/test/my folder ünïcødé 📁").is_dir());
+    }
+
+    #[tokio::test]
+    async fn test_handle_mkdir_request_with_relative_path() {
+        let root = TempDir::new().unwrap();
+        std::fs::create_dir_all(root.path().join("home/test")).unwrap();
+        let sftp_session = create_sftp_session(&root, &SftpSessionConfig::default());
+
+        assert_eq!(
+            SftpSession::build_successful_response(1),
+            sftp_session
+                .handle_request(Request::Mkdir(request::path_attributes::PathAttributes {
+                    id: 1,
+                    path: String::from("dir"),
+                    file_attributes: FileAttributes::default(),
+                }))
+                .await
+        );
+
+        assert!(root.path().join("home/test/dir").is_dir());
+    }
+
+    #[tokio::test]
+    async fn test_handle_open_request_with_parent_directory_component() {
+        let root = TempDir::new().unwrap();
+        std::fs::create_dir_all(root.path().join("home/test")).unwrap();
+        let sftp_session = create_sftp_session(&root, &SftpSessionConfig::default());
+
+        // Storage receives the normalized path that was authorized, rather than the path with the
+        // `..` component.
+        let handle = open_write_handle(&sftp_session, "/home/test/sub/../file.txt").await;
+
+        assert_eq!(
+            SftpSession::build_successful_response(2),
+            sftp_session
+                .handle_request(Request::Close(request::handle::Handle { id: 2, handle }))
+                .await
+        );
+        assert!(root.path().join("home/test/file.txt").is_file());
+        assert!(!root.path().join("home/test/sub").exists());
+    }
+
+    #[tokio::test]
+    async fn test_handle_opendir_request_with_current_directory() {
+        let root = TempDir::new().unwrap();
+        let sftp_session = create_sftp_session(&root, &SftpSessionConfig::default());
+
+        std::fs::create_dir_all(root.path().join("home/test")).unwrap();
+        std::fs::write(root.path().join("home/test/file.txt"), "").unwrap();
+        std::fs::write(root.path().join("root.txt"), "").unwrap();
+
+        for path in [".", "", "./"] {
+            let handle = match sftp_session
+                .handle_request(Request::Opendir(request::path::Path {
+                    id: 1,
+                    path: String::from(path),
+                }))
+                .await
+            {
+                Response::Handle(handle) => handle.handle,
+                response => panic!("Unexpected response {:?}", response),
+            };
+
+            let file_names = match sftp_session
+                .handle_request(Request::Readdir(request::handle::Handle { id: 2, handle }))
+                .await
+            {
+                Response::Name(name) => name
+                    .files
+                    .into_iter()
+                    .map(|file| file.file_name)
+                    .collect::<Vec<String>>(),
+                response => panic!("Unexpected response {:?}", response),
+            };
+
+            assert_eq!(vec![String::from("file.txt")], file_names);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handle_opendir_request_with_relative_path() {
+        let root = TempDir::new().unwrap();
+        let sftp_session = create_sftp_session(&root, &SftpSessionConfig::default());
+
+        std::fs::create_dir_all(root.path().join("home/test/dir")).unwrap();
+        std::fs::create_dir_all(root.path().join("home/other")).unwrap();
+
+        assert!(matches!(
+            sftp_session
+                .handle_request(Request::Opendir(request::path::Path {
+                    id: 1,
+                    path: String::from("dir"),
+                }))
+                .await,
+            Response::Handle(_)
+        ));
+        assert_eq!(
+            Response::build_error_response(1, Error::PermissionDenied),
+            sftp_session
+                .handle_request(Request::Opendir(request::path::Path {
+                    id: 1,
+                    path: String::from("../other"),
+                }))
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_realpath_request_returns_templated_home() {
+        let root = TempDir::new().unwrap();
+        let sftp_session = create_sftp_session(
+            &root,
+            &SftpSessionConfig {
+                home_template: String::from("/tenants/{user}"),
+                ..Default::default()
+            },
+        );
+
+        let response = sftp_session
+            .handle_request(Request::Realpath(request::realpath::Realpath {
+                id: 1,
+                path: String::from("."),
+                control_byte: None,
+                compose_paths: vec![],
+            }))
+            .await;
+
+        match response {
+            Response::Name(name) => assert_eq!("/tenants/test", name.files[0].file_name),
+            _ => panic!("Expected a name response, but received {:?}", response),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handle_realpath_request_with_compose_paths() {
+        let root = TempDir::new().unwrap();
+        let sftp_session = create_sftp_session(&root, &SftpSessionConfig::default());
+
+        let name = handle_realpath_request(
+            &sftp_session,
+            ".",
+            Some(request::realpath::ControlByte::NoCheck),
+            vec!["dir", "../file.txt"],
+        )
+        .await
+        .unwrap();
+
+        assert_eq!("/home/test/file.txt", name.file_name);
+        assert_eq!(FileAttributes::default(), name.file_attributes);
+    }
+
+    #[tokio::test]
+    async fn test_handle_realpath_request_resolves_relative_paths_from_home() {
+        let root = TempDir::new().unwrap();
+        let sftp_session = create_sftp_session(&root, &SftpSessionConfig::default());
+
+        for (path, expected_path) in [
+            ("", "/home/test"),
+            ("foo", "/home/test/foo"),
+            ("./foo", "/home/test/foo"),
+        ] {
+            let name = handle_realpath_request(
+                &sftp_session,
+                path,
+                Some(request::realpath::ControlByte::NoCheck),
+                vec![],
+            )
+            .await
+            .unwrap();
+
+            assert_eq!(expected_path, name.file_name);
+        }
+
+        let name = handle_realpath_request(
+            &sftp_session,
+            "",
+            Some(request::realpath::ControlByte::NoCheck),
+            vec!["foo"],
+        )
+        .await
+        .unwrap();
+
+        assert_eq!("/home/test/foo", name.file_name);
+    }
+
+    #[tokio::test]
+    async fn test_handle_realpath_request_with_stat_if() {
+        let root = TempDir::new().unwrap();
+        std::fs::create_dir_all(root.path().join("home/test")).unwrap();
+        std::fs::write(root.path().join("home/test/file.txt"), "data").unwrap();
+
+        let sftp_session = create_sftp_session(&root, &SftpSessionConfig::default());
+
+        let name = handle_realpath_request(
+            &sftp_session,
+            "/home/test/file.txt",
+            Some(request::realpath::ControlByte::StatIf),
+            vec![],
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(Some(4), name.file_attributes.size);
+
+        let name = handle_realpath_request(
+            &sftp_session,
+            "/home/test/missing.txt",
+            Some(request::realpath::ControlByte::StatIf),
+            vec![],
+        )
+        .await
+        .unwrap();
+
+        assert_eq!("/home/test/missing.txt", name.file_name);
+        assert_eq!(FileAttributes::default(), name.file_attributes);
+    }
+
+    #[tokio::test]
+    async fn test_handle_realpath_request_with_stat_always() {
+        let root = TempDir::new().unwrap();
+        std::fs::create_dir_all(root.path().join("home/test")).unwrap();
+        std::fs::write(root.path().join("home/test/file.txt"), "data").unwrap();
+
+        let sftp_session = create_sftp_session(&root, &SftpSessionConfig::default());
+
+        let name = handle_realpath_request(
+            &sftp_session,
+            "/home/test",
+            Some(request::realpath::ControlByte::StatAlways),
+            vec!["file.txt"],
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(Some(4), name.file_attributes.size);
+
+        assert_eq!(
+            Err(Response::build_error_response(1, Error::NoSuchFile)),
+            handle_realpath_request(
+                &sftp_session,
+                "/home/test/missing.txt",
+                Some(request::realpath::ControlByte::StatAlways),
+                vec![],
+            )
+            .await
+        );
+        assert_eq!(
+            Err(Response::build_error_response(1, Error::PermissionDenied)),
+            handle_realpath_request(
+                &sftp_session,
+                "/home/other",
+                Some(request::realpath::ControlByte::StatAlways),
+                vec![],
+            )
+            .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_realpath_request_with_realpath_attributes_quirk() {
+        let root = TempDir::new().unwrap();
+        std::fs::create_dir_all(root.path().join("home/test")).unwrap();
+        std::fs::write(root.path().join("home/test/file.txt"), "data").unwrap();
+
+        let sftp_session = create_sftp_session(&root, &SftpSessionConfig::default())
+            .with_client_quirks(ClientQuirks::from_ssh_id("SSH-2.0-WinSCP_release_6.3.3"));
+
+        let name = handle_realpath_request(&sftp_session, "/home/test/file.txt", None, vec![])
+            .await
+            .unwrap();
+
+        assert_eq!(Some(4), name.file_attributes.size);
+
+        let name = handle_realpath_request(&sftp_session, "/home/test/missing.txt", None, vec![])
+            .await
+            .unwrap();
+
+        assert_eq!(None, name.file_attributes.size);
+        assert_eq!(Some(0o40777), name.file_attributes.permissions);
+
+        let name = handle_realpath_request(&sftp_session, "/home/other", None, vec![])
+            .await
+            .unwrap();
+
+        assert_eq!("/home/other", name.file_name);
+        assert_eq!(Some(0o40777), name.file_attributes.permissions);
+
+        let sftp_session = create_sftp_session(&root, &SftpSessionConfig::default());
+
+        let name = handle_realpath_request(&sftp_session, "/home/test/file.txt", None, vec![])
+            .await
+            .unwrap();
+
+        assert_eq!(None, name.file_attributes.size);
+        assert_eq!(Some(0o40777), name.file_attributes.permissions);
+    }
+
+    async fn handle_realpath_request(
+        sftp_session: &SftpSession,
+        path: &str,
+        control_byte: Option<request::realpath::ControlByte>,
+        compose_paths: Vec<&str>,
+    ) -> Result<response::name::File, Response> {
+        let response = sftp_session
+            .handle_request(Request::Realpath(request::realpath::Realpath {
+                id: 1,
+                path: String::from(path),
+                control_byte,
+                compose_paths: compose_paths.into_iter().map(String::from).collect(),
+            }))
+            .await;
+
+        match response {
+            Response::Name(mut name) => Ok(name.files.remove(0)),
+            response => Err(response),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handle_readdir_request_returns_eof_after_single_page() {
+        let root = TempDir::new().unwrap();
+        let sftp_session = create_sftp_session(&root, &SftpSessionConfig::default());
+
+        std::fs::create_dir_all(root.path().join("home/test")).unwrap();
+        std::fs::write(root.path().join("home/test/file.txt"), "").unwrap();
+
+        let handle = match sftp_session
+            .handle_request(Request::Opendir(request::path::Path {
+                id: 1,
+                path: String::from("/home/test"),
+            }))
+            .await
+        {
+            Response::Handle(handle) => handle.handle,
+            response => panic!("Unexpected response {:?}", response),
+        };
+
+        match sftp_session
+            .handle_request(Request::Readdir(request::handle::Handle {
+                id: 2,
+                handle: handle.clone(),
+            }))
+            .await
+        {
+            Response::Name(name) => assert_eq!(1, name.files.len()),
+            response => panic!("Unexpected response {:?}", response),
+        };
+
+        for id in [3, 4] {
+            assert_eq!(
+                Response::Status(response::status::Status {
+                    id,
+                    status_code: response::status::StatusCode::Eof,
+                    error_message: String::from("End of file."),
+                }),
+                sftp_session
+                    .handle_request(Request::Readdir(request::handle::Handle {
+                        id,
+                        handle: handle.clone(),
+                    }))
+                    .await
+            );
+        }
+    }
+
+    #[test]
+    fn test_new_with_unsafe_user() {
+        let object_storage = Arc::new(FilesystemStorage::new(
+            std::env::temp_dir(),
+            DEFAULT_MAX_HANDLES,
+        ));
+
+        assert!(SftpSession::new(
+            object_storage,
+            String::from(".."),
+            &SftpSessionConfig::default()
+        )
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_handle_extended_request_fsync() {
+        let root = TempDir::new().unwrap();
+        std::fs::create_dir_all(root.path().join("home/test")).unwrap();
+
+        let sftp_session = create_sftp_session(&root, &SftpSessionConfig::default());
+        let handle = open_write_handle(&sftp_session, "/home/test/file.txt").await;
+
+        sftp_session
+            .handle_request(Request::Write(request::write::Write {
+                id: 2,
+                handle: handle.clone(),
+                offset: 0,
+                data: bytes::Bytes::from("hello"),
+            }))
+            .await;
+
+        assert_eq!(
+            SftpSession::build_successful_response(3),
+            sftp_session
+                .handle_request(Request::Extended(request::extended::Extended {
+                    id: 3,
+                    extended_request: request::extended::ExtendedRequest::Fsync { handle },
+                }))
+                .await
+        );
+        assert_eq!(
+            "hello",
+            std::fs::read_to_string(root.path().join("home/test/file.txt")).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_extended_request_posix_rename_replaces_file() {
+        let root = TempDir::new().unwrap();
+        std::fs::create_dir_all(root.path().join("home/test")).unwrap();
+        std::fs::write(root.path().join("home/test/new.txt"), "new").unwrap();
+        std::fs::write(root.path().join("home/test/existing.txt"), "existing").unwrap();
+
+        let sftp_session = create_sftp_session(&root, &SftpSessionConfig::default());
+
+        assert_eq!(
+            SftpSession::build_successful_response(1),
+            sftp_session
+                .handle_request(Request::Extended(request::extended::Extended {
+                    id: 1,
+                    extended_request: request::extended::ExtendedRequest::PosixRename {
+                        old_path: String::from("/home/test/new.txt"),
+                        new_path: String::from("/home/test/existing.txt"),
+                    },
+                }))
+                .await
+        );
+        assert_eq!(
+            "new",
+            std::fs::read_to_string(root.path().join("home/test/existing.txt")).unwrap()
+        );
+        assert!(!root.path().join("home/test/new.txt").exists());
+    }
+
+    #[tokio::test]
+    async fn test_handle_extended_request_hardlink_copies_file() {
+        let root = TempDir::new().unwrap();
+        std::fs::create_dir_all(root.path().join("home/test")).unwrap();
+        std::fs::write(root.path().join("home/test/file.txt"), "data").unwrap();
+
+        let sftp_session = create_sftp_session(&root, &SftpSessionConfig::default());
+
+        assert_eq!(
+            SftpSession::build_successful_response(1),
+            handle_hardlink_request(&sftp_session, "/home/test/file.txt", "/home/test/link.txt")
+                .await
+        );
+
+        // The link is a copy, so later changes to the file are not visible through it.
+        std::fs::write(root.path().join("home/test/file.txt"), "changed").unwrap();
+
+        assert_eq!(
+            "data",
+            std::fs::read_to_string(root.path().join("home/test/link.txt")).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_extended_request_hardlink_with_existing_file() {
+        let root = TempDir::new().unwrap();
+        std::fs::create_dir_all(root.path().join("home/test")).unwrap();
+        std::fs::write(root.path().join("home/test/file.txt"), "data").unwrap();
+        std::fs::write(root.path().join("home/test/link.txt"), "existing").unwrap();
+
+        let sftp_session = create_sftp_session(&root, &SftpSessionConfig::default());
+
+        match handle_hardlink_request(&sftp_session, "/home/test/file.txt", "/home/test/link.txt")
+            .await
+        {
+            Response::Status(status) => {
+                assert_eq!(response::status::StatusCode::Failure, status.status_code)
+            }
+            response => panic!("Expected a status response, but received {:?}", response),
+        }
+        assert_eq!(
+            "existing",
+            std::fs::read_to_string(root.path().join("home/test/link.txt")).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_extended_request_hardlink_with_directory() {
+        let root = TempDir::new().unwrap();
+        std::fs::create_dir_all(root.path().join("home/test/dir")).unwrap();
+
+        let sftp_session = create_sftp_session(&root, &SftpSessionConfig::default());
+
+        match handle_hardlink_request(&sftp_session, "/home/test/dir", "/home/test/link").await {
+            Response::Status(status) => {
+                assert_eq!(response::status::StatusCode::Failure, status.status_code)
+            }
+            response => panic!("Expected a status response, but received {:?}", response),
+        }
+        assert!(!root.path().join("home/test/link").exists());
+    }
+
+    #[tokio::test]
+    async fn test_handle_extended_request_hardlink_with_permission_error() {
+        let root = TempDir::new().unwrap();
+        std::fs::create_dir_all(root.path().join("home/other")).unwrap();
+        std::fs::write(root.path().join("home/other/file.txt"), "data").unwrap();
+
+        let sftp_session = create_sftp_session(&root, &SftpSessionConfig::default());
+
+        match handle_hardlink_request(&sftp_session, "/home/other/file.txt", "/home/test/link.txt")
+            .await
+        {
+            Response::Status(status) => assert_eq!(
+                response::status::StatusCode::PermissionDenied,
+                status.status_code
+            ),
+            response => panic!("Expected a status response, but received {:?}", response),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handle_extended_request_hardlink_over_quota() {
+        let root = TempDir::new().unwrap();
+        std::fs::create_dir_all(root.path().join("home/test")).unwrap();
+        std::fs::write(root.path().join("home/test/file.txt"), "data").unwrap();
+
+        let sftp_session = create_sftp_session(
+            &root,
+            &SftpSessionConfig {
+                user_quota_bytes: Some(6),
+                ..Default::default()
+            },
+        );
+
+        match handle_hardlink_request(&sftp_session, "/home/test/file.txt", "/home/test/link.txt")
+            .await
+        {
+            Response::Status(status) => {
+                assert_eq!(response::status::StatusCode::Failure, status.status_code)
+            }
+            response => panic!("Expected a status response, but received {:?}", response),
+        }
+        assert!(!root.path().join("home/test/link.txt").exists());
+    }
+
+    async fn handle_hardlink_request(
+        sftp_session: &SftpSession,
+        old_path: &str,
+        new_path: &str,
+    ) -> Response {
+        sftp_session
+            .handle_request(Request::Extended(request::extended::Extended {
+                id: 1,
+                extended_request: request::extended::ExtendedRequest::Hardlink {
+                    old_path: String::from(old_path),
+                    new_path: String::from(new_path),
+                },
+            }))
+            .await
+    }
+
+    #[tokio::test]
+    async fn test_handle_extended_request_posix_rename_with_permission_error() {
+        let root = TempDir::new().unwrap();
+        std::fs::create_dir_all(root.path().join("home/test")).unwrap();
+        std::fs::write(root.path().join("home/test/new.txt"), "new").unwrap();
+
+        let sftp_session = create_sftp_session(&root, &SftpSessionConfig::default());
+
+        let response = sftp_session
+            .handle_request(Request::Extended(request::extended::Extended {
+                id: 1,
+                extended_request: request::extended::ExtendedRequest::PosixRename {
+                    old_path: String::from("/home/test/new.txt"),
+                    new_path: String::from("/home/other/existing.txt"),
+                },
+            }))
+            .await;
+
+        match response {
+            Response::Status(status) => assert_eq!(
+                response::status::StatusCode::PermissionDenied,
+                status.status_code
+            ),
+            _ => panic!("Expected a status response, but received {:?}", response),
+        }
+        assert!(root.path().join("home/test/new.txt").exists());
+    }
+
+    #[tokio::test]
+    async fn test_handle_extended_request_limits() {
+        let root = TempDir::new().unwrap();
+        let sftp_session = create_sftp_session(
+            &root,
+            &SftpSessionConfig {
+                max_read_length: 1024,
+                max_handles: 8,
+                ..Default::default()
+            },
+        );
+
+        let response = sftp_session
+            .handle_request(Request::Extended(request::extended::Extended {
+                id: 1,
+                extended_request: request::extended::ExtendedRequest::Limits,
+            }))
+            .await;
+
+        assert_eq!(
+            Response::ExtendedReply(response::extended_reply::ExtendedReply {
+                id: 1,
+                extended_reply: response::extended_reply::ExtendedReplyData::Limits(
+                    response::extended_reply::Limits {
+                        max_packet_length: 262144,
+                        max_read_length: 1024,
+                        max_write_length: 261120,
+                        max_open_handles: 8,
+                    }
+                ),
+            }),
+            response
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_extended_request_statvfs_without_quota() {
+        let root = TempDir::new().unwrap();
+        std::fs::create_dir_all(root.path().join("home/test")).unwrap();
+
+        let sftp_session = create_sftp_session(&root, &SftpSessionConfig::default());
+
+        let statvfs = handle_statvfs_request(&sftp_session).await;
+
+        assert_eq!(STATVFS_BLOCK_SIZE, statvfs.block_size);
+        assert_eq!(UNLIMITED_CAPACITY / STATVFS_BLOCK_SIZE, statvfs.blocks);
+        assert_eq!(UNLIMITED_CAPACITY / STATVFS_BLOCK_SIZE, statvfs.free_blocks);
+        assert_eq!(
+            UNLIMITED_CAPACITY / STATVFS_BLOCK_SIZE,
+            statvfs.available_blocks
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_extended_request_statvfs_with_quota() {
+        let root = TempDir::new().unwrap();
+        std::fs::create_dir_all(root.path().join("home/test")).unwrap();
+        std::fs::write(root.path().join("home/test/file.txt"), vec![0; 8192]).unwrap();
+
+        let sftp_session = create_sftp_session(
+            &root,
+            &SftpSessionConfig {
+                user_quota_bytes: Some(40960),
+                ..Default::default()
+            },
+        );
+
+        let statvfs = handle_statvfs_request(&sftp_session).await;
+
+        assert_eq!(10, statvfs.blocks);
+        assert_eq!(8, statvfs.free_blocks);
+        assert_eq!(8, statvfs.available_blocks);
+    }
+
+    #[tokio::test]
+    async fn test_handle_extended_request_statvfs_with_exceeded_quota() {
+        let root = TempDir::new().unwrap();
+        std::fs::create_dir_all(root.path().join("home/test")).unwrap();
+        std::fs::write(root.path().join("home/test/file.txt"), vec![0; 8192]).unwrap();
+
+        let sftp_session = create_sftp_session(
+            &root,
+            &SftpSessionConfig {
+                user_quota_bytes: Some(4096),
+                ..Default::default()
+            },
+        );
+
+        let statvfs = handle_statvfs_request(&sftp_session).await;
+
+        assert_eq!(1, statvfs.blocks);
+        assert_eq!(0, statvfs.free_blocks);
+    }
+
+    #[tokio::test]
+    async fn test_handle_write_request_with_zero_length() {
+        let root = TempDir::new().unwrap();
+        std::fs::create_dir_all(root.path().join("home/test")).unwrap();
+
+        let sftp_session = create_sftp_session(
+            &root,
+            &SftpSessionConfig {
+                user_quota_bytes: Some(10),
+                ..Default::default()
+            },
+        );
+
+        let handle = match sftp_session
+            .handle_request(build_open_request("/home/test/file.txt", true))
+            .await
+        {
+            Response::Handle(handle) => handle.handle,
+            response => panic!("Unexpected response {:?}", response),
+        };
+
+        assert_eq!(
+            SftpSession::build_successful_response(2),
+            sftp_session
+                .handle_request(Request::Write(request::write::Write {
+                    id: 2,
+                    handle,
+                    offset: 0,
+                    data: bytes::Bytes::new(),
+                }))
+                .await
+        );
+
+        // The home directory is only listed for the quota when a write reserves space.
+        assert!(sftp_session.used_bytes.lock().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_handle_write_request_with_zero_length_and_invalid_handle() {
+        let root = TempDir::new().unwrap();
+        let sftp_session = create_sftp_session(&root, &SftpSessionConfig::default());
+
+        assert_eq!(
+            Response::build_error_response(2, Error::InvalidHandle),
+            sftp_session
+                .handle_request(Request::Write(request::write::Write {
+                    id: 2,
+                    handle: String::from("missing"),
+                    offset: 0,
+                    data: bytes::Bytes::new(),
+                }))
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_write_request_under_quota() {
+        let root = TempDir::new().unwrap();
+        std::fs::create_dir_all(root.path().join("home/test")).unwrap();
+        std::fs::write(root.path().join("home/test/existing.txt"), vec![0; 5]).unwrap();
+
+        let sftp_session = create_sftp_session(
+            &root,
+            &SftpSessionConfig {
+                user_quota_bytes: Some(10),
+                ..Default::default()
+            },
+        );
+        let handle = open_write_handle(&sftp_session, "/home/test/file.txt").await;
+
+        assert_eq!(
+            SftpSession::build_successful_response(2),
+            sftp_session
+                .handle_request(Request::Write(request::write::Write {
+                    id: 2,
+                    handle,
+                    offset: 0,
+                    data: bytes::Bytes::from("hello"),
+                }))
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_write_request_over_quota() {
+        let root = TempDir::new().unwrap();
+        std::fs::create_dir_all(root.path().join("home/test")).unwrap();
+        std::fs::write(root.path().join("home/test/existing.txt"), vec![0; 5]).unwrap();
+
+        let sftp_session = create_sftp_session(
+            &root,
+            &SftpSessionConfig {
+                user_quota_bytes: Some(10),
+                ..Default::default()
+            },
+        );
+        let handle = open_write_handle(&sftp_session, "/home/test/file.txt").await;
+
+        assert_eq!(
+            SftpSession::build_successful_response(2),
+            sftp_session
+                .handle_request(Request::Write(request::write::Write {
+                    id: 2,
+                    handle: handle.clone(),
+                    offset: 0,
+                    data: bytes::Bytes::from("hell"),
+                }))
+                .await
+        );
+
+        assert_eq!(
+            Response::build_error_response(3, Error::QuotaExceeded),
+            sftp_session
+                .handle_request(Request::Write(request::write::Write {
+                    id: 3,
+                    handle: handle.clone(),
+                    offset: 4,
+                    data: bytes::Bytes::from("o!"),
+                }))
+                .await
+        );
+
+        // The handle is aborted, so later writes fail even if they fit within the quota.
+        let response = sftp_session
+            .handle_request(Request::Write(request::write::Write {
+                id: 4,
+                handle,
+                offset: 4,
+                data: bytes::Bytes::from("o"),
+            }))
+            .await;
+
+        match response {
+            Response::Status(status) => {
+                assert_eq!(response::status::StatusCode::Failure, status.status_code)
+            }
+            _ => panic!("Expected a status response, but received {:?}", response),
+        }
+        assert_eq!(Some(9), *sftp_session.used_bytes.lock().await);
+    }
+
+    #[tokio::test]
+    async fn test_handle_write_request_under_quota_after_remove() {
+        let root = TempDir::new().unwrap();
+        std::fs::create_dir_all(root.path().join("home/test")).unwrap();
+        std::fs::write(root.path().join("home/test/existing.txt"), vec![0; 5]).unwrap();
+
+        let sftp_session = create_sftp_session(
+            &root,
+            &SftpSessionConfig {
+                user_quota_bytes: Some(10),
+                ..Default::default()
+            },
+        );
+        let handle = open_write_handle(&sftp_session, "/home/test/file.txt").await;
+
+        assert_eq!(
+            SftpSession::build_successful_response(2),
+            sftp_session
+                .handle_request(Request::Write(request::write::Write {
+                    id: 2,
+                    handle: handle.clone(),
+                    offset: 0,
+                    data: bytes::Bytes::from("hello"),
+                }))
+                .await
+        );
+        sftp_session
+            .handle_request(Request::Close(request::handle::Handle { id: 3, handle }))
+            .await;
+
+        assert_eq!(
+            SftpSession::build_successful_response(4),
+            sftp_session
+                .handle_request(Request::Remove(request::path::Path {
+                    id: 4,
+                    path: String::from("/home/test/file.txt"),
+                }))
+                .await
+        );
+
+        let handle = open_write_handle(&sftp_session, "/home/test/other.txt").await;
+
+        assert_eq!(
+            SftpSession::build_successful_response(5),
+            sftp_session
+                .handle_request(Request::Write(request::write::Write {
+                    id: 5,
+                    handle,
+                    offset: 0,
+                    data: bytes::Bytes::from("hello"),
+                }))
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_write_request_overwrite_under_quota() {
+        let root = TempDir::new().unwrap();
+        std::fs::create_dir_all(root.path().join("home/test")).unwrap();
+        std::fs::write(root.path().join("home/test/existing.txt"), vec![0; 5]).unwrap();
+
+        let sftp_session = create_sftp_session(
+            &root,
+            &SftpSessionConfig {
+                user_quota_bytes: Some(10),
+                ..Default::default()
+            },
+        );
+
+        for id in [2, 4] {
+            let handle = open_write_handle(&sftp_session, "/home/test/file.txt").await;
+
+            assert_eq!(
+                SftpSession::build_successful_response(id),
+                sftp_session
+                    .handle_request(Request::Write(request::write::Write {
+                        id,
+                        handle: handle.clone(),
+                        offset: 0,
+                        data: bytes::Bytes::from("hello"),
+                    }))
+                    .await
+            );
+            sftp_session
+                .handle_request(Request::Close(request::handle::Handle {
+                    id: id + 1,
+                    handle,
+                }))
+                .await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handle_write_request_under_max_upload_bytes() {
+        let root = TempDir::new().unwrap();
+        std::fs::create_dir_all(root.path().join("home/test")).unwrap();
+
+        let sftp_session = create_sftp_session(
+            &root,
+            &SftpSessionConfig {
+                max_upload_bytes: Some(10),
+                ..Default::default()
+            },
+        );
+        let handle = open_write_handle(&sftp_session, "/home/test/file.txt").await;
+
+        for (id, offset) in [(2, 0), (3, 5)] {
+            assert_eq!(
+                SftpSession::build_successful_response(id),
+                sftp_session
+                    .handle_request(Request::Write(request::write::Write {
+                        id,
+                        handle: handle.clone(),
+                        offset,
+                        data: bytes::Bytes::from("hello"),
+                    }))
+                    .await
+            );
+        }
+
+        assert_eq!(
+            SftpSession::build_successful_response(4),
+            sftp_session
+                .handle_request(Request::Close(request::handle::Handle { id: 4, handle }))
+                .await
+        );
+        assert!(sftp_session.upload_bytes.lock().await.is_empty());
+        assert_eq!(
+            "hellohello",
+            std::fs::read_to_string(root.path().join("home/test/file.txt")).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_write_request_over_max_upload_bytes() {
+        let root = TempDir::new().unwrap();
+        std::fs::create_dir_all(root.path().join("home/test")).unwrap();
+
+        let sftp_session = create_sftp_session(
+            &root,
+            &SftpSessionConfig {
+                max_upload_bytes: Some(10),
+                ..Default::default()
+            },
+        );
+        let handle = open_write_handle(&sftp_session, "/home/test/file.txt").await;
+
+        assert_eq!(
+            SftpSession::build_successful_response(2),
+            sftp_session
+                .handle_request(Request::Write(request::write::Write {
+                    id: 2,
+                    handle: handle.clone(),
+                    offset: 0,
+                    data: bytes::Bytes::from("hello"),
+                }))
+                .await
+        );
+
+        assert_eq!(
+            Response::build_error_response(3, Error::FileTooLarge),
+            sftp_session
+                .handle_request(Request::Write(request::write::Write {
+                    id: 3,
+                    handle: handle.clone(),
+                    offset: 5,
+                    data: bytes::Bytes::from("world!"),
+                }))
+                .await
+        );
+        assert!(sftp_session.upload_bytes.lock().await.is_empty());
+
+        // The handle is aborted, so later writes fail even if they fit within the maximum.
+        let response = sftp_session
+            .handle_request(Request::Write(request::write::Write {
+                id: 4,
+                handle,
+                offset: 5,
+                data: bytes::Bytes::from("!"),
+            }))
+            .await;
+
+        match response {
+            Response::Status(status) => {
+                assert_eq!(response::status::StatusCode::Failure, status.status_code)
+            }
+            _ => panic!("Expected a status response, but received {:?}", response),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handle_write_request_failure_releases_upload_bytes() {
+        let root = TempDir::new().unwrap();
+        std::fs::create_dir_all(root.path().join("home/test")).unwrap();
+
+        let sftp_session = create_sftp_session(
+            &root,
+            &SftpSessionConfig {
+                max_upload_bytes: Some(10),
+                ..Default::default()
+            },
+        );
+
+        // Each failed write would use most of the maximum if its bytes were kept.
+        for id in [2, 3] {
+            assert_eq!(
+                Response::build_error_response(id, Error::InvalidHandle),
+                sftp_session
+                    .handle_request(Request::Write(request::write::Write {
+                        id,
+                        handle: String::from("bogus"),
+                        offset: 0,
+                        data: bytes::Bytes::from("hello!"),
+                    }))
+                    .await
+            );
+        }
+
+        assert!(sftp_session.upload_bytes.lock().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_handle_extended_request_with_unsupported_extension() {
+        let root = TempDir::new().unwrap();
+
+        let sftp_session = create_sftp_session(&root, &SftpSessionConfig::default());
+
+        assert_eq!(
+            Response::Status(response::status::Status {
+                id: 1,
+                status_code: response::status::StatusCode::OperationUnsupported,
+                error_message: String::from("The extension bogus@example.com is unsupported."),
+            }),
+            sftp_session
+                .handle_request(Request::Extended(request::extended::Extended {
+                    id: 1,
+                    extended_request: request::extended::ExtendedRequest::Unsupported {
+                        name: String::from("bogus@example.com"),
+                    },
+                }))
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_unsupported_request() {
+        let root = TempDir::new().unwrap();
+
+        let sftp_session = create_sftp_session(&root, &SftpSessionConfig::default());
+
+        assert_eq!(
+            Response::build_error_response(7, Error::Unimplemented),
+            sftp_session
+                .handle_request(Request::Unsupported(request::unsupported::Unsupported {
+                    id: 7,
+                    data_type: 22,
+                }))
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_request_records_audit_events() {
+        let root = TempDir::new().unwrap();
+        std::fs::create_dir_all(root.path().join("home/test")).unwrap();
+
+        let log_writer = LogWriter::default();
+        let sftp_session = create_sftp_session(&root, &SftpSessionConfig::default())
+            .with_audit_log(Some(Arc::new(AuditLog::new(Box::new(log_writer.clone())))));
+
+        let handle = open_write_handle(&sftp_session, "/home/test/file.txt").await;
+
+        sftp_session
+            .handle_request(Request::Write(request::write::Write {
+                id: 2,
+                handle: handle.clone(),
+                offset: 0,
+                data: bytes::Bytes::from("hello"),
+            }))
+            .await;
+        sftp_session
+            .handle_request(Request::Close(request::handle::Handle { id: 3, handle }))
+            .await;
+        sftp_session
+            .handle_request(Request::Remove(request::path::Path {
+                id: 4,
+                path: String::from("/home/other/file.txt"),
+            }))
+            .await;
+
+        let output = String::from_utf8(log_writer.0.lock().unwrap().clone()).unwrap();
+        let records: Vec<serde_json::Value> = output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+
+        assert_eq!(3, records.len());
+
+        assert_eq!("test", records[0]["user"]);
+        assert_eq!("open", records[0]["op"]);
+        assert_eq!(
+            serde_json::json!(["/home/test/file.txt"]),
+            records[0]["paths"]
+        );
+        assert_eq!("success", records[0]["result"]);
+        assert!(records[0]["timestamp"].is_string());
+
+        assert_eq!("close", records[1]["op"]);
+        assert_eq!(
+            serde_json::json!(["/home/test/file.txt"]),
+            records[1]["paths"]
+        );
+        assert_eq!("success", records[1]["result"]);
+
+        assert_eq!("remove", records[2]["op"]);
+        assert_eq!("Permission denied.", records[2]["result"]);
+
+        assert!(sftp_session.audit_handles.lock().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_handle_request_without_audit_log() {
+        let root = TempDir::new().unwrap();
+        std::fs::create_dir_all(root.path().join("home/test")).unwrap();
+
+        let sftp_session = create_sftp_session(&root, &SftpSessionConfig::default());
+
+        open_write_handle(&sftp_session, "/home/test/file.txt").await;
+
+        assert!(sftp_session.audit_handles.lock().await.is_empty());
+    }
+
+    #[derive(Clone, Default)]
+    struct LogWriter(Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for LogWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handle_request_with_storage_op_timeout() {
+        let root = TempDir::new().unwrap();
+        std::fs::create_dir_all(root.path().join("home/test")).unwrap();
+
+        let object_storage = Arc::new(SlowStorage {
+            storage: Arc::new(FilesystemStorage::new(
+                root.path().to_path_buf(),
+                DEFAULT_MAX_HANDLES,
+            )),
+        });
+
+        let sftp_session = SftpSession::new(
+            object_storage,
+            String::from("test"),
+            &SftpSessionConfig {
+                storage_op_timeout: Some(Duration::from_millis(50)),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        sftp_session.initialized.store(true, Ordering::SeqCst);
+
+        let start = Instant::now();
+
+        assert_eq!(
+            Response::build_error_response(1, Error::Storage(String::from("Operation timed out."))),
+            sftp_session
+                .handle_request(Request::Lstat(request::path::Path {
+                    id: 1,
+                    path: String::from("/home/test"),
+                }))
+                .await
+        );
+        assert!(start.elapsed() < Duration::from_secs(60));
+
+        // Requests that complete in time are unaffected.
+        match sftp_session
+            .handle_request(Request::Opendir(request::path::Path {
+                id: 2,
+                path: String::from("/home/test"),
+            }))
+            .await
+        {
+            Response::Handle(_) => {}
+            response => panic!("Unexpected response {:?}", response),
+        };
+    }
+
+    #[tokio::test]
+    async fn test_handle_write_request_with_storage_op_timeout_releases_reservations() {
+        let root = TempDir::new().unwrap();
+        std::fs::create_dir_all(root.path().join("home/test")).unwrap();
+
+        let object_storage = Arc::new(SlowStorage {
+            storage: Arc::new(FilesystemStorage::new(
+                root.path().to_path_buf(),
+                DEFAULT_MAX_HANDLES,
+            )),
+        });
+
+        let sftp_session = SftpSession::new(
+            object_storage,
+            String::from("test"),
+            &SftpSessionConfig {
+                storage_op_timeout: Some(Duration::from_millis(50)),
+                max_upload_bytes: Some(10),
+                user_quota_bytes: Some(10),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        sftp_session.initialized.store(true, Ordering::SeqCst);
+
+        assert_eq!(
+            Response::build_error_response(1, Error::Storage(String::from("Operation timed out."))),
+            sftp_session
+                .handle_request(Request::Write(request::write::Write {
+                    id: 1,
+                    handle: String::from("handle"),
+                    offset: 0,
+                    data: bytes::Bytes::from("hello"),
+                }))
+                .await
+        );
+
+        assert!(sftp_session.upload_bytes.lock().await.is_empty());
+        assert_eq!(Some(0), *sftp_session.used_bytes.lock().await);
+    }
+
+    /// Storage that takes a minute to retrieve file metadata and write data, as if a call to
+    /// storage were stuck.
+    struct SlowStorage {
+        storage: Arc<dyn Storage>,
+    }
+
+    #[async_trait::async_trait]
+    impl Storage for SlowStorage {
+        async fn init(&self) -> Result<(), Error> {
+            self.storage.init().await
+        }
+
+        async fn health_check(&self) -> Result<(), Error> {
+            self.storage.health_check().await
+        }
+
+        async fn get_authorized_keys_fingerprints(&self, user: &str) -> Result<Vec<String>, Error> {
+            self.storage.get_authorized_keys_fingerprints(user).await
+        }
+
+        async fn open_dir_handle(&self, dir_name: String) -> Result<String, Error> {
+            self.storage.open_dir_handle(dir_name).await
+        }
+
+        async fn create_dir(&self, dir_name: String) -> Result<(), Error> {
+            self.storage.create_dir(dir_name).await
+        }
+
+        async fn read_dir(&self, handle: &str) -> Result<Vec<response::name::File>, Error> {
+            self.storage.read_dir(handle).await
+        }
+
+        async fn remove_dir(&self, dir_name: String) -> Result<(), Error> {
+            self.storage.remove_dir(dir_name).await
+        }
+
+        async fn get_dir_size(&self, dir_name: String) -> Result<u64, Error> {
+            self.storage.get_dir_size(dir_name).await
+        }
+
+        async fn get_file_metadata(
+            &self,
+            file_name: String,
+        ) -> Result<response::name::File, Error> {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            self.storage.get_file_metadata(file_name).await
+        }
+
+        async fn set_file_metadata(
+            &self,
+            file_name: String,
+            file_attributes: FileAttributes,
+        ) -> Result<(), Error> {
+            self.storage
+                .set_file_metadata(file_name, file_attributes)
+                .await
+        }
+
+        async fn set_handle_metadata(
+            &self,
+            handle: &str,
+            file_attributes: FileAttributes,
+        ) -> Result<(), Error> {
+            self.storage
+                .set_handle_metadata(handle, file_attributes)
+                .await
+        }
+
+        async fn get_handle_metadata(&self, handle: &str) -> Result<response::name::File, Error> {
+            self.storage.get_handle_metadata(handle).await
+        }
+
+        async fn open_read_handle(&self, file_name: String) -> Result<String, Error> {
+            self.storage.open_read_handle(file_name).await
+        }
+
+        async fn read_data(&self, handle: &str, offset: u64, len: u32) -> Result<Vec<u8>, Error> {
+            self.storage.read_data(handle, offset, len).await
+        }
+
+        async fn open_write_handle(&self, file_name: String) -> Result<String, Error> {
+            self.storage.open_write_handle(file_name).await
+        }
+
+        async fn open_existing_write_handle(
+            &self,
+            file_name: String,
+            truncate: bool,
+        ) -> Result<String, Error> {
+            self.storage
+                .open_existing_write_handle(file_name, truncate)
+                .await
+        }
+
+        async fn open_read_write_handle(
+            &self,
+            file_name: String,
+            truncate: bool,
+        ) -> Result<String, Error> {
+            self.storage
+                .open_read_write_handle(file_name, truncate)
+                .await
+        }
+
+        async fn open_append_handle(
+            &self,
+            file_name: String,
+            create: bool,
+        ) -> Result<String, Error> {
+            self.storage.open_append_handle(file_name, create).await
+        }
+
+        async fn write_data(
+            &self,
+            handle: &str,
+            offset: u64,
+            data: bytes::Bytes,
+        ) -> Result<(), Error> {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            self.storage.write_data(handle, offset, data).await
+        }
+
+        async fn remove_file(&self, key: String) -> Result<(), Error> {
+            self.storage.remove_file(key).await
+        }
+
+        async fn close_handle(&self, handle: &str) -> Result<(), Error> {
+            self.storage.close_handle(handle).await
+        }
+
+        async fn fsync(&self, handle: &str) -> Result<(), Error> {
+            self.storage.fsync(handle).await
+        }
+
+        async fn rename(&self, current: String, new: String) -> Result<(), Error> {
+            self.storage.rename(current, new).await
+        }
+
+        async fn posix_rename(&self, current: String, new: String) -> Result<(), Error> {
+            self.storage.posix_rename(current, new).await
+        }
+
+        async fn copy_file(&self, current: String, new: String) -> Result<(), Error> {
+            self.storage.copy_file(current, new).await
+        }
+
+        async fn create_symlink(&self, link: String, target: String) -> Result<(), Error> {
+            self.storage.create_symlink(link, target).await
+        }
+
+        async fn read_symlink(&self, link: String) -> Result<String, Error> {
+            self.storage.read_symlink(link).await
+        }
+
+        async fn abort_write_handle(&self, handle: &str) -> Result<(), Error> {
+            self.storage.abort_write_handle(handle).await
+        }
+
+        async fn abort_write_handles(&self) -> Result<(), Error> {
+            self.storage.abort_write_handles().await
+        }
+    }
+
+    fn create_sftp_session(root: &TempDir, session_config: &SftpSessionConfig) -> SftpSession {
+        let sftp_session = create_uninitialized_sftp_session(root, session_config);
+        sftp_session.initialized.store(true, Ordering::SeqCst);
+
+        sftp_session
+    }
+
+    fn create_uninitialized_sftp_session(
+        root: &TempDir,
+        session_config: &SftpSessionConfig,
+    ) -> SftpSession {
+        let object_storage = Arc::new(FilesystemStorage::new(
+            root.path().to_path_buf(),
+            DEFAULT_MAX_HANDLES,
+        ));
+
+        SftpSession::new(object_storage, String::from("test"), session_config).unwrap()
+    }
+
+    fn build_exclusive_create_request(filename: &str) -> Request {
+        Request::Open(request::open::Open {
+            id: 1,
+            filename: String::from(filename),
+            file_attributes: FileAttributes::default(),
+            open_options: request::open::OpenOptions {
+                read: false,
+                write: true,
+                create: true,
+                create_new_only: true,
+                append: false,
+                truncate: true,
+            },
+        })
+    }
+
+    async fn open_read_handle(sftp_session: &SftpSession, filename: &str) -> String {
+        let response = sftp_session
+            .handle_request(Request::Open(request::open::Open {
+                id: 1,
+                filename: filename.to_string(),
+                file_attributes: FileAttributes::default(),
+                open_options: request::open::OpenOptions {
+                    read: true,
+                    write: false,
+                    create: false,
+                    create_new_only: false,
+                    append: false,
+                    truncate: false,
+                },
+            }))
+            .await;
+
+        match response {
+            Response::Handle(handle) => handle.handle,
+            _ => panic!("Expected a handle response, but received {:?}", response),
+        }
+    }
+
+    async fn open_existing_handle(
+        sftp_session: &SftpSession,
+        filename: &str,
+        read: bool,
+    ) -> String {
+        let response = sftp_session
+            .handle_request(Request::Open(request::open::Open {
+                id: 1,
+                filename: filename.to_string(),
+                file_attributes: FileAttributes::default(),
+                open_options: request::open::OpenOptions {
+                    read,
+                    write: true,
+                    create: false,
+                    create_new_only: false,
+                    append: false,
+                    truncate: false,
+                },
+            }))
+            .await;
+
+        match response {
+            Response::Handle(handle) => handle.handle,
+            _ => panic!("Expected a handle response, but received {:?}", response),
+        }
+    }
+
+    async fn write_and_close(sftp_session: &SftpSession, handle: String, offset: u64, data: &str) {
+        assert_eq!(
+            SftpSession::build_successful_response(3),
+            sftp_session
+                .handle_request(Request::Write(request::write::Write {
+                    id: 3,
+                    handle: handle.clone(),
+                    offset,
+                    data: bytes::Bytes::from(data.to_string()),
+                }))
+                .await
+        );
+        assert_eq!(
+            SftpSession::build_successful_response(4),
+            sftp_session
+                .handle_request(Request::Close(request::handle::Handle { id: 4, handle }))
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_extended_request_list_glob() {
+        let root = TempDir::new().unwrap();
+        std::fs::create_dir_all(root.path().join("home/test/dir.txt")).unwrap();
+        for file in ["a.txt", "b.txt", "ab.csv", "c1.txt", "c12.txt"] {
+            std::fs::write(root.path().join("home/test").join(file), "data").unwrap();
+        }
+
+        let sftp_session = create_sftp_session(&root, &SftpSessionConfig::default());
+
+        assert_eq!(
+            vec!["a.txt", "b.txt", "c1.txt", "c12.txt", "dir.txt"],
+            handle_list_glob_request(&sftp_session, "/home/test", "*.txt").await
+        );
+        assert_eq!(
+            vec!["c1.txt"],
+            handle_list_glob_request(&sftp_session, "/home/test", "c?.txt").await
+        );
+        assert_eq!(
+            vec!["a.txt", "ab.csv"],
+            handle_list_glob_request(&sftp_session, "/home/test", "a*").await
+        );
+        assert!(
+            handle_list_glob_request(&sftp_session, "/home/test", "*.pdf")
+                .await
+                .is_empty()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_extended_request_list_glob_across_pages() {
+        let root = TempDir::new().unwrap();
+        std::fs::create_dir_all(root.path().join("home/test")).unwrap();
+        for i in 0..250 {
+            std::fs::write(root.path().join(format!("home/test/{}.csv", i)), "data").unwrap();
+        }
+        for i in 0..150 {
+            std::fs::write(root.path().join(format!("home/test/{}.txt", i)), "data").unwrap();
+        }
+
+        let sftp_session = create_sftp_session(&root, &SftpSessionConfig::default());
+
+        let file_names = handle_list_glob_request(&sftp_session, "/home/test", "*.txt").await;
+
+        assert_eq!(150, file_names.len());
+        assert!(file_names
+            .iter()
+            .all(|file_name| file_name.ends_with(".txt")));
+    }
+
+    #[tokio::test]
+    async fn test_handle_extended_request_list_glob_outside_home() {
+        let root = TempDir::new().unwrap();
+        std::fs::create_dir_all(root.path().join("home/other")).unwrap();
+        std::fs::write(root.path().join("home/other/file.txt"), "data").unwrap();
+
+        let sftp_session = create_sftp_session(&root, &SftpSessionConfig::default());
+
+        assert_eq!(
+            Response::build_error_response(1, Error::PermissionDenied),
+            sftp_session
+                .handle_request(build_list_glob_request("/home/other", "*"))
+                .await
+        );
+    }
+
+    /// Opens a glob listing and reads it until the end, returning the sorted file names.
+    async fn handle_list_glob_request(
+        sftp_session: &SftpSession,
+        path: &str,
+        pattern: &str,
+    ) -> Vec<String> {
+        let response = sftp_session
+            .handle_request(build_list_glob_request(path, pattern))
+            .await;
+
+        let handle = match response {
+            Response::Handle(handle) => handle.handle,
+            _ => panic!("Expected a handle response, but received {:?}", response),
+        };
+
+        let file_names = read_dir_to_end(sftp_session, &handle).await;
+
+        assert_eq!(
+            SftpSession::build_successful_response(3),
+            sftp_session
+                .handle_request(Request::Close(request::handle::Handle { id: 3, handle }))
+                .await
+        );
+        assert!(sftp_session.glob_patterns.lock().await.is_empty());
+
+        file_names
+    }
+
+    async fn read_dir_to_end(sftp_session: &SftpSession, handle: &str) -> Vec<String> {
+        let mut file_names = Vec::new();
+
+        loop {
+            let response = sftp_session
+                .handle_request(Request::Readdir(request::handle::Handle {
+                    id: 2,
+                    handle: String::from(handle),
+                }))
+                .await;
+
+            match response {
+                Response::Name(name) => {
+                    assert!(!name.files.is_empty());
+                    file_names.extend(name.files.into_iter().map(|file| file.file_name));
+                }
+                Response::Status(status) => {
+                    assert_eq!(response::status::StatusCode::Eof, status.status_code);
+                    file_names.sort();
+                    return file_names;
+                }
+                _ => panic!("Expected a name response, but received {:?}", response),
+            }
+        }
+    }
+
+    fn build_list_glob_request(path: &str, pattern: &str) -> Request {
+        Request::Extended(request::extended::Extended {
+            id: 1,
+            extended_request: request::extended::ExtendedRequest::ListGlob {
+                path: String::from(path),
+                pattern: String::from(pattern),
+            },
+        })
+    }
+
+    async fn handle_statvfs_request(
+        sftp_session: &SftpSession,
+    ) -> response::extended_reply::Statvfs {
+        let response = sftp_session
+            .handle_request(Request::Extended(request::extended::Extended {
+                id: 1,
+                extended_request: request::extended::ExtendedRequest::Statvfs {
+                    path: String::from("/home/test"),
+                },
+            }))
+            .await;
+
+        match response {
+            Response::ExtendedReply(response::extended_reply::ExtendedReply {
+                extended_reply: response::extended_reply::ExtendedReplyData::Statvfs(statvfs),
+                ..
+            }) => statvfs,
+            _ => panic!("Expected an extended reply, but received {:?}", response),
+        }
+    }
+
+    async fn open_write_handle(sftp_session: &SftpSession, filename: &str) -> String {
+        let response = sftp_session
+            .handle_request(Request::Open(request::open::Open {
+                id: 1,
+                filename: filename.to_string(),
+                file_attributes: FileAttributes::default(),
+                open_options: request::open::OpenOptions {
+                    read: false,
+                    write: true,
+                    create: true,
+                    create_new_only: false,
+                    append: false,
+                    truncate: true,
+                },
+            }))
+            .await;
+
+        match response {
+            Response::Handle(handle) => handle.handle,
+            _ => panic!("Expected a handle response, but received {:?}", response),
         }
     }
 }
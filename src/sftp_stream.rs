@@ -1,60 +1,967 @@
-use std::{convert::TryFrom, mem};
+use std::{
+    convert::TryFrom,
+    future::Future,
+    mem,
+    time::{Duration, Instant},
+};
 
-use bytes::{BufMut, Bytes};
-use russh::{server::Msg, ChannelStream};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tracing::error;
+use bytes::{Buf, Bytes, BytesMut};
+use futures::stream::{FuturesUnordered, StreamExt};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info};
 
-use crate::{error::Error, protocol::request::Request, sftp_session::SftpSession};
+use crate::{
+    error::Error,
+    protocol::request::{self, Request},
+    protocol::response::Response,
+    rate_limiter::RateLimiter,
+    sftp_session::SftpSession,
+};
 
+/// The maximum length of a single request, which matches the OpenSSH limit. A request may span
+/// multiple SSH packets, so this is larger than the SSH maximum packet size.
+pub(crate) const MAX_REQUEST_LENGTH: usize = 256 * 1024;
+
+/// The maximum number of reads processed at once. Clients pipeline reads, and each read waits on
+/// storage, so reads are processed concurrently to keep transfers from being bound by latency.
+/// Each read holds up to the maximum read length in memory until its response is sent.
+const MAX_CONCURRENT_READS: usize = 8;
+
+/// Reads SFTP requests from a stream, passes them to a session, and writes back the responses.
+/// The stream can be any byte stream, such as an SSH channel or an in-memory pipe.
 pub struct SftpStream {
     sftp_session: SftpSession,
+    rate_limiter: Option<Mutex<RateLimiter>>,
+    idle_timeout: Option<Duration>,
 }
 
 impl SftpStream {
-    pub fn new(sftp_session: SftpSession) -> SftpStream {
-        SftpStream { sftp_session }
+    /// Creates a stream for a session. When a rate is given, requests beyond that many per
+    /// second are delayed so that a single client cannot flood the storage backend. When an idle
+    /// timeout is given, the stream is closed once the client has sent nothing for that long.
+    pub fn new(
+        sftp_session: SftpSession,
+        requests_per_second: Option<u32>,
+        idle_timeout: Option<Duration>,
+    ) -> SftpStream {
+        SftpStream {
+            sftp_session,
+            rate_limiter: requests_per_second.map(|rps| Mutex::new(RateLimiter::new(rps))),
+            idle_timeout,
+        }
     }
 
-    #[tracing::instrument(skip_all)]
-    pub async fn process_stream(&self, mut stream: ChannelStream<Msg>) -> Result<(), Error> {
-        loop {
-            match self.process_request(&mut stream).await {
-                Ok(_) => {}
-                Err(error) => match error {
-                    Error::EndOfFile => break Ok(()),
-                    _ => break Err(error),
-                },
-            }
+    /// Processes requests until the client closes the stream or shutdown is requested. On
+    /// shutdown, the request in progress is finished. Files the client has not closed when
+    /// processing stops are aborted so that no partial uploads are left behind. A summary of the
+    /// data transferred is logged once processing stops.
+    #[tracing::instrument(skip_all, fields(user = %self.sftp_session.get_user()))]
+    pub async fn process_stream<S>(
+        &self,
+        mut stream: S,
+        shutdown_token: CancellationToken,
+    ) -> Result<(), Error>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let start = Instant::now();
+        let result = self.process_requests(&mut stream, shutdown_token).await;
+
+        if let Err(error) = self.sftp_session.abort_write_handles().await {
+            error!("Failed to abort open write handles: {}", error);
         }
+
+        info!(
+            user = %self.sftp_session.get_user(),
+            bytes_in = self.sftp_session.get_bytes_in(),
+            bytes_out = self.sftp_session.get_bytes_out(),
+            duration = ?start.elapsed(),
+            "Sftp session finished"
+        );
+
+        result
     }
 
-    #[tracing::instrument(skip_all)]
-    async fn process_request(&self, stream: &mut ChannelStream<Msg>) -> Result<(), Error> {
-        let request_data_size = stream.read_u32().await?;
-        let request_size = request_data_size as usize + mem::size_of::<u32>();
+    /// Reads are processed concurrently, and their responses are sent as they complete, since
+    /// each response carries the id of its request. Any other request waits for the reads before
+    /// it, so that a read is not affected by a later request, such as closing its handle.
+    async fn process_requests<S>(
+        &self,
+        stream: &mut S,
+        shutdown_token: CancellationToken,
+    ) -> Result<(), Error>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let mut buffer = BytesMut::new();
+        let mut reads = FuturesUnordered::new();
+
+        let result = loop {
+            // A request can arrive split across multiple reads, so data is buffered until a
+            // complete request frame is available.
+            while let Some(mut request_frame) = parse_request_frame(&mut buffer)? {
+                if let Some(rate_limiter) = &self.rate_limiter {
+                    rate_limiter.lock().await.acquire().await;
+                }
+
+                let request_id = request::peek_request_id(&request_frame);
+
+                match Request::try_from(&mut request_frame) {
+                    Ok(Request::Read(read_request)) => {
+                        if reads.len() >= MAX_CONCURRENT_READS {
+                            if let Some(response) = reads.next().await {
+                                write_response(stream, &response).await?;
+                            }
+                        }
 
-        let mut request_buffer: Vec<u8> = Vec::with_capacity(request_size);
-        request_buffer.put_u32(request_data_size);
+                        reads.push(
+                            self.sftp_session
+                                .handle_request(Request::Read(read_request)),
+                        );
+                    }
+                    request => {
+                        finish_reads(stream, &mut reads).await?;
 
-        let mut request_data_buffer = vec![0; request_data_size as usize];
-        stream.read_exact(&mut request_data_buffer).await?;
-        request_buffer.put_slice(&request_data_buffer);
+                        let response = self.process_request(request_id, request).await;
+                        write_response(stream, &response).await?;
+                    }
+                }
+            }
+
+            let completed_read = tokio::select! {
+                _ = shutdown_token.cancelled() => {
+                    info!("Stopping sftp subsystem for shutdown");
+                    break Ok(());
+                }
+                Some(response) = reads.next(), if !reads.is_empty() => Some(response),
+                read_result = read_with_idle_timeout(stream, &mut buffer, self.idle_timeout) => {
+                    match read_result {
+                        None => {
+                            info!("Stopping sftp subsystem for idle session");
+                            break Ok(());
+                        }
+                        Some(Ok(0)) => break Ok(()),
+                        Some(Ok(_)) => None,
+                        Some(Err(error)) => match Error::from(error) {
+                            Error::EndOfFile => break Ok(()),
+                            error => break Err(error),
+                        },
+                    }
+                }
+            };
 
-        let request = Request::try_from(&mut Bytes::from(request_buffer));
+            if let Some(response) = completed_read {
+                write_response(stream, &response).await?;
+            }
+        };
 
-        let response = match request {
+        // Reads received before processing stopped are still answered.
+        finish_reads(stream, &mut reads).await?;
+
+        result
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn process_request(
+        &self,
+        request_id: Option<u32>,
+        request: Result<Request, Error>,
+    ) -> Response {
+        match request {
             Ok(request) => self.sftp_session.handle_request(request).await,
             Err(_) => {
-                let response = SftpSession::build_invalid_request_message_response();
+                let response =
+                    SftpSession::build_invalid_request_message_response(request_id.unwrap_or(0));
                 error!("Sending error response: {:?}", response);
                 response
             }
-        };
+        }
+    }
+}
+
+/// Waits for the reads in progress, sending each response as its read completes.
+async fn finish_reads<S, F>(stream: &mut S, reads: &mut FuturesUnordered<F>) -> Result<(), Error>
+where
+    S: AsyncWrite + Unpin,
+    F: Future<Output = Response>,
+{
+    while let Some(response) = reads.next().await {
+        write_response(stream, &response).await?;
+    }
+
+    Ok(())
+}
+
+async fn write_response<S>(stream: &mut S, response: &Response) -> Result<(), Error>
+where
+    S: AsyncWrite + Unpin,
+{
+    let mut response_bytes = Bytes::from(response);
+    stream.write_all_buf(&mut response_bytes).await?;
+
+    Ok(())
+}
+
+/// Reads more data from the client into the buffer. `None` is returned if the client sends nothing
+/// before the idle timeout expires.
+async fn read_with_idle_timeout<S>(
+    stream: &mut S,
+    buffer: &mut BytesMut,
+    idle_timeout: Option<Duration>,
+) -> Option<std::io::Result<usize>>
+where
+    S: AsyncRead + Unpin,
+{
+    match idle_timeout {
+        Some(idle_timeout) => tokio::time::timeout(idle_timeout, stream.read_buf(buffer))
+            .await
+            .ok(),
+        None => Some(stream.read_buf(buffer).await),
+    }
+}
+
+/// Splits a complete length-prefixed request frame off of the buffer. `None` is returned without
+/// consuming any data if the buffer does not yet contain a complete frame.
+fn parse_request_frame(buffer: &mut BytesMut) -> Result<Option<Bytes>, Error> {
+    if buffer.len() < mem::size_of::<u32>() {
+        return Ok(None);
+    }
+
+    let request_data_size = (&buffer[..mem::size_of::<u32>()]).get_u32() as usize;
+
+    // The stream cannot be resynchronized after an invalid length, so the frame is rejected
+    // before any data is buffered for it.
+    if request_data_size == 0 || request_data_size > MAX_REQUEST_LENGTH {
+        error!("Received request with invalid length {}", request_data_size);
+        return Err(Error::BadMessage);
+    }
+
+    let request_size = request_data_size + mem::size_of::<u32>();
+
+    if buffer.len() < request_size {
+        return Ok(None);
+    }
+
+    Ok(Some(buffer.split_to(request_size).freeze()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::protocol::{file_attributes::FileAttributes, response::name::File};
+    use crate::sftp_session::SftpSessionConfig;
+    use crate::storage::{filesystem::FilesystemStorage, Storage, DEFAULT_MAX_HANDLES};
+    use bytes::BufMut;
+    use std::sync::Arc;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_parse_request_frame_with_complete_frame() {
+        let mut buffer = BytesMut::new();
+        buffer.put_slice(&build_realpath_frame());
+
+        assert_eq!(
+            Ok(Some(Bytes::from(build_realpath_frame()))),
+            parse_request_frame(&mut buffer)
+        );
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn test_parse_request_frame_with_one_byte_at_a_time() {
+        let frame = build_realpath_frame();
+        let mut buffer = BytesMut::new();
+
+        for (index, byte) in frame.iter().enumerate() {
+            buffer.put_u8(*byte);
+
+            let result = parse_request_frame(&mut buffer).unwrap();
+
+            if index < frame.len() - 1 {
+                assert_eq!(None, result);
+                assert_eq!(index + 1, buffer.len());
+            } else {
+                assert_eq!(Some(Bytes::from(frame.clone())), result);
+            }
+        }
+
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn test_parse_request_frame_with_split_length_and_body() {
+        let frame = build_realpath_frame();
+        let mut buffer = BytesMut::new();
+
+        buffer.put_slice(&frame[..4]);
+        assert_eq!(Ok(None), parse_request_frame(&mut buffer));
+
+        buffer.put_slice(&frame[4..]);
+        assert_eq!(
+            Ok(Some(Bytes::from(frame))),
+            parse_request_frame(&mut buffer)
+        );
+    }
+
+    #[test]
+    fn test_parse_request_frame_leaves_next_frame_in_buffer() {
+        let frame = build_realpath_frame();
+        let mut buffer = BytesMut::new();
+
+        buffer.put_slice(&frame);
+        buffer.put_slice(&frame[..6]);
+
+        assert_eq!(
+            Ok(Some(Bytes::from(frame.clone()))),
+            parse_request_frame(&mut buffer)
+        );
+        assert_eq!(&frame[..6], &buffer[..]);
+        assert_eq!(Ok(None), parse_request_frame(&mut buffer));
+    }
+
+    #[test]
+    fn test_parse_request_frame_with_zero_length() {
+        let mut buffer = BytesMut::new();
+        buffer.put_u32(0);
+
+        assert_eq!(Err(Error::BadMessage), parse_request_frame(&mut buffer));
+    }
+
+    #[test]
+    fn test_parse_request_frame_with_length_over_maximum() {
+        let mut buffer = BytesMut::new();
+        buffer.put_u32(MAX_REQUEST_LENGTH as u32 + 1);
+
+        assert_eq!(Err(Error::BadMessage), parse_request_frame(&mut buffer));
+    }
+
+    #[tokio::test]
+    async fn test_process_stream_aborts_write_handles_on_disconnect() {
+        let root = TempDir::new().unwrap();
+        let object_storage = Arc::new(FilesystemStorage::new(
+            root.path().to_path_buf(),
+            DEFAULT_MAX_HANDLES,
+        ));
+        let sftp_session = SftpSession::new(
+            object_storage.clone(),
+            String::from("test"),
+            &SftpSessionConfig::default(),
+        )
+        .unwrap();
+        let sftp_stream = SftpStream::new(sftp_session, None, None);
+
+        let (mut client, server) = tokio::io::duplex(1024);
+
+        let process_stream = tokio::spawn(async move {
+            sftp_stream
+                .process_stream(server, CancellationToken::new())
+                .await
+        });
+
+        client.write_all(&build_init_frame()).await.unwrap();
+        read_response_frame(&mut client).await;
+
+        client.write_all(&build_open_write_frame()).await.unwrap();
+        let mut handle_response = read_response_frame(&mut client).await;
+
+        assert_eq!(102, handle_response.get_u8()); // SSH_FXP_HANDLE
+        assert_eq!(1, handle_response.get_u32());
+        let handle_length = handle_response.get_u32() as usize;
+        let handle = String::from_utf8(handle_response[..handle_length].to_vec()).unwrap();
+
+        drop(client);
+
+        assert_eq!(Ok(()), process_stream.await.unwrap());
+        assert!(object_storage
+            .write_data(&handle, 0, Bytes::from("data"))
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_process_stream_counts_bytes_transferred() {
+        let root = TempDir::new().unwrap();
+        let object_storage = Arc::new(FilesystemStorage::new(
+            root.path().to_path_buf(),
+            DEFAULT_MAX_HANDLES,
+        ));
+        let sftp_session = SftpSession::new(
+            object_storage,
+            String::from("test"),
+            &SftpSessionConfig::default(),
+        )
+        .unwrap();
+        let sftp_stream = Arc::new(SftpStream::new(sftp_session, None, None));
+
+        let (mut client, server) = tokio::io::duplex(1024);
+
+        let process_stream = tokio::spawn({
+            let sftp_stream = sftp_stream.clone();
+            async move {
+                sftp_stream
+                    .process_stream(server, CancellationToken::new())
+                    .await
+            }
+        });
+
+        client.write_all(&build_init_frame()).await.unwrap();
+        read_response_frame(&mut client).await;
+
+        client.write_all(&build_open_write_frame()).await.unwrap();
+        let handle = read_handle_response(&mut client).await;
+
+        client
+            .write_all(&build_write_frame(&handle, &[7; 100]))
+            .await
+            .unwrap();
+        read_response_frame(&mut client).await;
+
+        client.write_all(&build_close_frame(&handle)).await.unwrap();
+        read_response_frame(&mut client).await;
+
+        client.write_all(&build_open_read_frame()).await.unwrap();
+        let handle = read_handle_response(&mut client).await;
+
+        client
+            .write_all(&build_read_frame(5, &handle, 0, 60))
+            .await
+            .unwrap();
+        let mut response = read_response_frame(&mut client).await;
+        assert_eq!(103, response.get_u8()); // SSH_FXP_DATA
+
+        // Reads at the end of the file return no data, so they are not counted.
+        client
+            .write_all(&build_read_frame(6, &handle, 100, 60))
+            .await
+            .unwrap();
+        let mut response = read_response_frame(&mut client).await;
+        assert_eq!(101, response.get_u8()); // SSH_FXP_STATUS
+
+        drop(client);
+
+        assert_eq!(Ok(()), process_stream.await.unwrap());
+        assert_eq!(100, sftp_stream.sftp_session.get_bytes_in());
+        assert_eq!(60, sftp_stream.sftp_session.get_bytes_out());
+    }
+
+    #[tokio::test]
+    async fn test_process_stream_sends_read_responses_as_reads_complete() {
+        let root = TempDir::new().unwrap();
+        std::fs::create_dir_all(root.path().join("home/test")).unwrap();
+        std::fs::write(
+            root.path().join("home/test/file.txt"),
+            "0123456789abcdefghij",
+        )
+        .unwrap();
+
+        let object_storage = Arc::new(SlowFirstReadStorage {
+            storage: Arc::new(FilesystemStorage::new(
+                root.path().to_path_buf(),
+                DEFAULT_MAX_HANDLES,
+            )),
+        });
+        let sftp_session = SftpSession::new(
+            object_storage,
+            String::from("test"),
+            &SftpSessionConfig::default(),
+        )
+        .unwrap();
+        let sftp_stream = SftpStream::new(sftp_session, None, None);
+
+        let (mut client, server) = tokio::io::duplex(1024);
+
+        tokio::spawn(async move {
+            sftp_stream
+                .process_stream(server, CancellationToken::new())
+                .await
+        });
+
+        client.write_all(&build_init_frame()).await.unwrap();
+        read_response_frame(&mut client).await;
+
+        client.write_all(&build_open_read_frame()).await.unwrap();
+        let handle = read_handle_response(&mut client).await;
+
+        client
+            .write_all(&build_read_frame(5, &handle, 0, 10))
+            .await
+            .unwrap();
+        client
+            .write_all(&build_read_frame(6, &handle, 10, 10))
+            .await
+            .unwrap();
+        client.write_all(&build_close_frame(&handle)).await.unwrap();
+
+        // The second read does not wait for the slow first read, and the close waits for both.
+        for (id, data) in [(6, &b"abcdefghij"[..]), (5, &b"0123456789"[..])] {
+            let mut response = read_response_frame(&mut client).await;
+
+            assert_eq!(103, response.get_u8()); // SSH_FXP_DATA
+            assert_eq!(id, response.get_u32());
+            assert_eq!(10, response.get_u32());
+            assert_eq!(data, &response[..]);
+        }
+
+        let mut response = read_response_frame(&mut client).await;
+
+        assert_eq!(101, response.get_u8()); // SSH_FXP_STATUS
+        assert_eq!(3, response.get_u32());
+        assert_eq!(0, response.get_u32()); // SSH_FX_OK
+    }
+
+    #[tokio::test]
+    async fn test_process_stream_closes_idle_session() {
+        let root = TempDir::new().unwrap();
+        let object_storage = Arc::new(FilesystemStorage::new(
+            root.path().to_path_buf(),
+            DEFAULT_MAX_HANDLES,
+        ));
+        let sftp_session = SftpSession::new(
+            object_storage.clone(),
+            String::from("test"),
+            &SftpSessionConfig::default(),
+        )
+        .unwrap();
+        let sftp_stream = SftpStream::new(
+            sftp_session,
+            None,
+            Some(std::time::Duration::from_millis(50)),
+        );
+
+        let (mut client, server) = tokio::io::duplex(1024);
+
+        let process_stream = tokio::spawn(async move {
+            sftp_stream
+                .process_stream(server, CancellationToken::new())
+                .await
+        });
+
+        client.write_all(&build_init_frame()).await.unwrap();
+        read_response_frame(&mut client).await;
+
+        client.write_all(&build_open_write_frame()).await.unwrap();
+        let mut handle_response = read_response_frame(&mut client).await;
+
+        assert_eq!(102, handle_response.get_u8()); // SSH_FXP_HANDLE
+        assert_eq!(1, handle_response.get_u32());
+        let handle_length = handle_response.get_u32() as usize;
+        let handle = String::from_utf8(handle_response[..handle_length].to_vec()).unwrap();
+
+        // The client stays connected but sends nothing else.
+        let result = tokio::time::timeout(std::time::Duration::from_secs(5), process_stream)
+            .await
+            .expect("idle session should be closed");
+
+        assert_eq!(Ok(()), result.unwrap());
+        assert!(object_storage
+            .write_data(&handle, 0, Bytes::from("data"))
+            .await
+            .is_err());
+
+        drop(client);
+    }
+
+    #[tokio::test]
+    async fn test_process_stream_throttles_requests_over_rate_limit() {
+        let root = TempDir::new().unwrap();
+        let object_storage = Arc::new(FilesystemStorage::new(
+            root.path().to_path_buf(),
+            DEFAULT_MAX_HANDLES,
+        ));
+        let sftp_session = SftpSession::new(
+            object_storage,
+            String::from("test"),
+            &SftpSessionConfig::default(),
+        )
+        .unwrap();
+        let sftp_stream = SftpStream::new(sftp_session, Some(20), None);
+
+        let (mut client, server) = tokio::io::duplex(64 * 1024);
+
+        tokio::spawn(async move {
+            sftp_stream
+                .process_stream(server, CancellationToken::new())
+                .await
+        });
+
+        let started_at = std::time::Instant::now();
+
+        client.write_all(&build_init_frame()).await.unwrap();
+        read_response_frame(&mut client).await;
+
+        // The first 20 requests are allowed as a burst, and the next 10 are delayed by 50
+        // milliseconds each.
+        for _ in 0..29 {
+            client.write_all(&build_realpath_frame()).await.unwrap();
+        }
+
+        for _ in 0..29 {
+            let mut response = read_response_frame(&mut client).await;
+            assert_eq!(104, response.get_u8()); // SSH_FXP_NAME
+        }
+
+        assert!(started_at.elapsed() >= std::time::Duration::from_millis(450));
+    }
+
+    #[tokio::test]
+    async fn test_process_stream_with_unsupported_request() {
+        let root = TempDir::new().unwrap();
+        let object_storage = Arc::new(FilesystemStorage::new(
+            root.path().to_path_buf(),
+            DEFAULT_MAX_HANDLES,
+        ));
+        let sftp_session = SftpSession::new(
+            object_storage,
+            String::from("test"),
+            &SftpSessionConfig::default(),
+        )
+        .unwrap();
+        let sftp_stream = SftpStream::new(sftp_session, None, None);
+
+        let (mut client, server) = tokio::io::duplex(1024);
+
+        tokio::spawn(async move {
+            sftp_stream
+                .process_stream(server, CancellationToken::new())
+                .await
+        });
+
+        client.write_all(&build_init_frame()).await.unwrap();
+        read_response_frame(&mut client).await;
+
+        let handle = b"handle";
+        let mut frame = Vec::new();
+        frame.put_u32(29 + handle.len() as u32);
+        frame.put_u8(22); // SSH_FXP_BLOCK
+        frame.put_u32(7);
+        frame.put_u32(handle.len() as u32);
+        frame.put_slice(handle);
+        frame.put_u64(0); // offset
+        frame.put_u64(1024); // length
+        frame.put_u32(0); // lock mask
+
+        client.write_all(&frame).await.unwrap();
+        let mut response = read_response_frame(&mut client).await;
+
+        assert_eq!(101, response.get_u8()); // SSH_FXP_STATUS
+        assert_eq!(7, response.get_u32());
+        assert_eq!(8, response.get_u32()); // SSH_FX_OP_UNSUPPORTED
+
+        // The session continues with the next request.
+        client.write_all(&build_realpath_frame()).await.unwrap();
+        let mut response = read_response_frame(&mut client).await;
+
+        assert_eq!(104, response.get_u8()); // SSH_FXP_NAME
+    }
+
+    #[tokio::test]
+    async fn test_process_stream_with_invalid_request_payload() {
+        let root = TempDir::new().unwrap();
+        let object_storage = Arc::new(FilesystemStorage::new(
+            root.path().to_path_buf(),
+            DEFAULT_MAX_HANDLES,
+        ));
+        let sftp_session = SftpSession::new(
+            object_storage,
+            String::from("test"),
+            &SftpSessionConfig::default(),
+        )
+        .unwrap();
+        let sftp_stream = SftpStream::new(sftp_session, None, None);
+
+        let (mut client, server) = tokio::io::duplex(1024);
+
+        tokio::spawn(async move {
+            sftp_stream
+                .process_stream(server, CancellationToken::new())
+                .await
+        });
+
+        client.write_all(&build_init_frame()).await.unwrap();
+        read_response_frame(&mut client).await;
+
+        // The path's length is longer than the rest of the request.
+        let mut frame = Vec::new();
+        frame.put_u32(9);
+        frame.put_u8(16); // SSH_FXP_REALPATH
+        frame.put_u32(42);
+        frame.put_u32(100);
+
+        client.write_all(&frame).await.unwrap();
+        let mut response = read_response_frame(&mut client).await;
+
+        assert_eq!(101, response.get_u8()); // SSH_FXP_STATUS
+        assert_eq!(42, response.get_u32());
+        assert_eq!(5, response.get_u32()); // SSH_FX_BAD_MESSAGE
+    }
+
+    /// Storage that takes longer to read from the start of a file than from anywhere else.
+    struct SlowFirstReadStorage {
+        storage: Arc<dyn Storage>,
+    }
+
+    #[async_trait::async_trait]
+    impl Storage for SlowFirstReadStorage {
+        async fn init(&self) -> Result<(), Error> {
+            self.storage.init().await
+        }
+
+        async fn health_check(&self) -> Result<(), Error> {
+            self.storage.health_check().await
+        }
+
+        async fn get_authorized_keys_fingerprints(&self, user: &str) -> Result<Vec<String>, Error> {
+            self.storage.get_authorized_keys_fingerprints(user).await
+        }
+
+        async fn open_dir_handle(&self, dir_name: String) -> Result<String, Error> {
+            self.storage.open_dir_handle(dir_name).await
+        }
+
+        async fn create_dir(&self, dir_name: String) -> Result<(), Error> {
+            self.storage.create_dir(dir_name).await
+        }
+
+        async fn read_dir(&self, handle: &str) -> Result<Vec<File>, Error> {
+            self.storage.read_dir(handle).await
+        }
+
+        async fn remove_dir(&self, dir_name: String) -> Result<(), Error> {
+            self.storage.remove_dir(dir_name).await
+        }
+
+        async fn get_dir_size(&self, dir_name: String) -> Result<u64, Error> {
+            self.storage.get_dir_size(dir_name).await
+        }
+
+        async fn get_file_metadata(&self, file_name: String) -> Result<File, Error> {
+            self.storage.get_file_metadata(file_name).await
+        }
+
+        async fn set_file_metadata(
+            &self,
+            file_name: String,
+            file_attributes: FileAttributes,
+        ) -> Result<(), Error> {
+            self.storage
+                .set_file_metadata(file_name, file_attributes)
+                .await
+        }
+
+        async fn set_handle_metadata(
+            &self,
+            handle: &str,
+            file_attributes: FileAttributes,
+        ) -> Result<(), Error> {
+            self.storage
+                .set_handle_metadata(handle, file_attributes)
+                .await
+        }
+
+        async fn get_handle_metadata(&self, handle: &str) -> Result<File, Error> {
+            self.storage.get_handle_metadata(handle).await
+        }
+
+        async fn open_read_handle(&self, file_name: String) -> Result<String, Error> {
+            self.storage.open_read_handle(file_name).await
+        }
+
+        async fn read_data(&self, handle: &str, offset: u64, len: u32) -> Result<Vec<u8>, Error> {
+            if offset == 0 {
+                tokio::time::sleep(Duration::from_millis(200)).await;
+            }
+
+            self.storage.read_data(handle, offset, len).await
+        }
+
+        async fn open_write_handle(&self, file_name: String) -> Result<String, Error> {
+            self.storage.open_write_handle(file_name).await
+        }
+
+        async fn open_existing_write_handle(
+            &self,
+            file_name: String,
+            truncate: bool,
+        ) -> Result<String, Error> {
+            self.storage
+                .open_existing_write_handle(file_name, truncate)
+                .await
+        }
+
+        async fn open_read_write_handle(
+            &self,
+            file_name: String,
+            truncate: bool,
+        ) -> Result<String, Error> {
+            self.storage
+                .open_read_write_handle(file_name, truncate)
+                .await
+        }
+
+        async fn open_append_handle(
+            &self,
+            file_name: String,
+            create: bool,
+        ) -> Result<String, Error> {
+            self.storage.open_append_handle(file_name, create).await
+        }
+
+        async fn write_data(&self, handle: &str, offset: u64, data: Bytes) -> Result<(), Error> {
+            self.storage.write_data(handle, offset, data).await
+        }
+
+        async fn remove_file(&self, key: String) -> Result<(), Error> {
+            self.storage.remove_file(key).await
+        }
+
+        async fn close_handle(&self, handle: &str) -> Result<(), Error> {
+            self.storage.close_handle(handle).await
+        }
+
+        async fn fsync(&self, handle: &str) -> Result<(), Error> {
+            self.storage.fsync(handle).await
+        }
+
+        async fn rename(&self, current: String, new: String) -> Result<(), Error> {
+            self.storage.rename(current, new).await
+        }
+
+        async fn posix_rename(&self, current: String, new: String) -> Result<(), Error> {
+            self.storage.posix_rename(current, new).await
+        }
+
+        async fn copy_file(&self, current: String, new: String) -> Result<(), Error> {
+            self.storage.copy_file(current, new).await
+        }
+
+        async fn create_symlink(&self, link: String, target: String) -> Result<(), Error> {
+            self.storage.create_symlink(link, target).await
+        }
+
+        async fn read_symlink(&self, link: String) -> Result<String, Error> {
+            self.storage.read_symlink(link).await
+        }
+
+        async fn abort_write_handle(&self, handle: &str) -> Result<(), Error> {
+            self.storage.abort_write_handle(handle).await
+        }
+
+        async fn abort_write_handles(&self) -> Result<(), Error> {
+            self.storage.abort_write_handles().await
+        }
+    }
+
+    async fn read_response_frame(client: &mut tokio::io::DuplexStream) -> Bytes {
+        let response_length = client.read_u32().await.unwrap() as usize;
+        let mut response = vec![0; response_length];
+        client.read_exact(&mut response).await.unwrap();
+
+        Bytes::from(response)
+    }
+
+    async fn read_handle_response(client: &mut tokio::io::DuplexStream) -> Vec<u8> {
+        let mut response = read_response_frame(client).await;
+
+        assert_eq!(102, response.get_u8()); // SSH_FXP_HANDLE
+        response.get_u32();
+        let handle_length = response.get_u32() as usize;
+
+        response[..handle_length].to_vec()
+    }
+
+    fn build_init_frame() -> Vec<u8> {
+        let mut frame = Vec::new();
+
+        frame.put_u32(5);
+        frame.put_u8(1); // SSH_FXP_INIT
+        frame.put_u32(3);
+
+        frame
+    }
+
+    fn build_open_write_frame() -> Vec<u8> {
+        let filename = b"/home/test/file.txt";
+        let mut frame = Vec::new();
+
+        frame.put_u32(17 + filename.len() as u32);
+        frame.put_u8(3); // SSH_FXP_OPEN
+        frame.put_u32(1);
+        frame.put_u32(filename.len() as u32);
+        frame.put_slice(filename);
+        frame.put_u32(0x0000000a); // SSH_FXF_WRITE | SSH_FXF_CREAT
+        frame.put_u32(0);
+
+        frame
+    }
+
+    fn build_open_read_frame() -> Vec<u8> {
+        let filename = b"/home/test/file.txt";
+        let mut frame = Vec::new();
+
+        frame.put_u32(17 + filename.len() as u32);
+        frame.put_u8(3); // SSH_FXP_OPEN
+        frame.put_u32(4);
+        frame.put_u32(filename.len() as u32);
+        frame.put_slice(filename);
+        frame.put_u32(0x00000001); // SSH_FXF_READ
+        frame.put_u32(0);
+
+        frame
+    }
+
+    fn build_write_frame(handle: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut frame = Vec::new();
+
+        frame.put_u32(21 + handle.len() as u32 + data.len() as u32);
+        frame.put_u8(6); // SSH_FXP_WRITE
+        frame.put_u32(2);
+        frame.put_u32(handle.len() as u32);
+        frame.put_slice(handle);
+        frame.put_u64(0);
+        frame.put_u32(data.len() as u32);
+        frame.put_slice(data);
+
+        frame
+    }
+
+    fn build_close_frame(handle: &[u8]) -> Vec<u8> {
+        let mut frame = Vec::new();
+
+        frame.put_u32(9 + handle.len() as u32);
+        frame.put_u8(4); // SSH_FXP_CLOSE
+        frame.put_u32(3);
+        frame.put_u32(handle.len() as u32);
+        frame.put_slice(handle);
+
+        frame
+    }
+
+    fn build_read_frame(id: u32, handle: &[u8], offset: u64, len: u32) -> Vec<u8> {
+        let mut frame = Vec::new();
+
+        frame.put_u32(21 + handle.len() as u32);
+        frame.put_u8(5); // SSH_FXP_READ
+        frame.put_u32(id);
+        frame.put_u32(handle.len() as u32);
+        frame.put_slice(handle);
+        frame.put_u64(offset);
+        frame.put_u32(len);
+
+        frame
+    }
+
+    fn build_realpath_frame() -> Vec<u8> {
+        let mut frame = Vec::new();
 
-        let mut response_bytes = Bytes::from(&response);
-        stream.write_all_buf(&mut response_bytes).await?;
+        frame.put_u32(10);
+        frame.put_u8(16); // SSH_FXP_REALPATH
+        frame.put_u32(1);
+        frame.put_u32(1);
+        frame.put_u8(b'.');
 
-        Ok(())
+        frame
     }
 }
@@ -1,20 +1,77 @@
+use russh_keys::key::PublicKey;
+
+/// The key types that may start an authorized key, which distinguish the key from the options
+/// that may come before it.
+const KEY_TYPES: &[&str] = &[
+    "ssh-ed25519",
+    "ssh-rsa",
+    "ssh-dss",
+    "ecdsa-sha2-nistp256",
+    "ecdsa-sha2-nistp384",
+    "ecdsa-sha2-nistp521",
+    "sk-ssh-ed25519@openssh.com",
+    "sk-ecdsa-sha2-nistp256@openssh.com",
+];
+
 pub fn parse_authorized_keys(authorized_keys: &str) -> Vec<String> {
     authorized_keys
         .lines()
-        .filter(|line| !line.is_empty())
-        .filter_map(|line| {
-            let mut pieces = line.split_whitespace();
-
-            match (pieces.next(), pieces.next()) {
-                (Some(_), Some(key)) => russh_keys::parse_public_key_base64(key).ok(),
-                (Some(key), None) => russh_keys::parse_public_key_base64(key).ok(),
-                _ => None,
-            }
-        })
+        .filter_map(parse_authorized_key)
         .map(|key| key.fingerprint())
         .collect()
 }
 
+/// Parses a line of an authorized keys file, which has optional options, such as
+/// `no-pty,command="..."`, followed by the key type, the base64 encoded key, and an optional
+/// comment. Options are skipped, since they do not apply to SFTP sessions.
+fn parse_authorized_key(line: &str) -> Option<PublicKey> {
+    let line = line.trim();
+
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+
+    let starts_with_key_type = line
+        .split_whitespace()
+        .next()
+        .is_some_and(|piece| KEY_TYPES.contains(&piece));
+
+    // A line that is only a key, without a key type, has nothing after the first field.
+    let key = match skip_options(line) {
+        key if !starts_with_key_type && !key.is_empty() => key,
+        _ => line,
+    };
+
+    let mut pieces = key.split_whitespace();
+
+    match (pieces.next(), pieces.next()) {
+        (Some(_), Some(key)) => russh_keys::parse_public_key_base64(key).ok(),
+        (Some(key), None) => russh_keys::parse_public_key_base64(key).ok(),
+        _ => None,
+    }
+}
+
+/// Skips the options at the start of a line, which end at the first whitespace that is not in a
+/// quoted value.
+fn skip_options(line: &str) -> &str {
+    let mut in_quotes = false;
+    let mut escaped = false;
+
+    for (index, character) in line.char_indices() {
+        match character {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' => in_quotes = !in_quotes,
+            character if character.is_whitespace() && !in_quotes => {
+                return line[index..].trim_start();
+            }
+            _ => {}
+        }
+    }
+
+    ""
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -29,6 +86,67 @@ mod test {
         assert_eq!(2, authorized_keys.len());
     }
 
+    #[test]
+    fn test_parse_authorized_keys_str_with_plain_key() {
+        let authorized_keys = format!("ssh-ed25519 {}", TEST_ED25519_KEY);
+
+        assert_eq!(
+            vec![get_test_fingerprint()],
+            parse_authorized_keys(&authorized_keys)
+        );
+    }
+
+    #[test]
+    fn test_parse_authorized_keys_str_with_comment() {
+        let authorized_keys = format!("ssh-ed25519 {} user@host with spaces", TEST_ED25519_KEY);
+
+        assert_eq!(
+            vec![get_test_fingerprint()],
+            parse_authorized_keys(&authorized_keys)
+        );
+    }
+
+    #[test]
+    fn test_parse_authorized_keys_str_with_options() {
+        let authorized_keys = [
+            format!("no-pty ssh-ed25519 {} test", TEST_ED25519_KEY),
+            format!(
+                "no-pty,command=\"echo \\\"hello world\\\"\",from=\"10.0.0.1\" ssh-ed25519 {} test",
+                TEST_ED25519_KEY
+            ),
+            format!("restrict ssh-ed25519 {}", TEST_ED25519_KEY),
+        ];
+
+        for authorized_key in authorized_keys {
+            assert_eq!(
+                vec![get_test_fingerprint()],
+                parse_authorized_keys(&authorized_key),
+                "{}",
+                authorized_key
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_authorized_keys_str_with_comment_lines() {
+        let authorized_keys = format!(
+            "# ssh-ed25519 {}\n\nssh-ed25519 {} test\n",
+            TEST_ED25519_KEY, TEST_ED25519_KEY
+        );
+
+        assert_eq!(
+            vec![get_test_fingerprint()],
+            parse_authorized_keys(&authorized_keys)
+        );
+    }
+
+    #[test]
+    fn test_parse_authorized_keys_str_with_only_options() {
+        let authorized_keys = "no-pty,command=\"echo hello\"";
+
+        assert_eq!(0, parse_authorized_keys(authorized_keys).len());
+    }
+
     #[test]
     fn test_parse_authorized_keys_str_with_whitespace() {
         let authorized_keys = "    \n \n     \n  \n";
@@ -55,4 +173,13 @@ mod test {
 
         assert_eq!(0, authorized_keys.len());
     }
+
+    const TEST_ED25519_KEY: &str =
+        "AAAAC3NzaC1lZDI1NTE5AAAAIAIl1rX8ataKL7pSTnF5UIrRAgdWvjb+KHRf2oj6Kbgs";
+
+    fn get_test_fingerprint() -> String {
+        russh_keys::parse_public_key_base64(TEST_ED25519_KEY)
+            .unwrap()
+            .fingerprint()
+    }
 }
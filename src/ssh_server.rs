@@ -1,21 +1,30 @@
+use crate::audit::AuditLog;
+use crate::client_quirks::ClientQuirks;
 use crate::config::DrayConfig;
 use crate::error::Error;
-use crate::sftp_session::SftpSession;
+use crate::metrics;
+use crate::sftp_session::{self, SftpSession, SftpSessionConfig};
 use crate::sftp_stream::SftpStream;
-use crate::storage::{s3::S3StorageFactory, Storage, StorageFactory};
+use crate::storage::{Storage, StorageFactory};
 use async_trait::async_trait;
 use russh::SshId;
 use russh::{
-    server::{Auth, Config, Handler, Msg, Server, Session},
-    Channel, ChannelId,
+    server::{self, Auth, Config, Handler, Msg, Session},
+    Channel, ChannelId, Pty,
 };
 use russh_keys::{
     key::{self, PublicKey},
     PublicKeyBase64,
 };
+use socket2::{Domain, Protocol, Socket, TcpKeepalive, Type};
 use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
-use tokio::sync::{Mutex, RwLock};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::{Mutex, OwnedSemaphorePermit, RwLock, Semaphore};
+use tokio::task::JoinSet;
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
 use tracing::{debug, error, info};
 
 pub struct DraySshServer {
@@ -24,12 +33,98 @@ pub struct DraySshServer {
     object_storage: Arc<dyn Storage>,
     channels: Arc<Mutex<HashMap<ChannelId, Channel<Msg>>>>,
     user: RwLock<Option<String>>,
+    shutdown_token: CancellationToken,
+    task_tracker: TaskTracker,
+    session_limiter: Option<Arc<Semaphore>>,
+    connection_limiter: Option<Arc<ConnectionLimiter>>,
+    /// The connection's place in the count of connections from its IP address, which is released
+    /// when the connection closes.
+    _connection_permit: Option<ConnectionPermit>,
+    audit_log: Option<Arc<AuditLog>>,
+}
+
+/// Counts the open connections from each IP address, so that a single host cannot exhaust the
+/// server's connections.
+struct ConnectionLimiter {
+    max_connections_per_ip: usize,
+    connections: std::sync::Mutex<HashMap<IpAddr, usize>>,
+}
+
+/// An open connection from an IP address, which is no longer counted once it is dropped.
+struct ConnectionPermit {
+    ip: IpAddr,
+    connection_limiter: Arc<ConnectionLimiter>,
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        let mut connections = self
+            .connection_limiter
+            .connections
+            .lock()
+            .unwrap_or_else(|err| err.into_inner());
+
+        if let Some(count) = connections.get_mut(&self.ip) {
+            *count -= 1;
+
+            if *count == 0 {
+                connections.remove(&self.ip);
+            }
+        }
+    }
+}
+
+/// Signals a running server to shut down and waits for its sftp sessions to drain.
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    shutdown_token: CancellationToken,
+    task_tracker: TaskTracker,
+}
+
+impl ShutdownHandle {
+    /// Stops accepting connections and asks each sftp session to finish its current request.
+    /// An error is returned if the sessions have not drained within the timeout.
+    pub async fn shutdown(&self, drain_timeout: Duration) -> Result<(), Error> {
+        self.shutdown_token.cancel();
+        self.task_tracker.close();
+
+        tokio::time::timeout(drain_timeout, self.task_tracker.wait())
+            .await
+            .map_err(|_| {
+                Error::Failure(format!(
+                    "Timed out waiting for {} sftp sessions to drain.",
+                    self.task_tracker.len()
+                ))
+            })
+    }
 }
 
 impl DraySshServer {
-    pub async fn new(dray_config: DrayConfig) -> DraySshServer {
-        let object_storage_factory = Arc::from(S3StorageFactory::new(&dray_config.s3).await);
+    pub async fn new(dray_config: DrayConfig) -> Result<DraySshServer, Error> {
+        let object_storage_factory = dray_config.create_storage_factory().await?;
+
+        Ok(DraySshServer::with_storage_factory(
+            dray_config,
+            object_storage_factory,
+        ))
+    }
+
+    /// Creates a server that stores files with the given factory instead of the configured
+    /// storage backend, such as in-memory storage for tests.
+    pub fn with_storage_factory(
+        dray_config: DrayConfig,
+        object_storage_factory: Arc<dyn StorageFactory>,
+    ) -> DraySshServer {
         let object_storage = object_storage_factory.create_storage();
+        let session_limiter = dray_config
+            .max_sessions
+            .map(|max_sessions| Arc::new(Semaphore::new(max_sessions)));
+        let connection_limiter = dray_config.max_conn_per_ip.map(|max_connections_per_ip| {
+            Arc::new(ConnectionLimiter {
+                max_connections_per_ip,
+                connections: std::sync::Mutex::new(HashMap::new()),
+            })
+        });
 
         DraySshServer {
             dray_config: Arc::from(dray_config),
@@ -37,48 +132,148 @@ impl DraySshServer {
             object_storage,
             channels: Arc::from(Mutex::from(HashMap::new())),
             user: RwLock::from(Option::None),
+            shutdown_token: CancellationToken::new(),
+            task_tracker: TaskTracker::new(),
+            session_limiter,
+            connection_limiter,
+            _connection_permit: None,
+            audit_log: None,
+        }
+    }
+
+    pub fn get_shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle {
+            shutdown_token: self.shutdown_token.clone(),
+            task_tracker: self.task_tracker.clone(),
         }
     }
 
+    /// Creates the user's home directory if it does not exist, so that new users start in an
+    /// empty directory. Existing homes are only checked, so this is cheap on later logins.
+    async fn create_home_if_missing(&self, user: &str) -> Result<(), Error> {
+        let home = sftp_session::get_home(&self.dray_config.home_template, user)?;
+
+        match self.object_storage.get_file_metadata(home.clone()).await {
+            Ok(_) => Ok(()),
+            Err(Error::NoSuchFile) => {
+                info!("Creating home directory {} for {}", home, user);
+                self.object_storage.create_dir(home).await
+            }
+            Err(error) => Err(error),
+        }
+    }
+
+    pub fn get_object_storage(&self) -> Arc<dyn Storage> {
+        self.object_storage.clone()
+    }
+
     pub async fn health_check(&self) -> Result<(), Error> {
         self.object_storage.health_check().await?;
         Ok(())
     }
 
     pub async fn run_server(mut self) -> Result<(), Error> {
-        let ssh_config = Config {
-            server_id: SshId::Standard(format!(
-                "SSH-2.0-{}_{}",
-                env!("CARGO_PKG_NAME"),
-                env!("CARGO_PKG_VERSION")
-            )),
-            keys: self.dray_config.get_ssh_keys()?,
-            window_size: 16777216,
-            maximum_packet_size: 32768,
-            ..Default::default()
-        };
+        let ssh_config = Arc::new(build_ssh_config(
+            &self.dray_config,
+            self.dray_config.get_ssh_keys()?,
+        )?);
 
-        let ssh_config = Arc::new(ssh_config);
-        let addr = &self.dray_config.get_host_socket_addr()?;
+        self.audit_log = self
+            .dray_config
+            .audit_log
+            .as_deref()
+            .map(AuditLog::open)
+            .transpose()?
+            .map(Arc::new);
 
-        info!("Binding to Host {}", self.dray_config.host);
+        let listeners = bind_listeners(
+            &self.dray_config.get_host_socket_addrs()?,
+            self.dray_config.get_listen_backlog()?,
+            self.dray_config.get_tcp_keepalive()?,
+        )?;
 
-        self.run_on_address(ssh_config, addr)
-            .await
-            .map_err(|error| Error::Failure(error.to_string()))
+        // Each address is served by its own copy of the server, which shares the state of this
+        // server with the connections it accepts.
+        let mut servers = JoinSet::new();
+
+        for listener in listeners {
+            let server = self.new_connection(None);
+            let ssh_config = ssh_config.clone();
+
+            servers.spawn(async move { server.run_on_listener(ssh_config, &listener).await });
+        }
+
+        let shutdown_token = self.shutdown_token.clone();
+
+        tokio::select! {
+            Some(result) = servers.join_next() => {
+                match result {
+                    Ok(result) => result,
+                    Err(error) => Err(Error::Failure(error.to_string())),
+                }
+            }
+            _ = shutdown_token.cancelled() => {
+                info!("Stopped accepting connections");
+                Ok(())
+            }
+        }
     }
-}
 
-impl Server for DraySshServer {
-    type Handler = Self;
+    /// Accepts connections on a listener and runs an SSH session for each of them. Connections
+    /// over the limit for their IP address are closed before the SSH handshake, so that they do
+    /// not hold any session state.
+    pub async fn run_on_listener(
+        &self,
+        ssh_config: Arc<Config>,
+        listener: &TcpListener,
+    ) -> Result<(), Error> {
+        loop {
+            let (stream, peer_addr) = listener.accept().await?;
+
+            let connection_permit =
+                match acquire_connection_permit(&self.connection_limiter, Some(peer_addr)) {
+                    Ok(connection_permit) => connection_permit,
+                    Err(error) => {
+                        info!("Rejected connection: {}", error);
+                        metrics::record_rejected_connection();
+                        continue;
+                    }
+                };
 
-    fn new_client(&mut self, _peer_addr: Option<std::net::SocketAddr>) -> Self::Handler {
+            let handler = self.new_connection(connection_permit);
+            let ssh_config = ssh_config.clone();
+
+            tokio::spawn(async move {
+                let session = match server::run_stream(ssh_config, stream, handler).await {
+                    Ok(session) => session,
+                    Err(error) => {
+                        debug!("Connection setup from {} failed: {}", peer_addr, error);
+                        return;
+                    }
+                };
+
+                match session.await {
+                    Ok(_) => debug!("Connection from {} closed", peer_addr),
+                    Err(error) => debug!("Connection from {} closed: {}", peer_addr, error),
+                }
+            });
+        }
+    }
+
+    /// Creates the handler for a connection, which shares the state of this server.
+    fn new_connection(&self, connection_permit: Option<ConnectionPermit>) -> DraySshServer {
         DraySshServer {
             dray_config: self.dray_config.clone(),
             object_storage_factory: self.object_storage_factory.clone(),
             object_storage: self.object_storage_factory.create_storage(),
             channels: Arc::from(Mutex::from(HashMap::new())),
             user: RwLock::from(None),
+            shutdown_token: self.shutdown_token.clone(),
+            task_tracker: self.task_tracker.clone(),
+            session_limiter: self.session_limiter.clone(),
+            connection_limiter: self.connection_limiter.clone(),
+            _connection_permit: connection_permit,
+            audit_log: self.audit_log.clone(),
         }
     }
 }
@@ -87,11 +282,43 @@ impl Server for DraySshServer {
 impl Handler for DraySshServer {
     type Error = Error;
 
+    async fn auth_none(&mut self, _user: &str) -> Result<Auth, Self::Error> {
+        Ok(Auth::Reject {
+            proceed_with_methods: Option::None,
+        })
+    }
+
+    async fn auth_password(&mut self, _user: &str, _password: &str) -> Result<Auth, Self::Error> {
+        Ok(Auth::Reject {
+            proceed_with_methods: Option::None,
+        })
+    }
+
+    async fn auth_publickey_offered(
+        &mut self,
+        _user: &str,
+        _public_key: &PublicKey,
+    ) -> Result<Auth, Self::Error> {
+        Ok(Auth::Accept)
+    }
+
     async fn auth_publickey(
         &mut self,
         user: &str,
         public_key: &PublicKey,
     ) -> Result<Auth, Self::Error> {
+        // Users that are not allowed are rejected before their keys are looked up, so unknown
+        // users do not cause requests to storage.
+        if !self.dray_config.is_user_allowed(user) {
+            info!(
+                "Rejected public key authentication attempt from {} who is not an allowed user",
+                user
+            );
+            return Ok(Auth::Reject {
+                proceed_with_methods: Option::None,
+            });
+        }
+
         let public_key =
             key::parse_public_key(&public_key.public_key_bytes(), Option::None).unwrap();
 
@@ -119,6 +346,14 @@ impl Handler for DraySshServer {
                     user
                 );
 
+                // The user has authenticated, so a failure to create their home is logged rather
+                // than rejecting them. Requests in the missing home fail with their own errors.
+                if self.dray_config.auto_create_home {
+                    if let Err(error) = self.create_home_if_missing(user).await {
+                        error!("Failed to create home directory for {}: {}", user, error);
+                    }
+                }
+
                 {
                     let mut self_user = self.user.write().await;
                     *self_user = Some(user.to_string());
@@ -161,6 +396,102 @@ impl Handler for DraySshServer {
         Ok(())
     }
 
+    // Only the sftp subsystem is supported, so requests for shells, commands, terminals, and
+    // forwarding are refused rather than ignored.
+
+    async fn shell_request(
+        &mut self,
+        channel_id: ChannelId,
+        session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        refuse_channel_request(channel_id, "shell", session);
+        Ok(())
+    }
+
+    async fn exec_request(
+        &mut self,
+        channel_id: ChannelId,
+        _data: &[u8],
+        session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        refuse_channel_request(channel_id, "exec", session);
+        Ok(())
+    }
+
+    async fn pty_request(
+        &mut self,
+        channel_id: ChannelId,
+        _term: &str,
+        _col_width: u32,
+        _row_height: u32,
+        _pix_width: u32,
+        _pix_height: u32,
+        _modes: &[(Pty, u32)],
+        session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        refuse_channel_request(channel_id, "pty", session);
+        Ok(())
+    }
+
+    async fn x11_request(
+        &mut self,
+        channel_id: ChannelId,
+        _single_connection: bool,
+        _x11_auth_protocol: &str,
+        _x11_auth_cookie: &str,
+        _x11_screen_number: u32,
+        session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        refuse_channel_request(channel_id, "x11", session);
+        Ok(())
+    }
+
+    async fn agent_request(
+        &mut self,
+        channel_id: ChannelId,
+        _session: &mut Session,
+    ) -> Result<bool, Self::Error> {
+        info!("Refused agent forwarding request on channel {}", channel_id);
+        Ok(false)
+    }
+
+    async fn channel_open_direct_tcpip(
+        &mut self,
+        channel: Channel<Msg>,
+        _host_to_connect: &str,
+        _port_to_connect: u32,
+        _originator_address: &str,
+        _originator_port: u32,
+        _session: &mut Session,
+    ) -> Result<bool, Self::Error> {
+        info!("Refused port forwarding channel {}", channel.id());
+        Ok(false)
+    }
+
+    async fn channel_open_x11(
+        &mut self,
+        channel: Channel<Msg>,
+        _originator_address: &str,
+        _originator_port: u32,
+        _session: &mut Session,
+    ) -> Result<bool, Self::Error> {
+        info!("Refused x11 channel {}", channel.id());
+        Ok(false)
+    }
+
+    async fn tcpip_forward(
+        &mut self,
+        address: &str,
+        port: &mut u32,
+        _session: &mut Session,
+    ) -> Result<bool, Self::Error> {
+        info!(
+            "Refused remote port forwarding request for {}:{}",
+            address, port
+        );
+        Ok(false)
+    }
+
     async fn subsystem_request(
         &mut self,
         channel_id: ChannelId,
@@ -173,6 +504,16 @@ impl Handler for DraySshServer {
             return Ok(());
         }
 
+        let session_permit = match acquire_session_permit(&self.session_limiter) {
+            Ok(session_permit) => session_permit,
+            Err(error) => {
+                error!("Failed to start sftp subsystem: {}", error);
+                metrics::record_rejected_session();
+                session.channel_failure(channel_id);
+                return Ok(());
+            }
+        };
+
         let user = {
             let user = self.user.read().await;
             user.clone()
@@ -206,18 +547,40 @@ impl Handler for DraySshServer {
             }
         };
 
+        let sftp_session = match SftpSession::new(
+            self.object_storage.clone(),
+            user,
+            &SftpSessionConfig::from(self.dray_config.as_ref()),
+        ) {
+            Ok(sftp_session) => sftp_session
+                .with_audit_log(self.audit_log.clone())
+                .with_client_quirks(ClientQuirks::from_ssh_id(&String::from_utf8_lossy(
+                    session.remote_sshid(),
+                ))),
+            Err(error) => {
+                error!("Failed to start sftp subsystem: {}", error);
+                session.channel_failure(channel_id);
+                return Ok(());
+            }
+        };
+
         session.channel_success(channel_id);
 
         let handle = session.handle();
-        let sftp_session = SftpSession::new(self.object_storage.clone(), user);
-        let sftp_stream = SftpStream::new(sftp_session);
+        let sftp_stream = SftpStream::new(
+            sftp_session,
+            self.dray_config.session_rps,
+            self.dray_config.get_idle_timeout()?,
+        );
+        let shutdown_token = self.shutdown_token.clone();
 
-        tokio::spawn(async move {
+        self.task_tracker.spawn(async move {
             info!("Sftp subsystem starting");
+            metrics::increment_active_sessions();
 
             let stream = channel.into_stream();
 
-            match sftp_stream.process_stream(stream).await {
+            match sftp_stream.process_stream(stream, shutdown_token).await {
                 Ok(_) => info!("Sftp subsystem finished"),
                 Err(error) => error!("Sftp subsystem failed: {}", error),
             };
@@ -228,8 +591,742 @@ impl Handler for DraySshServer {
                 Ok(_) => debug!("Successfully closed channel"),
                 Err(_) => error!("Failed to close channel"),
             };
+
+            metrics::decrement_active_sessions();
+            drop(session_permit);
         });
 
         Ok(())
     }
 }
+
+fn build_ssh_config(dray_config: &DrayConfig, keys: Vec<key::KeyPair>) -> Result<Config, Error> {
+    // The server configuration lives for as long as the server, and russh requires the banner
+    // to be static, so it is leaked rather than copied into each connection.
+    let auth_banner = dray_config
+        .get_login_banner()?
+        .map(|login_banner| &*Box::leak(login_banner.into_boxed_str()));
+
+    Ok(Config {
+        server_id: SshId::Standard(format!(
+            "SSH-2.0-{}_{}",
+            env!("CARGO_PKG_NAME"),
+            env!("CARGO_PKG_VERSION")
+        )),
+        keys,
+        auth_banner,
+        window_size: dray_config.ssh_window_size,
+        maximum_packet_size: dray_config.ssh_max_packet,
+        ..Default::default()
+    })
+}
+
+fn refuse_channel_request(channel_id: ChannelId, request: &str, session: &mut Session) {
+    info!(
+        "Refused {} request on channel {} because only the sftp subsystem is supported",
+        request, channel_id
+    );
+    session.channel_failure(channel_id);
+}
+
+/// Binds a listener to each address. When IPv4 and IPv6 addresses are both configured, IPv6
+/// listeners only accept IPv6 connections, so that they do not conflict with IPv4 listeners on the
+/// same port. Otherwise, an IPv6 listener on an unspecified address such as `[::]` accepts both.
+fn bind_listeners(
+    addrs: &[SocketAddr],
+    backlog: i32,
+    keepalive: Option<Duration>,
+) -> Result<Vec<TcpListener>, Error> {
+    let only_v6 = addrs.iter().any(SocketAddr::is_ipv4);
+
+    addrs
+        .iter()
+        .map(|addr| {
+            let listener = bind_listener(*addr, only_v6, backlog, keepalive)?;
+
+            info!("Binding to Host {}", listener.local_addr()?);
+
+            Ok(listener)
+        })
+        .collect()
+}
+
+/// Binds a listener for an address. Keepalive is set on the listener, and connections inherit it
+/// when they are accepted.
+fn bind_listener(
+    addr: SocketAddr,
+    only_v6: bool,
+    backlog: i32,
+    keepalive: Option<Duration>,
+) -> Result<TcpListener, Error> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+
+    if addr.is_ipv6() {
+        socket.set_only_v6(only_v6)?;
+    }
+
+    if let Some(keepalive) = keepalive {
+        socket.set_tcp_keepalive(&TcpKeepalive::new().with_time(keepalive))?;
+    }
+
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(backlog)?;
+
+    Ok(TcpListener::from_std(socket.into())?)
+}
+
+/// Reserves one of the limited number of concurrent sftp sessions. The session is released when
+/// the permit is dropped. No permit is needed when sessions are not limited.
+fn acquire_session_permit(
+    session_limiter: &Option<Arc<Semaphore>>,
+) -> Result<Option<OwnedSemaphorePermit>, Error> {
+    match session_limiter {
+        Some(session_limiter) => session_limiter
+            .clone()
+            .try_acquire_owned()
+            .map(Some)
+            .map_err(|_| Error::Failure("The maximum number of sessions are active.".to_string())),
+        None => Ok(None),
+    }
+}
+
+/// Counts a connection from a peer, failing if its IP address already has the maximum number of
+/// connections open. Connections without a peer address are not counted.
+fn acquire_connection_permit(
+    connection_limiter: &Option<Arc<ConnectionLimiter>>,
+    peer_addr: Option<SocketAddr>,
+) -> Result<Option<ConnectionPermit>, Error> {
+    let (connection_limiter, ip) = match (connection_limiter, peer_addr) {
+        (Some(connection_limiter), Some(peer_addr)) => (connection_limiter, peer_addr.ip()),
+        _ => return Ok(None),
+    };
+
+    let mut connections = connection_limiter
+        .connections
+        .lock()
+        .unwrap_or_else(|err| err.into_inner());
+    let count = connections.entry(ip).or_insert(0);
+
+    if *count >= connection_limiter.max_connections_per_ip {
+        return Err(Error::Failure(format!(
+            "The maximum number of connections from {} are open.",
+            ip
+        )));
+    }
+
+    *count += 1;
+
+    Ok(Some(ConnectionPermit {
+        ip,
+        connection_limiter: connection_limiter.clone(),
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::storage::{memory::InMemoryStorageFactory, DEFAULT_MAX_HANDLES};
+    use russh::{client, ChannelMsg};
+    use tokio::io::AsyncReadExt;
+
+    const TEST_PUBLIC_KEY: &str =
+        "AAAAC3NzaC1lZDI1NTE5AAAAIAIl1rX8ataKL7pSTnF5UIrRAgdWvjb+KHRf2oj6Kbgs";
+
+    #[tokio::test]
+    async fn test_auth_publickey_with_allowed_user() {
+        let mut server = create_server_with_authorized_key(Some("test,other")).await;
+
+        let auth = server
+            .auth_publickey("test", &parse_test_public_key())
+            .await
+            .unwrap();
+
+        assert!(matches!(auth, Auth::Accept));
+        assert_eq!(Some(String::from("test")), *server.user.read().await);
+    }
+
+    #[tokio::test]
+    async fn test_auth_publickey_with_disallowed_user() {
+        let mut server = create_server_with_authorized_key(Some("other")).await;
+
+        // The user has an authorized key, but is rejected before the key is checked.
+        let auth = server
+            .auth_publickey("test", &parse_test_public_key())
+            .await
+            .unwrap();
+
+        assert!(matches!(auth, Auth::Reject { .. }));
+        assert_eq!(None, *server.user.read().await);
+    }
+
+    #[tokio::test]
+    async fn test_auth_publickey_without_allowed_users() {
+        let mut server = create_server_with_authorized_key(None).await;
+
+        let auth = server
+            .auth_publickey("test", &parse_test_public_key())
+            .await
+            .unwrap();
+
+        assert!(matches!(auth, Auth::Accept));
+    }
+
+    #[test]
+    fn test_build_ssh_config_with_channel_sizes() {
+        let dray_config: DrayConfig = envy::prefixed("DRAY_")
+            .from_iter(vec![
+                (String::from("DRAY_HOST"), String::from("127.0.0.1:0")),
+                (String::from("DRAY_SSH_KEY_PATHS"), String::new()),
+                (
+                    String::from("DRAY_SSH_WINDOW_SIZE"),
+                    String::from("1048576"),
+                ),
+                (String::from("DRAY_SSH_MAX_PACKET"), String::from("16384")),
+            ])
+            .unwrap();
+
+        let ssh_config = build_ssh_config(&dray_config, vec![]).unwrap();
+
+        assert_eq!(1048576, ssh_config.window_size);
+        assert_eq!(16384, ssh_config.maximum_packet_size);
+    }
+
+    #[test]
+    fn test_build_ssh_config_with_default_channel_sizes() {
+        let dray_config: DrayConfig = envy::prefixed("DRAY_")
+            .from_iter(vec![
+                (String::from("DRAY_HOST"), String::from("127.0.0.1:0")),
+                (String::from("DRAY_SSH_KEY_PATHS"), String::new()),
+            ])
+            .unwrap();
+
+        let ssh_config = build_ssh_config(&dray_config, vec![]).unwrap();
+
+        assert_eq!(16777216, ssh_config.window_size);
+        assert_eq!(32768, ssh_config.maximum_packet_size);
+    }
+
+    #[tokio::test]
+    async fn test_auth_publickey_with_auto_create_home() {
+        let mut server = create_server_with_env(vec![
+            (String::from("DRAY_HOST"), String::from("127.0.0.1:0")),
+            (String::from("DRAY_SSH_KEY_PATHS"), String::new()),
+            (String::from("DRAY_AUTO_CREATE_HOME"), String::from("true")),
+        ])
+        .await;
+        let object_storage = server.get_object_storage();
+
+        assert_eq!(
+            Err(Error::NoSuchFile),
+            object_storage
+                .get_file_metadata(String::from("/home/test"))
+                .await
+                .map(|_| ())
+        );
+
+        let auth = server
+            .auth_publickey("test", &parse_test_public_key())
+            .await
+            .unwrap();
+
+        assert!(matches!(auth, Auth::Accept));
+        assert!(object_storage
+            .get_file_metadata(String::from("/home/test"))
+            .await
+            .unwrap()
+            .file_attributes
+            .is_dir());
+
+        let handle = object_storage
+            .open_write_handle(String::from("/home/test/file.txt"))
+            .await
+            .unwrap();
+        object_storage.close_handle(&handle).await.unwrap();
+
+        // The home exists on the second login, so it is left as it is.
+        let auth = server
+            .auth_publickey("test", &parse_test_public_key())
+            .await
+            .unwrap();
+
+        assert!(matches!(auth, Auth::Accept));
+        assert!(object_storage
+            .get_file_metadata(String::from("/home/test/file.txt"))
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_auth_publickey_without_auto_create_home() {
+        let mut server = create_server_with_authorized_key(None).await;
+
+        let auth = server
+            .auth_publickey("test", &parse_test_public_key())
+            .await
+            .unwrap();
+
+        assert!(matches!(auth, Auth::Accept));
+        assert!(server
+            .get_object_storage()
+            .get_file_metadata(String::from("/home/test"))
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_login_banner_is_sent_before_authentication() {
+        let (_client, banner) = connect_test_client(vec![(
+            String::from("DRAY_LOGIN_BANNER"),
+            String::from("Authorized use only."),
+        )])
+        .await;
+
+        assert_eq!(
+            Some(String::from("Authorized use only.")),
+            *banner.lock().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_login_banner_is_not_sent_without_configuration() {
+        let (_client, banner) = connect_test_client(vec![]).await;
+
+        assert_eq!(None, *banner.lock().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_exec_request_is_refused() {
+        let (_client, mut channel) = open_test_channel().await;
+
+        channel.exec(true, "ls").await.unwrap();
+
+        assert!(matches!(
+            wait_for_reply(&mut channel).await,
+            ChannelMsg::Failure
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_shell_and_pty_requests_are_refused() {
+        let (_client, mut channel) = open_test_channel().await;
+
+        channel
+            .request_pty(true, "xterm", 80, 24, 0, 0, &[])
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            wait_for_reply(&mut channel).await,
+            ChannelMsg::Failure
+        ));
+
+        channel.request_shell(true).await.unwrap();
+
+        assert!(matches!(
+            wait_for_reply(&mut channel).await,
+            ChannelMsg::Failure
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_sftp_subsystem_request_is_accepted() {
+        let (_client, mut channel) = open_test_channel().await;
+
+        channel.request_subsystem(true, "sftp").await.unwrap();
+
+        assert!(matches!(
+            wait_for_reply(&mut channel).await,
+            ChannelMsg::Success
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_port_forwarding_is_refused() {
+        let (client, _channel) = open_test_channel().await;
+
+        assert!(client
+            .channel_open_direct_tcpip("127.0.0.1", 22, "127.0.0.1", 50000)
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn test_acquire_session_permit_limits_sessions() {
+        let session_limiter = Some(Arc::new(Semaphore::new(1)));
+
+        let session_permit = acquire_session_permit(&session_limiter).unwrap();
+
+        assert!(session_permit.is_some());
+        assert!(acquire_session_permit(&session_limiter).is_err());
+
+        drop(session_permit);
+
+        assert!(acquire_session_permit(&session_limiter).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_acquire_connection_permit_limits_connections_per_ip() {
+        let connection_limiter = Some(Arc::new(ConnectionLimiter {
+            max_connections_per_ip: 2,
+            connections: std::sync::Mutex::new(HashMap::new()),
+        }));
+        let peer_addr = Some("192.0.2.1:50000".parse().unwrap());
+
+        let first_permit = acquire_connection_permit(&connection_limiter, peer_addr).unwrap();
+        let second_permit = acquire_connection_permit(&connection_limiter, peer_addr).unwrap();
+
+        assert!(first_permit.is_some());
+        assert!(second_permit.is_some());
+        assert!(acquire_connection_permit(&connection_limiter, peer_addr).is_err());
+
+        // Connections from other addresses are counted separately.
+        assert!(acquire_connection_permit(
+            &connection_limiter,
+            Some("192.0.2.2:50000".parse().unwrap())
+        )
+        .unwrap()
+        .is_some());
+
+        drop(first_permit);
+
+        assert!(acquire_connection_permit(&connection_limiter, peer_addr)
+            .unwrap()
+            .is_some());
+    }
+
+    #[test]
+    fn test_acquire_connection_permit_releases_address_when_closed() {
+        let connection_limiter = Arc::new(ConnectionLimiter {
+            max_connections_per_ip: 1,
+            connections: std::sync::Mutex::new(HashMap::new()),
+        });
+
+        let permit = acquire_connection_permit(
+            &Some(connection_limiter.clone()),
+            Some("192.0.2.1:50000".parse().unwrap()),
+        )
+        .unwrap();
+        drop(permit);
+
+        assert!(connection_limiter.connections.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_acquire_connection_permit_without_limit_or_peer() {
+        let connection_limiter = Some(Arc::new(ConnectionLimiter {
+            max_connections_per_ip: 1,
+            connections: std::sync::Mutex::new(HashMap::new()),
+        }));
+
+        assert!(
+            acquire_connection_permit(&None, Some("192.0.2.1:50000".parse().unwrap()))
+                .unwrap()
+                .is_none()
+        );
+        assert!(acquire_connection_permit(&connection_limiter, None)
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_run_on_listener_closes_connections_over_limit_per_ip() {
+        let server = create_server_with_env(vec![
+            (String::from("DRAY_HOST"), String::from("127.0.0.1:0")),
+            (String::from("DRAY_SSH_KEY_PATHS"), String::new()),
+            (String::from("DRAY_MAX_CONN_PER_IP"), String::from("1")),
+        ])
+        .await;
+        let ssh_config = Arc::new(
+            build_ssh_config(
+                &server.dray_config,
+                vec![key::KeyPair::generate_ed25519().unwrap()],
+            )
+            .unwrap(),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move { server.run_on_listener(ssh_config, &listener).await });
+
+        let mut first_stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        assert!(read_ssh_id(&mut first_stream).await.starts_with("SSH-2.0-"));
+
+        // The connection is closed before the server sends its identification.
+        let mut rejected_stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        assert_eq!("", read_ssh_id(&mut rejected_stream).await);
+
+        drop(first_stream);
+
+        // The first connection's place is released once the server notices that it closed.
+        for _ in 0..50 {
+            let mut next_stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+
+            if !read_ssh_id(&mut next_stream).await.is_empty() {
+                return;
+            }
+
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+
+        panic!("The connection limit was not released");
+    }
+
+    /// Reads the identification line that the server sends when a connection starts, which is
+    /// empty if the connection is closed instead.
+    async fn read_ssh_id(stream: &mut tokio::net::TcpStream) -> String {
+        let mut ssh_id = Vec::new();
+        let mut byte = [0; 1];
+
+        while stream.read(&mut byte).await.unwrap_or(0) == 1 && byte[0] != b'\n' {
+            ssh_id.push(byte[0]);
+        }
+
+        String::from_utf8(ssh_id).unwrap()
+    }
+
+    #[test]
+    fn test_acquire_session_permit_without_limit() {
+        assert!(acquire_session_permit(&None).unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_waits_for_sessions_to_drain() {
+        let shutdown_handle = create_shutdown_handle();
+        let shutdown_token = shutdown_handle.shutdown_token.clone();
+
+        let session = shutdown_handle
+            .task_tracker
+            .spawn(async move { shutdown_token.cancelled().await });
+
+        assert_eq!(
+            Ok(()),
+            shutdown_handle.shutdown(Duration::from_secs(10)).await
+        );
+        assert!(session.is_finished());
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_times_out_with_undrained_session() {
+        let shutdown_handle = create_shutdown_handle();
+
+        shutdown_handle
+            .task_tracker
+            .spawn(tokio::time::sleep(Duration::from_secs(60)));
+
+        assert!(shutdown_handle
+            .shutdown(Duration::from_millis(10))
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_bind_listeners_with_ipv4_and_ipv6() {
+        let listeners = bind_listeners(
+            &["127.0.0.1:0".parse().unwrap(), "[::1]:0".parse().unwrap()],
+            1024,
+            None,
+        )
+        .unwrap();
+
+        for listener in listeners {
+            let local_addr = listener.local_addr().unwrap();
+
+            tokio::net::TcpStream::connect(local_addr).await.unwrap();
+            listener.accept().await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_bind_listeners_with_dual_stack() {
+        let listeners = bind_listeners(&["[::]:0".parse().unwrap()], 1024, None).unwrap();
+        let port = listeners[0].local_addr().unwrap().port();
+
+        for addr in [format!("127.0.0.1:{}", port), format!("[::1]:{}", port)] {
+            tokio::net::TcpStream::connect(addr).await.unwrap();
+            listeners[0].accept().await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_bind_listeners_with_ipv4_and_ipv6_on_same_port() {
+        let ipv4_listener = bind_listeners(&["0.0.0.0:0".parse().unwrap()], 1024, None).unwrap();
+        let port = ipv4_listener[0].local_addr().unwrap().port();
+        drop(ipv4_listener);
+
+        let listeners = bind_listeners(
+            &[
+                format!("0.0.0.0:{}", port).parse().unwrap(),
+                format!("[::]:{}", port).parse().unwrap(),
+            ],
+            1024,
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(2, listeners.len());
+    }
+
+    #[tokio::test]
+    async fn test_bind_listeners_with_backlog_and_keepalive() {
+        let listeners = bind_listeners(
+            &["127.0.0.1:0".parse().unwrap()],
+            16,
+            Some(Duration::from_secs(30)),
+        )
+        .unwrap();
+        let local_addr = listeners[0].local_addr().unwrap();
+
+        tokio::net::TcpStream::connect(local_addr).await.unwrap();
+        let (stream, _) = listeners[0].accept().await.unwrap();
+
+        assert!(socket2::SockRef::from(&stream).keepalive().unwrap());
+    }
+
+    async fn create_server_with_authorized_key(allowed_users: Option<&str>) -> DraySshServer {
+        let mut env = vec![
+            (String::from("DRAY_HOST"), String::from("127.0.0.1:0")),
+            (String::from("DRAY_SSH_KEY_PATHS"), String::new()),
+        ];
+
+        if let Some(allowed_users) = allowed_users {
+            env.push((
+                String::from("DRAY_ALLOWED_USERS"),
+                String::from(allowed_users),
+            ));
+        }
+
+        create_server_with_env(env).await
+    }
+
+    async fn create_server_with_env(env: Vec<(String, String)>) -> DraySshServer {
+        let dray_config = envy::prefixed("DRAY_").from_iter(env).unwrap();
+
+        let storage_factory = InMemoryStorageFactory::new(DEFAULT_MAX_HANDLES);
+        storage_factory
+            .put_file(
+                "/.ssh/test/authorized_keys",
+                format!("ssh-ed25519 {} test", TEST_PUBLIC_KEY).into_bytes(),
+            )
+            .await
+            .unwrap();
+
+        DraySshServer::with_storage_factory(dray_config, Arc::new(storage_factory))
+    }
+
+    /// Starts a server on a random port, authenticates a client, and opens a session channel.
+    async fn open_test_channel() -> (client::Handle<TestClient>, Channel<client::Msg>) {
+        let (client, _) = connect_test_client(vec![]).await;
+        let channel = client.channel_open_session().await.unwrap();
+
+        (client, channel)
+    }
+
+    /// Starts a server on a random port with additional configuration, and authenticates a
+    /// client. The banner the client received, if any, is returned with the client.
+    async fn connect_test_client(
+        env: Vec<(String, String)>,
+    ) -> (
+        client::Handle<TestClient>,
+        Arc<std::sync::Mutex<Option<String>>>,
+    ) {
+        let client_key = key::KeyPair::generate_ed25519().unwrap();
+
+        let mut env = env;
+        env.push((String::from("DRAY_HOST"), String::from("127.0.0.1:0")));
+        env.push((String::from("DRAY_SSH_KEY_PATHS"), String::new()));
+
+        let dray_config: DrayConfig = envy::prefixed("DRAY_").from_iter(env).unwrap();
+
+        let storage_factory = InMemoryStorageFactory::new(DEFAULT_MAX_HANDLES);
+        storage_factory
+            .put_file(
+                "/.ssh/test/authorized_keys",
+                format!(
+                    "ssh-ed25519 {} test",
+                    client_key.clone_public_key().unwrap().public_key_base64()
+                )
+                .into_bytes(),
+            )
+            .await
+            .unwrap();
+
+        let ssh_config = Arc::new(
+            build_ssh_config(
+                &dray_config,
+                vec![key::KeyPair::generate_ed25519().unwrap()],
+            )
+            .unwrap(),
+        );
+        let server = DraySshServer::with_storage_factory(dray_config, Arc::new(storage_factory));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move { server.run_on_listener(ssh_config, &listener).await });
+
+        let banner = Arc::new(std::sync::Mutex::new(None));
+        let test_client = TestClient {
+            banner: banner.clone(),
+        };
+
+        let mut client = client::connect(Arc::new(client::Config::default()), addr, test_client)
+            .await
+            .unwrap();
+
+        assert!(client
+            .authenticate_publickey("test", Arc::new(client_key))
+            .await
+            .unwrap());
+
+        (client, banner)
+    }
+
+    /// Waits for the server to reply to a channel request.
+    async fn wait_for_reply(channel: &mut Channel<client::Msg>) -> ChannelMsg {
+        loop {
+            if let reply @ (ChannelMsg::Success | ChannelMsg::Failure) =
+                channel.wait().await.unwrap()
+            {
+                return reply;
+            }
+        }
+    }
+
+    struct TestClient {
+        banner: Arc<std::sync::Mutex<Option<String>>>,
+    }
+
+    #[async_trait]
+    impl client::Handler for TestClient {
+        type Error = russh::Error;
+
+        async fn auth_banner(
+            &mut self,
+            banner: &str,
+            _session: &mut client::Session,
+        ) -> Result<(), Self::Error> {
+            *self.banner.lock().unwrap() = Some(banner.to_string());
+            Ok(())
+        }
+
+        async fn check_server_key(
+            &mut self,
+            _server_public_key: &PublicKey,
+        ) -> Result<bool, Self::Error> {
+            Ok(true)
+        }
+    }
+
+    fn parse_test_public_key() -> PublicKey {
+        russh_keys::parse_public_key_base64(TEST_PUBLIC_KEY).unwrap()
+    }
+
+    fn create_shutdown_handle() -> ShutdownHandle {
+        ShutdownHandle {
+            shutdown_token: CancellationToken::new(),
+            task_tracker: TaskTracker::new(),
+        }
+    }
+}
@@ -0,0 +1,114 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
+
+/// Caches the authorized key fingerprints of each user, so that authentication attempts do not
+/// each require a request to the storage backend. The cache is shared between SSH sessions.
+pub struct AuthorizedKeysCache {
+    ttl: Duration,
+    entries: RwLock<HashMap<String, CacheEntry>>,
+}
+
+struct CacheEntry {
+    cached_at: Instant,
+    fingerprints: Vec<String>,
+}
+
+impl AuthorizedKeysCache {
+    pub fn new(ttl: Duration) -> AuthorizedKeysCache {
+        AuthorizedKeysCache {
+            ttl,
+            entries: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Retrieves a user's cached fingerprints, unless they have expired.
+    pub async fn get(&self, user: &str) -> Option<Vec<String>> {
+        self.entries
+            .read()
+            .await
+            .get(user)
+            .filter(|entry| entry.cached_at.elapsed() < self.ttl)
+            .map(|entry| entry.fingerprints.clone())
+    }
+
+    /// Caches a user's fingerprints. Users without authorized keys should be cached with an
+    /// empty list, so that probing for users does not bypass the cache.
+    pub async fn insert(&self, user: &str, fingerprints: Vec<String>) {
+        let mut entries = self.entries.write().await;
+
+        // Expired entries are removed as new entries are added, so that probing with many
+        // different users cannot grow the cache without bound.
+        entries.retain(|_, entry| entry.cached_at.elapsed() < self.ttl);
+
+        entries.insert(
+            user.to_string(),
+            CacheEntry {
+                cached_at: Instant::now(),
+                fingerprints,
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_get_cache_hit() {
+        let cache = AuthorizedKeysCache::new(Duration::from_secs(60));
+
+        cache
+            .insert("test", vec![String::from("fingerprint")])
+            .await;
+
+        assert_eq!(
+            Some(vec![String::from("fingerprint")]),
+            cache.get("test").await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_cache_miss() {
+        let cache = AuthorizedKeysCache::new(Duration::from_secs(60));
+
+        cache
+            .insert("test", vec![String::from("fingerprint")])
+            .await;
+
+        assert_eq!(None, cache.get("other").await);
+    }
+
+    #[tokio::test]
+    async fn test_get_expired_entry() {
+        let cache = AuthorizedKeysCache::new(Duration::from_millis(10));
+
+        cache
+            .insert("test", vec![String::from("fingerprint")])
+            .await;
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        assert_eq!(None, cache.get("test").await);
+    }
+
+    #[tokio::test]
+    async fn test_get_user_without_keys() {
+        let cache = AuthorizedKeysCache::new(Duration::from_secs(60));
+
+        cache.insert("missing", vec![]).await;
+
+        assert_eq!(Some(vec![]), cache.get("missing").await);
+    }
+
+    #[tokio::test]
+    async fn test_insert_removes_expired_entries() {
+        let cache = AuthorizedKeysCache::new(Duration::from_millis(10));
+
+        cache.insert("expired", vec![]).await;
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        cache.insert("test", vec![]).await;
+
+        assert_eq!(1, cache.entries.read().await.len());
+    }
+}
@@ -0,0 +1,1265 @@
+use super::handle::HandleManager;
+use super::Storage;
+use super::StorageFactory;
+use super::DEFAULT_MAX_WRITE_HANDLES;
+use super::{get_symlink_name, is_symlink_name, map_sentinel_to_symlink};
+use crate::error::Error;
+use crate::protocol::file_attributes::FileAttributes;
+use crate::protocol::response::name::File;
+use crate::ssh_keys;
+use async_trait::async_trait;
+use serde::Deserialize;
+use std::fs::{FileTimes, Metadata, Permissions};
+use std::io::{ErrorKind, SeekFrom};
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+use tokio::fs;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tracing::Level;
+use tracing::{error, info};
+
+const READ_DIR_BATCH_SIZE: usize = 100;
+
+#[derive(Clone, Deserialize, Debug)]
+pub struct FilesystemConfig {
+    #[serde(rename(deserialize = "filesystem_root"))]
+    pub root: String,
+}
+
+pub struct FilesystemStorageFactory {
+    root: PathBuf,
+    max_handles: usize,
+    max_write_handles: usize,
+}
+
+impl FilesystemStorageFactory {
+    pub fn new(
+        filesystem_config: &FilesystemConfig,
+        max_handles: usize,
+    ) -> FilesystemStorageFactory {
+        FilesystemStorageFactory {
+            root: PathBuf::from(&filesystem_config.root),
+            max_handles,
+            max_write_handles: DEFAULT_MAX_WRITE_HANDLES,
+        }
+    }
+
+    pub fn with_max_write_handles(mut self, max_write_handles: usize) -> FilesystemStorageFactory {
+        self.max_write_handles = max_write_handles;
+        self
+    }
+}
+
+impl StorageFactory for FilesystemStorageFactory {
+    fn create_storage(&self) -> Arc<dyn Storage> {
+        Arc::new(
+            FilesystemStorage::new(self.root.clone(), self.max_handles)
+                .with_max_write_handles(self.max_write_handles),
+        )
+    }
+}
+
+pub struct FilesystemStorage {
+    root: PathBuf,
+    handle_manager: HandleManager<ReadHandle, WriteHandle, DirHandle>,
+}
+
+impl FilesystemStorage {
+    pub fn new(root: PathBuf, max_handles: usize) -> FilesystemStorage {
+        FilesystemStorage {
+            root,
+            handle_manager: HandleManager::with_capacity(max_handles),
+        }
+    }
+
+    pub fn with_max_write_handles(mut self, max_write_handles: usize) -> FilesystemStorage {
+        self.handle_manager = self
+            .handle_manager
+            .with_max_write_handles(max_write_handles);
+        self
+    }
+
+    /// Maps an SFTP path onto the storage root. Paths are always treated as relative to the root,
+    /// and parent directory components are rejected so that a path cannot escape the root.
+    fn get_path(&self, path: &str) -> Result<PathBuf, Error> {
+        let mut fs_path = self.root.clone();
+
+        for component in Path::new(path).components() {
+            match component {
+                Component::Normal(component) => fs_path.push(component),
+                Component::RootDir | Component::CurDir => {}
+                Component::ParentDir | Component::Prefix(_) => return Err(Error::PermissionDenied),
+            }
+        }
+
+        Ok(fs_path)
+    }
+}
+
+#[async_trait]
+impl Storage for FilesystemStorage {
+    async fn init(&self) -> Result<(), Error> {
+        fs::create_dir_all(&self.root).await.map_err(map_err)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn health_check(&self) -> Result<(), Error> {
+        info!("Running health check for storage root {:?}", self.root);
+
+        let result = match fs::metadata(&self.root).await {
+            Ok(metadata) if metadata.is_dir() => Ok(()),
+            Ok(_) => Err(Error::Storage(format!(
+                "Storage root {:?} is not a directory.",
+                self.root
+            ))),
+            Err(err) => Err(Error::Storage(err.to_string())),
+        };
+
+        match result {
+            Ok(_) => {
+                info!(
+                    "Successfully completed health check for storage root {:?}",
+                    self.root
+                );
+                Ok(())
+            }
+            Err(error) => {
+                error!(
+                    "Failed to complete health check for storage root {:?}: {}",
+                    self.root, error
+                );
+                Err(error)
+            }
+        }
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn get_authorized_keys_fingerprints(&self, user: &str) -> Result<Vec<String>, Error> {
+        let authorized_keys_path = self.get_path(&format!(".ssh/{}/authorized_keys", user))?;
+
+        match fs::read_to_string(authorized_keys_path).await {
+            Ok(authorized_keys) => Ok(ssh_keys::parse_authorized_keys(&authorized_keys)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(vec![]),
+            Err(err) => Err(map_err(err)),
+        }
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn open_dir_handle(&self, dir_name: String) -> Result<String, Error> {
+        let path = self.get_path(&dir_name)?;
+        let read_dir = fs::read_dir(&path).await.map_err(map_err)?;
+
+        self.handle_manager
+            .create_dir_handle(DirHandle { path, read_dir })
+            .await
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn create_dir(&self, dir_name: String) -> Result<(), Error> {
+        fs::create_dir_all(self.get_path(&dir_name)?)
+            .await
+            .map_err(map_err)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn read_dir(&self, handle: &str) -> Result<Vec<File>, Error> {
+        let dir_handle = match self.handle_manager.get_dir_handle(handle).await {
+            Some(dir_handle) => dir_handle,
+            None => return Err(Error::InvalidHandle),
+        };
+
+        let mut dir_handle = dir_handle.lock().await;
+        let mut files = Vec::new();
+
+        while files.len() < READ_DIR_BATCH_SIZE {
+            let entry = match dir_handle.read_dir.next_entry().await.map_err(map_err)? {
+                Some(entry) => entry,
+                None => break,
+            };
+
+            let metadata = entry.metadata().await.map_err(map_err)?;
+            let file = map_metadata_to_file(&entry.file_name().to_string_lossy(), &metadata);
+
+            files.push(match is_symlink_name(&file.file_name) {
+                true => map_sentinel_to_symlink(file),
+                false => file,
+            });
+        }
+
+        Ok(files)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn remove_dir(&self, dir_name: String) -> Result<(), Error> {
+        fs::remove_dir_all(self.get_path(&dir_name)?)
+            .await
+            .map_err(map_err)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn get_dir_size(&self, dir_name: String) -> Result<u64, Error> {
+        let path = self.get_path(&dir_name)?;
+
+        tokio::task::spawn_blocking(move || match get_path_size(&path) {
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(0),
+            result => result,
+        })
+        .await
+        .map_err(|err| Error::Failure(err.to_string()))?
+        .map_err(map_err)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn get_file_metadata(&self, file_name: String) -> Result<File, Error> {
+        let path = self.get_path(&file_name)?;
+
+        match fs::metadata(&path).await {
+            Ok(metadata) => Ok(map_metadata_to_file(&get_file_name(&path), &metadata)),
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                let symlink_path = self.get_path(&get_symlink_name(&file_name))?;
+                let metadata = fs::metadata(&symlink_path).await.map_err(map_err)?;
+
+                Ok(map_sentinel_to_symlink(map_metadata_to_file(
+                    &get_file_name(&symlink_path),
+                    &metadata,
+                )))
+            }
+            Err(err) => Err(map_err(err)),
+        }
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn set_file_metadata(
+        &self,
+        file_name: String,
+        file_attributes: FileAttributes,
+    ) -> Result<(), Error> {
+        let path = self.get_path(&file_name)?;
+
+        set_path_metadata(&path, file_attributes).await
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn set_handle_metadata(
+        &self,
+        handle: &str,
+        file_attributes: FileAttributes,
+    ) -> Result<(), Error> {
+        if let Some(write_handle) = self.handle_manager.get_write_handle(handle).await {
+            let write_handle = write_handle.lock().await;
+            set_path_metadata(&write_handle.path, file_attributes).await
+        } else if self.handle_manager.get_read_handle(handle).await.is_some()
+            || self.handle_manager.get_dir_handle(handle).await.is_some()
+        {
+            Ok(())
+        } else {
+            Err(Error::InvalidHandle)
+        }
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn get_handle_metadata(&self, handle: &str) -> Result<File, Error> {
+        let path = if let Some(read_handle) = self.handle_manager.get_read_handle(handle).await {
+            read_handle.lock().await.path.clone()
+        } else if let Some(write_handle) = self.handle_manager.get_write_handle(handle).await {
+            write_handle.lock().await.path.clone()
+        } else if let Some(dir_handle) = self.handle_manager.get_dir_handle(handle).await {
+            dir_handle.lock().await.path.clone()
+        } else {
+            return Err(Error::InvalidHandle);
+        };
+
+        let metadata = fs::metadata(&path).await.map_err(map_err)?;
+
+        Ok(map_metadata_to_file(&get_file_name(&path), &metadata))
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn open_read_handle(&self, file_name: String) -> Result<String, Error> {
+        let path = self.get_path(&file_name)?;
+        let file = fs::File::open(&path).await.map_err(map_err)?;
+
+        self.handle_manager
+            .create_read_handle(ReadHandle { path, file })
+            .await
+    }
+
+    #[tracing::instrument(skip(self), level = Level::TRACE)]
+    async fn read_data(&self, handle: &str, offset: u64, len: u32) -> Result<Vec<u8>, Error> {
+        let read_handle = match self.handle_manager.get_read_handle(handle).await {
+            Some(read_handle) => read_handle,
+            None => return Err(Error::InvalidHandle),
+        };
+
+        let mut read_handle = read_handle.lock().await;
+        let mut buffer = Vec::new();
+
+        read_handle.file.seek(SeekFrom::Start(offset)).await?;
+
+        (&mut read_handle.file)
+            .take(len as u64)
+            .read_to_end(&mut buffer)
+            .await?;
+
+        Ok(buffer)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn open_write_handle(&self, file_name: String) -> Result<String, Error> {
+        let path = self.get_path(&file_name)?;
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await.map_err(map_err)?;
+        }
+
+        let file = fs::File::create(&path).await.map_err(map_err)?;
+
+        self.handle_manager
+            .create_write_handle(WriteHandle { path, file })
+            .await
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn open_existing_write_handle(
+        &self,
+        file_name: String,
+        truncate: bool,
+    ) -> Result<String, Error> {
+        let path = self.get_path(&file_name)?;
+        let file = open_existing_file(&path, truncate).await?;
+
+        self.handle_manager
+            .create_write_handle(WriteHandle { path, file })
+            .await
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn open_read_write_handle(
+        &self,
+        file_name: String,
+        truncate: bool,
+    ) -> Result<String, Error> {
+        let path = self.get_path(&file_name)?;
+
+        // Reads and writes each seek to their own offsets, so each side has its own file
+        // descriptor to avoid sharing a file position.
+        let write_file = open_existing_file(&path, truncate).await?;
+        let read_file = fs::File::open(&path).await.map_err(map_err)?;
+
+        self.handle_manager
+            .create_read_write_handle(
+                ReadHandle {
+                    path: path.clone(),
+                    file: read_file,
+                },
+                WriteHandle {
+                    path,
+                    file: write_file,
+                },
+            )
+            .await
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn open_append_handle(&self, file_name: String, create: bool) -> Result<String, Error> {
+        let path = self.get_path(&file_name)?;
+
+        if create {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent).await.map_err(map_err)?;
+            }
+        }
+
+        // Files opened for appending ignore the position that writes seek to.
+        let file = fs::OpenOptions::new()
+            .append(true)
+            .create(create)
+            .open(&path)
+            .await
+            .map_err(map_err)?;
+
+        self.handle_manager
+            .create_write_handle(WriteHandle { path, file })
+            .await
+    }
+
+    #[tracing::instrument(skip(self, data), level = Level::TRACE)]
+    async fn write_data(&self, handle: &str, offset: u64, data: bytes::Bytes) -> Result<(), Error> {
+        let write_handle = match self.handle_manager.get_write_handle(handle).await {
+            Some(write_handle) => write_handle,
+            None => return Err(Error::InvalidHandle),
+        };
+
+        let mut write_handle = write_handle.lock().await;
+
+        write_handle.file.seek(SeekFrom::Start(offset)).await?;
+        write_handle.file.write_all(&data).await?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn remove_file(&self, file_name: String) -> Result<(), Error> {
+        match fs::remove_file(self.get_path(&file_name)?).await {
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                fs::remove_file(self.get_path(&get_symlink_name(&file_name))?)
+                    .await
+                    .map_err(map_err)
+            }
+            result => result.map_err(map_err),
+        }
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn close_handle(&self, handle: &str) -> Result<(), Error> {
+        if let Some(write_handle) = self.handle_manager.get_write_handle(handle).await {
+            let mut write_handle = write_handle.lock().await;
+
+            write_handle.file.flush().await?;
+            write_handle.file.sync_all().await?;
+        }
+
+        self.handle_manager.remove_handle(handle).await;
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn fsync(&self, handle: &str) -> Result<(), Error> {
+        if let Some(write_handle) = self.handle_manager.get_write_handle(handle).await {
+            let mut write_handle = write_handle.lock().await;
+
+            write_handle.file.flush().await?;
+            write_handle.file.sync_all().await?;
+
+            Ok(())
+        } else if self.handle_manager.get_read_handle(handle).await.is_some()
+            || self.handle_manager.get_dir_handle(handle).await.is_some()
+        {
+            Ok(())
+        } else {
+            Err(Error::InvalidHandle)
+        }
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn rename(&self, current: String, new: String) -> Result<(), Error> {
+        let file = self.get_file_metadata(current.clone()).await?;
+
+        let (current, new) = match file.file_attributes.is_symlink() {
+            true => (get_symlink_name(&current), get_symlink_name(&new)),
+            false => (current, new),
+        };
+
+        fs::rename(self.get_path(&current)?, self.get_path(&new)?)
+            .await
+            .map_err(map_err)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn copy_file(&self, current: String, new: String) -> Result<(), Error> {
+        fs::copy(self.get_path(&current)?, self.get_path(&new)?)
+            .await
+            .map_err(map_err)?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn create_symlink(&self, link: String, target: String) -> Result<(), Error> {
+        fs::write(self.get_path(&get_symlink_name(&link))?, target)
+            .await
+            .map_err(map_err)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn read_symlink(&self, link: String) -> Result<String, Error> {
+        fs::read_to_string(self.get_path(&get_symlink_name(&link))?)
+            .await
+            .map_err(map_err)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn abort_write_handle(&self, handle: &str) -> Result<(), Error> {
+        if let Some(write_handle) = self.handle_manager.remove_write_handle(handle).await {
+            write_handle.lock().await.file.flush().await?;
+        }
+
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn abort_write_handles(&self) -> Result<(), Error> {
+        // Data is written to the file as it arrives, so the files only need to be flushed.
+        for write_handle in self.handle_manager.remove_write_handles().await {
+            write_handle.lock().await.file.flush().await?;
+        }
+
+        Ok(())
+    }
+}
+
+struct DirHandle {
+    path: PathBuf,
+    read_dir: fs::ReadDir,
+}
+
+struct ReadHandle {
+    path: PathBuf,
+    file: fs::File,
+}
+
+struct WriteHandle {
+    path: PathBuf,
+    file: fs::File,
+}
+
+fn get_file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|file_name| file_name.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+/// Calculates the size of a file, or the total size of the files within a directory. Symbolic
+/// links are not followed.
+fn get_path_size(path: &Path) -> std::io::Result<u64> {
+    let metadata = std::fs::symlink_metadata(path)?;
+
+    if !metadata.is_dir() {
+        return Ok(metadata.len());
+    }
+
+    std::fs::read_dir(path)?.try_fold(0, |size, entry| Ok(size + get_path_size(&entry?.path())?))
+}
+
+/// Applies attributes from a client. Only the permission bits are applied, since setuid, setgid,
+/// and the owner would let a client create files that run as another user, such as root. Owner
+/// changes are ignored, as they are for S3.
+async fn set_path_metadata(path: &Path, file_attributes: FileAttributes) -> Result<(), Error> {
+    let path = path.to_path_buf();
+
+    tokio::task::spawn_blocking(move || {
+        if let Some(permissions) = file_attributes.permissions {
+            std::fs::set_permissions(&path, Permissions::from_mode(permissions & 0o777))?;
+        }
+
+        if let Some(size) = file_attributes.size {
+            std::fs::OpenOptions::new()
+                .write(true)
+                .open(&path)?
+                .set_len(size)?;
+        }
+
+        if file_attributes.atime.is_some() || file_attributes.mtime.is_some() {
+            let file = std::fs::File::options().write(true).open(&path)?;
+            let mut file_times = FileTimes::new();
+
+            if let Some(atime) = file_attributes.atime {
+                file_times =
+                    file_times.set_accessed(UNIX_EPOCH + Duration::from_secs(atime as u64));
+            }
+
+            if let Some(mtime) = file_attributes.mtime {
+                file_times =
+                    file_times.set_modified(UNIX_EPOCH + Duration::from_secs(mtime as u64));
+            }
+
+            file.set_times(file_times)?;
+        }
+
+        Ok::<(), std::io::Error>(())
+    })
+    .await
+    .map_err(|err| Error::Failure(err.to_string()))?
+    .map_err(map_err)
+}
+
+fn map_metadata_to_file(file_name: &str, metadata: &Metadata) -> File {
+    File {
+        file_name: file_name.to_string(),
+        file_attributes: FileAttributes {
+            size: match metadata.is_dir() {
+                true => None,
+                false => Some(metadata.len()),
+            },
+            uid: Some(metadata.uid()),
+            gid: Some(metadata.gid()),
+            permissions: Some(metadata.mode()),
+            atime: Some(metadata.atime() as u32),
+            mtime: Some(metadata.mtime() as u32),
+        },
+    }
+}
+
+async fn open_existing_file(path: &Path, truncate: bool) -> Result<fs::File, Error> {
+    fs::OpenOptions::new()
+        .write(true)
+        .truncate(truncate)
+        .open(path)
+        .await
+        .map_err(map_err)
+}
+
+fn map_err(io_error: std::io::Error) -> Error {
+    match io_error.kind() {
+        ErrorKind::NotFound => Error::NoSuchFile,
+        ErrorKind::PermissionDenied => Error::PermissionDenied,
+        ErrorKind::AlreadyExists => Error::AlreadyExists,
+        ErrorKind::NotADirectory => Error::NotADirectory,
+        ErrorKind::IsADirectory => Error::IsADirectory,
+        _ => Error::Storage(io_error.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::storage::DEFAULT_MAX_HANDLES;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_get_path_maps_path_onto_root() {
+        let storage = FilesystemStorage::new(PathBuf::from("/root"), DEFAULT_MAX_HANDLES);
+
+        assert_eq!(
+            PathBuf::from("/root/home/test/file.txt"),
+            storage.get_path("/home/test/./file.txt").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_get_path_rejects_parent_directory() {
+        let storage = FilesystemStorage::new(PathBuf::from("/root"), DEFAULT_MAX_HANDLES);
+
+        assert_eq!(
+            Err(Error::PermissionDenied),
+            storage.get_path("/home/test/../../../etc/passwd")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_health_check_with_missing_root() {
+        let root = TempDir::new().unwrap();
+        let storage = FilesystemStorage::new(root.path().join("missing"), DEFAULT_MAX_HANDLES);
+
+        assert!(storage.health_check().await.is_err());
+
+        storage.init().await.unwrap();
+
+        assert!(storage.health_check().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_get_authorized_keys_fingerprints_with_missing_user() {
+        let root = TempDir::new().unwrap();
+        let storage = FilesystemStorage::new(root.path().to_path_buf(), DEFAULT_MAX_HANDLES);
+
+        assert_eq!(
+            Ok(vec![]),
+            storage.get_authorized_keys_fingerprints("missing").await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_write_and_read_file() {
+        let root = TempDir::new().unwrap();
+        let storage = FilesystemStorage::new(root.path().to_path_buf(), DEFAULT_MAX_HANDLES);
+
+        let handle = storage
+            .open_write_handle("/home/test/file.txt".to_string())
+            .await
+            .unwrap();
+        storage
+            .write_data(&handle, 5, bytes::Bytes::from(" world"))
+            .await
+            .unwrap();
+        storage
+            .write_data(&handle, 0, bytes::Bytes::from("hello"))
+            .await
+            .unwrap();
+        storage.close_handle(&handle).await.unwrap();
+
+        let handle = storage
+            .open_read_handle("/home/test/file.txt".to_string())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            b"hello",
+            storage.read_data(&handle, 0, 5).await.unwrap().as_slice()
+        );
+        assert_eq!(
+            b" world",
+            storage.read_data(&handle, 5, 100).await.unwrap().as_slice()
+        );
+        assert!(storage
+            .read_data(&handle, 11, 100)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_open_existing_write_handle_modifies_file_in_place() {
+        let root = TempDir::new().unwrap();
+        std::fs::create_dir_all(root.path().join("home/test")).unwrap();
+        std::fs::write(root.path().join("home/test/file.txt"), "hello world").unwrap();
+
+        let storage = FilesystemStorage::new(root.path().to_path_buf(), DEFAULT_MAX_HANDLES);
+
+        let handle = storage
+            .open_existing_write_handle("/home/test/file.txt".to_string(), false)
+            .await
+            .unwrap();
+        storage
+            .write_data(&handle, 6, bytes::Bytes::from("there"))
+            .await
+            .unwrap();
+        storage.close_handle(&handle).await.unwrap();
+
+        assert_eq!(
+            "hello there",
+            std::fs::read_to_string(root.path().join("home/test/file.txt")).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_open_existing_write_handle_with_truncate() {
+        let root = TempDir::new().unwrap();
+        std::fs::create_dir_all(root.path().join("home/test")).unwrap();
+        std::fs::write(root.path().join("home/test/file.txt"), "hello world").unwrap();
+
+        let storage = FilesystemStorage::new(root.path().to_path_buf(), DEFAULT_MAX_HANDLES);
+
+        let handle = storage
+            .open_existing_write_handle("/home/test/file.txt".to_string(), true)
+            .await
+            .unwrap();
+        storage
+            .write_data(&handle, 0, bytes::Bytes::from("hi"))
+            .await
+            .unwrap();
+        storage.close_handle(&handle).await.unwrap();
+
+        assert_eq!(
+            "hi",
+            std::fs::read_to_string(root.path().join("home/test/file.txt")).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_open_existing_write_handle_with_missing_file() {
+        let root = TempDir::new().unwrap();
+        std::fs::create_dir_all(root.path().join("home/test")).unwrap();
+
+        let storage = FilesystemStorage::new(root.path().to_path_buf(), DEFAULT_MAX_HANDLES);
+
+        assert_eq!(
+            Err(Error::NoSuchFile),
+            storage
+                .open_existing_write_handle("/home/test/missing.txt".to_string(), false)
+                .await
+        );
+        assert!(!root.path().join("home/test/missing.txt").exists());
+    }
+
+    #[tokio::test]
+    async fn test_open_read_write_handle() {
+        let root = TempDir::new().unwrap();
+        std::fs::create_dir_all(root.path().join("home/test")).unwrap();
+        std::fs::write(root.path().join("home/test/file.txt"), "hello world").unwrap();
+
+        let storage = FilesystemStorage::new(root.path().to_path_buf(), DEFAULT_MAX_HANDLES);
+
+        let handle = storage
+            .open_read_write_handle("/home/test/file.txt".to_string(), false)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            b"hello",
+            storage.read_data(&handle, 0, 5).await.unwrap().as_slice()
+        );
+
+        storage
+            .write_data(&handle, 0, bytes::Bytes::from("HELLO"))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            b" world",
+            storage.read_data(&handle, 5, 100).await.unwrap().as_slice()
+        );
+
+        storage.close_handle(&handle).await.unwrap();
+
+        assert!(storage.read_data(&handle, 11, 100).await.is_err());
+        assert_eq!(
+            "HELLO world",
+            std::fs::read_to_string(root.path().join("home/test/file.txt")).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_open_append_handle_appends_to_file() {
+        let root = TempDir::new().unwrap();
+        std::fs::create_dir_all(root.path().join("home/test")).unwrap();
+        std::fs::write(root.path().join("home/test/file.txt"), "hello").unwrap();
+
+        let storage = FilesystemStorage::new(root.path().to_path_buf(), DEFAULT_MAX_HANDLES);
+
+        let handle = storage
+            .open_append_handle("/home/test/file.txt".to_string(), false)
+            .await
+            .unwrap();
+        storage
+            .write_data(&handle, 0, bytes::Bytes::from(" world"))
+            .await
+            .unwrap();
+        storage.close_handle(&handle).await.unwrap();
+
+        assert_eq!(
+            "hello world",
+            std::fs::read_to_string(root.path().join("home/test/file.txt")).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_open_append_handle_with_missing_file() {
+        let root = TempDir::new().unwrap();
+        let storage = FilesystemStorage::new(root.path().to_path_buf(), DEFAULT_MAX_HANDLES);
+
+        assert_eq!(
+            Err(Error::NoSuchFile),
+            storage
+                .open_append_handle("/home/test/file.txt".to_string(), false)
+                .await
+        );
+
+        let handle = storage
+            .open_append_handle("/home/test/file.txt".to_string(), true)
+            .await
+            .unwrap();
+        storage
+            .write_data(&handle, 0, bytes::Bytes::from("hello"))
+            .await
+            .unwrap();
+        storage.close_handle(&handle).await.unwrap();
+
+        assert_eq!(
+            "hello",
+            std::fs::read_to_string(root.path().join("home/test/file.txt")).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_abort_write_handles() {
+        let root = TempDir::new().unwrap();
+        let storage = FilesystemStorage::new(root.path().to_path_buf(), DEFAULT_MAX_HANDLES);
+
+        let handle = storage
+            .open_write_handle("/home/test/file.txt".to_string())
+            .await
+            .unwrap();
+        storage
+            .write_data(&handle, 0, bytes::Bytes::from("hello"))
+            .await
+            .unwrap();
+
+        storage.abort_write_handles().await.unwrap();
+
+        assert!(storage
+            .write_data(&handle, 5, bytes::Bytes::from(" world"))
+            .await
+            .is_err());
+        assert_eq!(
+            "hello",
+            std::fs::read_to_string(root.path().join("home/test/file.txt")).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fsync() {
+        let root = TempDir::new().unwrap();
+        let storage = FilesystemStorage::new(root.path().to_path_buf(), DEFAULT_MAX_HANDLES);
+
+        let handle = storage
+            .open_write_handle("/home/test/file.txt".to_string())
+            .await
+            .unwrap();
+        storage
+            .write_data(&handle, 0, bytes::Bytes::from("hello"))
+            .await
+            .unwrap();
+
+        storage.fsync(&handle).await.unwrap();
+
+        assert_eq!(
+            "hello",
+            std::fs::read_to_string(root.path().join("home/test/file.txt")).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fsync_with_missing_handle() {
+        let root = TempDir::new().unwrap();
+        let storage = FilesystemStorage::new(root.path().to_path_buf(), DEFAULT_MAX_HANDLES);
+
+        assert!(storage.fsync("missing").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_get_dir_size() {
+        let root = TempDir::new().unwrap();
+        std::fs::create_dir_all(root.path().join("home/test/nested")).unwrap();
+        std::fs::write(root.path().join("home/test/file.txt"), vec![0; 100]).unwrap();
+        std::fs::write(root.path().join("home/test/nested/file.txt"), vec![0; 50]).unwrap();
+        std::fs::write(root.path().join("home/other.txt"), vec![0; 25]).unwrap();
+
+        let storage = FilesystemStorage::new(root.path().to_path_buf(), DEFAULT_MAX_HANDLES);
+
+        assert_eq!(
+            Ok(150),
+            storage.get_dir_size("/home/test".to_string()).await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_dir_size_with_missing_dir() {
+        let root = TempDir::new().unwrap();
+        let storage = FilesystemStorage::new(root.path().to_path_buf(), DEFAULT_MAX_HANDLES);
+
+        assert_eq!(
+            Ok(0),
+            storage.get_dir_size("/home/missing".to_string()).await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_open_read_handle_with_missing_file() {
+        let root = TempDir::new().unwrap();
+        let storage = FilesystemStorage::new(root.path().to_path_buf(), DEFAULT_MAX_HANDLES);
+
+        assert_eq!(
+            Err(Error::NoSuchFile),
+            storage
+                .open_read_handle("/home/test/missing.txt".to_string())
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_read_dir() {
+        let root = TempDir::new().unwrap();
+        let storage = FilesystemStorage::new(root.path().to_path_buf(), DEFAULT_MAX_HANDLES);
+
+        storage
+            .create_dir("/home/test/dir".to_string())
+            .await
+            .unwrap();
+        std::fs::write(root.path().join("home/test/file.txt"), b"data").unwrap();
+
+        let handle = storage
+            .open_dir_handle("/home/test".to_string())
+            .await
+            .unwrap();
+
+        let mut files = storage.read_dir(&handle).await.unwrap();
+        files.sort_by(|a, b| a.file_name.cmp(&b.file_name));
+
+        assert_eq!(2, files.len());
+        assert_eq!("dir", files[0].file_name);
+        assert!(files[0].file_attributes.is_dir());
+        assert_eq!("file.txt", files[1].file_name);
+        assert_eq!(Some(4), files[1].file_attributes.size);
+        assert!(!files[1].file_attributes.is_dir());
+
+        assert!(storage.read_dir(&handle).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_get_file_metadata_preserves_permissions() {
+        let root = TempDir::new().unwrap();
+        let storage = FilesystemStorage::new(root.path().to_path_buf(), DEFAULT_MAX_HANDLES);
+
+        std::fs::write(root.path().join("file.txt"), b"data").unwrap();
+
+        storage
+            .set_file_metadata(
+                "/file.txt".to_string(),
+                FileAttributes {
+                    permissions: Some(0o600),
+                    mtime: Some(1000000000),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+
+        let file = storage
+            .get_file_metadata("/file.txt".to_string())
+            .await
+            .unwrap();
+
+        assert_eq!("file.txt", file.file_name);
+        assert_eq!(Some(0o100600), file.file_attributes.permissions);
+        assert_eq!(Some(1000000000), file.file_attributes.mtime);
+        assert_eq!(Some(4), file.file_attributes.size);
+    }
+
+    #[tokio::test]
+    async fn test_set_file_metadata_ignores_special_bits_and_owner() {
+        let root = TempDir::new().unwrap();
+        let storage = FilesystemStorage::new(root.path().to_path_buf(), DEFAULT_MAX_HANDLES);
+
+        std::fs::write(root.path().join("file.txt"), b"data").unwrap();
+        let owner = std::fs::metadata(root.path().join("file.txt")).unwrap();
+
+        storage
+            .set_file_metadata(
+                "/file.txt".to_string(),
+                FileAttributes {
+                    permissions: Some(0o7755),
+                    uid: Some(owner.uid() + 1),
+                    gid: Some(owner.gid() + 1),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+
+        let metadata = std::fs::metadata(root.path().join("file.txt")).unwrap();
+
+        assert_eq!(0o100755, metadata.mode());
+        assert_eq!(owner.uid(), metadata.uid());
+        assert_eq!(owner.gid(), metadata.gid());
+    }
+
+    #[tokio::test]
+    async fn test_set_file_metadata_truncates_file() {
+        let root = TempDir::new().unwrap();
+        let storage = FilesystemStorage::new(root.path().to_path_buf(), DEFAULT_MAX_HANDLES);
+
+        std::fs::write(root.path().join("file.txt"), b"data").unwrap();
+
+        storage
+            .set_file_metadata(
+                "/file.txt".to_string(),
+                FileAttributes {
+                    size: Some(2),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            b"da",
+            std::fs::read(root.path().join("file.txt"))
+                .unwrap()
+                .as_slice()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_set_file_metadata_extends_file() {
+        let root = TempDir::new().unwrap();
+        let storage = FilesystemStorage::new(root.path().to_path_buf(), DEFAULT_MAX_HANDLES);
+
+        std::fs::write(root.path().join("file.txt"), b"data").unwrap();
+
+        storage
+            .set_file_metadata(
+                "/file.txt".to_string(),
+                FileAttributes {
+                    size: Some(6),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            b"data\0\0",
+            std::fs::read(root.path().join("file.txt"))
+                .unwrap()
+                .as_slice()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rename_and_remove_file() {
+        let root = TempDir::new().unwrap();
+        let storage = FilesystemStorage::new(root.path().to_path_buf(), DEFAULT_MAX_HANDLES);
+
+        std::fs::write(root.path().join("old.txt"), b"data").unwrap();
+
+        storage
+            .rename("/old.txt".to_string(), "/new.txt".to_string())
+            .await
+            .unwrap();
+        assert!(root.path().join("new.txt").exists());
+
+        storage.remove_file("/new.txt".to_string()).await.unwrap();
+        assert!(!root.path().join("new.txt").exists());
+    }
+
+    #[tokio::test]
+    async fn test_copy_file() {
+        let root = TempDir::new().unwrap();
+        let storage = FilesystemStorage::new(root.path().to_path_buf(), DEFAULT_MAX_HANDLES);
+
+        std::fs::write(root.path().join("old.txt"), b"data").unwrap();
+
+        storage
+            .copy_file("/old.txt".to_string(), "/new.txt".to_string())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            b"data",
+            std::fs::read(root.path().join("old.txt"))
+                .unwrap()
+                .as_slice()
+        );
+        assert_eq!(
+            b"data",
+            std::fs::read(root.path().join("new.txt"))
+                .unwrap()
+                .as_slice()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_create_and_read_symlink() {
+        let root = TempDir::new().unwrap();
+        let storage = FilesystemStorage::new(root.path().to_path_buf(), DEFAULT_MAX_HANDLES);
+
+        storage
+            .create_symlink("/link".to_string(), "/target.txt".to_string())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            "/target.txt",
+            storage.read_symlink("/link".to_string()).await.unwrap()
+        );
+
+        let file = storage
+            .get_file_metadata("/link".to_string())
+            .await
+            .unwrap();
+        assert_eq!("link", file.file_name);
+        assert!(file.file_attributes.is_symlink());
+
+        let handle = storage.open_dir_handle("/".to_string()).await.unwrap();
+        let files = storage.read_dir(&handle).await.unwrap();
+        assert_eq!(1, files.len());
+        assert_eq!("link", files[0].file_name);
+        assert!(files[0].file_attributes.is_symlink());
+    }
+
+    #[tokio::test]
+    async fn test_rename_and_remove_symlink() {
+        let root = TempDir::new().unwrap();
+        let storage = FilesystemStorage::new(root.path().to_path_buf(), DEFAULT_MAX_HANDLES);
+
+        storage
+            .create_symlink("/old".to_string(), "/target.txt".to_string())
+            .await
+            .unwrap();
+
+        storage
+            .rename("/old".to_string(), "/new".to_string())
+            .await
+            .unwrap();
+        assert!(root.path().join("new.draylink").exists());
+
+        storage.remove_file("/new".to_string()).await.unwrap();
+        assert!(!root.path().join("new.draylink").exists());
+    }
+
+    #[tokio::test]
+    async fn test_read_symlink_with_missing_link() {
+        let root = TempDir::new().unwrap();
+        let storage = FilesystemStorage::new(root.path().to_path_buf(), DEFAULT_MAX_HANDLES);
+
+        assert_eq!(
+            Err(Error::NoSuchFile),
+            storage.read_symlink("/link".to_string()).await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_remove_dir() {
+        let root = TempDir::new().unwrap();
+        let storage = FilesystemStorage::new(root.path().to_path_buf(), DEFAULT_MAX_HANDLES);
+
+        storage
+            .create_dir("/home/test/dir/subdir".to_string())
+            .await
+            .unwrap();
+
+        storage
+            .remove_dir("/home/test/dir".to_string())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            Err(Error::NoSuchFile),
+            storage
+                .get_file_metadata("/home/test/dir".to_string())
+                .await
+        );
+    }
+
+    #[test]
+    fn test_map_err_maps_not_found_to_no_such_file() {
+        assert_eq!(
+            Error::NoSuchFile,
+            map_err(std::io::Error::from(ErrorKind::NotFound))
+        );
+    }
+
+    #[test]
+    fn test_map_err_maps_file_type_errors() {
+        assert_eq!(
+            Error::AlreadyExists,
+            map_err(std::io::Error::from(ErrorKind::AlreadyExists))
+        );
+        assert_eq!(
+            Error::NotADirectory,
+            map_err(std::io::Error::from(ErrorKind::NotADirectory))
+        );
+        assert_eq!(
+            Error::IsADirectory,
+            map_err(std::io::Error::from(ErrorKind::IsADirectory))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_create_dir_with_file_in_path() {
+        let root = TempDir::new().unwrap();
+        std::fs::create_dir_all(root.path().join("home/test")).unwrap();
+        std::fs::write(root.path().join("home/test/file.txt"), "data").unwrap();
+
+        let storage = FilesystemStorage::new(root.path().to_path_buf(), DEFAULT_MAX_HANDLES);
+
+        assert_eq!(
+            Err(Error::NotADirectory),
+            storage
+                .create_dir("/home/test/file.txt/dir".to_string())
+                .await
+        );
+    }
+
+    #[test]
+    fn test_map_err_maps_generic_error_to_storage_error() {
+        assert!(matches!(
+            map_err(std::io::Error::from(ErrorKind::Other)),
+            Error::Storage(_)
+        ));
+    }
+}
@@ -0,0 +1,357 @@
+use super::authorized_keys_cache::AuthorizedKeysCache;
+use super::s3::{
+    S3Storage, DEFAULT_MULTIPART_THRESHOLD, DEFAULT_PART_SIZE, DEFAULT_READ_AHEAD_SIZE,
+};
+use super::DefaultModes;
+use super::Storage;
+use super::StorageFactory;
+use super::DEFAULT_MAX_WRITE_HANDLES;
+use aws_config::BehaviorVersion;
+use aws_config::Region;
+use aws_sdk_s3::config::Credentials;
+use serde::Deserialize;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+const DEFAULT_ENDPOINT_NAME: &str = "https://storage.googleapis.com";
+
+/// GCS does not use regions to route requests, but requests must still be signed with one.
+const REGION: &str = "auto";
+
+#[derive(Clone, Deserialize)]
+pub struct GcsConfig {
+    #[serde(
+        rename(deserialize = "gcs_endpoint_name"),
+        default = "get_default_endpoint_name"
+    )]
+    pub endpoint_name: String,
+
+    #[serde(rename(deserialize = "gcs_bucket"))]
+    pub bucket: String,
+
+    /// The access ID of an HMAC key for a service account with access to the bucket.
+    #[serde(rename(deserialize = "gcs_hmac_access_id"))]
+    pub hmac_access_id: String,
+
+    #[serde(rename(deserialize = "gcs_hmac_secret"))]
+    pub hmac_secret: String,
+}
+
+impl fmt::Debug for GcsConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GcsConfig")
+            .field("endpoint_name", &self.endpoint_name)
+            .field("bucket", &self.bucket)
+            .field("hmac_access_id", &self.hmac_access_id)
+            .field("hmac_secret", &"<redacted>")
+            .finish()
+    }
+}
+
+/// Creates storage for Google Cloud Storage. GCS's XML API is interoperable with S3, so GCS is
+/// accessed with the S3 storage backend, authenticating with an HMAC key instead of AWS
+/// credentials. The XML API cannot copy objects into multipart uploads, so storage is created
+/// without part copies.
+pub struct GcsStorageFactory {
+    s3_client: aws_sdk_s3::Client,
+    bucket: String,
+    max_handles: usize,
+    authorized_keys_cache: Arc<AuthorizedKeysCache>,
+    default_modes: DefaultModes,
+    max_write_handles: usize,
+}
+
+impl GcsStorageFactory {
+    pub fn new(
+        gcs_config: &GcsConfig,
+        max_handles: usize,
+        authorized_keys_ttl: Duration,
+    ) -> GcsStorageFactory {
+        let s3_sdk_config = build_s3_sdk_config(gcs_config).build();
+
+        GcsStorageFactory {
+            s3_client: aws_sdk_s3::Client::from_conf(s3_sdk_config),
+            bucket: gcs_config.bucket.clone(),
+            max_handles,
+            authorized_keys_cache: Arc::new(AuthorizedKeysCache::new(authorized_keys_ttl)),
+            default_modes: DefaultModes::default(),
+            max_write_handles: DEFAULT_MAX_WRITE_HANDLES,
+        }
+    }
+
+    pub fn with_default_modes(mut self, default_modes: DefaultModes) -> GcsStorageFactory {
+        self.default_modes = default_modes;
+        self
+    }
+
+    pub fn with_max_write_handles(mut self, max_write_handles: usize) -> GcsStorageFactory {
+        self.max_write_handles = max_write_handles;
+        self
+    }
+}
+
+impl StorageFactory for GcsStorageFactory {
+    fn create_storage(&self) -> Arc<dyn Storage> {
+        Arc::new(
+            S3Storage::new(
+                self.s3_client.clone(),
+                self.bucket.clone(),
+                self.max_handles,
+                DEFAULT_PART_SIZE,
+                DEFAULT_MULTIPART_THRESHOLD,
+                DEFAULT_READ_AHEAD_SIZE,
+                self.authorized_keys_cache.clone(),
+            )
+            .with_default_modes(self.default_modes)
+            .with_max_write_handles(self.max_write_handles)
+            .with_part_copy(false),
+        )
+    }
+}
+
+/// Configures the S3 client for the GCS endpoint, signing requests with the HMAC key.
+fn build_s3_sdk_config(gcs_config: &GcsConfig) -> aws_sdk_s3::config::Builder {
+    let credentials = Credentials::new(
+        &gcs_config.hmac_access_id,
+        &gcs_config.hmac_secret,
+        None,
+        None,
+        "gcs-hmac",
+    );
+
+    aws_sdk_s3::Config::builder()
+        .behavior_version(BehaviorVersion::latest())
+        .endpoint_url(&gcs_config.endpoint_name)
+        .region(Region::new(REGION))
+        .credentials_provider(credentials)
+        .force_path_style(true)
+}
+
+fn get_default_endpoint_name() -> String {
+    String::from(DEFAULT_ENDPOINT_NAME)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::error::Error;
+    use crate::storage::{DEFAULT_DIR_MODE, DEFAULT_FILE_MODE, DEFAULT_MAX_HANDLES};
+    use aws_smithy_runtime_api::client::http::{
+        http_client_fn, HttpConnector, HttpConnectorFuture, SharedHttpConnector,
+    };
+    use aws_smithy_runtime_api::client::orchestrator::{HttpRequest, HttpResponse};
+    use aws_smithy_runtime_api::http::StatusCode;
+    use aws_smithy_types::body::SdkBody;
+    use std::convert::TryFrom;
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_gcs_config_from_env() {
+        let gcs_config = envy::prefixed("DRAY_")
+            .from_iter::<_, GcsConfig>(vec![
+                (String::from("DRAY_GCS_BUCKET"), String::from("bucket")),
+                (String::from("DRAY_GCS_HMAC_ACCESS_ID"), String::from("id")),
+                (String::from("DRAY_GCS_HMAC_SECRET"), String::from("secret")),
+            ])
+            .unwrap();
+
+        assert_eq!(DEFAULT_ENDPOINT_NAME, gcs_config.endpoint_name);
+        assert_eq!("bucket", gcs_config.bucket);
+        assert_eq!("id", gcs_config.hmac_access_id);
+        assert_eq!("secret", gcs_config.hmac_secret);
+    }
+
+    #[test]
+    fn test_gcs_config_from_env_with_missing_hmac_key() {
+        let result = envy::prefixed("DRAY_").from_iter::<_, GcsConfig>(vec![(
+            String::from("DRAY_GCS_BUCKET"),
+            String::from("bucket"),
+        )]);
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_gcs_config_debug_redacts_secret() {
+        let debug = format!("{:?}", create_gcs_config());
+
+        assert!(debug.contains("hmac_access_id: \"id\""));
+        assert!(!debug.contains("hmac-key"));
+    }
+
+    #[tokio::test]
+    async fn test_gcs_storage_factory_uses_gcs_endpoint() {
+        let factory = GcsStorageFactory::new(
+            &create_gcs_config(),
+            DEFAULT_MAX_HANDLES,
+            Duration::from_secs(60),
+        );
+
+        let config = factory.s3_client.config();
+
+        assert_eq!(Some(REGION), config.region().map(|region| region.as_ref()));
+        assert_eq!("bucket", factory.bucket);
+
+        let (storage, requests) = create_gcs_storage();
+        assert!(storage.get_file_metadata(String::from("key")).await.is_ok());
+
+        assert_eq!(
+            "HEAD https://storage.googleapis.com/bucket/key",
+            requests.lock().unwrap()[0]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_gcs_storage_maps_listed_objects_to_files() {
+        let (storage, _) = create_gcs_storage();
+
+        let handle = storage
+            .open_dir_handle(String::from("/home/test"))
+            .await
+            .unwrap();
+        let files = storage.read_dir(&handle).await.unwrap();
+
+        assert_eq!(2, files.len());
+
+        assert_eq!("dir", files[0].file_name);
+        assert_eq!(
+            Some(0o40000 | DEFAULT_DIR_MODE),
+            files[0].file_attributes.permissions
+        );
+
+        assert_eq!("file.txt", files[1].file_name);
+        assert_eq!(Some(4), files[1].file_attributes.size);
+        assert_eq!(Some(1700000000), files[1].file_attributes.mtime);
+        assert_eq!(
+            Some(0o100000 | DEFAULT_FILE_MODE),
+            files[1].file_attributes.permissions
+        );
+
+        assert!(storage.read_dir(&handle).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_gcs_storage_maps_object_metadata_to_file() {
+        let (storage, _) = create_gcs_storage();
+
+        let file = storage
+            .get_file_metadata(String::from("home/test/file.txt"))
+            .await
+            .unwrap();
+
+        assert_eq!("file.txt", file.file_name);
+        assert_eq!(Some(4), file.file_attributes.size);
+        assert_eq!(Some(1700000000), file.file_attributes.mtime);
+        assert_eq!(
+            Some(0o100000 | DEFAULT_FILE_MODE),
+            file.file_attributes.permissions
+        );
+    }
+
+    #[tokio::test]
+    async fn test_gcs_storage_rejects_part_copies() {
+        let (storage, requests) = create_gcs_storage();
+
+        // The object is larger than the multipart threshold, so appending would copy it into a
+        // multipart upload.
+        assert_eq!(
+            Err(Error::Unimplemented),
+            storage
+                .open_append_handle(String::from("home/test/large.bin"), false)
+                .await
+        );
+        assert!(requests
+            .lock()
+            .unwrap()
+            .iter()
+            .all(|request| !request.starts_with("POST")));
+    }
+
+    /// Responds like the GCS XML API, which lists objects with GCS's own elements and describes
+    /// objects with `x-goog-` headers. Requests are recorded as their method and URI, without the
+    /// query.
+    #[derive(Debug)]
+    struct GcsHttpConnector(Arc<Mutex<Vec<String>>>);
+
+    impl HttpConnector for GcsHttpConnector {
+        fn call(&self, request: HttpRequest) -> HttpConnectorFuture {
+            let (uri, query) = request.uri().split_once('?').unwrap_or((request.uri(), ""));
+
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("{} {}", request.method(), uri));
+
+            if query.contains("list-type=2") {
+                return HttpConnectorFuture::ready(Ok(HttpResponse::new(
+                    StatusCode::try_from(200).unwrap(),
+                    SdkBody::from(
+                        "<?xml version='1.0' encoding='UTF-8'?>\
+                         <ListBucketResult xmlns=\"http://doc.s3.amazonaws.com/2006-03-01\">\
+                         <Name>bucket</Name><Prefix>home/test/</Prefix><KeyCount>2</KeyCount>\
+                         <MaxKeys>1000</MaxKeys><Delimiter>/</Delimiter>\
+                         <IsTruncated>false</IsTruncated>\
+                         <Contents><Key>home/test/file.txt</Key>\
+                         <Generation>1700000000000000</Generation>\
+                         <MetaGeneration>1</MetaGeneration>\
+                         <LastModified>2023-11-14T22:13:20.000Z</LastModified>\
+                         <ETag>\"etag\"</ETag><Size>4</Size></Contents>\
+                         <CommonPrefixes><Prefix>home/test/dir/</Prefix></CommonPrefixes>\
+                         </ListBucketResult>",
+                    ),
+                )));
+            }
+
+            let size = match uri.rsplit('/').next() {
+                Some("large.bin") => (DEFAULT_MULTIPART_THRESHOLD + 1).to_string(),
+                _ => String::from("4"),
+            };
+
+            let mut response =
+                HttpResponse::new(StatusCode::try_from(200).unwrap(), SdkBody::empty());
+            let headers = response.headers_mut();
+            headers.insert("Content-Length", size.clone());
+            headers.insert("Content-Type", "text/plain");
+            headers.insert("ETag", "\"etag\"");
+            headers.insert("Last-Modified", "Tue, 14 Nov 2023 22:13:20 GMT");
+            headers.insert("x-goog-generation", "1700000000000000");
+            headers.insert("x-goog-metageneration", "1");
+            headers.insert("x-goog-stored-content-encoding", "identity");
+            headers.insert("x-goog-stored-content-length", size);
+            headers.insert("x-goog-storage-class", "STANDARD");
+
+            HttpConnectorFuture::ready(Ok(response))
+        }
+    }
+
+    fn create_gcs_storage() -> (Arc<dyn Storage>, Arc<Mutex<Vec<String>>>) {
+        let requests = Arc::new(Mutex::new(vec![]));
+        let connector = SharedHttpConnector::new(GcsHttpConnector(requests.clone()));
+
+        let s3_sdk_config = build_s3_sdk_config(&create_gcs_config())
+            .http_client(http_client_fn(move |_, _| connector.clone()))
+            .build();
+
+        let factory = GcsStorageFactory {
+            s3_client: aws_sdk_s3::Client::from_conf(s3_sdk_config),
+            ..GcsStorageFactory::new(
+                &create_gcs_config(),
+                DEFAULT_MAX_HANDLES,
+                Duration::from_secs(60),
+            )
+        };
+
+        (factory.create_storage(), requests)
+    }
+
+    fn create_gcs_config() -> GcsConfig {
+        GcsConfig {
+            endpoint_name: String::from(DEFAULT_ENDPOINT_NAME),
+            bucket: String::from("bucket"),
+            hmac_access_id: String::from("id"),
+            hmac_secret: String::from("hmac-key"),
+        }
+    }
+}
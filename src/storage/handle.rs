@@ -3,22 +3,48 @@ use tokio::sync::{Mutex, RwLock};
 use uuid::Uuid;
 
 use crate::error::Error;
+use crate::metrics;
+
+/// The default maximum number of handles a session may hold open at once.
+pub const DEFAULT_MAX_HANDLES: usize = 64;
+
+/// The default maximum number of handles a session may hold open for writing at once. Write
+/// handles buffer data before it is uploaded, so they are limited separately from other handles.
+pub const DEFAULT_MAX_WRITE_HANDLES: usize = 16;
 
 pub struct HandleManager<ReadHandle, WriteHandle, DirHandle> {
     read_handles: RwLock<HashMap<String, Arc<Mutex<ReadHandle>>>>,
     write_handles: RwLock<HashMap<String, Arc<Mutex<WriteHandle>>>>,
     dir_handles: RwLock<HashMap<String, Arc<Mutex<DirHandle>>>>,
+    max_handles: usize,
+    max_write_handles: usize,
 }
 
 impl<ReadHandle, WriteHandle, DirHandle> HandleManager<ReadHandle, WriteHandle, DirHandle> {
     pub fn new() -> HandleManager<ReadHandle, WriteHandle, DirHandle> {
+        HandleManager::with_capacity(DEFAULT_MAX_HANDLES)
+    }
+
+    pub fn with_capacity(max_handles: usize) -> HandleManager<ReadHandle, WriteHandle, DirHandle> {
         HandleManager {
             read_handles: RwLock::new(HashMap::new()),
             write_handles: RwLock::new(HashMap::new()),
             dir_handles: RwLock::new(HashMap::new()),
+            max_handles,
+            max_write_handles: DEFAULT_MAX_WRITE_HANDLES,
         }
     }
 
+    /// Sets the maximum number of write handles, which also count towards the maximum number of
+    /// handles.
+    pub fn with_max_write_handles(
+        mut self,
+        max_write_handles: usize,
+    ) -> HandleManager<ReadHandle, WriteHandle, DirHandle> {
+        self.max_write_handles = max_write_handles;
+        self
+    }
+
     pub async fn create_dir_handle(&self, dir_handle: DirHandle) -> Result<String, Error> {
         self.check_handle_capacity().await?;
 
@@ -29,6 +55,8 @@ impl<ReadHandle, WriteHandle, DirHandle> HandleManager<ReadHandle, WriteHandle,
             .await
             .insert(handle_id.clone(), Arc::from(Mutex::from(dir_handle)));
 
+        metrics::increment_open_handles(metrics::DIR_HANDLE);
+
         Ok(handle_id)
     }
 
@@ -42,11 +70,14 @@ impl<ReadHandle, WriteHandle, DirHandle> HandleManager<ReadHandle, WriteHandle,
             .await
             .insert(handle_id.clone(), Arc::from(Mutex::from(read_handle)));
 
+        metrics::increment_open_handles(metrics::READ_HANDLE);
+
         Ok(handle_id)
     }
 
     pub async fn create_write_handle(&self, write_handle: WriteHandle) -> Result<String, Error> {
         self.check_handle_capacity().await?;
+        self.check_write_handle_capacity().await?;
 
         let handle_id = generate_handle_id();
 
@@ -55,6 +86,35 @@ impl<ReadHandle, WriteHandle, DirHandle> HandleManager<ReadHandle, WriteHandle,
             .await
             .insert(handle_id.clone(), Arc::from(Mutex::from(write_handle)));
 
+        metrics::increment_open_handles(metrics::WRITE_HANDLE);
+
+        Ok(handle_id)
+    }
+
+    /// Creates a handle that can be both read and written. The read and write sides are tracked
+    /// under the same handle id, and both are removed when the handle is closed.
+    pub async fn create_read_write_handle(
+        &self,
+        read_handle: ReadHandle,
+        write_handle: WriteHandle,
+    ) -> Result<String, Error> {
+        self.check_handle_capacity().await?;
+        self.check_write_handle_capacity().await?;
+
+        let handle_id = generate_handle_id();
+
+        self.read_handles
+            .write()
+            .await
+            .insert(handle_id.clone(), Arc::from(Mutex::from(read_handle)));
+        self.write_handles
+            .write()
+            .await
+            .insert(handle_id.clone(), Arc::from(Mutex::from(write_handle)));
+
+        metrics::increment_open_handles(metrics::READ_HANDLE);
+        metrics::increment_open_handles(metrics::WRITE_HANDLE);
+
         Ok(handle_id)
     }
 
@@ -71,23 +131,99 @@ impl<ReadHandle, WriteHandle, DirHandle> HandleManager<ReadHandle, WriteHandle,
     }
 
     pub async fn remove_handle(&self, handle: &str) {
-        self.dir_handles.write().await.remove(handle);
-        self.read_handles.write().await.remove(handle);
-        self.write_handles.write().await.remove(handle);
+        if self.dir_handles.write().await.remove(handle).is_some() {
+            metrics::decrement_open_handles(metrics::DIR_HANDLE, 1);
+        }
+
+        if self.read_handles.write().await.remove(handle).is_some() {
+            metrics::decrement_open_handles(metrics::READ_HANDLE, 1);
+        }
+
+        if self.write_handles.write().await.remove(handle).is_some() {
+            metrics::decrement_open_handles(metrics::WRITE_HANDLE, 1);
+        }
+    }
+
+    /// Removes a write handle, returning it so that any pending writes can be discarded by the
+    /// caller.
+    pub async fn remove_write_handle(&self, handle: &str) -> Option<Arc<Mutex<WriteHandle>>> {
+        let write_handle = self.write_handles.write().await.remove(handle);
+
+        if write_handle.is_some() {
+            metrics::decrement_open_handles(metrics::WRITE_HANDLE, 1);
+        }
+
+        write_handle
     }
 
+    /// Removes every open write handle, returning them so that any pending writes can be
+    /// discarded by the caller.
+    pub async fn remove_write_handles(&self) -> Vec<Arc<Mutex<WriteHandle>>> {
+        let write_handles: Vec<_> = self
+            .write_handles
+            .write()
+            .await
+            .drain()
+            .map(|(_, write_handle)| write_handle)
+            .collect();
+
+        metrics::decrement_open_handles(metrics::WRITE_HANDLE, write_handles.len());
+
+        write_handles
+    }
+
+    /// Checks that another handle can be opened. Read and write handles that share an id are
+    /// counted once.
     async fn check_handle_capacity(&self) -> Result<(), Error> {
-        let read_handles = self.read_handles.read().await.len();
-        let write_handles = self.write_handles.read().await.len();
+        let write_handles = self.write_handles.read().await;
+        let read_handles = self
+            .read_handles
+            .read()
+            .await
+            .keys()
+            .filter(|handle_id| !write_handles.contains_key(*handle_id))
+            .count();
+        let write_handles = write_handles.len();
         let dir_handles = self.dir_handles.read().await.len();
 
         let handles_len = read_handles + write_handles + dir_handles;
 
-        match handles_len < 5 {
+        match handles_len < self.max_handles {
             true => Ok(()),
             false => Err(Error::Storage("The handle manager is full.".to_string())),
         }
     }
+
+    /// Checks that another file can be opened for writing. The limit is reported to the client,
+    /// so that it can close files before opening more.
+    async fn check_write_handle_capacity(&self) -> Result<(), Error> {
+        match self.write_handles.read().await.len() < self.max_write_handles {
+            true => Ok(()),
+            false => Err(Error::Failure(format!(
+                "Too many files are open for writing. At most {} may be open at once.",
+                self.max_write_handles
+            ))),
+        }
+    }
+}
+
+impl<ReadHandle, WriteHandle, DirHandle> Default
+    for HandleManager<ReadHandle, WriteHandle, DirHandle>
+{
+    fn default() -> Self {
+        HandleManager::new()
+    }
+}
+
+impl<ReadHandle, WriteHandle, DirHandle> Drop
+    for HandleManager<ReadHandle, WriteHandle, DirHandle>
+{
+    fn drop(&mut self) {
+        // Handles left open when a session ends are released with the handle manager.
+        metrics::decrement_open_handles(metrics::DIR_HANDLE, self.dir_handles.get_mut().len());
+        metrics::decrement_open_handles(metrics::READ_HANDLE, self.read_handles.get_mut().len());
+        metrics::decrement_open_handles(metrics::WRITE_HANDLE, self.write_handles.get_mut().len());
+    }
 }
 
 pub trait Handle {
@@ -120,18 +256,19 @@ mod test {
 
     #[tokio::test]
     async fn test_handle_manager_dir_handle_handles_full() {
-        let handle_manager: HandleManager<String, String, String> = HandleManager::new();
+        let handle_manager: HandleManager<String, String, String> = HandleManager::with_capacity(3);
 
-        let mut is_error = false;
-
-        for _ in 0..=5 {
-            is_error = handle_manager
+        for _ in 0..3 {
+            handle_manager
                 .create_dir_handle(String::from("dir"))
                 .await
-                .is_err();
+                .unwrap();
         }
 
-        assert!(is_error);
+        assert!(handle_manager
+            .create_dir_handle(String::from("dir"))
+            .await
+            .is_err());
     }
 
     #[tokio::test]
@@ -175,18 +312,19 @@ mod test {
 
     #[tokio::test]
     async fn test_handle_manager_read_handle_handles_full() {
-        let handle_manager: HandleManager<String, String, String> = HandleManager::new();
+        let handle_manager: HandleManager<String, String, String> = HandleManager::with_capacity(3);
 
-        let mut is_error = false;
-
-        for _ in 0..=5 {
-            is_error = handle_manager
+        for _ in 0..3 {
+            handle_manager
                 .create_read_handle(String::from("read"))
                 .await
-                .is_err();
+                .unwrap();
         }
 
-        assert!(is_error);
+        assert!(handle_manager
+            .create_read_handle(String::from("read"))
+            .await
+            .is_err());
     }
 
     #[tokio::test]
@@ -229,19 +367,51 @@ mod test {
     }
 
     #[tokio::test]
-    async fn test_handle_manager_write_handle_handles_full() {
+    async fn test_handle_manager_read_write_handle_create_get() {
         let handle_manager: HandleManager<String, String, String> = HandleManager::new();
 
-        let mut is_error = false;
+        let handle_id = handle_manager
+            .create_read_write_handle(String::from("read"), String::from("write"))
+            .await
+            .unwrap();
+
+        let read_handle = handle_manager.get_read_handle(&handle_id).await.unwrap();
+        let write_handle = handle_manager.get_write_handle(&handle_id).await.unwrap();
+
+        assert_eq!("read", &*read_handle.lock().await);
+        assert_eq!("write", &*write_handle.lock().await);
+    }
+
+    #[tokio::test]
+    async fn test_handle_manager_read_write_handle_remove() {
+        let handle_manager: HandleManager<String, String, String> = HandleManager::new();
+
+        let handle_id = handle_manager
+            .create_read_write_handle(String::from("read"), String::from("write"))
+            .await
+            .unwrap();
+
+        handle_manager.remove_handle(&handle_id).await;
+
+        assert!(handle_manager.get_read_handle(&handle_id).await.is_none());
+        assert!(handle_manager.get_write_handle(&handle_id).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_handle_manager_write_handle_handles_full() {
+        let handle_manager: HandleManager<String, String, String> = HandleManager::with_capacity(3);
 
-        for _ in 0..=5 {
-            is_error = handle_manager
+        for _ in 0..3 {
+            handle_manager
                 .create_write_handle(String::from("write"))
                 .await
-                .is_err();
+                .unwrap();
         }
 
-        assert!(is_error);
+        assert!(handle_manager
+            .create_write_handle(String::from("write"))
+            .await
+            .is_err());
     }
 
     #[tokio::test]
@@ -280,6 +450,204 @@ mod test {
             .is_none());
     }
 
+    #[tokio::test]
+    async fn test_handle_manager_remove_write_handle() {
+        let handle_manager: HandleManager<String, String, String> = HandleManager::new();
+
+        let handle_id = handle_manager
+            .create_write_handle(String::from("write"))
+            .await
+            .unwrap();
+
+        let write_handle = handle_manager.remove_write_handle(&handle_id).await;
+
+        assert_eq!("write", *write_handle.unwrap().lock().await);
+        assert!(handle_manager.get_write_handle(&handle_id).await.is_none());
+        assert!(handle_manager
+            .remove_write_handle(&handle_id)
+            .await
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_handle_manager_remove_write_handles() {
+        let handle_manager: HandleManager<String, String, String> = HandleManager::new();
+
+        let read_handle_id = handle_manager
+            .create_read_handle(String::from("read"))
+            .await
+            .unwrap();
+        let write_handle_id = handle_manager
+            .create_write_handle(String::from("write"))
+            .await
+            .unwrap();
+
+        let write_handles = handle_manager.remove_write_handles().await;
+
+        assert_eq!(1, write_handles.len());
+        assert_eq!("write", *write_handles[0].lock().await);
+        assert!(handle_manager
+            .get_write_handle(&write_handle_id)
+            .await
+            .is_none());
+        assert!(handle_manager
+            .get_read_handle(&read_handle_id)
+            .await
+            .is_some());
+    }
+
+    #[tokio::test]
+    async fn test_handle_manager_capacity_counts_all_handle_types() {
+        let handle_manager: HandleManager<String, String, String> = HandleManager::with_capacity(3);
+
+        handle_manager
+            .create_dir_handle(String::from("dir"))
+            .await
+            .unwrap();
+        handle_manager
+            .create_read_handle(String::from("read"))
+            .await
+            .unwrap();
+        let handle_id = handle_manager
+            .create_write_handle(String::from("write"))
+            .await
+            .unwrap();
+
+        assert!(handle_manager
+            .create_read_handle(String::from("read"))
+            .await
+            .is_err());
+
+        handle_manager.remove_handle(&handle_id).await;
+
+        assert!(handle_manager
+            .create_read_handle(String::from("read"))
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_handle_manager_capacity_counts_read_write_handles_once() {
+        let handle_manager: HandleManager<String, String, String> = HandleManager::with_capacity(3);
+
+        for _ in 0..2 {
+            handle_manager
+                .create_read_write_handle(String::from("read"), String::from("write"))
+                .await
+                .unwrap();
+        }
+
+        let handle_id = handle_manager
+            .create_read_write_handle(String::from("read"), String::from("write"))
+            .await
+            .unwrap();
+
+        assert!(handle_manager
+            .create_read_handle(String::from("read"))
+            .await
+            .is_err());
+
+        handle_manager.remove_handle(&handle_id).await;
+
+        assert!(handle_manager
+            .create_read_handle(String::from("read"))
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_handle_manager_new_uses_default_capacity() {
+        let handle_manager: HandleManager<String, String, String> = HandleManager::new();
+
+        for _ in 0..DEFAULT_MAX_HANDLES {
+            handle_manager
+                .create_dir_handle(String::from("dir"))
+                .await
+                .unwrap();
+        }
+
+        assert!(handle_manager
+            .create_dir_handle(String::from("dir"))
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_handle_manager_write_handles_full() {
+        let handle_manager: HandleManager<String, String, String> =
+            HandleManager::with_capacity(10).with_max_write_handles(2);
+
+        for _ in 0..2 {
+            handle_manager
+                .create_write_handle(String::from("write"))
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(
+            Err(Error::Failure(String::from(
+                "Too many files are open for writing. At most 2 may be open at once."
+            ))),
+            handle_manager
+                .create_write_handle(String::from("write"))
+                .await
+        );
+        assert!(handle_manager
+            .create_read_write_handle(String::from("read"), String::from("write"))
+            .await
+            .is_err());
+
+        // Read and directory handles are still available below the total capacity.
+        assert!(handle_manager
+            .create_read_handle(String::from("read"))
+            .await
+            .is_ok());
+        assert!(handle_manager
+            .create_dir_handle(String::from("dir"))
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_handle_manager_write_handles_available_after_close() {
+        let handle_manager: HandleManager<String, String, String> =
+            HandleManager::with_capacity(10).with_max_write_handles(1);
+
+        let handle_id = handle_manager
+            .create_write_handle(String::from("write"))
+            .await
+            .unwrap();
+
+        assert!(handle_manager
+            .create_write_handle(String::from("write"))
+            .await
+            .is_err());
+
+        handle_manager.remove_handle(&handle_id).await;
+
+        assert!(handle_manager
+            .create_write_handle(String::from("write"))
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_handle_manager_new_uses_default_write_capacity() {
+        let handle_manager: HandleManager<String, String, String> = HandleManager::new();
+
+        for _ in 0..DEFAULT_MAX_WRITE_HANDLES {
+            handle_manager
+                .create_write_handle(String::from("write"))
+                .await
+                .unwrap();
+        }
+
+        assert!(handle_manager
+            .create_write_handle(String::from("write"))
+            .await
+            .is_err());
+    }
+
     #[test]
     fn test_generate_handle_id_creates_uuid() {
         let handle = generate_handle_id();
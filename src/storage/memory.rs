@@ -0,0 +1,1515 @@
+use super::handle::HandleManager;
+use super::Storage;
+use super::StorageFactory;
+use super::{
+    get_symlink_name, is_symlink_name, map_sentinel_to_symlink, DEFAULT_DIR_MODE, DEFAULT_FILE_MODE,
+};
+use crate::error::Error;
+use crate::protocol::file_attributes::FileAttributes;
+use crate::protocol::response::name::File;
+use crate::ssh_keys;
+use async_trait::async_trait;
+use bytes::Bytes;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::Mutex;
+use tracing::Level;
+
+const DIR_TYPE: u32 = 0o040000;
+const REGULAR_FILE_TYPE: u32 = 0o100000;
+
+/// Files are kept in a map ordered by path, so that directory listings are in a stable order.
+type Entries = Arc<Mutex<BTreeMap<String, Entry>>>;
+
+/// Creates storage that keeps files in memory. Every session created by the factory shares the
+/// same files, which are lost when the factory is dropped. This is intended for tests that
+/// exercise the protocol without a real storage backend.
+#[derive(Default)]
+pub struct InMemoryStorageFactory {
+    entries: Entries,
+    max_handles: usize,
+}
+
+impl InMemoryStorageFactory {
+    pub fn new(max_handles: usize) -> InMemoryStorageFactory {
+        InMemoryStorageFactory {
+            entries: Arc::new(Mutex::new(BTreeMap::new())),
+            max_handles,
+        }
+    }
+
+    /// Stores a file, creating its parent directories, so that tests can seed files such as a
+    /// user's authorized keys.
+    pub async fn put_file(&self, file_name: &str, data: Vec<u8>) -> Result<(), Error> {
+        let key = get_key(file_name)?;
+        let mut entries = self.entries.lock().await;
+
+        create_parent_dirs(&mut entries, &key)?;
+        entries.insert(key, Entry::file(data));
+
+        Ok(())
+    }
+}
+
+impl StorageFactory for InMemoryStorageFactory {
+    fn create_storage(&self) -> Arc<dyn Storage> {
+        Arc::new(InMemoryStorage::new(self.entries.clone(), self.max_handles))
+    }
+}
+
+pub struct InMemoryStorage {
+    entries: Entries,
+    handle_manager: HandleManager<ReadHandle, WriteHandle, DirHandle>,
+}
+
+impl InMemoryStorage {
+    fn new(entries: Entries, max_handles: usize) -> InMemoryStorage {
+        InMemoryStorage {
+            entries,
+            handle_manager: HandleManager::with_capacity(max_handles),
+        }
+    }
+
+    async fn get_file_data(&self, key: &str) -> Result<Vec<u8>, Error> {
+        match self.entries.lock().await.get(key) {
+            Some(Entry::File { data, .. }) => Ok(data.clone()),
+            Some(Entry::Dir { .. }) => Err(Error::IsADirectory),
+            None => Err(Error::NoSuchFile),
+        }
+    }
+
+    /// Stores the data written to a handle, replacing the file's contents.
+    async fn commit_write_handle(&self, write_handle: &WriteHandle) -> Result<(), Error> {
+        let mut entries = self.entries.lock().await;
+
+        let mut entry = match entries.remove(&write_handle.key) {
+            Some(Entry::File { attributes, .. }) => Entry::File {
+                data: write_handle.data.clone(),
+                attributes,
+            },
+            Some(dir @ Entry::Dir { .. }) => {
+                entries.insert(write_handle.key.clone(), dir);
+                return Err(Error::IsADirectory);
+            }
+            None => Entry::file(write_handle.data.clone()),
+        };
+
+        if let Some(file_attributes) = &write_handle.file_attributes {
+            entry.set_attributes(file_attributes);
+        }
+
+        entry.touch();
+
+        create_parent_dirs(&mut entries, &write_handle.key)?;
+        entries.insert(write_handle.key.clone(), entry);
+
+        Ok(())
+    }
+
+    async fn open_existing_file(
+        &self,
+        file_name: &str,
+        truncate: bool,
+    ) -> Result<WriteHandle, Error> {
+        let key = get_key(file_name)?;
+        let data = self.get_file_data(&key).await?;
+
+        Ok(WriteHandle {
+            key,
+            data: match truncate {
+                true => Vec::new(),
+                false => data,
+            },
+            appending: false,
+            file_attributes: None,
+        })
+    }
+}
+
+#[async_trait]
+impl Storage for InMemoryStorage {
+    #[tracing::instrument(skip(self))]
+    async fn init(&self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn health_check(&self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn get_authorized_keys_fingerprints(&self, user: &str) -> Result<Vec<String>, Error> {
+        let key = get_key(&format!(".ssh/{}/authorized_keys", user))?;
+
+        match self.get_file_data(&key).await {
+            Ok(data) => Ok(ssh_keys::parse_authorized_keys(&String::from_utf8_lossy(
+                &data,
+            ))),
+            Err(Error::NoSuchFile) => Ok(vec![]),
+            Err(err) => Err(err),
+        }
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn open_dir_handle(&self, dir_name: String) -> Result<String, Error> {
+        let key = get_key(&dir_name)?;
+
+        match self.entries.lock().await.get(&key) {
+            Some(Entry::Dir { .. }) => {}
+            None if key.is_empty() => {}
+            Some(Entry::File { .. }) => return Err(Error::NotADirectory),
+            None => return Err(Error::NoSuchFile),
+        };
+
+        self.handle_manager
+            .create_dir_handle(DirHandle { key, is_eof: false })
+            .await
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn create_dir(&self, dir_name: String) -> Result<(), Error> {
+        let key = get_key(&dir_name)?;
+        let mut entries = self.entries.lock().await;
+
+        create_parent_dirs(&mut entries, &key)?;
+
+        match entries.get(&key) {
+            Some(Entry::Dir { .. }) => Ok(()),
+            Some(Entry::File { .. }) => Err(Error::AlreadyExists),
+            None => {
+                entries.insert(key, Entry::dir());
+                Ok(())
+            }
+        }
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn read_dir(&self, handle: &str) -> Result<Vec<File>, Error> {
+        let dir_handle = match self.handle_manager.get_dir_handle(handle).await {
+            Some(dir_handle) => dir_handle,
+            None => return Err(Error::InvalidHandle),
+        };
+
+        let mut dir_handle = dir_handle.lock().await;
+
+        if dir_handle.is_eof {
+            return Ok(Vec::new());
+        }
+
+        let entries = self.entries.lock().await;
+        let prefix = get_dir_prefix(&dir_handle.key);
+
+        // Entries are ordered by path, so the directory's children are listed in order.
+        let files = entries
+            .range(prefix.clone()..)
+            .take_while(|(key, _)| key.starts_with(&prefix))
+            .filter(|(key, _)| !key[prefix.len()..].contains('/'))
+            .map(|(key, entry)| entry.to_file(&key[prefix.len()..]))
+            .map(|file| match is_symlink_name(&file.file_name) {
+                true => map_sentinel_to_symlink(file),
+                false => file,
+            })
+            .collect();
+
+        dir_handle.is_eof = true;
+
+        Ok(files)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn remove_dir(&self, dir_name: String) -> Result<(), Error> {
+        let key = get_key(&dir_name)?;
+        let mut entries = self.entries.lock().await;
+
+        match entries.get(&key) {
+            Some(Entry::Dir { .. }) => {}
+            Some(Entry::File { .. }) => return Err(Error::NotADirectory),
+            None => return Err(Error::NoSuchFile),
+        };
+
+        let prefix = get_dir_prefix(&key);
+
+        entries.retain(|entry_key, _| *entry_key != key && !entry_key.starts_with(&prefix));
+
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn get_dir_size(&self, dir_name: String) -> Result<u64, Error> {
+        let prefix = get_dir_prefix(&get_key(&dir_name)?);
+
+        Ok(self
+            .entries
+            .lock()
+            .await
+            .iter()
+            .filter(|(key, _)| key.starts_with(&prefix))
+            .map(|(_, entry)| match entry {
+                Entry::File { data, .. } => data.len() as u64,
+                Entry::Dir { .. } => 0,
+            })
+            .sum())
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn get_file_metadata(&self, file_name: String) -> Result<File, Error> {
+        let key = get_key(&file_name)?;
+        let entries = self.entries.lock().await;
+
+        if key.is_empty() {
+            return Ok(Entry::dir().to_file(""));
+        }
+
+        if let Some(entry) = entries.get(&key) {
+            return Ok(entry.to_file(get_file_name(&key)));
+        }
+
+        let symlink_key = get_symlink_name(&key);
+
+        match entries.get(&symlink_key) {
+            Some(entry) => Ok(map_sentinel_to_symlink(
+                entry.to_file(get_file_name(&symlink_key)),
+            )),
+            None => Err(Error::NoSuchFile),
+        }
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn set_file_metadata(
+        &self,
+        file_name: String,
+        file_attributes: FileAttributes,
+    ) -> Result<(), Error> {
+        let key = get_key(&file_name)?;
+
+        match self.entries.lock().await.get_mut(&key) {
+            Some(entry) => {
+                entry.set_attributes(&file_attributes);
+                Ok(())
+            }
+            None => Err(Error::NoSuchFile),
+        }
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn set_handle_metadata(
+        &self,
+        handle: &str,
+        file_attributes: FileAttributes,
+    ) -> Result<(), Error> {
+        if let Some(write_handle) = self.handle_manager.get_write_handle(handle).await {
+            write_handle.lock().await.file_attributes = Some(file_attributes);
+            Ok(())
+        } else if self.handle_manager.get_read_handle(handle).await.is_some()
+            || self.handle_manager.get_dir_handle(handle).await.is_some()
+        {
+            Ok(())
+        } else {
+            Err(Error::InvalidHandle)
+        }
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn get_handle_metadata(&self, handle: &str) -> Result<File, Error> {
+        if let Some(write_handle) = self.handle_manager.get_write_handle(handle).await {
+            let write_handle = write_handle.lock().await;
+            let mut entry = Entry::file(write_handle.data.clone());
+
+            if let Some(file_attributes) = &write_handle.file_attributes {
+                entry.set_attributes(file_attributes);
+            }
+
+            return Ok(entry.to_file(get_file_name(&write_handle.key)));
+        }
+
+        let key = if let Some(read_handle) = self.handle_manager.get_read_handle(handle).await {
+            read_handle.lock().await.key.clone()
+        } else if let Some(dir_handle) = self.handle_manager.get_dir_handle(handle).await {
+            dir_handle.lock().await.key.clone()
+        } else {
+            return Err(Error::InvalidHandle);
+        };
+
+        self.get_file_metadata(key).await
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn open_read_handle(&self, file_name: String) -> Result<String, Error> {
+        let key = get_key(&file_name)?;
+        let data = self.get_file_data(&key).await?;
+
+        self.handle_manager
+            .create_read_handle(ReadHandle { key, data })
+            .await
+    }
+
+    #[tracing::instrument(skip(self), level = Level::TRACE)]
+    async fn read_data(&self, handle: &str, offset: u64, len: u32) -> Result<Vec<u8>, Error> {
+        let read_handle = match self.handle_manager.get_read_handle(handle).await {
+            Some(read_handle) => read_handle,
+            None => return Err(Error::InvalidHandle),
+        };
+
+        let read_handle = read_handle.lock().await;
+
+        let start = usize::try_from(offset)
+            .unwrap_or(usize::MAX)
+            .min(read_handle.data.len());
+        let end = start
+            .saturating_add(len as usize)
+            .min(read_handle.data.len());
+
+        Ok(read_handle.data[start..end].to_vec())
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn open_write_handle(&self, file_name: String) -> Result<String, Error> {
+        let key = get_key(&file_name)?;
+
+        self.handle_manager
+            .create_write_handle(WriteHandle {
+                key,
+                data: Vec::new(),
+                appending: false,
+                file_attributes: None,
+            })
+            .await
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn open_existing_write_handle(
+        &self,
+        file_name: String,
+        truncate: bool,
+    ) -> Result<String, Error> {
+        let write_handle = self.open_existing_file(&file_name, truncate).await?;
+
+        self.handle_manager.create_write_handle(write_handle).await
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn open_read_write_handle(
+        &self,
+        file_name: String,
+        truncate: bool,
+    ) -> Result<String, Error> {
+        let write_handle = self.open_existing_file(&file_name, truncate).await?;
+
+        // Reads see the file as it was opened, and writes are stored when the handle is closed.
+        let read_handle = ReadHandle {
+            key: write_handle.key.clone(),
+            data: self.get_file_data(&write_handle.key).await?,
+        };
+
+        self.handle_manager
+            .create_read_write_handle(read_handle, write_handle)
+            .await
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn open_append_handle(&self, file_name: String, create: bool) -> Result<String, Error> {
+        let key = get_key(&file_name)?;
+
+        let data = match self.get_file_data(&key).await {
+            Ok(data) => data,
+            Err(Error::NoSuchFile) if create => Vec::new(),
+            Err(err) => return Err(err),
+        };
+
+        self.handle_manager
+            .create_write_handle(WriteHandle {
+                key,
+                data,
+                appending: true,
+                file_attributes: None,
+            })
+            .await
+    }
+
+    #[tracing::instrument(skip(self, data), level = Level::TRACE)]
+    async fn write_data(&self, handle: &str, offset: u64, data: Bytes) -> Result<(), Error> {
+        let write_handle = match self.handle_manager.get_write_handle(handle).await {
+            Some(write_handle) => write_handle,
+            None => return Err(Error::InvalidHandle),
+        };
+
+        let mut write_handle = write_handle.lock().await;
+
+        let offset = match write_handle.appending {
+            true => write_handle.data.len(),
+            false => offset as usize,
+        };
+
+        let end = offset + data.len();
+
+        if write_handle.data.len() < end {
+            write_handle.data.resize(end, 0);
+        }
+
+        write_handle.data[offset..end].copy_from_slice(&data);
+
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn remove_file(&self, file_name: String) -> Result<(), Error> {
+        let key = get_key(&file_name)?;
+        let mut entries = self.entries.lock().await;
+
+        match entries.get(&key) {
+            Some(Entry::File { .. }) => {
+                entries.remove(&key);
+                Ok(())
+            }
+            Some(Entry::Dir { .. }) => Err(Error::IsADirectory),
+            None => match entries.remove(&get_symlink_name(&key)) {
+                Some(_) => Ok(()),
+                None => Err(Error::NoSuchFile),
+            },
+        }
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn close_handle(&self, handle: &str) -> Result<(), Error> {
+        if let Some(write_handle) = self.handle_manager.get_write_handle(handle).await {
+            self.commit_write_handle(&*write_handle.lock().await)
+                .await?;
+        }
+
+        self.handle_manager.remove_handle(handle).await;
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn fsync(&self, handle: &str) -> Result<(), Error> {
+        if let Some(write_handle) = self.handle_manager.get_write_handle(handle).await {
+            self.commit_write_handle(&*write_handle.lock().await).await
+        } else if self.handle_manager.get_read_handle(handle).await.is_some()
+            || self.handle_manager.get_dir_handle(handle).await.is_some()
+        {
+            Ok(())
+        } else {
+            Err(Error::InvalidHandle)
+        }
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn rename(&self, current: String, new: String) -> Result<(), Error> {
+        let file = self.get_file_metadata(current.clone()).await?;
+
+        let (current, new) = match file.file_attributes.is_symlink() {
+            true => (get_symlink_name(&current), get_symlink_name(&new)),
+            false => (current, new),
+        };
+
+        let current_key = get_key(&current)?;
+        let new_key = get_key(&new)?;
+        let mut entries = self.entries.lock().await;
+
+        if let Some(Entry::Dir { .. }) = entries.get(&new_key) {
+            return Err(Error::IsADirectory);
+        }
+
+        let current_prefix = get_dir_prefix(&current_key);
+        let new_prefix = get_dir_prefix(&new_key);
+
+        if new_key.starts_with(&current_prefix) {
+            return Err(Error::Failure(format!(
+                "{} cannot be moved into itself.",
+                current
+            )));
+        }
+
+        let renamed_keys: Vec<String> = entries
+            .keys()
+            .filter(|key| **key == current_key || key.starts_with(&current_prefix))
+            .cloned()
+            .collect();
+
+        create_parent_dirs(&mut entries, &new_key)?;
+
+        for key in renamed_keys {
+            if let Some(entry) = entries.remove(&key) {
+                let renamed_key = match key == current_key {
+                    true => new_key.clone(),
+                    false => format!("{}{}", new_prefix, &key[current_prefix.len()..]),
+                };
+
+                entries.insert(renamed_key, entry);
+            }
+        }
+
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn copy_file(&self, current: String, new: String) -> Result<(), Error> {
+        let current_key = get_key(&current)?;
+        let new_key = get_key(&new)?;
+        let mut entries = self.entries.lock().await;
+
+        let entry = match entries.get(&current_key) {
+            Some(entry @ Entry::File { .. }) => entry.clone(),
+            Some(Entry::Dir { .. }) => return Err(Error::IsADirectory),
+            None => return Err(Error::NoSuchFile),
+        };
+
+        if let Some(Entry::Dir { .. }) = entries.get(&new_key) {
+            return Err(Error::IsADirectory);
+        }
+
+        create_parent_dirs(&mut entries, &new_key)?;
+        entries.insert(new_key, entry);
+
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn create_symlink(&self, link: String, target: String) -> Result<(), Error> {
+        let key = get_key(&get_symlink_name(&link))?;
+        let mut entries = self.entries.lock().await;
+
+        create_parent_dirs(&mut entries, &key)?;
+        entries.insert(key, Entry::file(target.into_bytes()));
+
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn read_symlink(&self, link: String) -> Result<String, Error> {
+        let data = self
+            .get_file_data(&get_key(&get_symlink_name(&link))?)
+            .await?;
+
+        String::from_utf8(data).map_err(|err| Error::Storage(err.to_string()))
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn abort_write_handle(&self, handle: &str) -> Result<(), Error> {
+        self.handle_manager.remove_write_handle(handle).await;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn abort_write_handles(&self) -> Result<(), Error> {
+        self.handle_manager.remove_write_handles().await;
+
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+enum Entry {
+    File {
+        data: Vec<u8>,
+        attributes: EntryAttributes,
+    },
+    Dir {
+        attributes: EntryAttributes,
+    },
+}
+
+#[derive(Clone)]
+struct EntryAttributes {
+    permissions: u32,
+    uid: Option<u32>,
+    gid: Option<u32>,
+    mtime: u32,
+}
+
+impl Entry {
+    fn file(data: Vec<u8>) -> Entry {
+        Entry::File {
+            data,
+            attributes: EntryAttributes::new(DEFAULT_FILE_MODE),
+        }
+    }
+
+    fn dir() -> Entry {
+        Entry::Dir {
+            attributes: EntryAttributes::new(DEFAULT_DIR_MODE),
+        }
+    }
+
+    fn attributes_mut(&mut self) -> &mut EntryAttributes {
+        match self {
+            Entry::File { attributes, .. } => attributes,
+            Entry::Dir { attributes } => attributes,
+        }
+    }
+
+    fn touch(&mut self) {
+        self.attributes_mut().mtime = get_now();
+    }
+
+    /// Applies the attributes a client sets. A file is truncated if the size is smaller than the
+    /// file, but it is not extended.
+    fn set_attributes(&mut self, file_attributes: &FileAttributes) {
+        if let (Entry::File { data, .. }, Some(size)) = (&mut *self, file_attributes.size) {
+            if size < data.len() as u64 {
+                data.truncate(size as usize);
+            }
+        }
+
+        let attributes = self.attributes_mut();
+
+        if let Some(permissions) = file_attributes.permissions {
+            attributes.permissions = permissions & 0o7777;
+        }
+
+        if file_attributes.uid.is_some() {
+            attributes.uid = file_attributes.uid;
+        }
+
+        if file_attributes.gid.is_some() {
+            attributes.gid = file_attributes.gid;
+        }
+
+        if let Some(mtime) = file_attributes.mtime {
+            attributes.mtime = mtime;
+        }
+    }
+
+    fn to_file(&self, file_name: &str) -> File {
+        let (size, file_type, attributes) = match self {
+            Entry::File { data, attributes } => {
+                (Some(data.len() as u64), REGULAR_FILE_TYPE, attributes)
+            }
+            Entry::Dir { attributes } => (None, DIR_TYPE, attributes),
+        };
+
+        File {
+            file_name: file_name.to_string(),
+            file_attributes: FileAttributes {
+                size,
+                uid: attributes.uid,
+                gid: attributes.gid,
+                permissions: Some(file_type | attributes.permissions),
+                atime: None,
+                mtime: Some(attributes.mtime),
+            },
+        }
+    }
+}
+
+impl EntryAttributes {
+    fn new(permissions: u32) -> EntryAttributes {
+        EntryAttributes {
+            permissions,
+            uid: None,
+            gid: None,
+            mtime: get_now(),
+        }
+    }
+}
+
+struct DirHandle {
+    key: String,
+    is_eof: bool,
+}
+
+struct ReadHandle {
+    key: String,
+    data: Vec<u8>,
+}
+
+struct WriteHandle {
+    key: String,
+    data: Vec<u8>,
+    appending: bool,
+    file_attributes: Option<FileAttributes>,
+}
+
+/// Maps an SFTP path to the key of an entry. Keys do not have leading or trailing slashes, and
+/// the root directory is the empty key. Parent directory components are rejected so that paths
+/// match the keys of the other storage backends.
+fn get_key(path: &str) -> Result<String, Error> {
+    let mut components = Vec::new();
+
+    for component in path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => return Err(Error::PermissionDenied),
+            component => components.push(component),
+        }
+    }
+
+    Ok(components.join("/"))
+}
+
+/// Builds the prefix shared by the keys of a directory's descendants.
+fn get_dir_prefix(key: &str) -> String {
+    match key.is_empty() {
+        true => String::new(),
+        false => format!("{}/", key),
+    }
+}
+
+fn get_file_name(key: &str) -> &str {
+    key.rsplit('/').next().unwrap_or_default()
+}
+
+/// Creates the directories containing a key, like `mkdir -p`. An error is returned if one of the
+/// parents is a file.
+fn create_parent_dirs(entries: &mut BTreeMap<String, Entry>, key: &str) -> Result<(), Error> {
+    let mut parent_end = 0;
+
+    while let Some(separator) = key[parent_end..].find('/') {
+        parent_end += separator;
+
+        match entries.get(&key[..parent_end]) {
+            Some(Entry::Dir { .. }) => {}
+            Some(Entry::File { .. }) => return Err(Error::NotADirectory),
+            None => {
+                entries.insert(key[..parent_end].to_string(), Entry::dir());
+            }
+        }
+
+        parent_end += 1;
+    }
+
+    Ok(())
+}
+
+fn get_now() -> u32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs() as u32)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::storage::DEFAULT_MAX_HANDLES;
+
+    #[tokio::test]
+    async fn test_init_and_health_check() {
+        let storage = create_storage();
+
+        assert_eq!(Ok(()), storage.init().await);
+        assert_eq!(Ok(()), storage.health_check().await);
+    }
+
+    #[tokio::test]
+    async fn test_get_authorized_keys_fingerprints() {
+        let factory = InMemoryStorageFactory::new(DEFAULT_MAX_HANDLES);
+        factory
+            .put_file(
+                "/.ssh/test/authorized_keys",
+                b"ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIAIl1rX8ataKL7pSTnF5UIrRAgdWvjb+KHRf2oj6Kbgs test"
+                    .to_vec(),
+            )
+            .await
+            .unwrap();
+        let storage = factory.create_storage();
+
+        assert_eq!(
+            1,
+            storage
+                .get_authorized_keys_fingerprints("test")
+                .await
+                .unwrap()
+                .len()
+        );
+        assert_eq!(
+            Ok(vec![]),
+            storage.get_authorized_keys_fingerprints("missing").await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_write_and_read_file() {
+        let storage = create_storage();
+
+        let handle = storage
+            .open_write_handle("/home/test/file.txt".to_string())
+            .await
+            .unwrap();
+        storage
+            .write_data(&handle, 5, Bytes::from(" world"))
+            .await
+            .unwrap();
+        storage
+            .write_data(&handle, 0, Bytes::from("hello"))
+            .await
+            .unwrap();
+        storage.close_handle(&handle).await.unwrap();
+
+        let handle = storage
+            .open_read_handle("/home/test/file.txt".to_string())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            b"hello",
+            storage.read_data(&handle, 0, 5).await.unwrap().as_slice()
+        );
+        assert_eq!(
+            b" world",
+            storage.read_data(&handle, 5, 100).await.unwrap().as_slice()
+        );
+        assert!(storage
+            .read_data(&handle, 11, 100)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_write_is_visible_only_after_close() {
+        let storage = create_storage();
+
+        let handle = storage
+            .open_write_handle("/home/test/file.txt".to_string())
+            .await
+            .unwrap();
+        storage
+            .write_data(&handle, 0, Bytes::from("hello"))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            Err(Error::NoSuchFile),
+            storage
+                .get_file_metadata("/home/test/file.txt".to_string())
+                .await
+        );
+
+        storage.fsync(&handle).await.unwrap();
+
+        assert_eq!(
+            Some(5),
+            storage
+                .get_file_metadata("/home/test/file.txt".to_string())
+                .await
+                .unwrap()
+                .file_attributes
+                .size
+        );
+    }
+
+    #[tokio::test]
+    async fn test_files_are_shared_between_sessions() {
+        let factory = InMemoryStorageFactory::new(DEFAULT_MAX_HANDLES);
+
+        write_file(&*factory.create_storage(), "/home/test/file.txt", "hello").await;
+
+        assert_eq!(
+            "hello",
+            read_file(&*factory.create_storage(), "/home/test/file.txt").await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_open_read_handle_with_missing_file() {
+        let storage = create_storage();
+
+        assert_eq!(
+            Err(Error::NoSuchFile),
+            storage
+                .open_read_handle("/home/test/missing.txt".to_string())
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_open_existing_write_handle_modifies_file_in_place() {
+        let storage = create_storage();
+        write_file(&storage, "/home/test/file.txt", "hello world").await;
+
+        let handle = storage
+            .open_existing_write_handle("/home/test/file.txt".to_string(), false)
+            .await
+            .unwrap();
+        storage
+            .write_data(&handle, 6, Bytes::from("there"))
+            .await
+            .unwrap();
+        storage.close_handle(&handle).await.unwrap();
+
+        assert_eq!(
+            "hello there",
+            read_file(&storage, "/home/test/file.txt").await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_open_existing_write_handle_with_truncate() {
+        let storage = create_storage();
+        write_file(&storage, "/home/test/file.txt", "hello world").await;
+
+        let handle = storage
+            .open_existing_write_handle("/home/test/file.txt".to_string(), true)
+            .await
+            .unwrap();
+        storage
+            .write_data(&handle, 0, Bytes::from("hi"))
+            .await
+            .unwrap();
+        storage.close_handle(&handle).await.unwrap();
+
+        assert_eq!("hi", read_file(&storage, "/home/test/file.txt").await);
+    }
+
+    #[tokio::test]
+    async fn test_open_existing_write_handle_with_missing_file() {
+        let storage = create_storage();
+
+        assert_eq!(
+            Err(Error::NoSuchFile),
+            storage
+                .open_existing_write_handle("/home/test/missing.txt".to_string(), false)
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_open_read_write_handle() {
+        let storage = create_storage();
+        write_file(&storage, "/home/test/file.txt", "hello world").await;
+
+        let handle = storage
+            .open_read_write_handle("/home/test/file.txt".to_string(), false)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            b"hello",
+            storage.read_data(&handle, 0, 5).await.unwrap().as_slice()
+        );
+
+        storage
+            .write_data(&handle, 0, Bytes::from("HELLO"))
+            .await
+            .unwrap();
+        storage.close_handle(&handle).await.unwrap();
+
+        assert_eq!(
+            "HELLO world",
+            read_file(&storage, "/home/test/file.txt").await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_open_append_handle() {
+        let storage = create_storage();
+        write_file(&storage, "/home/test/file.txt", "hello").await;
+
+        let handle = storage
+            .open_append_handle("/home/test/file.txt".to_string(), false)
+            .await
+            .unwrap();
+        storage
+            .write_data(&handle, 0, Bytes::from(" world"))
+            .await
+            .unwrap();
+        storage.close_handle(&handle).await.unwrap();
+
+        assert_eq!(
+            "hello world",
+            read_file(&storage, "/home/test/file.txt").await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_open_append_handle_with_missing_file() {
+        let storage = create_storage();
+
+        assert_eq!(
+            Err(Error::NoSuchFile),
+            storage
+                .open_append_handle("/home/test/missing.txt".to_string(), false)
+                .await
+        );
+
+        let handle = storage
+            .open_append_handle("/home/test/missing.txt".to_string(), true)
+            .await
+            .unwrap();
+        storage
+            .write_data(&handle, 10, Bytes::from("data"))
+            .await
+            .unwrap();
+        storage.close_handle(&handle).await.unwrap();
+
+        assert_eq!("data", read_file(&storage, "/home/test/missing.txt").await);
+    }
+
+    #[tokio::test]
+    async fn test_abort_write_handle() {
+        let storage = create_storage();
+
+        let handle = storage
+            .open_write_handle("/home/test/file.txt".to_string())
+            .await
+            .unwrap();
+        storage
+            .write_data(&handle, 0, Bytes::from("hello"))
+            .await
+            .unwrap();
+        storage.abort_write_handle(&handle).await.unwrap();
+
+        assert!(storage
+            .write_data(&handle, 0, Bytes::from("hello"))
+            .await
+            .is_err());
+        assert_eq!(
+            Err(Error::NoSuchFile),
+            storage
+                .get_file_metadata("/home/test/file.txt".to_string())
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_abort_write_handles() {
+        let storage = create_storage();
+
+        let handle = storage
+            .open_write_handle("/home/test/file.txt".to_string())
+            .await
+            .unwrap();
+        storage.abort_write_handles().await.unwrap();
+        storage.close_handle(&handle).await.unwrap();
+
+        assert_eq!(
+            Err(Error::NoSuchFile),
+            storage
+                .get_file_metadata("/home/test/file.txt".to_string())
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_create_and_read_dir() {
+        let storage = create_storage();
+        storage
+            .create_dir("/home/test/dir".to_string())
+            .await
+            .unwrap();
+        write_file(&storage, "/home/test/b.txt", "b").await;
+        write_file(&storage, "/home/test/a.txt", "a").await;
+        write_file(&storage, "/home/test/dir/nested.txt", "nested").await;
+        storage
+            .create_symlink("/home/test/link".to_string(), "a.txt".to_string())
+            .await
+            .unwrap();
+
+        let handle = storage
+            .open_dir_handle("/home/test".to_string())
+            .await
+            .unwrap();
+        let files = storage.read_dir(&handle).await.unwrap();
+
+        assert_eq!(
+            vec!["a.txt", "b.txt", "dir", "link"],
+            files
+                .iter()
+                .map(|file| file.file_name.as_str())
+                .collect::<Vec<&str>>()
+        );
+        assert_eq!(Some(1), files[0].file_attributes.size);
+        assert!(files[2].file_attributes.is_dir());
+        assert!(files[3].file_attributes.is_symlink());
+        assert!(storage.read_dir(&handle).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_read_dir_of_root() {
+        let storage = create_storage();
+        write_file(&storage, "/home/test/file.txt", "data").await;
+
+        let handle = storage.open_dir_handle("/".to_string()).await.unwrap();
+        let files = storage.read_dir(&handle).await.unwrap();
+
+        assert_eq!(1, files.len());
+        assert_eq!("home", files[0].file_name);
+    }
+
+    #[tokio::test]
+    async fn test_open_dir_handle_with_missing_dir() {
+        let storage = create_storage();
+
+        assert_eq!(
+            Err(Error::NoSuchFile),
+            storage.open_dir_handle("/home/missing".to_string()).await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_remove_dir() {
+        let storage = create_storage();
+        write_file(&storage, "/home/test/dir/file.txt", "data").await;
+        write_file(&storage, "/home/test/dir2/file.txt", "data").await;
+
+        storage
+            .remove_dir("/home/test/dir".to_string())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            Err(Error::NoSuchFile),
+            storage
+                .get_file_metadata("/home/test/dir/file.txt".to_string())
+                .await
+        );
+        assert_eq!(
+            Err(Error::NoSuchFile),
+            storage
+                .get_file_metadata("/home/test/dir".to_string())
+                .await
+        );
+        assert!(storage
+            .get_file_metadata("/home/test/dir2/file.txt".to_string())
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_get_dir_size() {
+        let storage = create_storage();
+        write_file(&storage, "/home/test/file.txt", "12345").await;
+        write_file(&storage, "/home/test/dir/file.txt", "123").await;
+        write_file(&storage, "/home/test2/file.txt", "1").await;
+
+        assert_eq!(Ok(8), storage.get_dir_size("/home/test".to_string()).await);
+        assert_eq!(
+            Ok(0),
+            storage.get_dir_size("/home/missing".to_string()).await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_file_metadata() {
+        let storage = create_storage();
+        write_file(&storage, "/home/test/file.txt", "hello").await;
+
+        let file = storage
+            .get_file_metadata("/home/test/file.txt".to_string())
+            .await
+            .unwrap();
+
+        assert_eq!("file.txt", file.file_name);
+        assert_eq!(Some(5), file.file_attributes.size);
+        assert_eq!(Some(0o100644), file.file_attributes.permissions);
+        assert!(storage
+            .get_file_metadata("/home/test".to_string())
+            .await
+            .unwrap()
+            .file_attributes
+            .is_dir());
+        assert!(storage
+            .get_file_metadata("/".to_string())
+            .await
+            .unwrap()
+            .file_attributes
+            .is_dir());
+    }
+
+    #[tokio::test]
+    async fn test_set_file_metadata() {
+        let storage = create_storage();
+        write_file(&storage, "/home/test/file.txt", "hello world").await;
+
+        storage
+            .set_file_metadata(
+                "/home/test/file.txt".to_string(),
+                FileAttributes {
+                    size: Some(5),
+                    uid: Some(1000),
+                    gid: Some(2000),
+                    permissions: Some(0o600),
+                    atime: None,
+                    mtime: Some(1000000000),
+                },
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            FileAttributes {
+                size: Some(5),
+                uid: Some(1000),
+                gid: Some(2000),
+                permissions: Some(0o100600),
+                atime: None,
+                mtime: Some(1000000000),
+            },
+            storage
+                .get_file_metadata("/home/test/file.txt".to_string())
+                .await
+                .unwrap()
+                .file_attributes
+        );
+        assert_eq!("hello", read_file(&storage, "/home/test/file.txt").await);
+    }
+
+    #[tokio::test]
+    async fn test_set_file_metadata_with_missing_file() {
+        let storage = create_storage();
+
+        assert_eq!(
+            Err(Error::NoSuchFile),
+            storage
+                .set_file_metadata(
+                    "/home/test/missing.txt".to_string(),
+                    FileAttributes::default()
+                )
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_set_and_get_handle_metadata() {
+        let storage = create_storage();
+
+        let handle = storage
+            .open_write_handle("/home/test/file.txt".to_string())
+            .await
+            .unwrap();
+        storage
+            .write_data(&handle, 0, Bytes::from("hello"))
+            .await
+            .unwrap();
+        storage
+            .set_handle_metadata(
+                &handle,
+                FileAttributes {
+                    permissions: Some(0o600),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+
+        let file = storage.get_handle_metadata(&handle).await.unwrap();
+
+        assert_eq!(Some(5), file.file_attributes.size);
+        assert_eq!(Some(0o100600), file.file_attributes.permissions);
+
+        storage.close_handle(&handle).await.unwrap();
+
+        assert_eq!(
+            Some(0o100600),
+            storage
+                .get_file_metadata("/home/test/file.txt".to_string())
+                .await
+                .unwrap()
+                .file_attributes
+                .permissions
+        );
+        assert!(storage.get_handle_metadata(&handle).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_remove_file() {
+        let storage = create_storage();
+        write_file(&storage, "/home/test/file.txt", "hello").await;
+
+        storage
+            .remove_file("/home/test/file.txt".to_string())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            Err(Error::NoSuchFile),
+            storage.remove_file("/home/test/file.txt".to_string()).await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rename_file() {
+        let storage = create_storage();
+        write_file(&storage, "/home/test/file.txt", "hello").await;
+
+        storage
+            .rename(
+                "/home/test/file.txt".to_string(),
+                "/home/test/dir/renamed.txt".to_string(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            "hello",
+            read_file(&storage, "/home/test/dir/renamed.txt").await
+        );
+        assert_eq!(
+            Err(Error::NoSuchFile),
+            storage
+                .get_file_metadata("/home/test/file.txt".to_string())
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rename_dir() {
+        let storage = create_storage();
+        write_file(&storage, "/home/test/dir/file.txt", "hello").await;
+        write_file(&storage, "/home/test/dir2/file.txt", "other").await;
+
+        storage
+            .rename("/home/test/dir".to_string(), "/home/test/moved".to_string())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            "hello",
+            read_file(&storage, "/home/test/moved/file.txt").await
+        );
+        assert_eq!(
+            "other",
+            read_file(&storage, "/home/test/dir2/file.txt").await
+        );
+        assert_eq!(
+            Err(Error::NoSuchFile),
+            storage
+                .get_file_metadata("/home/test/dir".to_string())
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rename_dir_into_itself() {
+        let storage = create_storage();
+        write_file(&storage, "/home/test/dir/file.txt", "hello").await;
+
+        assert!(storage
+            .rename(
+                "/home/test/dir".to_string(),
+                "/home/test/dir/nested".to_string()
+            )
+            .await
+            .is_err());
+        assert_eq!(
+            "hello",
+            read_file(&storage, "/home/test/dir/file.txt").await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_posix_rename_replaces_file() {
+        let storage = create_storage();
+        write_file(&storage, "/home/test/file.txt", "new").await;
+        write_file(&storage, "/home/test/existing.txt", "old").await;
+
+        storage
+            .posix_rename(
+                "/home/test/file.txt".to_string(),
+                "/home/test/existing.txt".to_string(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!("new", read_file(&storage, "/home/test/existing.txt").await);
+    }
+
+    #[tokio::test]
+    async fn test_copy_file() {
+        let storage = create_storage();
+        write_file(&storage, "/home/test/file.txt", "hello").await;
+
+        storage
+            .copy_file(
+                "/home/test/file.txt".to_string(),
+                "/home/test/dir/copy.txt".to_string(),
+            )
+            .await
+            .unwrap();
+        write_file(&storage, "/home/test/file.txt", "changed").await;
+
+        assert_eq!(
+            "hello",
+            read_file(&storage, "/home/test/dir/copy.txt").await
+        );
+        assert_eq!(
+            Err(Error::NoSuchFile),
+            storage
+                .copy_file(
+                    "/home/test/missing.txt".to_string(),
+                    "/home/test/copy.txt".to_string()
+                )
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_create_and_read_symlink() {
+        let storage = create_storage();
+
+        storage
+            .create_symlink(
+                "/home/test/link".to_string(),
+                "/home/test/file.txt".to_string(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            Ok("/home/test/file.txt".to_string()),
+            storage.read_symlink("/home/test/link".to_string()).await
+        );
+        assert!(storage
+            .get_file_metadata("/home/test/link".to_string())
+            .await
+            .unwrap()
+            .file_attributes
+            .is_symlink());
+
+        storage
+            .rename(
+                "/home/test/link".to_string(),
+                "/home/test/moved".to_string(),
+            )
+            .await
+            .unwrap();
+        storage
+            .remove_file("/home/test/moved".to_string())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            Err(Error::NoSuchFile),
+            storage.read_symlink("/home/test/moved".to_string()).await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_key_rejects_parent_directory() {
+        assert_eq!(Ok(String::from("home/test")), get_key("/home//./test/"));
+        assert_eq!(Err(Error::PermissionDenied), get_key("/home/../etc"));
+    }
+
+    fn create_storage() -> InMemoryStorage {
+        InMemoryStorage::new(Arc::new(Mutex::new(BTreeMap::new())), DEFAULT_MAX_HANDLES)
+    }
+
+    async fn write_file(storage: &dyn Storage, file_name: &str, data: &str) {
+        let handle = storage
+            .open_write_handle(file_name.to_string())
+            .await
+            .unwrap();
+        storage
+            .write_data(&handle, 0, Bytes::from(data.to_string()))
+            .await
+            .unwrap();
+        storage.close_handle(&handle).await.unwrap();
+    }
+
+    async fn read_file(storage: &dyn Storage, file_name: &str) -> String {
+        let handle = storage
+            .open_read_handle(file_name.to_string())
+            .await
+            .unwrap();
+        let data = storage.read_data(&handle, 0, u32::MAX).await.unwrap();
+        storage.close_handle(&handle).await.unwrap();
+
+        String::from_utf8(data).unwrap()
+    }
+}
@@ -1,12 +1,48 @@
+mod authorized_keys_cache;
+pub mod filesystem;
+pub mod gcs;
 mod handle;
+pub mod memory;
 pub mod s3;
+mod s3_tls;
+
+pub use handle::{DEFAULT_MAX_HANDLES, DEFAULT_MAX_WRITE_HANDLES};
 
 use std::sync::Arc;
 
 use async_trait::async_trait;
 use bytes::Bytes;
 
-use crate::{error::Error, protocol::response::name::File};
+use crate::{
+    error::Error,
+    protocol::{file_attributes::FileAttributes, response::name::File},
+};
+
+/// Storage backends do not support symbolic links, so links are emulated with a sentinel file
+/// named after the link with this suffix. The sentinel file contains the link target.
+const SYMLINK_SUFFIX: &str = ".draylink";
+
+const SYMLINK_PERMISSIONS: u32 = 0o120777;
+
+pub const DEFAULT_FILE_MODE: u32 = 0o644;
+pub const DEFAULT_DIR_MODE: u32 = 0o755;
+
+/// The permissions reported for files and directories that have no stored permissions, such as
+/// objects uploaded to a bucket outside of Dray.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DefaultModes {
+    pub file_mode: u32,
+    pub dir_mode: u32,
+}
+
+impl Default for DefaultModes {
+    fn default() -> DefaultModes {
+        DefaultModes {
+            file_mode: DEFAULT_FILE_MODE,
+            dir_mode: DEFAULT_DIR_MODE,
+        }
+    }
+}
 
 /// Builds an instance of a Storage backend, such as AWS S3.
 ///
@@ -23,14 +59,6 @@ pub trait Storage: Send + Sync {
     /// storage backend if it does not already exist.
     async fn init(&self) -> Result<(), Error>;
 
-    /// Retrieves the expected home directory of a user.
-    ///
-    /// # Note
-    /// - The calculation of the home directory must always succeed.
-    /// - The framework will check the if home exists if required.
-    /// - The framework will sanitize the user.
-    fn get_home(&self, user: &str) -> String;
-
     /// Checks if storage is available. An error will be returned if  storage
     /// operations cannot be performed.
     async fn health_check(&self) -> Result<(), Error>;
@@ -50,29 +78,72 @@ pub trait Storage: Send + Sync {
     /// Creates a directory.
     async fn create_dir(&self, dir_name: String) -> Result<(), Error>;
 
-    // Reads a file listing from the prefix associated with a given handle.
+    /// Reads the next batch of files from the directory associated with a given handle. An empty
+    /// batch is only returned once every file has been listed.
     async fn read_dir(&self, handle: &str) -> Result<Vec<File>, Error>;
 
     /// Removes a directory.
     async fn remove_dir(&self, dir_name: String) -> Result<(), Error>;
 
+    /// Calculates the total size of the files within a directory, including its subdirectories.
+    async fn get_dir_size(&self, dir_name: String) -> Result<u64, Error>;
+
     /// Retrieves an file's metadata.
     async fn get_file_metadata(&self, file_name: String) -> Result<File, Error>;
 
+    /// Updates a file's metadata, such as permissions and ownership. A file is truncated if the
+    /// requested size is smaller than the current size.
+    async fn set_file_metadata(
+        &self,
+        file_name: String,
+        file_attributes: FileAttributes,
+    ) -> Result<(), Error>;
+
+    /// Updates the metadata of the file associated with a given handle. Metadata for write handles
+    /// is applied when the handle is closed. Read and directory handles are left unchanged.
+    async fn set_handle_metadata(
+        &self,
+        handle: &str,
+        file_attributes: FileAttributes,
+    ) -> Result<(), Error>;
+
     /// Retrieves a handle's metadata.
     async fn get_handle_metadata(&self, handle: &str) -> Result<File, Error>;
 
     /// Creates a read handle for a file.
     async fn open_read_handle(&self, file_name: String) -> Result<String, Error>;
 
-    /// Reads up to len bytes of data data from a file associated with a given handle.
-    async fn read_data(&self, handle: &str, len: u32) -> Result<Vec<u8>, Error>;
+    /// Reads up to len bytes of data from a file associated with a given handle, starting at the
+    /// offset. Clients usually read sequentially, but may read from any offset, such as when
+    /// resuming a download.
+    async fn read_data(&self, handle: &str, offset: u64, len: u32) -> Result<Vec<u8>, Error>;
 
     /// Creates a write handle for a file.
     async fn open_write_handle(&self, file_name: String) -> Result<String, Error>;
 
-    /// Writes data to a file associated with a given handle.
-    async fn write_data(&self, handle: &str, data: Bytes) -> Result<(), Error>;
+    /// Creates a write handle for an existing file. The file's contents are kept, so that writes
+    /// modify the file in place, unless the file is truncated.
+    async fn open_existing_write_handle(
+        &self,
+        file_name: String,
+        truncate: bool,
+    ) -> Result<String, Error>;
+
+    /// Creates a handle for an existing file that can be both read and written. The file's
+    /// contents are kept unless the file is truncated.
+    async fn open_read_write_handle(
+        &self,
+        file_name: String,
+        truncate: bool,
+    ) -> Result<String, Error>;
+
+    /// Creates a write handle that appends to a file. Data is written to the end of the file
+    /// regardless of the offsets requested by the client. A missing file is created if create is
+    /// set.
+    async fn open_append_handle(&self, file_name: String, create: bool) -> Result<String, Error>;
+
+    /// Writes data at an offset to a file associated with a given handle.
+    async fn write_data(&self, handle: &str, offset: u64, data: Bytes) -> Result<(), Error>;
 
     /// Removes a file.
     async fn remove_file(&self, key: String) -> Result<(), Error>;
@@ -80,6 +151,112 @@ pub trait Storage: Send + Sync {
     // Closes a handle.
     async fn close_handle(&self, handle: &str) -> Result<(), Error>;
 
+    /// Flushes data written to a handle to storage, as far as the backend allows before the
+    /// handle is closed.
+    async fn fsync(&self, handle: &str) -> Result<(), Error>;
+
     /// Renames a file or directory.
     async fn rename(&self, current: String, new: String) -> Result<(), Error>;
+
+    /// Renames a file or directory, replacing the destination if it is an existing file. This
+    /// allows clients to atomically save a file by writing a temporary file and renaming it.
+    async fn posix_rename(&self, current: String, new: String) -> Result<(), Error> {
+        match self.get_file_metadata(new.clone()).await {
+            Ok(file) if !file.file_attributes.is_dir() => self.remove_file(new.clone()).await?,
+            _ => {}
+        };
+
+        self.rename(current, new).await
+    }
+
+    /// Copies a file to a new path. Storage cannot share data between paths, so hard links are
+    /// emulated with copies.
+    async fn copy_file(&self, current: String, new: String) -> Result<(), Error>;
+
+    /// Creates an emulated symbolic link that points to a target.
+    async fn create_symlink(&self, link: String, target: String) -> Result<(), Error>;
+
+    /// Retrieves the target of an emulated symbolic link.
+    async fn read_symlink(&self, link: String) -> Result<String, Error>;
+
+    /// Discards a write handle without committing its data. Backends that write files in place
+    /// may keep the data that has already been written.
+    async fn abort_write_handle(&self, handle: &str) -> Result<(), Error>;
+
+    /// Discards every open write handle without committing its data, such as when the server
+    /// shuts down before a client has closed its files.
+    async fn abort_write_handles(&self) -> Result<(), Error>;
+}
+
+fn get_symlink_name(link: &str) -> String {
+    format!("{}{}", link, SYMLINK_SUFFIX)
+}
+
+pub(crate) fn is_symlink_name(file_name: &str) -> bool {
+    file_name.ends_with(SYMLINK_SUFFIX)
+}
+
+/// Maps the sentinel file for an emulated symbolic link to the link it represents.
+fn map_sentinel_to_symlink(file: File) -> File {
+    let file_name = match file.file_name.strip_suffix(SYMLINK_SUFFIX) {
+        Some(file_name) => file_name.to_string(),
+        None => file.file_name,
+    };
+
+    File {
+        file_name,
+        file_attributes: FileAttributes {
+            permissions: Some(SYMLINK_PERMISSIONS),
+            ..file.file_attributes
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_get_symlink_name() {
+        assert_eq!(
+            "/home/test/link.draylink",
+            get_symlink_name("/home/test/link")
+        );
+    }
+
+    #[test]
+    fn test_is_symlink_name() {
+        assert!(is_symlink_name("link.draylink"));
+        assert!(!is_symlink_name("link.txt"));
+    }
+
+    #[test]
+    fn test_map_sentinel_to_symlink() {
+        let file = File {
+            file_name: String::from("link.draylink"),
+            file_attributes: FileAttributes {
+                size: Some(16),
+                uid: Some(1000),
+                gid: Some(1000),
+                permissions: Some(0o100644),
+                atime: None,
+                mtime: Some(1),
+            },
+        };
+
+        assert_eq!(
+            File {
+                file_name: String::from("link"),
+                file_attributes: FileAttributes {
+                    size: Some(16),
+                    uid: Some(1000),
+                    gid: Some(1000),
+                    permissions: Some(0o120777),
+                    atime: None,
+                    mtime: Some(1),
+                },
+            },
+            map_sentinel_to_symlink(file)
+        );
+    }
 }
@@ -1,81 +1,355 @@
+use super::authorized_keys_cache::AuthorizedKeysCache;
 use super::handle::HandleManager;
+use super::s3_tls;
 use super::Storage;
 use super::StorageFactory;
+use super::{
+    get_symlink_name, is_symlink_name, map_sentinel_to_symlink, DefaultModes,
+    DEFAULT_MAX_WRITE_HANDLES,
+};
+use crate::config::deserialize_from_str;
 use crate::error::Error;
 use crate::protocol::file_attributes::FileAttributes;
 use crate::protocol::response::name::File;
 use crate::ssh_keys;
 use async_trait::async_trait;
 use aws_config::BehaviorVersion;
+use aws_config::ConfigLoader;
 use aws_config::Region;
+use aws_config::SdkConfig;
+use aws_sdk_s3::error::ProvideErrorMetadata;
 use aws_sdk_s3::primitives::ByteStream;
 use aws_sdk_s3::types::CommonPrefix;
 use aws_sdk_s3::types::CompletedMultipartUpload;
 use aws_sdk_s3::types::CompletedPart;
+use aws_sdk_s3::types::MetadataDirective;
 use aws_sdk_s3::types::Object;
-use bytes::BufMut;
+use aws_sdk_s3::types::ObjectCannedAcl;
+use aws_sdk_s3::types::ServerSideEncryption;
+use aws_sdk_s3::types::StorageClass;
+use aws_smithy_runtime_api::client::http::SharedHttpClient;
+use bytes::{BufMut, BytesMut};
 use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::collections::HashSet;
 use std::pin::Pin;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::io::AsyncRead;
 use tokio::io::AsyncReadExt;
 use tracing::Level;
-use tracing::{error, info};
+use tracing::{debug, error, info};
+
+const METADATA_PERMISSIONS: &str = "permissions";
+const METADATA_UID: &str = "uid";
+const METADATA_GID: &str = "gid";
+
+/// The file type bits for a regular file, which are combined with the permissions stored in an
+/// object's metadata.
+const REGULAR_FILE_TYPE: u32 = 0o100000;
+const DIRECTORY_TYPE: u32 = 0o40000;
+
+/// The largest object that can be copied with a single copy request.
+const MAX_COPY_OBJECT_SIZE: u64 = 5 * 1024 * 1024 * 1024;
+
+/// The size of each part when an object is too large for a single copy request.
+const COPY_PART_SIZE: u64 = 1024 * 1024 * 1024;
+
+/// The smallest part S3 accepts in a multipart upload, other than the last part.
+const MIN_PART_SIZE: usize = 5 * 1024 * 1024;
+
+/// The name of the object that keeps an empty directory's prefix in the bucket. Markers are hidden
+/// from listings, and the name is reserved so that clients cannot overwrite them.
+const FOLDER_MARKER: &str = "_$folder$";
+
+pub(super) const DEFAULT_PART_SIZE: usize = 10000000;
+pub(super) const DEFAULT_MULTIPART_THRESHOLD: usize = DEFAULT_PART_SIZE;
+pub(super) const DEFAULT_READ_AHEAD_SIZE: usize = 1024 * 1024;
 
 #[derive(Clone, Deserialize, Debug)]
 pub struct S3Config {
     #[serde(rename(deserialize = "s3_endpoint_name"))]
     pub endpoint_name: Option<String>,
 
-    #[serde(default = "get_default_endpoint_region")]
-    pub endpoint_region: String,
+    /// The region of the endpoint. When neither it nor `DRAY_AWS_REGION` is set, the region is
+    /// loaded from the AWS configuration.
+    pub endpoint_region: Option<String>,
+
+    /// The named profile that credentials and settings are loaded from, in place of the profile
+    /// selected by `AWS_PROFILE`.
+    pub aws_profile: Option<String>,
+
+    /// The region that requests are sent to, in place of the endpoint region and the region
+    /// selected by `AWS_REGION`.
+    pub aws_region: Option<String>,
 
     #[serde(rename(deserialize = "s3_bucket"))]
     pub bucket: String,
+
+    /// The size a write buffer must exceed before it is uploaded as a part of a multipart upload.
+    #[serde(
+        rename(deserialize = "s3_part_size"),
+        default = "get_default_part_size",
+        deserialize_with = "deserialize_from_str"
+    )]
+    pub part_size: usize,
+
+    /// The size at which an upload switches from a single put to a multipart upload.
+    #[serde(
+        rename(deserialize = "s3_multipart_threshold"),
+        default = "get_default_multipart_threshold",
+        deserialize_with = "deserialize_from_str"
+    )]
+    pub multipart_threshold: usize,
+
+    /// The number of bytes prefetched from an object when a download needs more data.
+    #[serde(
+        rename(deserialize = "s3_read_ahead_size"),
+        default = "get_default_read_ahead_size",
+        deserialize_with = "deserialize_from_str"
+    )]
+    pub read_ahead_size: usize,
+
+    /// The path of a PEM bundle of CA certificates that are trusted for the endpoint, in addition
+    /// to the system's trusted certificates.
+    #[serde(rename(deserialize = "s3_ca_bundle"))]
+    pub ca_bundle: Option<String>,
+
+    /// Disables verification of the endpoint's certificate. This is only accepted for
+    /// development, when `tls_insecure_dev_override` is also set.
+    #[serde(
+        rename(deserialize = "s3_tls_insecure"),
+        default,
+        deserialize_with = "deserialize_from_str"
+    )]
+    pub tls_insecure: bool,
+
+    #[serde(
+        rename(deserialize = "s3_tls_insecure_dev_override"),
+        default,
+        deserialize_with = "deserialize_from_str"
+    )]
+    pub tls_insecure_dev_override: bool,
+
+    #[serde(rename(deserialize = "s3_force_path_style"), default)]
+    pub force_path_style: ForcePathStyle,
+
+    /// Checks the size of each object completed by a multipart upload against the data written,
+    /// at the cost of an extra request per upload.
+    #[serde(
+        rename(deserialize = "s3_verify_uploads"),
+        default,
+        deserialize_with = "deserialize_from_str"
+    )]
+    pub verify_uploads: bool,
+
+    /// The storage class that uploaded files are written to, such as `STANDARD_IA`. The bucket's
+    /// default is used when it is not set.
+    #[serde(rename(deserialize = "s3_storage_class"))]
+    pub storage_class: Option<String>,
+
+    /// The server-side encryption applied to written objects, either `AES256` for keys managed
+    /// by S3 or `aws:kms` for keys managed by KMS. The bucket's default is used when it is not set.
+    #[serde(rename(deserialize = "s3_sse"))]
+    pub sse: Option<String>,
+
+    /// The KMS key that objects are encrypted with, which is required with `aws:kms`.
+    #[serde(rename(deserialize = "s3_sse_kms_key_id"))]
+    pub sse_kms_key_id: Option<String>,
+
+    /// Applies a canned ACL when a client changes a file's permissions, so that files others may
+    /// read are public. This is only meaningful for buckets that use object ACLs.
+    #[serde(
+        rename(deserialize = "s3_map_chmod_to_acl"),
+        default,
+        deserialize_with = "deserialize_from_str"
+    )]
+    pub map_chmod_to_acl: bool,
 }
 
-pub struct S3StorageFactory {
-    s3_client: aws_sdk_s3::Client,
-    bucket: String,
+/// Whether requests address the bucket in the path of the URL, such as
+/// `https://s3.example.com/bucket/key`, rather than in the host name, such as
+/// `https://bucket.s3.example.com/key`.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ForcePathStyle {
+    /// Path-style addressing is used with a custom endpoint, since S3-compatible services often
+    /// do not support virtual-host-style addressing, and is not used with AWS.
+    #[default]
+    Auto,
+    True,
+    False,
 }
 
-impl S3StorageFactory {
-    pub async fn new(s3_config: &S3Config) -> S3StorageFactory {
-        let mut config_loader = aws_config::defaults(BehaviorVersion::latest());
+impl S3Config {
+    pub fn validate_tls(&self) -> Result<(), Error> {
+        if self.tls_insecure && !self.tls_insecure_dev_override {
+            return Err(Error::Configuration(
+                "DRAY_S3_TLS_INSECURE disables certificate verification, so it is only allowed for \
+                 development with DRAY_S3_TLS_INSECURE_DEV_OVERRIDE."
+                    .to_string(),
+            ));
+        }
 
-        if let Some(endpoint_name) = &s3_config.endpoint_name {
-            config_loader = config_loader.endpoint_url(endpoint_name);
-        };
+        Ok(())
+    }
+
+    /// Parses the storage class for uploads. Classes the SDK does not know are rejected rather than
+    /// sent to S3, so that a misspelled class is reported at startup instead of failing uploads.
+    pub fn get_storage_class(&self) -> Result<Option<StorageClass>, Error> {
+        self.storage_class
+            .as_deref()
+            .map(
+                |storage_class| match StorageClass::values().contains(&storage_class) {
+                    true => Ok(StorageClass::from(storage_class)),
+                    false => Err(Error::Configuration(format!(
+                        "DRAY_S3_STORAGE_CLASS {} is not one of {}.",
+                        storage_class,
+                        StorageClass::values().join(", ")
+                    ))),
+                },
+            )
+            .transpose()
+    }
 
-        let config = config_loader.load().await;
+    /// Parses the server-side encryption for written objects, and checks that a KMS key is
+    /// configured exactly when KMS encryption is used.
+    pub fn get_server_side_encryption(&self) -> Result<Option<ServerSideEncryption>, Error> {
+        let server_side_encryption = self
+            .sse
+            .as_deref()
+            .map(|sse| match ServerSideEncryption::values().contains(&sse) {
+                true => Ok(ServerSideEncryption::from(sse)),
+                false => Err(Error::Configuration(format!(
+                    "DRAY_S3_SSE {} is not one of {}.",
+                    sse,
+                    ServerSideEncryption::values().join(", ")
+                ))),
+            })
+            .transpose()?;
 
-        let s3_client_builder = aws_sdk_s3::config::Builder::new();
+        let uses_kms = matches!(
+            server_side_encryption,
+            Some(ServerSideEncryption::AwsKms | ServerSideEncryption::AwsKmsDsse)
+        );
 
-        if config.endpoint_url().is_some() {
-            s3_client_builder.force_path_style(true);
+        match (uses_kms, &self.sse_kms_key_id) {
+            (true, None) => Err(Error::Configuration(
+                "DRAY_S3_SSE_KMS_KEY_ID is required when DRAY_S3_SSE uses KMS.".to_string(),
+            )),
+            (false, Some(_)) => Err(Error::Configuration(
+                "DRAY_S3_SSE_KMS_KEY_ID is only used when DRAY_S3_SSE uses KMS.".to_string(),
+            )),
+            _ => Ok(server_side_encryption),
         }
+    }
 
-        let mut s3_sdk_config = aws_sdk_s3::config::Builder::from(&config);
+    /// The region that requests are sent to, which is the endpoint region unless a region is
+    /// configured with `DRAY_AWS_REGION`. No region is returned when neither is configured, so
+    /// that the region of the AWS configuration is used.
+    pub fn get_region(&self) -> Option<Region> {
+        self.aws_region
+            .clone()
+            .or_else(|| self.endpoint_region.clone())
+            .map(Region::new)
+    }
+
+    /// Builds the HTTP client for the endpoint when TLS settings are configured. Otherwise, the
+    /// SDK's default client is used.
+    fn get_http_client(&self) -> Result<Option<SharedHttpClient>, Error> {
+        self.validate_tls()?;
 
-        if config.endpoint_url().is_some() {
-            s3_sdk_config = s3_sdk_config.force_path_style(true);
+        if self.tls_insecure {
+            return Ok(Some(s3_tls::build_insecure_http_client()));
         }
 
-        s3_sdk_config = s3_sdk_config.region(Region::new(s3_config.endpoint_region.clone()));
+        self.ca_bundle
+            .as_deref()
+            .map(s3_tls::build_ca_bundle_http_client)
+            .transpose()
+    }
+}
+
+pub struct S3StorageFactory {
+    s3_client: aws_sdk_s3::Client,
+    bucket: String,
+    max_handles: usize,
+    part_size: usize,
+    multipart_threshold: usize,
+    read_ahead_size: usize,
+    authorized_keys_cache: Arc<AuthorizedKeysCache>,
+    default_modes: DefaultModes,
+    verify_uploads: bool,
+    storage_class: Option<StorageClass>,
+    server_side_encryption: Option<ServerSideEncryption>,
+    sse_kms_key_id: Option<String>,
+    map_chmod_to_acl: bool,
+    max_write_handles: usize,
+}
+
+impl S3StorageFactory {
+    pub async fn new(
+        s3_config: &S3Config,
+        max_handles: usize,
+        authorized_keys_ttl: Duration,
+    ) -> Result<S3StorageFactory, Error> {
+        let config = build_config_loader(s3_config)?.load().await;
 
-        let s3_client = aws_sdk_s3::Client::from_conf(s3_sdk_config.build());
+        let s3_client = aws_sdk_s3::Client::from_conf(build_s3_sdk_config(&config, s3_config));
 
-        S3StorageFactory {
+        Ok(S3StorageFactory {
             s3_client,
             bucket: s3_config.bucket.clone(),
-        }
+            max_handles,
+            part_size: s3_config.part_size,
+            multipart_threshold: s3_config.multipart_threshold,
+            read_ahead_size: s3_config.read_ahead_size,
+            authorized_keys_cache: Arc::new(AuthorizedKeysCache::new(authorized_keys_ttl)),
+            default_modes: DefaultModes::default(),
+            verify_uploads: s3_config.verify_uploads,
+            storage_class: s3_config.get_storage_class()?,
+            server_side_encryption: s3_config.get_server_side_encryption()?,
+            sse_kms_key_id: s3_config.sse_kms_key_id.clone(),
+            map_chmod_to_acl: s3_config.map_chmod_to_acl,
+            max_write_handles: DEFAULT_MAX_WRITE_HANDLES,
+        })
+    }
+
+    pub fn with_default_modes(mut self, default_modes: DefaultModes) -> S3StorageFactory {
+        self.default_modes = default_modes;
+        self
+    }
+
+    pub fn with_max_write_handles(mut self, max_write_handles: usize) -> S3StorageFactory {
+        self.max_write_handles = max_write_handles;
+        self
     }
 }
 
 #[async_trait]
 impl StorageFactory for S3StorageFactory {
     fn create_storage(&self) -> Arc<dyn Storage> {
-        Arc::new(S3Storage::new(self.s3_client.clone(), self.bucket.clone()))
+        Arc::new(
+            S3Storage::new(
+                self.s3_client.clone(),
+                self.bucket.clone(),
+                self.max_handles,
+                self.part_size,
+                self.multipart_threshold,
+                self.read_ahead_size,
+                self.authorized_keys_cache.clone(),
+            )
+            .with_default_modes(self.default_modes)
+            .with_verify_uploads(self.verify_uploads)
+            .with_storage_class(self.storage_class.clone())
+            .with_server_side_encryption(
+                self.server_side_encryption.clone(),
+                self.sse_kms_key_id.clone(),
+            )
+            .with_map_chmod_to_acl(self.map_chmod_to_acl)
+            .with_max_write_handles(self.max_write_handles),
+        )
     }
 }
 
@@ -83,49 +357,318 @@ pub struct S3Storage {
     s3_client: aws_sdk_s3::Client,
     bucket: String,
     handle_manager: HandleManager<ReadHandle, WriteHandle, DirHandle>,
+    part_size: usize,
+    multipart_threshold: usize,
+    read_ahead_size: usize,
+    authorized_keys_cache: Arc<AuthorizedKeysCache>,
+    default_modes: DefaultModes,
+    /// The prefixes of directories this session has created, and their parents, which already
+    /// exist because of the created directories' markers. Creating one of them again does not
+    /// write another marker. Directories removed by other sessions are not detected.
+    known_dirs: std::sync::Mutex<HashSet<String>>,
+    verify_uploads: bool,
+    storage_class: Option<StorageClass>,
+    server_side_encryption: Option<ServerSideEncryption>,
+    sse_kms_key_id: Option<String>,
+    map_chmod_to_acl: bool,
+    /// Whether ranges of objects can be copied into the parts of a multipart upload, which GCS's
+    /// XML API does not support.
+    part_copy: bool,
 }
 
 impl S3Storage {
-    pub fn new(s3_client: aws_sdk_s3::Client, bucket: String) -> S3Storage {
+    pub fn new(
+        s3_client: aws_sdk_s3::Client,
+        bucket: String,
+        max_handles: usize,
+        part_size: usize,
+        multipart_threshold: usize,
+        read_ahead_size: usize,
+        authorized_keys_cache: Arc<AuthorizedKeysCache>,
+    ) -> S3Storage {
         S3Storage {
             s3_client,
             bucket,
-            handle_manager: HandleManager::new(),
+            handle_manager: HandleManager::with_capacity(max_handles),
+            part_size,
+            multipart_threshold,
+            read_ahead_size,
+            authorized_keys_cache,
+            default_modes: DefaultModes::default(),
+            known_dirs: std::sync::Mutex::new(HashSet::new()),
+            verify_uploads: false,
+            storage_class: None,
+            server_side_encryption: None,
+            sse_kms_key_id: None,
+            map_chmod_to_acl: false,
+            part_copy: true,
+        }
+    }
+
+    /// Sets the permissions reported for objects and prefixes that have no stored permissions.
+    pub fn with_default_modes(mut self, default_modes: DefaultModes) -> S3Storage {
+        self.default_modes = default_modes;
+        self
+    }
+
+    /// Sets the maximum number of files that may be open for writing, each of which buffers up to
+    /// two parts in memory.
+    pub fn with_max_write_handles(mut self, max_write_handles: usize) -> S3Storage {
+        self.handle_manager = self
+            .handle_manager
+            .with_max_write_handles(max_write_handles);
+        self
+    }
+
+    /// Applies a canned ACL to objects when their permissions are set, based on whether others may
+    /// read them.
+    pub fn with_map_chmod_to_acl(mut self, map_chmod_to_acl: bool) -> S3Storage {
+        self.map_chmod_to_acl = map_chmod_to_acl;
+        self
+    }
+
+    /// Sets whether objects can be copied into multipart uploads. Without part copies, objects
+    /// that are truncated or appended to are downloaded and uploaded again when they are no
+    /// larger than the multipart threshold, and copies of larger objects are unsupported.
+    pub fn with_part_copy(mut self, part_copy: bool) -> S3Storage {
+        self.part_copy = part_copy;
+        self
+    }
+
+    /// Checks the size of objects completed by multipart uploads when their handles are closed.
+    pub fn with_verify_uploads(mut self, verify_uploads: bool) -> S3Storage {
+        self.verify_uploads = verify_uploads;
+        self
+    }
+
+    /// Sets the storage class that files are written to, including when they are copied, since a
+    /// copy would otherwise be written to the bucket's default class.
+    pub fn with_storage_class(mut self, storage_class: Option<StorageClass>) -> S3Storage {
+        self.storage_class = storage_class;
+        self
+    }
+
+    /// Sets the server-side encryption of every object written, including folder markers,
+    /// symbolic links, and copies, so that buckets requiring encryption accept each request.
+    pub fn with_server_side_encryption(
+        mut self,
+        server_side_encryption: Option<ServerSideEncryption>,
+        sse_kms_key_id: Option<String>,
+    ) -> S3Storage {
+        self.server_side_encryption = server_side_encryption;
+        self.sse_kms_key_id = sse_kms_key_id;
+        self
+    }
+
+    /// Opens an object for reading. The object's size and ETag are recorded, so that reads at
+    /// other offsets can reopen the same version of the object with a ranged request.
+    async fn create_read_handle(&self, file_name: String) -> Result<ReadHandle, Error> {
+        let read_response = self
+            .s3_client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(&file_name)
+            .send()
+            .await
+            .map_err(aws_sdk_s3::Error::from)
+            .map_err(map_err)?;
+
+        Ok(ReadHandle {
+            object: Arc::new(ReadObject {
+                key: file_name.clone(),
+                size: read_response
+                    .content_length
+                    .map(|content_length| content_length as u64),
+                e_tag: read_response.e_tag.clone(),
+            }),
+            ..ReadHandle::new(
+                file_name,
+                Box::pin(read_response.body.into_async_read()),
+                self.read_ahead_size,
+            )
+        })
+    }
+
+    /// Moves a read stream to an offset. Sequential reads continue from the prefetched data, and
+    /// other offsets, such as a client resuming a download, reopen the object at the offset.
+    async fn seek_read_stream(
+        &self,
+        read_object: &ReadObject,
+        read_stream: &mut ReadStream,
+        offset: u64,
+    ) -> Result<(), Error> {
+        if read_stream.seek(offset) {
+            return Ok(());
+        }
+
+        // Once the stream has reached the end of the object, reads past it are the end of the
+        // file, and the object is not reopened.
+        if read_stream.is_past_end(offset) {
+            read_stream.reset(Box::pin(tokio::io::empty()), offset);
+            return Ok(());
         }
+
+        debug!(key = %read_object.key, offset, "Reopening S3 object at offset");
+
+        let read_response = match self
+            .s3_client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(&read_object.key)
+            .range(format!("bytes={}-", offset))
+            .set_if_match(read_object.e_tag.clone())
+            .send()
+            .await
+            .map_err(aws_sdk_s3::Error::from)
+        {
+            Ok(read_response) => read_response,
+            Err(err) if is_invalid_range(&err) => {
+                read_stream.reset(Box::pin(tokio::io::empty()), offset);
+                return Ok(());
+            }
+            Err(err) => return Err(map_err(err)),
+        };
+
+        read_stream.reset(Box::pin(read_response.body.into_async_read()), offset);
+
+        Ok(())
+    }
+
+    /// Reads a range of an object with its own request, without using the handle's stream.
+    async fn read_range(
+        &self,
+        read_object: &ReadObject,
+        offset: u64,
+        len: u32,
+    ) -> Result<Vec<u8>, Error> {
+        debug!(key = %read_object.key, offset, len, "Reading S3 object range");
+
+        let read_response = match self
+            .s3_client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(&read_object.key)
+            .range(get_read_range(offset, len))
+            .set_if_match(read_object.e_tag.clone())
+            .send()
+            .await
+            .map_err(aws_sdk_s3::Error::from)
+        {
+            Ok(read_response) => read_response,
+            Err(err) if is_invalid_range(&err) => return Ok(Vec::new()),
+            Err(err) => return Err(map_err(err)),
+        };
+
+        let mut data = Vec::with_capacity(len as usize);
+        read_response
+            .body
+            .into_async_read()
+            .read_to_end(&mut data)
+            .await?;
+
+        Ok(data)
     }
 
     #[tracing::instrument(skip_all)]
-    async fn complete_part_upload(
+    async fn start_multipart_upload(
         &self,
         write_handle: &mut tokio::sync::MutexGuard<'_, WriteHandle>,
     ) -> Result<(), Error> {
-        let part_number = (write_handle.completed_parts.len() as i32) + 1;
+        if write_handle.is_multipart() {
+            return Ok(());
+        }
 
-        let upload_part_response = self
+        let multipart_response = self
             .s3_client
-            .upload_part()
+            .create_multipart_upload()
             .bucket(&self.bucket)
+            .set_server_side_encryption(self.server_side_encryption.clone())
+            .set_ssekms_key_id(self.sse_kms_key_id.clone())
             .key(&write_handle.key)
-            .upload_id(&write_handle.upload_id)
-            .part_number(part_number)
-            .body(ByteStream::from(write_handle.buffer.clone()))
+            .set_storage_class(self.storage_class.clone())
             .send()
             .await
             .map_err(aws_sdk_s3::Error::from)
             .map_err(map_err)?;
 
-        write_handle.completed_parts.push(
-            CompletedPart::builder()
+        write_handle.upload_id = Some(map_create_multipart_response_to_upload_id(
+            multipart_response,
+        )?);
+
+        Ok(())
+    }
+
+    /// Starts uploading the buffer as the next part in the background, so that the client can
+    /// keep writing while the part is sent. The buffer is moved into the request rather than
+    /// copied. Only one part is uploaded at a time, so the previous part is waited for first,
+    /// which holds back writes until S3 keeps up.
+    #[tracing::instrument(skip_all)]
+    async fn start_part_upload(
+        &self,
+        write_handle: &mut tokio::sync::MutexGuard<'_, WriteHandle>,
+    ) -> Result<(), Error> {
+        finish_part_uploads(write_handle).await?;
+
+        let part_number = write_handle.get_next_part_number();
+
+        let upload_part = self
+            .s3_client
+            .upload_part()
+            .bucket(&self.bucket)
+            .key(&write_handle.key)
+            .set_upload_id(write_handle.upload_id.clone())
+            .part_number(part_number)
+            .body(ByteStream::from(std::mem::take(&mut write_handle.buffer)));
+
+        write_handle.part_uploads.push(tokio::spawn(async move {
+            let upload_part_response = upload_part
+                .send()
+                .await
+                .map_err(aws_sdk_s3::Error::from)
+                .map_err(map_err)?;
+
+            Ok(CompletedPart::builder()
                 .e_tag(upload_part_response.e_tag().unwrap_or_default())
                 .part_number(part_number)
-                .build(),
-        );
-
-        write_handle.buffer.clear();
+                .build())
+        }));
 
         Ok(())
     }
 
+    /// Uploads the buffer as the next part of a multipart upload. The upload cannot continue
+    /// without the part, so a failure is recorded on the handle to fail later writes and closes.
+    async fn upload_buffered_part(
+        &self,
+        write_handle: &mut tokio::sync::MutexGuard<'_, WriteHandle>,
+    ) -> Result<(), Error> {
+        let result = match self.start_multipart_upload(write_handle).await {
+            Ok(()) => self.start_part_upload(write_handle).await,
+            Err(err) => Err(err),
+        };
+
+        if let Err(err) = &result {
+            write_handle.failure = Some(err.to_string());
+        }
+
+        result
+    }
+
+    /// Waits for the parts being uploaded in the background. A failure is recorded on the handle,
+    /// since the upload cannot continue without the part.
+    async fn wait_for_part_uploads(
+        &self,
+        write_handle: &mut tokio::sync::MutexGuard<'_, WriteHandle>,
+    ) -> Result<(), Error> {
+        let result = finish_part_uploads(write_handle).await;
+
+        if let Err(err) = &result {
+            write_handle.failure = Some(err.to_string());
+        }
+
+        result
+    }
+
     #[tracing::instrument(skip(self))]
     async fn get_directory_metadata(&self, folder_name: &str) -> Result<File, Error> {
         let list_objects_output = self
@@ -139,73 +682,75 @@ impl S3Storage {
             .map_err(aws_sdk_s3::Error::from)
             .map_err(map_err)?;
 
-        map_list_objects_to_directory(list_objects_output)
+        map_list_objects_to_directory(list_objects_output, self.default_modes.dir_mode)
     }
 
     #[tracing::instrument(skip(self))]
-    async fn rename_file(&self, current: String, new: String) -> Result<(), Error> {
-        self.s3_client
-            .copy_object()
+    async fn get_symlink_metadata(&self, link: &str) -> Result<File, Error> {
+        let symlink_key = get_symlink_name(link);
+
+        let head_object_response = self
+            .s3_client
+            .head_object()
             .bucket(&self.bucket)
-            .copy_source(get_s3_copy_source(&self.bucket, &current))
-            .key(&new)
+            .key(&symlink_key)
             .send()
             .await
             .map_err(aws_sdk_s3::Error::from)
             .map_err(map_err)?;
 
-        self.remove_file(current).await?;
-
-        Ok(())
+        Ok(map_sentinel_to_symlink(map_head_object_to_file(
+            &symlink_key,
+            &head_object_response,
+            self.default_modes.file_mode,
+        )))
     }
 
-    #[tracing::instrument(skip(self))]
-    async fn rename_dir(&self, current: String, new: String) -> Result<(), Error> {
-        let current_prefix = get_s3_prefix(&current);
-        let new_prefix = get_s3_prefix(&new);
+    /// Truncates an object by copying the data that is kept into a multipart upload, so that the
+    /// data does not pass through the server and objects of any size can be truncated. An object
+    /// truncated to nothing is replaced with an empty object, since an upload needs a part.
+    /// Without part copies, the data that is kept is downloaded and uploaded again instead.
+    #[tracing::instrument(skip(self, metadata))]
+    async fn truncate_file(
+        &self,
+        file_name: &str,
+        size: u64,
+        metadata: HashMap<String, String>,
+        content_type: Option<String>,
+    ) -> Result<(), Error> {
+        if size > 0 && (self.part_copy || size > self.multipart_threshold as u64) {
+            return self
+                .copy_object_multipart(file_name, file_name, size, Some(metadata), content_type)
+                .await;
+        }
 
-        let mut continuation_token = None;
+        let mut data = Vec::with_capacity(size as usize);
 
-        loop {
-            let objects = self
+        if size > 0 {
+            let object = self
                 .s3_client
-                .list_objects_v2()
+                .get_object()
                 .bucket(&self.bucket)
-                .prefix(&current_prefix)
-                .set_continuation_token(continuation_token.clone())
-                .set_delimiter(None)
+                .key(file_name)
+                .range(format!("bytes=0-{}", size - 1))
                 .send()
                 .await
                 .map_err(aws_sdk_s3::Error::from)
                 .map_err(map_err)?;
 
-            continuation_token = objects.continuation_token;
-
-            if let Some(contents) = objects.contents {
-                let keys = contents.into_iter().filter_map(|content| content.key);
-
-                for key in keys {
-                    let destination = key.replace(&current_prefix, &new_prefix);
-
-                    self.rename_file(key, destination).await?;
-                }
-            }
-
-            if continuation_token.is_none() {
-                break;
-            }
+            object.body.into_async_read().read_to_end(&mut data).await?;
         }
 
-        Ok(())
-    }
-}
-
-#[async_trait]
-impl Storage for S3Storage {
-    async fn init(&self) -> Result<(), Error> {
         self.s3_client
-            .head_bucket()
+            .put_object()
             .bucket(&self.bucket)
+            .set_server_side_encryption(self.server_side_encryption.clone())
+            .set_ssekms_key_id(self.sse_kms_key_id.clone())
+            .key(file_name)
+            .set_metadata(Some(metadata))
+            .set_content_type(content_type)
+            .set_storage_class(self.storage_class.clone())
+            .body(ByteStream::from(data))
             .send()
             .await
             .map_err(aws_sdk_s3::Error::from)
@@ -214,167 +759,194 @@ impl Storage for S3Storage {
         Ok(())
     }
 
-    fn get_home(&self, user: &str) -> String {
-        get_home(user)
+    #[tracing::instrument(skip(self))]
+    async fn rename_file(&self, current: String, new: String) -> Result<(), Error> {
+        self.copy_object(&current, &new).await?;
+        self.delete_object(&current).await?;
+
+        Ok(())
     }
 
+    /// Copies an object within the bucket, keeping its metadata.
     #[tracing::instrument(skip(self))]
-    async fn health_check(&self) -> Result<(), Error> {
-        info!("Running health check for S3 Bucket {}", self.bucket);
-
-        let result = self
+    async fn copy_object(&self, current: &str, new: &str) -> Result<(), Error> {
+        let head_object_response = self
             .s3_client
-            .head_bucket()
+            .head_object()
             .bucket(&self.bucket)
+            .key(current)
             .send()
             .await
             .map_err(aws_sdk_s3::Error::from)
-            .map_err(map_err);
+            .map_err(map_err)?;
 
-        match result {
-            Ok(_) => {
-                info!(
-                    "Successfully completed health check for S3 Bucket {}",
-                    self.bucket
-                );
-                Ok(())
-            }
-            Err(error) => {
-                error!(
-                    "Failed to complete health check for S3 Bucket {}: {}",
-                    self.bucket, error
-                );
-                Err(error)
-            }
+        let size = head_object_response.content_length.unwrap_or_default() as u64;
+
+        if requires_multipart_copy(size) {
+            self.copy_object_multipart(
+                current,
+                new,
+                size,
+                head_object_response.metadata,
+                head_object_response.content_type,
+            )
+            .await?;
+        } else {
+            self.s3_client
+                .copy_object()
+                .bucket(&self.bucket)
+                .set_server_side_encryption(self.server_side_encryption.clone())
+                .set_ssekms_key_id(self.sse_kms_key_id.clone())
+                .copy_source(get_s3_copy_source(&self.bucket, current))
+                .key(new)
+                .set_storage_class(self.storage_class.clone())
+                .send()
+                .await
+                .map_err(aws_sdk_s3::Error::from)
+                .map_err(map_err)?;
         }
+
+        Ok(())
     }
 
-    #[tracing::instrument(skip(self))]
-    async fn get_authorized_keys_fingerprints(&self, user: &str) -> Result<Vec<String>, Error> {
-        let authorized_keys_key = format!(".ssh/{}/authorized_keys", user);
+    /// Copies the first `size` bytes of an object by copying ranges of the object into the parts
+    /// of a multipart upload, which works for objects too large for a single copy request. The
+    /// upload is aborted if any part fails.
+    #[tracing::instrument(skip(self, metadata))]
+    async fn copy_object_multipart(
+        &self,
+        current: &str,
+        new: &str,
+        size: u64,
+        metadata: Option<HashMap<String, String>>,
+        content_type: Option<String>,
+    ) -> Result<(), Error> {
+        self.check_part_copy()?;
 
-        let object = self
+        let multipart_response = self
             .s3_client
-            .get_object()
+            .create_multipart_upload()
             .bucket(&self.bucket)
-            .key(authorized_keys_key)
+            .set_server_side_encryption(self.server_side_encryption.clone())
+            .set_ssekms_key_id(self.sse_kms_key_id.clone())
+            .key(new)
+            .set_metadata(metadata)
+            .set_content_type(content_type)
+            .set_storage_class(self.storage_class.clone())
             .send()
             .await
             .map_err(aws_sdk_s3::Error::from)
             .map_err(map_err)?;
 
-        let mut buffer = String::new();
-        object
-            .body
-            .into_async_read()
-            .read_to_string(&mut buffer)
-            .await?;
+        let upload_id = map_create_multipart_response_to_upload_id(multipart_response)?;
 
-        Ok(ssh_keys::parse_authorized_keys(&buffer))
-    }
+        let copy_source_ranges = get_copy_part_ranges(size, COPY_PART_SIZE);
 
-    #[tracing::instrument(skip(self))]
-    async fn open_dir_handle(&self, dir_name: String) -> Result<String, Error> {
-        let prefix = get_s3_prefix(&dir_name);
+        match self
+            .copy_parts(current, new, &upload_id, copy_source_ranges)
+            .await
+        {
+            Ok(completed_parts) => {
+                self.s3_client
+                    .complete_multipart_upload()
+                    .bucket(&self.bucket)
+                    .key(new)
+                    .multipart_upload(
+                        CompletedMultipartUpload::builder()
+                            .set_parts(Some(completed_parts))
+                            .build(),
+                    )
+                    .upload_id(&upload_id)
+                    .send()
+                    .await
+                    .map_err(aws_sdk_s3::Error::from)
+                    .map_err(map_err)?;
 
-        self.handle_manager
-            .create_dir_handle(DirHandle {
-                prefix,
-                continuation_token: None,
-                is_eof: false,
-            })
-            .await
+                Ok(())
+            }
+            Err(err) => {
+                if let Err(abort_err) = self.abort_multipart_upload(new, &upload_id).await {
+                    error!(
+                        "Failed to abort multipart copy {} for {}: {}",
+                        upload_id, new, abort_err
+                    );
+                }
+
+                Err(err)
+            }
+        }
     }
 
+    /// Creates a write handle for an existing object. Objects cannot be modified in place, so
+    /// unless the object is truncated, its contents are loaded into the handle and uploaded again
+    /// when the handle is closed.
     #[tracing::instrument(skip(self))]
-    async fn read_dir(&self, handle: &str) -> Result<Vec<File>, Error> {
-        let dir_handle = match self.handle_manager.get_dir_handle(handle).await {
-            Some(dir_handle) => dir_handle,
-            None => return Err(Error::Failure("Missing directory handle.".to_string())),
-        };
-
-        let mut dir_handle = dir_handle.lock().await;
-
-        if dir_handle.is_eof {
-            return Ok(Vec::new());
-        }
+    async fn load_write_handle(
+        &self,
+        file_name: String,
+        truncate: bool,
+    ) -> Result<WriteHandle, Error> {
+        trace_s3_key(&file_name);
+        check_not_folder_marker(&file_name)?;
 
-        let prefix = get_s3_prefix(&dir_handle.prefix);
-        let objects = self
+        let head_object_response = self
             .s3_client
-            .list_objects_v2()
+            .head_object()
             .bucket(&self.bucket)
-            .prefix(&prefix)
-            .set_continuation_token(dir_handle.continuation_token.clone())
-            .delimiter("/")
+            .key(&file_name)
             .send()
             .await
             .map_err(aws_sdk_s3::Error::from)
             .map_err(map_err)?;
 
-        dir_handle.continuation_token = objects.next_continuation_token.clone();
-        dir_handle.is_eof = objects.next_continuation_token.is_none();
-
-        Ok(map_list_objects_to_files(objects))
-    }
+        if truncate {
+            return Ok(WriteHandle::new(file_name));
+        }
 
-    #[tracing::instrument(skip(self))]
-    async fn create_dir(&self, dir_name: String) -> Result<(), Error> {
-        /*
-            S3 does not support creating empty prefixes. A marker file must be added to preserve empty
-            directories until the directories are explicitly deleted.
-        */
-        self.s3_client
-            .put_object()
-            .bucket(&self.bucket)
-            .key(get_s3_folder_marker(&dir_name))
-            .send()
-            .await
-            .map_err(aws_sdk_s3::Error::from)
-            .map_err(map_err)?;
+        let size = head_object_response.content_length.unwrap_or_default() as u64;
 
-        Ok(())
-    }
+        // The contents are held in memory until the handle is closed, so only objects that would
+        // be uploaded with a single put can be modified in place.
+        if size > self.multipart_threshold as u64 {
+            return Err(Error::Failure(format!(
+                "{} is too large to modify in place.",
+                file_name
+            )));
+        }
 
-    #[tracing::instrument(skip(self))]
-    async fn remove_dir(&self, dir_name: String) -> Result<(), Error> {
-        let prefix = get_s3_prefix(&dir_name);
-        let mut continuation_token = None;
+        let mut data = Vec::with_capacity(size as usize);
 
-        loop {
-            let objects = self
+        if size > 0 {
+            let object = self
                 .s3_client
-                .list_objects_v2()
+                .get_object()
                 .bucket(&self.bucket)
-                .prefix(&prefix)
-                .set_continuation_token(continuation_token.clone())
-                .set_delimiter(None)
+                .key(&file_name)
                 .send()
                 .await
                 .map_err(aws_sdk_s3::Error::from)
                 .map_err(map_err)?;
 
-            continuation_token = objects.continuation_token;
-
-            if let Some(contents) = objects.contents {
-                let keys = contents.into_iter().filter_map(|content| content.key);
-
-                for key in keys {
-                    self.remove_file(key).await?;
-                }
-            }
-
-            if continuation_token.is_none() {
-                break;
-            }
+            object.body.into_async_read().read_to_end(&mut data).await?;
         }
 
-        Ok(())
+        Ok(WriteHandle::with_data(file_name, data))
     }
 
+    /// Creates a write handle that appends to an existing object. Objects that are large enough
+    /// to be a part are copied into the first parts of a multipart upload. Smaller objects are
+    /// loaded into the handle's buffer, since only the last part of an upload may be that small.
+    /// Without part copies, objects up to the multipart threshold are loaded instead.
     #[tracing::instrument(skip(self))]
-    async fn get_file_metadata(&self, file_name: String) -> Result<File, Error> {
-        let head_object_response = self
+    async fn load_append_handle(
+        &self,
+        file_name: String,
+        create: bool,
+    ) -> Result<WriteHandle, Error> {
+        trace_s3_key(&file_name);
+        check_not_folder_marker(&file_name)?;
+
+        let head_object_response = match self
             .s3_client
             .head_object()
             .bucket(&self.bucket)
@@ -382,144 +954,203 @@ impl Storage for S3Storage {
             .send()
             .await
             .map_err(aws_sdk_s3::Error::from)
-            .map_err(map_err);
+            .map_err(map_err)
+        {
+            Ok(head_object_response) => head_object_response,
+            Err(Error::NoSuchFile) if create => {
+                return Ok(WriteHandle::for_append(file_name, Vec::new()))
+            }
+            Err(err) => return Err(err),
+        };
 
-        match head_object_response {
-            Ok(head_object_response) => {
-                Ok(map_head_object_to_file(&file_name, &head_object_response))
+        let size = head_object_response.content_length.unwrap_or_default() as u64;
+
+        let load_data = match self.part_copy {
+            true => size < MIN_PART_SIZE as u64,
+            false => size <= self.multipart_threshold as u64,
+        };
+
+        if load_data {
+            let mut data = Vec::with_capacity(size as usize);
+
+            if size > 0 {
+                let object = self
+                    .s3_client
+                    .get_object()
+                    .bucket(&self.bucket)
+                    .key(&file_name)
+                    .send()
+                    .await
+                    .map_err(aws_sdk_s3::Error::from)
+                    .map_err(map_err)?;
+
+                object.body.into_async_read().read_to_end(&mut data).await?;
             }
-            Err(err) => match err {
-                Error::NoSuchFile => self.get_directory_metadata(&file_name).await,
-                _ => Err(err),
-            },
-        }
-    }
 
-    #[tracing::instrument(skip(self))]
-    async fn get_handle_metadata(&self, handle: &str) -> Result<File, Error> {
-        if let Some(read_handle) = self.handle_manager.get_read_handle(handle).await {
-            let read_handle = read_handle.lock().await;
-            self.get_file_metadata(read_handle.key.to_string()).await
-        } else if let Some(write_handle) = self.handle_manager.get_write_handle(handle).await {
-            let write_handle = write_handle.lock().await;
-            self.get_file_metadata(write_handle.key.to_string()).await
-        } else if let Some(dir_handle) = self.handle_manager.get_dir_handle(handle).await {
-            let dir_handle = dir_handle.lock().await;
-            self.get_file_metadata(dir_handle.prefix.to_string()).await
-        } else {
-            Err(Error::Failure(format!("Handle {} does not exist!", handle)))
+            return Ok(WriteHandle::for_append(file_name, data));
         }
-    }
 
-    #[tracing::instrument(skip(self))]
-    async fn open_read_handle(&self, file_name: String) -> Result<String, Error> {
-        let read_response = self
+        self.check_part_copy()?;
+
+        let multipart_response = self
             .s3_client
-            .get_object()
+            .create_multipart_upload()
             .bucket(&self.bucket)
+            .set_server_side_encryption(self.server_side_encryption.clone())
+            .set_ssekms_key_id(self.sse_kms_key_id.clone())
             .key(&file_name)
+            .set_metadata(head_object_response.metadata)
+            .set_storage_class(self.storage_class.clone())
             .send()
             .await
             .map_err(aws_sdk_s3::Error::from)
             .map_err(map_err)?;
 
-        let read_stream = read_response.body.into_async_read();
+        let upload_id = map_create_multipart_response_to_upload_id(multipart_response)?;
+        let copy_source_ranges = get_append_copy_part_ranges(size, COPY_PART_SIZE);
 
-        self.handle_manager
-            .create_read_handle(ReadHandle::new(file_name, Box::pin(read_stream)))
+        match self
+            .copy_parts(&file_name, &file_name, &upload_id, copy_source_ranges)
             .await
-    }
-
-    #[tracing::instrument(skip(self), level = Level::TRACE)]
-    async fn read_data(&self, handle: &str, len: u32) -> Result<Vec<u8>, Error> {
-        let read_handle = match self.handle_manager.get_read_handle(handle).await {
-            Some(dir_handle) => dir_handle,
-            None => return Err(Error::Storage("Missing read handle.".to_string())),
-        };
-
-        let mut buffer = Vec::with_capacity(len as usize);
+        {
+            Ok(completed_parts) => Ok(WriteHandle {
+                upload_id: Some(upload_id),
+                completed_parts,
+                offset: size,
+                ..WriteHandle::for_append(file_name, Vec::new())
+            }),
+            Err(err) => {
+                if let Err(abort_err) = self.abort_multipart_upload(&file_name, &upload_id).await {
+                    error!(
+                        "Failed to abort multipart append {} for {}: {}",
+                        upload_id, file_name, abort_err
+                    );
+                }
 
-        read_handle
-            .lock()
-            .await
-            .async_read
-            .as_mut()
-            .take(len as u64)
-            .read_to_end(&mut buffer)
-            .await?;
+                Err(err)
+            }
+        }
+    }
 
-        Ok(buffer)
+    /// Checks that objects can be copied into multipart uploads, which is unsupported by some
+    /// S3-compatible services.
+    fn check_part_copy(&self) -> Result<(), Error> {
+        match self.part_copy {
+            true => Ok(()),
+            false => Err(Error::Unimplemented),
+        }
     }
 
+    /// Checks that a completed upload has the size of the data written to it, so that a part
+    /// dropped from the upload is reported rather than leaving a truncated object behind.
     #[tracing::instrument(skip(self))]
-    async fn open_write_handle(&self, file_name: String) -> Result<String, Error> {
-        let multipart_response = self
+    async fn verify_upload(&self, key: &str, size: u64) -> Result<(), Error> {
+        let head_object_response = self
             .s3_client
-            .create_multipart_upload()
+            .head_object()
             .bucket(&self.bucket)
-            .key(&file_name)
+            .key(key)
             .send()
             .await
             .map_err(aws_sdk_s3::Error::from)
             .map_err(map_err)?;
 
-        let write_handle = map_create_multipart_response_to_write_handle(multipart_response)?;
+        let content_length = head_object_response.content_length.unwrap_or_default() as u64;
 
-        self.handle_manager.create_write_handle(write_handle).await
-    }
+        if content_length != size {
+            return Err(Error::Failure(format!(
+                "Uploaded {} has {} bytes, but {} bytes were written.",
+                key, content_length, size
+            )));
+        }
 
-    #[tracing::instrument(skip(self), level = Level::TRACE)]
-    async fn write_data(&self, handle: &str, data: bytes::Bytes) -> Result<(), Error> {
-        let write_handle = match self.handle_manager.get_write_handle(handle).await {
-            Some(dir_handle) => dir_handle,
-            None => return Err(Error::Storage("Missing write handle.".to_string())),
-        };
+        Ok(())
+    }
 
-        let mut write_handle = write_handle.lock().await;
+    /// Aborts the multipart upload behind a write handle, if one has been started. Data buffered
+    /// in the handle, and any part still being uploaded, is discarded with it.
+    async fn abort_write(&self, write_handle: &WriteHandle) -> Result<(), Error> {
+        for part_upload in &write_handle.part_uploads {
+            part_upload.abort();
+        }
 
-        write_handle.buffer.put(data);
+        match &write_handle.upload_id {
+            Some(upload_id) => {
+                info!(
+                    "Aborting multipart upload {} for {}",
+                    upload_id, write_handle.key
+                );
+                self.abort_multipart_upload(&write_handle.key, upload_id)
+                    .await
+            }
+            None => Ok(()),
+        }
+    }
 
-        if write_handle.buffer.len() > 10000000 {
-            self.complete_part_upload(&mut write_handle).await?;
-            write_handle.buffer.clear();
-        };
+    #[tracing::instrument(skip(self))]
+    async fn abort_multipart_upload(&self, key: &str, upload_id: &str) -> Result<(), Error> {
+        self.s3_client
+            .abort_multipart_upload()
+            .bucket(&self.bucket)
+            .key(key)
+            .upload_id(upload_id)
+            .send()
+            .await
+            .map_err(aws_sdk_s3::Error::from)
+            .map_err(map_err)?;
 
         Ok(())
     }
 
     #[tracing::instrument(skip(self))]
-    async fn close_handle(&self, handle: &str) -> Result<(), Error> {
-        if let Some(write_handle) = self.handle_manager.get_write_handle(handle).await {
-            let mut write_handle = write_handle.lock().await;
-
-            self.complete_part_upload(&mut write_handle).await?;
+    async fn copy_parts(
+        &self,
+        current: &str,
+        new: &str,
+        upload_id: &str,
+        copy_source_ranges: Vec<String>,
+    ) -> Result<Vec<CompletedPart>, Error> {
+        let mut completed_parts = Vec::new();
 
-            let complete_multipart_upload = CompletedMultipartUpload::builder()
-                .set_parts(Some(write_handle.completed_parts.clone()))
-                .build();
+        for (index, copy_source_range) in copy_source_ranges.into_iter().enumerate() {
+            let part_number = (index as i32) + 1;
 
-            self.s3_client
-                .complete_multipart_upload()
+            let upload_part_copy_response = self
+                .s3_client
+                .upload_part_copy()
                 .bucket(&self.bucket)
-                .key(&write_handle.key)
-                .multipart_upload(complete_multipart_upload)
-                .upload_id(&write_handle.upload_id)
+                .copy_source(get_s3_copy_source(&self.bucket, current))
+                .copy_source_range(copy_source_range)
+                .key(new)
+                .upload_id(upload_id)
+                .part_number(part_number)
                 .send()
                 .await
                 .map_err(aws_sdk_s3::Error::from)
                 .map_err(map_err)?;
+
+            let e_tag = upload_part_copy_response
+                .copy_part_result
+                .and_then(|copy_part_result| copy_part_result.e_tag)
+                .unwrap_or_default();
+
+            completed_parts.push(
+                CompletedPart::builder()
+                    .e_tag(e_tag)
+                    .part_number(part_number)
+                    .build(),
+            );
         }
 
-        self.handle_manager.remove_handle(handle).await;
-        Ok(())
+        Ok(completed_parts)
     }
 
     #[tracing::instrument(skip(self))]
-    async fn remove_file(&self, file_name: String) -> Result<(), Error> {
+    async fn delete_object(&self, key: &str) -> Result<(), Error> {
         self.s3_client
             .delete_object()
             .bucket(&self.bucket)
-            .key(&file_name)
+            .key(key)
             .send()
             .await
             .map_err(aws_sdk_s3::Error::from)
@@ -528,513 +1159,4736 @@ impl Storage for S3Storage {
         Ok(())
     }
 
-    #[tracing::instrument(skip(self))]
-    async fn rename(&self, current: String, new: String) -> Result<(), Error> {
-        let file = self.get_file_metadata(current.clone()).await?;
-
-        match file.file_attributes.is_dir() {
-            true => self.rename_dir(current, new).await,
-            false => self.rename_file(current, new).await,
-        }?;
-
-        Ok(())
+    fn is_known_dir(&self, prefix: &str) -> bool {
+        let known_dirs = self
+            .known_dirs
+            .lock()
+            .unwrap_or_else(|err| err.into_inner());
+        known_dirs.contains(prefix)
     }
-}
-
-struct DirHandle {
-    prefix: String,
-    continuation_token: Option<String>,
-    is_eof: bool,
-}
 
-struct ReadHandle {
-    key: String,
-    async_read: Pin<Box<dyn AsyncRead + Send>>,
-}
+    /// Records that a directory and its parents exist once the directory's marker is written.
+    fn add_known_dir(&self, prefix: &str) {
+        let mut known_dirs = self
+            .known_dirs
+            .lock()
+            .unwrap_or_else(|err| err.into_inner());
 
-impl ReadHandle {
-    fn new(key: String, async_read: Pin<Box<dyn AsyncRead + Send>>) -> ReadHandle {
-        ReadHandle { key, async_read }
+        for (index, _) in prefix.match_indices('/') {
+            known_dirs.insert(prefix[..=index].to_string());
+        }
     }
-}
-
-struct WriteHandle {
-    key: String,
-    upload_id: String,
-    completed_parts: Vec<CompletedPart>,
-    buffer: Vec<u8>,
-}
-
-fn get_home(user: &str) -> String {
-    format!("/home/{}", user)
-}
 
-fn get_s3_prefix(dir_name: &str) -> String {
-    if "".eq(dir_name) {
-        return String::from("/");
+    /// Forgets a directory that was removed or renamed, along with its subdirectories and its
+    /// parents, which may have only existed because of the directory's objects.
+    fn forget_known_dir(&self, prefix: &str) {
+        let mut known_dirs = self
+            .known_dirs
+            .lock()
+            .unwrap_or_else(|err| err.into_inner());
+        known_dirs
+            .retain(|known_dir| !known_dir.starts_with(prefix) && !prefix.starts_with(known_dir));
     }
 
-    let prefix_builder = match dir_name.starts_with('/') {
-        true => &dir_name[1..dir_name.len()],
-        false => dir_name,
-    };
+    #[tracing::instrument(skip(self))]
+    async fn rename_dir(&self, current: String, new: String) -> Result<(), Error> {
+        let current_prefix = get_s3_prefix(&current);
+        let new_prefix = get_s3_prefix(&new);
 
-    match prefix_builder.ends_with('/') {
-        true => prefix_builder.to_string(),
-        false => format!("{}/", prefix_builder),
-    }
-}
+        check_not_root_prefix(&current_prefix)?;
+        check_not_root_prefix(&new_prefix)?;
 
-fn get_s3_copy_source(bucket: &str, key: &str) -> String {
-    format!("{}/{}", bucket, key)
-}
+        self.forget_known_dir(&current_prefix);
 
-fn get_s3_folder_marker(dir_name: &str) -> String {
-    let prefix = get_s3_prefix(dir_name);
-    format!("{}_$folder$", prefix)
-}
+        let mut continuation_token = None;
 
-fn map_list_objects_to_files(
-    list_objects: aws_sdk_s3::operation::list_objects_v2::ListObjectsV2Output,
-) -> Vec<File> {
-    let files = list_objects.contents.unwrap_or_default();
+        loop {
+            let objects = self
+                .s3_client
+                .list_objects_v2()
+                .bucket(&self.bucket)
+                .prefix(&current_prefix)
+                .set_continuation_token(continuation_token.clone())
+                .set_delimiter(None)
+                .send()
+                .await
+                .map_err(aws_sdk_s3::Error::from)
+                .map_err(map_err)?;
 
-    let directories = list_objects.common_prefixes.unwrap_or_default();
+            continuation_token = objects.next_continuation_token;
 
-    let mapped_files = files
-        .iter()
-        .map(map_object_to_file)
-        .filter(|file| !file.file_name.ends_with("_$folder$"));
+            if let Some(contents) = objects.contents {
+                let keys = contents.into_iter().filter_map(|content| content.key);
 
-    let mapped_dirs = directories.iter().map(map_prefix_to_file);
+                for key in keys {
+                    // Only the leading prefix is replaced, since the prefix may appear again
+                    // within the key.
+                    let destination = match key.strip_prefix(&current_prefix) {
+                        Some(relative_key) => format!("{}{}", new_prefix, relative_key),
+                        None => continue,
+                    };
 
-    mapped_dirs.chain(mapped_files).collect()
-}
+                    self.rename_file(key, destination).await?;
+                }
+            }
 
-fn map_object_to_file(object: &Object) -> File {
-    let key = match &object.key {
-        Some(key) => key,
-        None => "",
-    };
+            if continuation_token.is_none() {
+                break;
+            }
+        }
 
-    let mut key_pieces = key.rsplit('/');
-    let file_name = key_pieces.next().unwrap_or("");
+        Ok(())
+    }
+}
 
-    File {
-        file_name: file_name.to_string(),
+#[async_trait]
+impl Storage for S3Storage {
+    async fn init(&self) -> Result<(), Error> {
+        self.s3_client
+            .head_bucket()
+            .bucket(&self.bucket)
+            .send()
+            .await
+            .map_err(aws_sdk_s3::Error::from)
+            .map_err(map_err)?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn health_check(&self) -> Result<(), Error> {
+        info!("Running health check for S3 Bucket {}", self.bucket);
+
+        // Credentials may allow the bucket to be found but not listed, so a single object is
+        // listed to check that the bucket's contents can be accessed.
+        let result = match self
+            .s3_client
+            .head_bucket()
+            .bucket(&self.bucket)
+            .send()
+            .await
+        {
+            Ok(_) => self
+                .s3_client
+                .list_objects_v2()
+                .bucket(&self.bucket)
+                .max_keys(1)
+                .send()
+                .await
+                .map(|_| ())
+                .map_err(aws_sdk_s3::Error::from)
+                .map_err(map_err),
+            Err(error) => Err(map_err(aws_sdk_s3::Error::from(error))),
+        };
+
+        match result {
+            Ok(_) => {
+                info!(
+                    "Successfully completed health check for S3 Bucket {}",
+                    self.bucket
+                );
+                Ok(())
+            }
+            Err(error) => {
+                error!(
+                    "Failed to complete health check for S3 Bucket {}: {}",
+                    self.bucket, error
+                );
+                Err(error)
+            }
+        }
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn get_authorized_keys_fingerprints(&self, user: &str) -> Result<Vec<String>, Error> {
+        if let Some(fingerprints) = self.authorized_keys_cache.get(user).await {
+            return Ok(fingerprints);
+        }
+
+        let authorized_keys_key = format!(".ssh/{}/authorized_keys", user);
+
+        let object = self
+            .s3_client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(authorized_keys_key)
+            .send()
+            .await
+            .map_err(aws_sdk_s3::Error::from)
+            .map_err(map_err);
+
+        // Users without authorized keys are cached too, so that repeated authentication
+        // attempts for unknown users do not each require a request to S3.
+        let fingerprints = match object {
+            Ok(object) => {
+                let mut buffer = String::new();
+                object
+                    .body
+                    .into_async_read()
+                    .read_to_string(&mut buffer)
+                    .await?;
+
+                ssh_keys::parse_authorized_keys(&buffer)
+            }
+            Err(Error::NoSuchFile) => vec![],
+            Err(err) => return Err(err),
+        };
+
+        self.authorized_keys_cache
+            .insert(user, fingerprints.clone())
+            .await;
+
+        Ok(fingerprints)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn open_dir_handle(&self, dir_name: String) -> Result<String, Error> {
+        let prefix = get_s3_prefix(&dir_name);
+
+        self.handle_manager
+            .create_dir_handle(DirHandle {
+                prefix,
+                continuation_token: None,
+                is_eof: false,
+            })
+            .await
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn read_dir(&self, handle: &str) -> Result<Vec<File>, Error> {
+        let dir_handle = match self.handle_manager.get_dir_handle(handle).await {
+            Some(dir_handle) => dir_handle,
+            None => return Err(Error::InvalidHandle),
+        };
+
+        let mut dir_handle = dir_handle.lock().await;
+
+        if dir_handle.is_eof {
+            return Ok(Vec::new());
+        }
+
+        debug!(prefix = %dir_handle.prefix, "Listing S3 prefix");
+
+        // An empty response ends the client's listing, so pages that only contain hidden objects,
+        // such as folder markers, are skipped until files are found or the listing is complete.
+        loop {
+            // The handle's prefix is normalized when the handle is opened.
+            let objects = self
+                .s3_client
+                .list_objects_v2()
+                .bucket(&self.bucket)
+                .prefix(&dir_handle.prefix)
+                .set_continuation_token(dir_handle.continuation_token.clone())
+                .delimiter("/")
+                .send()
+                .await
+                .map_err(aws_sdk_s3::Error::from)
+                .map_err(map_err)?;
+
+            let files = dir_handle.read_page(objects, self.default_modes);
+
+            if !files.is_empty() || dir_handle.is_eof {
+                return Ok(files);
+            }
+        }
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn create_dir(&self, dir_name: String) -> Result<(), Error> {
+        /*
+            S3 does not support creating empty prefixes. A marker file must be added to preserve empty
+            directories until the directories are explicitly deleted.
+        */
+        check_not_folder_marker(&dir_name)?;
+
+        // Clients that create nested directories often create each parent as well, which already
+        // exists once a subdirectory's marker has been written.
+        let prefix = get_s3_prefix(&dir_name);
+
+        check_not_root_prefix(&prefix)?;
+
+        if self.is_known_dir(&prefix) {
+            debug!(prefix = %prefix, "Skipped marker for existing directory");
+            return Ok(());
+        }
+
+        self.s3_client
+            .put_object()
+            .bucket(&self.bucket)
+            .set_server_side_encryption(self.server_side_encryption.clone())
+            .set_ssekms_key_id(self.sse_kms_key_id.clone())
+            .key(get_s3_folder_marker(&dir_name))
+            .send()
+            .await
+            .map_err(aws_sdk_s3::Error::from)
+            .map_err(map_err)?;
+
+        self.add_known_dir(&prefix);
+
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn get_dir_size(&self, dir_name: String) -> Result<u64, Error> {
+        let prefix = get_s3_prefix(&dir_name);
+        let mut continuation_token = None;
+        let mut dir_size = 0;
+
+        loop {
+            let objects = self
+                .s3_client
+                .list_objects_v2()
+                .bucket(&self.bucket)
+                .prefix(&prefix)
+                .set_continuation_token(continuation_token)
+                .send()
+                .await
+                .map_err(aws_sdk_s3::Error::from)
+                .map_err(map_err)?;
+
+            dir_size += objects
+                .contents
+                .unwrap_or_default()
+                .iter()
+                .filter_map(|object| object.size)
+                .map(|size| size as u64)
+                .sum::<u64>();
+
+            continuation_token = objects.next_continuation_token;
+
+            if continuation_token.is_none() {
+                break;
+            }
+        }
+
+        Ok(dir_size)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn remove_dir(&self, dir_name: String) -> Result<(), Error> {
+        let prefix = get_s3_prefix(&dir_name);
+
+        check_not_root_prefix(&prefix)?;
+        let mut continuation_token = None;
+
+        self.forget_known_dir(&prefix);
+
+        loop {
+            let objects = self
+                .s3_client
+                .list_objects_v2()
+                .bucket(&self.bucket)
+                .prefix(&prefix)
+                .set_continuation_token(continuation_token.clone())
+                .set_delimiter(None)
+                .send()
+                .await
+                .map_err(aws_sdk_s3::Error::from)
+                .map_err(map_err)?;
+
+            continuation_token = objects.next_continuation_token;
+
+            if let Some(contents) = objects.contents {
+                let keys = contents.into_iter().filter_map(|content| content.key);
+
+                for key in keys {
+                    self.delete_object(&key).await?;
+                }
+            }
+
+            if continuation_token.is_none() {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn get_file_metadata(&self, file_name: String) -> Result<File, Error> {
+        trace_s3_key(&file_name);
+
+        if is_folder_marker(&file_name) {
+            return Err(Error::NoSuchFile);
+        }
+
+        let head_object_response = self
+            .s3_client
+            .head_object()
+            .bucket(&self.bucket)
+            .key(&file_name)
+            .send()
+            .await
+            .map_err(aws_sdk_s3::Error::from)
+            .map_err(map_err);
+
+        match head_object_response {
+            Ok(head_object_response) => Ok(map_head_object_to_file(
+                &file_name,
+                &head_object_response,
+                self.default_modes.file_mode,
+            )),
+            Err(err) => match err {
+                Error::NoSuchFile => match self.get_symlink_metadata(&file_name).await {
+                    Err(Error::NoSuchFile) => self.get_directory_metadata(&file_name).await,
+                    symlink_metadata => symlink_metadata,
+                },
+                _ => Err(err),
+            },
+        }
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn set_file_metadata(
+        &self,
+        file_name: String,
+        file_attributes: FileAttributes,
+    ) -> Result<(), Error> {
+        let head_object_response = self
+            .s3_client
+            .head_object()
+            .bucket(&self.bucket)
+            .key(&file_name)
+            .send()
+            .await
+            .map_err(aws_sdk_s3::Error::from)
+            .map_err(map_err);
+
+        let head_object_response = match head_object_response {
+            Ok(head_object_response) => head_object_response,
+            Err(Error::NoSuchFile) => {
+                // Directories are emulated with prefixes, so there is no object to update.
+                self.get_directory_metadata(&file_name).await?;
+                return Ok(());
+            }
+            Err(err) => return Err(err),
+        };
+
+        let metadata = map_file_attributes_to_metadata(
+            head_object_response.metadata.clone().unwrap_or_default(),
+            &file_attributes,
+        );
+
+        let content_length = head_object_response.content_length.unwrap_or_default() as u64;
+
+        // Replacing the metadata also replaces the content type, so the existing type is kept.
+        let content_type = head_object_response.content_type;
+
+        match file_attributes.size {
+            Some(size) if size < content_length => {
+                self.truncate_file(&file_name, size, metadata, content_type)
+                    .await?
+            }
+            _ if requires_multipart_copy(content_length) => {
+                self.copy_object_multipart(
+                    &file_name,
+                    &file_name,
+                    content_length,
+                    Some(metadata),
+                    content_type,
+                )
+                .await?
+            }
+            _ => {
+                self.s3_client
+                    .copy_object()
+                    .bucket(&self.bucket)
+                    .set_server_side_encryption(self.server_side_encryption.clone())
+                    .set_ssekms_key_id(self.sse_kms_key_id.clone())
+                    .copy_source(get_s3_copy_source(&self.bucket, &file_name))
+                    .key(&file_name)
+                    .metadata_directive(MetadataDirective::Replace)
+                    .set_metadata(Some(metadata))
+                    .set_content_type(content_type)
+                    .set_storage_class(self.storage_class.clone())
+                    .send()
+                    .await
+                    .map_err(aws_sdk_s3::Error::from)
+                    .map_err(map_err)?;
+            }
+        };
+
+        // The object is rewritten above, which resets its ACL, so the ACL is applied afterwards.
+        match (self.map_chmod_to_acl, file_attributes.permissions) {
+            (true, Some(permissions)) => {
+                self.s3_client
+                    .put_object_acl()
+                    .bucket(&self.bucket)
+                    .key(&file_name)
+                    .acl(map_permissions_to_acl(permissions))
+                    .send()
+                    .await
+                    .map_err(aws_sdk_s3::Error::from)
+                    .map_err(map_err)?;
+
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn set_handle_metadata(
+        &self,
+        handle: &str,
+        file_attributes: FileAttributes,
+    ) -> Result<(), Error> {
+        if let Some(write_handle) = self.handle_manager.get_write_handle(handle).await {
+            let mut write_handle = write_handle.lock().await;
+            write_handle.set_file_attributes(file_attributes);
+            Ok(())
+        } else if self.handle_manager.get_read_handle(handle).await.is_some()
+            || self.handle_manager.get_dir_handle(handle).await.is_some()
+        {
+            Ok(())
+        } else {
+            Err(Error::InvalidHandle)
+        }
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn get_handle_metadata(&self, handle: &str) -> Result<File, Error> {
+        if let Some(read_handle) = self.handle_manager.get_read_handle(handle).await {
+            let key = read_handle.lock().await.object.key.to_string();
+            self.get_file_metadata(key).await
+        } else if let Some(write_handle) = self.handle_manager.get_write_handle(handle).await {
+            let write_handle = write_handle.lock().await;
+            self.get_file_metadata(write_handle.key.to_string()).await
+        } else if let Some(dir_handle) = self.handle_manager.get_dir_handle(handle).await {
+            let dir_handle = dir_handle.lock().await;
+            self.get_file_metadata(dir_handle.prefix.to_string()).await
+        } else {
+            Err(Error::InvalidHandle)
+        }
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn open_read_handle(&self, file_name: String) -> Result<String, Error> {
+        trace_s3_key(&file_name);
+
+        if is_folder_marker(&file_name) {
+            return Err(Error::NoSuchFile);
+        }
+
+        let read_handle = self.create_read_handle(file_name).await?;
+
+        self.handle_manager.create_read_handle(read_handle).await
+    }
+
+    #[tracing::instrument(skip(self), level = Level::TRACE)]
+    async fn read_data(&self, handle: &str, offset: u64, len: u32) -> Result<Vec<u8>, Error> {
+        let read_handle = match self.handle_manager.get_read_handle(handle).await {
+            Some(dir_handle) => dir_handle,
+            None => return Err(Error::InvalidHandle),
+        };
+
+        // The handle is only locked long enough to copy out the object and stream, so a slow read
+        // does not hold up other reads from the handle.
+        let (read_object, read_stream) = {
+            let read_handle = read_handle.lock().await;
+            (read_handle.object.clone(), read_handle.stream.clone())
+        };
+
+        // S3 rejects ranges that start past the end of the object, so these reads are the end of
+        // the file without a request.
+        if len == 0 || read_object.size.is_some_and(|size| offset >= size) {
+            return Ok(Vec::new());
+        }
+
+        // Sequential reads share the handle's stream to benefit from its read-ahead. If another
+        // read is already using the stream, this read fetches its range independently rather
+        // than waiting for the stream.
+        if let Ok(mut read_stream) = read_stream.try_lock() {
+            self.seek_read_stream(&read_object, &mut read_stream, offset)
+                .await?;
+
+            return read_stream.read(len as usize).await;
+        }
+
+        self.read_range(&read_object, offset, len).await
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn open_write_handle(&self, file_name: String) -> Result<String, Error> {
+        trace_s3_key(&file_name);
+        check_not_folder_marker(&file_name)?;
+
+        // The multipart upload is not created until the file is known to be large enough to need
+        // one, so small files can be uploaded with a single put.
+        self.handle_manager
+            .create_write_handle(WriteHandle::new(file_name))
+            .await
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn open_existing_write_handle(
+        &self,
+        file_name: String,
+        truncate: bool,
+    ) -> Result<String, Error> {
+        let write_handle = self.load_write_handle(file_name, truncate).await?;
+
+        self.handle_manager.create_write_handle(write_handle).await
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn open_read_write_handle(
+        &self,
+        file_name: String,
+        truncate: bool,
+    ) -> Result<String, Error> {
+        let write_handle = self.load_write_handle(file_name.clone(), truncate).await?;
+
+        // Reads are served from the object as it was when the handle was opened. Writes are not
+        // visible to reads until the handle is closed and the object is uploaded.
+        let read_handle = self.create_read_handle(file_name).await?;
+
+        self.handle_manager
+            .create_read_write_handle(read_handle, write_handle)
+            .await
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn open_append_handle(&self, file_name: String, create: bool) -> Result<String, Error> {
+        let write_handle = self.load_append_handle(file_name, create).await?;
+
+        self.handle_manager.create_write_handle(write_handle).await
+    }
+
+    #[tracing::instrument(skip(self, data), level = Level::TRACE)]
+    async fn write_data(&self, handle: &str, offset: u64, data: bytes::Bytes) -> Result<(), Error> {
+        let write_handle = match self.handle_manager.get_write_handle(handle).await {
+            Some(dir_handle) => dir_handle,
+            None => return Err(Error::InvalidHandle),
+        };
+
+        let mut write_handle = write_handle.lock().await;
+
+        write_handle.check_failure()?;
+        write_handle.check_in_place_size(offset, data.len(), self.multipart_threshold)?;
+        write_handle.write(offset, data, self.part_size)?;
+
+        if write_handle.should_upload_part(self.part_size, self.multipart_threshold) {
+            self.upload_buffered_part(&mut write_handle).await?;
+        };
+
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn close_handle(&self, handle: &str) -> Result<(), Error> {
+        if let Some(write_handle) = self.handle_manager.get_write_handle(handle).await {
+            let mut write_handle = write_handle.lock().await;
+
+            // A handle with a failed part can never be completed, so its upload is discarded.
+            if let Err(err) = write_handle.check_failure() {
+                self.handle_manager.remove_handle(handle).await;
+
+                if let Err(abort_err) = self.abort_write(&write_handle).await {
+                    error!(
+                        "Failed to abort multipart upload for {}: {}",
+                        write_handle.key, abort_err
+                    );
+                }
+
+                return Err(err);
+            }
+
+            write_handle.check_complete()?;
+            self.wait_for_part_uploads(&mut write_handle).await?;
+
+            if write_handle.should_use_multipart(self.multipart_threshold) {
+                // An upload that appends to an object may not have any new data for its last part.
+                if !write_handle.buffer.is_empty() || write_handle.completed_parts.is_empty() {
+                    self.upload_buffered_part(&mut write_handle).await?;
+                } else {
+                    self.start_multipart_upload(&mut write_handle).await?;
+                }
+
+                self.wait_for_part_uploads(&mut write_handle).await?;
+
+                let complete_multipart_upload = CompletedMultipartUpload::builder()
+                    .set_parts(Some(write_handle.completed_parts.clone()))
+                    .build();
+
+                self.s3_client
+                    .complete_multipart_upload()
+                    .bucket(&self.bucket)
+                    .key(&write_handle.key)
+                    .multipart_upload(complete_multipart_upload)
+                    .set_upload_id(write_handle.upload_id.clone())
+                    .send()
+                    .await
+                    .map_err(aws_sdk_s3::Error::from)
+                    .map_err(map_err)?;
+
+                // The upload is complete, so a handle that fails verification cannot be retried.
+                if self.verify_uploads {
+                    if let Err(err) = self
+                        .verify_upload(&write_handle.key, write_handle.offset)
+                        .await
+                    {
+                        self.handle_manager.remove_handle(handle).await;
+                        return Err(err);
+                    }
+                }
+            } else {
+                self.s3_client
+                    .put_object()
+                    .bucket(&self.bucket)
+                    .set_server_side_encryption(self.server_side_encryption.clone())
+                    .set_ssekms_key_id(self.sse_kms_key_id.clone())
+                    .key(&write_handle.key)
+                    .set_storage_class(self.storage_class.clone())
+                    .body(ByteStream::from(std::mem::take(&mut write_handle.buffer)))
+                    .send()
+                    .await
+                    .map_err(aws_sdk_s3::Error::from)
+                    .map_err(map_err)?;
+            }
+
+            // Metadata cannot be changed after a multipart upload is created, so attributes set
+            // on the handle are applied once the object exists.
+            if let Some(file_attributes) = write_handle.file_attributes.take() {
+                self.set_file_metadata(write_handle.key.to_string(), file_attributes)
+                    .await?;
+            }
+        }
+
+        self.handle_manager.remove_handle(handle).await;
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn fsync(&self, handle: &str) -> Result<(), Error> {
+        if let Some(write_handle) = self.handle_manager.get_write_handle(handle).await {
+            let mut write_handle = write_handle.lock().await;
+
+            write_handle.check_failure()?;
+
+            // Only the last part of a multipart upload may be smaller than the minimum part size,
+            // so smaller buffers are held until the handle is closed.
+            if write_handle.should_flush_part(MIN_PART_SIZE, self.multipart_threshold) {
+                self.upload_buffered_part(&mut write_handle).await?;
+            }
+
+            self.wait_for_part_uploads(&mut write_handle).await
+        } else if self.handle_manager.get_read_handle(handle).await.is_some()
+            || self.handle_manager.get_dir_handle(handle).await.is_some()
+        {
+            Ok(())
+        } else {
+            Err(Error::InvalidHandle)
+        }
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn remove_file(&self, file_name: String) -> Result<(), Error> {
+        if is_folder_marker(&file_name) {
+            return Err(Error::NoSuchFile);
+        }
+
+        // Deleting a missing object succeeds in S3, so emulated symbolic links must be detected
+        // up front to remove the sentinel object instead.
+        let key = match self.get_file_metadata(file_name.clone()).await {
+            Ok(file) if file.file_attributes.is_symlink() => get_symlink_name(&file_name),
+            _ => file_name,
+        };
+
+        self.delete_object(&key).await
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn rename(&self, current: String, new: String) -> Result<(), Error> {
+        check_not_folder_marker(&new)?;
+
+        let file = self.get_file_metadata(current.clone()).await?;
+
+        if file.file_attributes.is_dir() {
+            self.rename_dir(current, new).await
+        } else if file.file_attributes.is_symlink() {
+            self.rename_file(get_symlink_name(&current), get_symlink_name(&new))
+                .await
+        } else {
+            self.rename_file(current, new).await
+        }
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn copy_file(&self, current: String, new: String) -> Result<(), Error> {
+        check_not_folder_marker(&new)?;
+
+        self.copy_object(&current, &new).await
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn create_symlink(&self, link: String, target: String) -> Result<(), Error> {
+        self.s3_client
+            .put_object()
+            .bucket(&self.bucket)
+            .set_server_side_encryption(self.server_side_encryption.clone())
+            .set_ssekms_key_id(self.sse_kms_key_id.clone())
+            .key(get_symlink_name(&link))
+            .body(ByteStream::from(target.into_bytes()))
+            .send()
+            .await
+            .map_err(aws_sdk_s3::Error::from)
+            .map_err(map_err)?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn read_symlink(&self, link: String) -> Result<String, Error> {
+        let object = self
+            .s3_client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(get_symlink_name(&link))
+            .send()
+            .await
+            .map_err(aws_sdk_s3::Error::from)
+            .map_err(map_err)?;
+
+        let mut target = String::new();
+        object
+            .body
+            .into_async_read()
+            .read_to_string(&mut target)
+            .await?;
+
+        Ok(target)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn abort_write_handle(&self, handle: &str) -> Result<(), Error> {
+        match self.handle_manager.remove_write_handle(handle).await {
+            Some(write_handle) => self.abort_write(&*write_handle.lock().await).await,
+            None => Ok(()),
+        }
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn abort_write_handles(&self) -> Result<(), Error> {
+        let mut result = Ok(());
+
+        // Every handle is aborted even if one fails, so that no uploads are left behind.
+        for write_handle in self.handle_manager.remove_write_handles().await {
+            let write_handle = write_handle.lock().await;
+
+            if let Err(err) = self.abort_write(&write_handle).await {
+                error!(
+                    "Failed to abort multipart upload for {}: {}",
+                    write_handle.key, err
+                );
+                result = Err(err);
+            }
+        }
+
+        result
+    }
+}
+
+struct DirHandle {
+    prefix: String,
+    continuation_token: Option<String>,
+    is_eof: bool,
+}
+
+impl DirHandle {
+    /// Maps a page of the listing to files and advances the handle to the next page. Each
+    /// `readdir` request returns one page, until the final page has been returned.
+    fn read_page(
+        &mut self,
+        list_objects: aws_sdk_s3::operation::list_objects_v2::ListObjectsV2Output,
+        default_modes: DefaultModes,
+    ) -> Vec<File> {
+        self.continuation_token = list_objects.next_continuation_token.clone();
+        self.is_eof = list_objects.next_continuation_token.is_none();
+
+        map_list_objects_to_files(list_objects, default_modes)
+    }
+}
+
+struct ReadHandle {
+    object: Arc<ReadObject>,
+    stream: Arc<tokio::sync::Mutex<ReadStream>>,
+}
+
+impl ReadHandle {
+    fn new(
+        key: String,
+        async_read: Pin<Box<dyn AsyncRead + Send>>,
+        read_ahead_size: usize,
+    ) -> ReadHandle {
+        ReadHandle {
+            object: Arc::new(ReadObject {
+                key,
+                size: None,
+                e_tag: None,
+            }),
+            stream: Arc::new(tokio::sync::Mutex::new(ReadStream::new(
+                async_read,
+                read_ahead_size,
+            ))),
+        }
+    }
+}
+
+/// The version of an object that a read handle was opened on. Reads at other offsets request the
+/// same ETag, so a handle never returns data from two versions of an object.
+struct ReadObject {
+    key: String,
+    size: Option<u64>,
+    e_tag: Option<String>,
+}
+
+struct ReadStream {
+    async_read: Pin<Box<dyn AsyncRead + Send>>,
+    read_ahead: BytesMut,
+    read_ahead_size: usize,
+    is_eof: bool,
+    /// The offset in the object of the first byte of the prefetched data.
+    position: u64,
+}
+
+impl ReadStream {
+    fn new(async_read: Pin<Box<dyn AsyncRead + Send>>, read_ahead_size: usize) -> ReadStream {
+        ReadStream {
+            async_read,
+            read_ahead: BytesMut::new(),
+            read_ahead_size,
+            is_eof: false,
+            position: 0,
+        }
+    }
+
+    /// Moves to an offset within the prefetched data, discarding the data before it. False is
+    /// returned if the offset is not prefetched, and the object must be reopened at the offset.
+    fn seek(&mut self, offset: u64) -> bool {
+        let skip = match offset.checked_sub(self.position) {
+            Some(skip) if skip <= self.read_ahead.len() as u64 => skip as usize,
+            _ => return false,
+        };
+
+        bytes::Buf::advance(&mut self.read_ahead, skip);
+        self.position = offset;
+
+        true
+    }
+
+    /// Checks if an offset is past the end of the object, which is only known once the stream has
+    /// been read to its end.
+    fn is_past_end(&self, offset: u64) -> bool {
+        self.is_eof && offset >= self.position + self.read_ahead.len() as u64
+    }
+
+    /// Replaces the stream with one that starts at an offset in the object.
+    fn reset(&mut self, async_read: Pin<Box<dyn AsyncRead + Send>>, offset: u64) {
+        self.async_read = async_read;
+        self.read_ahead.clear();
+        self.is_eof = false;
+        self.position = offset;
+    }
+
+    /// Reads up to `len` bytes. Clients pipeline many small reads, so data is prefetched from
+    /// the object in larger chunks and served from memory. An empty result indicates the end of
+    /// the object.
+    async fn read(&mut self, len: usize) -> Result<Vec<u8>, Error> {
+        if self.read_ahead.len() < len && !self.is_eof {
+            self.fill_read_ahead(len.max(self.read_ahead_size)).await?;
+        }
+
+        let len = len.min(self.read_ahead.len());
+        self.position += len as u64;
+
+        Ok(self.read_ahead.split_to(len).to_vec())
+    }
+
+    async fn fill_read_ahead(&mut self, target_len: usize) -> Result<(), Error> {
+        self.read_ahead
+            .reserve(target_len.saturating_sub(self.read_ahead.len()));
+
+        while self.read_ahead.len() < target_len {
+            if self.async_read.read_buf(&mut self.read_ahead).await? == 0 {
+                self.is_eof = true;
+                break;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+struct WriteHandle {
+    key: String,
+    upload_id: Option<String>,
+    completed_parts: Vec<CompletedPart>,
+    /// The parts being uploaded in the background while the next part is buffered.
+    part_uploads: Vec<tokio::task::JoinHandle<Result<CompletedPart, Error>>>,
+    buffer: Vec<u8>,
+    offset: u64,
+    pending_writes: BTreeMap<u64, bytes::Bytes>,
+    pending_size: usize,
+    file_attributes: Option<FileAttributes>,
+    in_place: bool,
+    appending: bool,
+    failure: Option<String>,
+}
+
+impl WriteHandle {
+    fn new(key: String) -> WriteHandle {
+        WriteHandle {
+            key,
+            upload_id: None,
+            completed_parts: Vec::new(),
+            part_uploads: Vec::new(),
+            buffer: Vec::new(),
+            offset: 0,
+            pending_writes: BTreeMap::new(),
+            pending_size: 0,
+            file_attributes: None,
+            in_place: false,
+            appending: false,
+            failure: None,
+        }
+    }
+
+    /// Creates a handle that appends to existing data. Writes are added to the end of the data
+    /// in the order that they arrive, regardless of their offsets.
+    fn for_append(key: String, data: Vec<u8>) -> WriteHandle {
+        WriteHandle {
+            offset: data.len() as u64,
+            buffer: data,
+            appending: true,
+            ..WriteHandle::new(key)
+        }
+    }
+
+    /// Creates a handle that modifies existing data in place. The data is held in the buffer
+    /// until the handle is closed, so writes may replace any part of it.
+    fn with_data(key: String, data: Vec<u8>) -> WriteHandle {
+        WriteHandle {
+            offset: data.len() as u64,
+            buffer: data,
+            in_place: true,
+            ..WriteHandle::new(key)
+        }
+    }
+
+    fn is_multipart(&self) -> bool {
+        self.upload_id.is_some()
+    }
+
+    /// Checks if the buffer should be uploaded as a part. Parts are only uploaded once the file
+    /// is large enough to need a multipart upload.
+    fn should_upload_part(&self, part_size: usize, multipart_threshold: usize) -> bool {
+        !self.in_place
+            && self.buffer.len() > part_size
+            && (self.is_multipart() || self.buffer.len() >= multipart_threshold)
+    }
+
+    /// Checks if the buffer can be uploaded as a part before the handle is closed, such as when
+    /// the client requests an fsync.
+    fn should_flush_part(&self, min_part_size: usize, multipart_threshold: usize) -> bool {
+        !self.in_place
+            && self.buffer.len() >= min_part_size
+            && self.should_use_multipart(multipart_threshold)
+    }
+
+    /// Checks if a multipart upload is needed when the handle is closed instead of a single put.
+    fn should_use_multipart(&self, multipart_threshold: usize) -> bool {
+        self.is_multipart() || self.buffer.len() >= multipart_threshold
+    }
+
+    /// Writes data at an offset. Multipart uploads are sequential, so writes that arrive ahead
+    /// of the current offset are held until the missing data arrives. Writes behind the current
+    /// offset would require rewriting data that may already be uploaded, so they are rejected
+    /// unless the handle modifies data in place. At most `max_pending_size` bytes are held, so
+    /// that a client cannot exhaust memory with writes far ahead of the current offset.
+    fn write(
+        &mut self,
+        offset: u64,
+        data: bytes::Bytes,
+        max_pending_size: usize,
+    ) -> Result<(), Error> {
+        if self.appending {
+            self.append(data);
+            return Ok(());
+        }
+
+        if offset < self.offset && !self.in_place {
+            return Err(Error::Failure(format!(
+                "Write at offset {} is behind the current offset {}.",
+                offset, self.offset
+            )));
+        }
+
+        if offset > self.offset {
+            return self.hold(offset, data, max_pending_size);
+        }
+
+        if offset < self.offset {
+            self.overwrite(offset, data);
+        } else {
+            self.append(data);
+        }
+
+        while let Some(data) = self.pending_writes.remove(&self.offset) {
+            self.pending_size -= data.len();
+            self.append(data);
+        }
+
+        match self.pending_writes.keys().next() {
+            Some(pending_offset) if *pending_offset < self.offset => Err(Error::Failure(format!(
+                "Write at offset {} overlaps previously written data.",
+                pending_offset
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Holds a write that arrived ahead of the current offset until the missing data arrives.
+    fn hold(
+        &mut self,
+        offset: u64,
+        data: bytes::Bytes,
+        max_pending_size: usize,
+    ) -> Result<(), Error> {
+        if self.pending_writes.contains_key(&offset) {
+            return Err(Error::Failure(format!(
+                "Write at offset {} was already received.",
+                offset
+            )));
+        }
+
+        if self.pending_size + data.len() > max_pending_size {
+            return Err(Error::Failure(format!(
+                "Writes ahead of the current offset {} exceed {} bytes.",
+                self.offset, max_pending_size
+            )));
+        }
+
+        self.pending_size += data.len();
+        self.pending_writes.insert(offset, data);
+
+        Ok(())
+    }
+
+    /// Handles that modify data in place hold all of their data until they are closed, so writes
+    /// that would make the data larger than a single put are rejected.
+    fn check_in_place_size(&self, offset: u64, len: usize, max_size: usize) -> Result<(), Error> {
+        match self.in_place && offset.saturating_add(len as u64) > max_size as u64 {
+            true => Err(Error::Failure(format!(
+                "{} is too large to modify in place.",
+                self.key
+            ))),
+            false => Ok(()),
+        }
+    }
+
+    /// Checks that no part of the upload has failed. Data from a failed part is not held by the
+    /// handle, so the upload cannot be completed.
+    fn check_failure(&self) -> Result<(), Error> {
+        match &self.failure {
+            Some(failure) => Err(Error::Failure(format!(
+                "A previous write to {} failed: {}",
+                self.key, failure
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// Checks that every write has been applied, so there are no gaps in the uploaded data.
+    fn check_complete(&self) -> Result<(), Error> {
+        match self.pending_writes.keys().next() {
+            Some(pending_offset) => Err(Error::Failure(format!(
+                "Missing data between offsets {} and {}.",
+                self.offset, pending_offset
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// Records attributes to apply when the handle is closed. Attributes from earlier requests are
+    /// kept unless a later request replaces them.
+    fn set_file_attributes(&mut self, file_attributes: FileAttributes) {
+        let current = self.file_attributes.take().unwrap_or_default();
+
+        self.file_attributes = Some(FileAttributes {
+            size: file_attributes.size.or(current.size),
+            uid: file_attributes.uid.or(current.uid),
+            gid: file_attributes.gid.or(current.gid),
+            permissions: file_attributes.permissions.or(current.permissions),
+            atime: file_attributes.atime.or(current.atime),
+            mtime: file_attributes.mtime.or(current.mtime),
+        });
+    }
+
+    /// Parts are numbered in the order they are started, which includes parts that are still
+    /// being uploaded.
+    fn get_next_part_number(&self) -> i32 {
+        (self.completed_parts.len() + self.part_uploads.len()) as i32 + 1
+    }
+
+    fn append(&mut self, data: bytes::Bytes) {
+        self.offset += data.len() as u64;
+        self.buffer.put(data);
+    }
+
+    /// Replaces buffered data at an offset, appending any data that extends past the end.
+    fn overwrite(&mut self, offset: u64, data: bytes::Bytes) {
+        let start = offset as usize;
+        let overlap = data.len().min(self.buffer.len() - start);
+
+        self.buffer[start..start + overlap].copy_from_slice(&data[..overlap]);
+        self.append(data.slice(overlap..));
+    }
+}
+
+/// Waits for every part being uploaded in the background and adds them to the completed parts.
+/// Every upload is waited for, even after one fails, so that none are left running.
+async fn finish_part_uploads(write_handle: &mut WriteHandle) -> Result<(), Error> {
+    let mut result = Ok(());
+
+    for part_upload in std::mem::take(&mut write_handle.part_uploads) {
+        let completed_part = part_upload
+            .await
+            .map_err(|err| Error::Failure(format!("Part upload did not finish: {}", err)))
+            .and_then(|completed_part| completed_part);
+
+        match completed_part {
+            Ok(completed_part) => write_handle.completed_parts.push(completed_part),
+            Err(err) => result = result.and(Err(err)),
+        }
+    }
+
+    // Parts may finish in any order, but S3 requires them to be completed in order.
+    write_handle
+        .completed_parts
+        .sort_by_key(|completed_part| completed_part.part_number());
+
+    result
+}
+
+fn get_s3_prefix(dir_name: &str) -> String {
+    let prefix_builder = match dir_name.starts_with('/') {
+        true => &dir_name[1..dir_name.len()],
+        false => dir_name,
+    };
+
+    // Keys do not start with a slash, so the bucket's root is listed without a prefix.
+    let prefix = match prefix_builder {
+        "" => String::new(),
+        prefix_builder if prefix_builder.ends_with('/') => prefix_builder.to_string(),
+        prefix_builder => format!("{}/", prefix_builder),
+    };
+
+    debug!(path = dir_name, prefix = %prefix, "Translated path to S3 prefix");
+
+    prefix
+}
+
+/// Object keys are the paths requested by clients. The translation is logged so that paths that
+/// do not map to the expected objects can be diagnosed.
+fn trace_s3_key(file_name: &str) {
+    debug!(
+        path = file_name,
+        key = file_name,
+        "Translated path to S3 key"
+    );
+}
+
+fn get_s3_copy_source(bucket: &str, key: &str) -> String {
+    format!("{}/{}", bucket, key)
+}
+
+fn requires_multipart_copy(size: u64) -> bool {
+    size > MAX_COPY_OBJECT_SIZE
+}
+
+/// Splits an object into the inclusive byte ranges copied by each part of a multipart copy.
+fn get_copy_part_ranges(size: u64, part_size: u64) -> Vec<String> {
+    (0..size)
+        .step_by(part_size as usize)
+        .map(|start| format!("bytes={}-{}", start, (start + part_size).min(size) - 1))
+        .collect()
+}
+
+/// Splits an object into the byte ranges copied into the first parts of an upload that appends
+/// to it. The appended data follows these parts, so a final range that is too small to be a part
+/// is merged into the previous range.
+fn get_append_copy_part_ranges(size: u64, part_size: u64) -> Vec<String> {
+    let remainder = size % part_size;
+
+    if size <= part_size || remainder == 0 || remainder >= MIN_PART_SIZE as u64 {
+        return get_copy_part_ranges(size, part_size);
+    }
+
+    let mut ranges = get_copy_part_ranges(size - remainder, part_size);
+    ranges.pop();
+    ranges.push(format!(
+        "bytes={}-{}",
+        size - remainder - part_size,
+        size - 1
+    ));
+    ranges
+}
+
+/// Gets the inclusive byte range of a read of `len` bytes at an offset.
+fn get_read_range(offset: u64, len: u32) -> String {
+    format!("bytes={}-{}", offset, offset + u64::from(len) - 1)
+}
+
+fn get_s3_folder_marker(dir_name: &str) -> String {
+    let prefix = get_s3_prefix(dir_name);
+    let key = format!("{}{}", prefix, FOLDER_MARKER);
+
+    debug!(path = dir_name, key = %key, "Translated path to S3 folder marker");
+
+    key
+}
+
+/// The bucket's root cannot be created, removed, or renamed, since its prefix would match every
+/// object in the bucket.
+fn check_not_root_prefix(prefix: &str) -> Result<(), Error> {
+    match prefix.is_empty() {
+        true => Err(Error::PermissionDenied),
+        false => Ok(()),
+    }
+}
+
+fn is_folder_marker(file_name: &str) -> bool {
+    file_name.rsplit('/').next() == Some(FOLDER_MARKER)
+}
+
+/// Folder markers are managed with their directories, so clients may not write objects with the
+/// marker's name.
+fn check_not_folder_marker(file_name: &str) -> Result<(), Error> {
+    match is_folder_marker(file_name) {
+        true => Err(Error::PermissionDenied),
+        false => Ok(()),
+    }
+}
+
+fn map_list_objects_to_files(
+    list_objects: aws_sdk_s3::operation::list_objects_v2::ListObjectsV2Output,
+    default_modes: DefaultModes,
+) -> Vec<File> {
+    let files = list_objects.contents.unwrap_or_default();
+
+    let directories = list_objects.common_prefixes.unwrap_or_default();
+
+    let mapped_dirs: Vec<File> = directories
+        .iter()
+        .map(|prefix| map_prefix_to_file(prefix, default_modes.dir_mode))
+        .collect();
+
+    // An object may share its name with a directory, such as an object written over a prefix
+    // that contains a folder marker. The directory is listed in place of the object.
+    let dir_names: HashSet<String> = mapped_dirs
+        .iter()
+        .map(|dir| dir.file_name.clone())
+        .collect();
+
+    let mapped_files = files
+        .iter()
+        .map(|object| map_object_to_file(object, default_modes.file_mode))
+        .filter(|file| !is_folder_marker(&file.file_name))
+        .map(|file| match is_symlink_name(&file.file_name) {
+            true => map_sentinel_to_symlink(file),
+            false => file,
+        })
+        .filter(|file| !dir_names.contains(&file.file_name));
+
+    // S3 returns directories separately from objects, so the page is sorted to list them together
+    // in the same order as the keys.
+    let mut files: Vec<File> = mapped_dirs.into_iter().chain(mapped_files).collect();
+    files.sort_by(|a, b| a.file_name.cmp(&b.file_name));
+
+    files
+}
+
+/// Maps a listed object to a file. Listings do not include object metadata, so objects are listed
+/// with the default file mode.
+fn map_object_to_file(object: &Object, file_mode: u32) -> File {
+    let key = match &object.key {
+        Some(key) => key,
+        None => "",
+    };
+
+    let mut key_pieces = key.rsplit('/');
+    let file_name = key_pieces.next().unwrap_or("");
+
+    File {
+        file_name: file_name.to_string(),
         file_attributes: FileAttributes {
             size: object.size.map(|size| size as u64),
             uid: None,
             gid: None,
-            permissions: Some(0o100777),
+            permissions: Some(REGULAR_FILE_TYPE | file_mode),
+            atime: None,
+            mtime: object.last_modified.as_ref().map(map_date_time_to_epoch),
+        },
+    }
+}
+
+/// Maps a file's permissions to a canned ACL. Files that others may read are public, and all other
+/// files are private.
+fn map_permissions_to_acl(permissions: u32) -> ObjectCannedAcl {
+    match permissions & 0o004 {
+        0 => ObjectCannedAcl::Private,
+        _ => ObjectCannedAcl::PublicRead,
+    }
+}
+
+/// Converts an S3 timestamp to the seconds since the Unix epoch used by file attributes.
+fn map_date_time_to_epoch(date_time: &aws_sdk_s3::primitives::DateTime) -> u32 {
+    (date_time.to_millis().unwrap_or_default() / 1000) as u32
+}
+
+fn map_list_objects_to_directory(
+    list_objects: aws_sdk_s3::operation::list_objects_v2::ListObjectsV2Output,
+    dir_mode: u32,
+) -> Result<File, Error> {
+    let contents = list_objects.contents.unwrap_or_default();
+
+    let prefix = match list_objects.prefix {
+        Some(prefix) => prefix,
+        None => return Err(Error::NoSuchFile),
+    };
+
+    if contents.is_empty() {
+        Err(Error::NoSuchFile)
+    } else {
+        Ok(map_prefix_to_file(
+            &CommonPrefix::builder().prefix(prefix).build(),
+            dir_mode,
+        ))
+    }
+}
+
+fn map_prefix_to_file(prefix: &CommonPrefix, dir_mode: u32) -> File {
+    let prefix = match prefix.prefix {
+        Some(ref prefix) => {
+            let mut prefix = prefix.to_string();
+            prefix.pop(); // strip trailing /
+            format!("/{}", prefix)
+        }
+        None => "".to_owned(),
+    };
+
+    let mut prefix_pieces = prefix.rsplit('/');
+    let file_name = prefix_pieces.next().unwrap_or("");
+
+    File {
+        file_name: file_name.to_string(),
+        file_attributes: FileAttributes {
+            size: None,
+            uid: None,
+            gid: None,
+            permissions: Some(DIRECTORY_TYPE | dir_mode),
+            atime: None,
+            mtime: None,
+        },
+    }
+}
+
+fn map_head_object_to_file(
+    key: &str,
+    head_object: &aws_sdk_s3::operation::head_object::HeadObjectOutput,
+    file_mode: u32,
+) -> File {
+    let mut key_pieces = key.rsplit('/');
+    let file_name = key_pieces.next().unwrap_or("");
+
+    // Attributes set with setstat are stored as object metadata. Objects uploaded by other means
+    // have no metadata, so they fall back to the configured default mode.
+    let metadata = head_object.metadata.as_ref();
+    let get_metadata = |key: &str| metadata.and_then(|metadata| metadata.get(key));
+
+    let permissions = get_metadata(METADATA_PERMISSIONS)
+        .and_then(|permissions| u32::from_str_radix(permissions, 8).ok())
+        .map(|permissions| permissions & 0o7777)
+        .unwrap_or(file_mode);
+
+    File {
+        file_name: file_name.to_string(),
+        file_attributes: FileAttributes {
+            size: head_object
+                .content_length
+                .map(|content_length| content_length as u64),
+            uid: get_metadata(METADATA_UID).and_then(|uid| uid.parse().ok()),
+            gid: get_metadata(METADATA_GID).and_then(|gid| gid.parse().ok()),
+            permissions: Some(REGULAR_FILE_TYPE | permissions),
             atime: None,
-            mtime: object
+            mtime: head_object
                 .last_modified
-                .map(|last_modified| (last_modified.to_millis().unwrap_or_default() / 1000) as u32),
+                .as_ref()
+                .map(map_date_time_to_epoch),
+        },
+    }
+}
+
+fn map_file_attributes_to_metadata(
+    mut metadata: HashMap<String, String>,
+    file_attributes: &FileAttributes,
+) -> HashMap<String, String> {
+    if let Some(permissions) = file_attributes.permissions {
+        metadata.insert(
+            METADATA_PERMISSIONS.to_string(),
+            format!("{:o}", permissions & 0o7777),
+        );
+    }
+
+    if let Some(uid) = file_attributes.uid {
+        metadata.insert(METADATA_UID.to_string(), uid.to_string());
+    }
+
+    if let Some(gid) = file_attributes.gid {
+        metadata.insert(METADATA_GID.to_string(), gid.to_string());
+    }
+
+    metadata
+}
+
+fn map_create_multipart_response_to_upload_id(
+    create_multipart_response: aws_sdk_s3::operation::create_multipart_upload::CreateMultipartUploadOutput,
+) -> Result<String, Error> {
+    match create_multipart_response.upload_id {
+        Some(upload_id) => Ok(upload_id),
+        None => Err(Error::Storage("Missing upload id.".to_string())),
+    }
+}
+
+fn get_default_endpoint_region() -> String {
+    String::from("custom")
+}
+
+fn get_default_part_size() -> usize {
+    DEFAULT_PART_SIZE
+}
+
+fn get_default_multipart_threshold() -> usize {
+    DEFAULT_MULTIPART_THRESHOLD
+}
+
+fn get_default_read_ahead_size() -> usize {
+    DEFAULT_READ_AHEAD_SIZE
+}
+
+fn map_err(s3_sdk_error: aws_sdk_s3::Error) -> Error {
+    match s3_sdk_error {
+        aws_sdk_s3::Error::NoSuchKey(_) => Error::NoSuchFile,
+        aws_sdk_s3::Error::NotFound(_) => Error::NoSuchFile,
+        // S3-compatible services do not always return errors that the SDK models, so missing
+        // objects are also recognized by their error code.
+        _ if matches!(s3_sdk_error.code(), Some("NoSuchKey" | "NotFound")) => Error::NoSuchFile,
+        _ => Error::Storage(s3_sdk_error.to_string()),
+    }
+}
+
+/// Configures the loader of the shared AWS configuration. The configured profile and region
+/// override the ones selected by the environment, so that instances on the same host can use
+/// different accounts.
+fn build_config_loader(s3_config: &S3Config) -> Result<ConfigLoader, Error> {
+    let mut config_loader = aws_config::defaults(BehaviorVersion::latest());
+
+    if let Some(region) = s3_config.get_region() {
+        config_loader = config_loader.region(region);
+    }
+
+    if let Some(aws_profile) = &s3_config.aws_profile {
+        config_loader = config_loader.profile_name(aws_profile);
+    }
+
+    if let Some(endpoint_name) = &s3_config.endpoint_name {
+        config_loader = config_loader.endpoint_url(endpoint_name);
+    };
+
+    if let Some(http_client) = s3_config.get_http_client()? {
+        config_loader = config_loader.http_client(http_client);
+    }
+
+    Ok(config_loader)
+}
+
+fn build_s3_sdk_config(config: &SdkConfig, s3_config: &S3Config) -> aws_sdk_s3::Config {
+    let force_path_style = match s3_config.force_path_style {
+        ForcePathStyle::Auto => config.endpoint_url().is_some(),
+        ForcePathStyle::True => true,
+        ForcePathStyle::False => false,
+    };
+
+    // Custom endpoints often have no region, but requests must still be signed with one.
+    let region = s3_config
+        .get_region()
+        .or_else(|| config.region().cloned())
+        .unwrap_or_else(|| Region::new(get_default_endpoint_region()));
+
+    aws_sdk_s3::config::Builder::from(config)
+        .force_path_style(force_path_style)
+        .region(region)
+        .build()
+}
+
+/// Checks if S3 rejected a ranged request because the range starts at or past the end of the
+/// object, which is the end of the file rather than a failure.
+fn is_invalid_range(s3_sdk_error: &aws_sdk_s3::Error) -> bool {
+    s3_sdk_error.code() == Some("InvalidRange")
+}
+
+#[cfg(test)]
+mod test {
+    use aws_sdk_s3::{
+        operation::{
+            create_multipart_upload::CreateMultipartUploadOutput, get_object::GetObjectError,
+            head_object::HeadObjectOutput, list_objects_v2::ListObjectsV2Output,
         },
+        primitives::DateTime,
+        types::error::{BucketAlreadyOwnedByYou, NoSuchKey, NotFound},
+    };
+
+    use super::*;
+
+    use crate::storage::{DEFAULT_DIR_MODE, DEFAULT_FILE_MODE, DEFAULT_MAX_HANDLES};
+    use aws_sdk_s3::config::{Credentials, ProvideCredentials, SharedCredentialsProvider};
+    use aws_smithy_runtime_api::client::http::{
+        http_client_fn, HttpConnector, HttpConnectorFuture, SharedHttpConnector,
+    };
+    use aws_smithy_runtime_api::client::orchestrator::{HttpRequest, HttpResponse};
+    use aws_smithy_runtime_api::client::result::ConnectorError;
+    use aws_smithy_runtime_api::http::StatusCode;
+    use aws_smithy_types::body::SdkBody;
+    use std::convert::TryFrom;
+    use std::io::Write;
+    use std::sync::Mutex;
+    use tracing_subscriber::{filter::LevelFilter, layer::SubscriberExt, Layer};
+
+    #[test]
+    fn test_s3_config_from_env_with_tls_settings() {
+        let s3_config = envy::prefixed("DRAY_")
+            .from_iter::<_, S3Config>(vec![
+                (String::from("DRAY_S3_BUCKET"), String::from("bucket")),
+                (
+                    String::from("DRAY_S3_CA_BUNDLE"),
+                    String::from("/etc/ca.pem"),
+                ),
+                (String::from("DRAY_S3_TLS_INSECURE"), String::from("true")),
+            ])
+            .unwrap();
+
+        assert_eq!(Some(String::from("/etc/ca.pem")), s3_config.ca_bundle);
+        assert!(s3_config.tls_insecure);
+        assert!(!s3_config.tls_insecure_dev_override);
+    }
+
+    #[test]
+    fn test_s3_config_from_env_with_verify_uploads() {
+        let s3_config = envy::prefixed("DRAY_")
+            .from_iter::<_, S3Config>(vec![
+                (String::from("DRAY_S3_BUCKET"), String::from("bucket")),
+                (String::from("DRAY_S3_VERIFY_UPLOADS"), String::from("true")),
+            ])
+            .unwrap();
+
+        assert!(s3_config.verify_uploads);
+
+        let s3_config = envy::prefixed("DRAY_")
+            .from_iter::<_, S3Config>(vec![(
+                String::from("DRAY_S3_BUCKET"),
+                String::from("bucket"),
+            )])
+            .unwrap();
+
+        assert!(!s3_config.verify_uploads);
+    }
+
+    #[test]
+    fn test_s3_config_from_env_with_map_chmod_to_acl() {
+        let s3_config = envy::prefixed("DRAY_")
+            .from_iter::<_, S3Config>(vec![
+                (String::from("DRAY_S3_BUCKET"), String::from("bucket")),
+                (
+                    String::from("DRAY_S3_MAP_CHMOD_TO_ACL"),
+                    String::from("true"),
+                ),
+            ])
+            .unwrap();
+
+        assert!(s3_config.map_chmod_to_acl);
+
+        let s3_config = envy::prefixed("DRAY_")
+            .from_iter::<_, S3Config>(vec![(
+                String::from("DRAY_S3_BUCKET"),
+                String::from("bucket"),
+            )])
+            .unwrap();
+
+        assert!(!s3_config.map_chmod_to_acl);
+    }
+
+    #[test]
+    fn test_s3_config_get_storage_class() {
+        let s3_config = S3Config {
+            storage_class: Some(String::from("STANDARD_IA")),
+            ..create_s3_config()
+        };
+
+        assert_eq!(
+            Ok(Some(StorageClass::StandardIa)),
+            s3_config.get_storage_class()
+        );
+        assert_eq!(Ok(None), create_s3_config().get_storage_class());
+    }
+
+    #[test]
+    fn test_s3_config_get_storage_class_rejects_unknown_class() {
+        let s3_config = S3Config {
+            storage_class: Some(String::from("STANDARD-IA")),
+            ..create_s3_config()
+        };
+
+        assert!(matches!(
+            s3_config.get_storage_class(),
+            Err(Error::Configuration(_))
+        ));
+    }
+
+    #[test]
+    fn test_s3_config_get_server_side_encryption() {
+        let s3_config = S3Config {
+            sse: Some(String::from("AES256")),
+            ..create_s3_config()
+        };
+
+        assert_eq!(
+            Ok(Some(ServerSideEncryption::Aes256)),
+            s3_config.get_server_side_encryption()
+        );
+
+        let s3_config = S3Config {
+            sse: Some(String::from("aws:kms")),
+            sse_kms_key_id: Some(String::from("key")),
+            ..create_s3_config()
+        };
+
+        assert_eq!(
+            Ok(Some(ServerSideEncryption::AwsKms)),
+            s3_config.get_server_side_encryption()
+        );
+        assert_eq!(Ok(None), create_s3_config().get_server_side_encryption());
+    }
+
+    #[test]
+    fn test_s3_config_get_server_side_encryption_rejects_invalid_settings() {
+        for (sse, sse_kms_key_id) in [
+            (Some("aes256"), None),
+            (Some("aws:kms"), None),
+            (Some("AES256"), Some("key")),
+            (None, Some("key")),
+        ] {
+            let s3_config = S3Config {
+                sse: sse.map(String::from),
+                sse_kms_key_id: sse_kms_key_id.map(String::from),
+                ..create_s3_config()
+            };
+
+            assert!(matches!(
+                s3_config.get_server_side_encryption(),
+                Err(Error::Configuration(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_s3_config_from_env_with_force_path_style() {
+        for (value, force_path_style) in [
+            ("auto", ForcePathStyle::Auto),
+            ("true", ForcePathStyle::True),
+            ("false", ForcePathStyle::False),
+        ] {
+            let s3_config = envy::prefixed("DRAY_")
+                .from_iter::<_, S3Config>(vec![
+                    (String::from("DRAY_S3_BUCKET"), String::from("bucket")),
+                    (
+                        String::from("DRAY_S3_FORCE_PATH_STYLE"),
+                        String::from(value),
+                    ),
+                ])
+                .unwrap();
+
+            assert_eq!(force_path_style, s3_config.force_path_style);
+        }
+
+        let s3_config = envy::prefixed("DRAY_")
+            .from_iter::<_, S3Config>(vec![(
+                String::from("DRAY_S3_BUCKET"),
+                String::from("bucket"),
+            )])
+            .unwrap();
+
+        assert_eq!(ForcePathStyle::Auto, s3_config.force_path_style);
+    }
+
+    #[test]
+    fn test_s3_config_from_env_with_aws_profile_and_region() {
+        let s3_config = envy::prefixed("DRAY_")
+            .from_iter::<_, S3Config>(vec![
+                (String::from("DRAY_S3_BUCKET"), String::from("bucket")),
+                (String::from("DRAY_AWS_PROFILE"), String::from("dev")),
+                (String::from("DRAY_AWS_REGION"), String::from("eu-west-1")),
+            ])
+            .unwrap();
+
+        assert_eq!(Some(String::from("dev")), s3_config.aws_profile);
+        assert_eq!(Some(String::from("eu-west-1")), s3_config.aws_region);
+    }
+
+    #[test]
+    fn test_s3_config_get_region() {
+        let s3_config = S3Config {
+            aws_region: Some(String::from("eu-west-1")),
+            ..create_s3_config()
+        };
+
+        assert_eq!(Some(Region::new("eu-west-1")), s3_config.get_region());
+        assert_eq!(Some(Region::new("custom")), create_s3_config().get_region());
+        assert_eq!(
+            None,
+            S3Config {
+                endpoint_region: None,
+                ..create_s3_config()
+            }
+            .get_region()
+        );
+    }
+
+    // Newer SDKs deprecate the profile file types in favor of types from aws-runtime, which is not
+    // a direct dependency.
+    #[allow(deprecated)]
+    #[tokio::test]
+    async fn test_build_config_loader_with_aws_profile_and_region() {
+        use aws_config::profile::profile_file::{ProfileFileKind, ProfileFiles};
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let credentials_path = dir.path().join("credentials");
+        std::fs::write(
+            &credentials_path,
+            "[default]\n\
+             aws_access_key_id = default_key\n\
+             aws_secret_access_key = default_secret\n\
+             [dev]\n\
+             aws_access_key_id = dev_key\n\
+             aws_secret_access_key = dev_secret\n",
+        )
+        .unwrap();
+
+        let s3_config = S3Config {
+            aws_profile: Some(String::from("dev")),
+            aws_region: Some(String::from("eu-west-1")),
+            ..create_s3_config()
+        };
+
+        let config = build_config_loader(&s3_config)
+            .unwrap()
+            .profile_files(
+                ProfileFiles::builder()
+                    .with_file(ProfileFileKind::Credentials, &credentials_path)
+                    .build(),
+            )
+            .load()
+            .await;
+
+        let credentials = config
+            .credentials_provider()
+            .unwrap()
+            .provide_credentials()
+            .await
+            .unwrap();
+
+        assert_eq!("dev_key", credentials.access_key_id());
+        assert_eq!(Some(&Region::new("eu-west-1")), config.region());
+        assert_eq!(
+            Some(&Region::new("eu-west-1")),
+            build_s3_sdk_config(&config, &s3_config).region()
+        );
+    }
+
+    #[allow(deprecated)]
+    #[tokio::test]
+    async fn test_build_config_loader_with_aws_profile_region() {
+        use aws_config::profile::profile_file::{ProfileFileKind, ProfileFiles};
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let config_path = dir.path().join("config");
+        std::fs::write(
+            &config_path,
+            "[default]\n\
+             region = us-west-2\n\
+             [profile dev]\n\
+             region = ap-southeast-2\n",
+        )
+        .unwrap();
+
+        let s3_config = S3Config {
+            endpoint_region: None,
+            aws_profile: Some(String::from("dev")),
+            ..create_s3_config()
+        };
+
+        let config = build_config_loader(&s3_config)
+            .unwrap()
+            .profile_files(
+                ProfileFiles::builder()
+                    .with_file(ProfileFileKind::Config, &config_path)
+                    .build(),
+            )
+            .load()
+            .await;
+
+        assert_eq!(Some(&Region::new("ap-southeast-2")), config.region());
+        assert_eq!(
+            Some(&Region::new("ap-southeast-2")),
+            build_s3_sdk_config(&config, &s3_config).region()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_build_s3_sdk_config_with_force_path_style_auto() {
+        assert_eq!(
+            "http://localhost:9000/bucket/key",
+            get_request_uri(Some("http://localhost:9000"), ForcePathStyle::Auto).await
+        );
+        assert_eq!(
+            "https://bucket.s3.us-east-1.amazonaws.com/key",
+            get_request_uri(None, ForcePathStyle::Auto).await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_build_s3_sdk_config_with_force_path_style_true() {
+        assert_eq!(
+            "http://localhost:9000/bucket/key",
+            get_request_uri(Some("http://localhost:9000"), ForcePathStyle::True).await
+        );
+        assert_eq!(
+            "https://s3.us-east-1.amazonaws.com/bucket/key",
+            get_request_uri(None, ForcePathStyle::True).await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_build_s3_sdk_config_with_force_path_style_false() {
+        assert_eq!(
+            "http://bucket.localhost:9000/key",
+            get_request_uri(Some("http://localhost:9000"), ForcePathStyle::False).await
+        );
+    }
+
+    #[test]
+    fn test_s3_config_validate_tls() {
+        assert_eq!(Ok(()), create_s3_config().validate_tls());
+    }
+
+    #[test]
+    fn test_s3_config_validate_tls_rejects_insecure_without_dev_override() {
+        let s3_config = S3Config {
+            tls_insecure: true,
+            ..create_s3_config()
+        };
+
+        assert!(matches!(
+            s3_config.validate_tls(),
+            Err(Error::Configuration(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_s3_storage_factory_with_insecure_dev_override() {
+        let s3_config = S3Config {
+            tls_insecure: true,
+            tls_insecure_dev_override: true,
+            ..create_s3_config()
+        };
+
+        assert_eq!(Ok(()), s3_config.validate_tls());
+        assert!(
+            S3StorageFactory::new(&s3_config, DEFAULT_MAX_HANDLES, Duration::from_secs(60))
+                .await
+                .is_ok()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_s3_storage_factory_with_missing_ca_bundle() {
+        let s3_config = S3Config {
+            ca_bundle: Some(String::from("/missing/ca.pem")),
+            ..create_s3_config()
+        };
+
+        assert!(matches!(
+            S3StorageFactory::new(&s3_config, DEFAULT_MAX_HANDLES, Duration::from_secs(60)).await,
+            Err(Error::Configuration(_))
+        ));
+    }
+
+    #[test]
+    fn test_get_default_part_size() {
+        assert_eq!(10000000, get_default_part_size());
+    }
+
+    #[test]
+    fn test_get_default_multipart_threshold() {
+        assert_eq!(10000000, get_default_multipart_threshold());
+    }
+
+    #[test]
+    fn test_get_default_endpoint_region() {
+        assert_eq!("custom", get_default_endpoint_region());
+    }
+
+    #[test]
+    fn test_get_s3_prefix_converts_unix_absolute_directory() {
+        assert_eq!(String::from("test/"), get_s3_prefix("/test"))
+    }
+
+    #[test]
+    fn test_get_s3_prefix_converts_unix_absolute_directory_with_trailing_slash() {
+        assert_eq!(String::from("test/"), get_s3_prefix("/test/"))
+    }
+
+    #[test]
+    fn test_get_s3_prefix_converts_blank_directory() {
+        assert_eq!("", get_s3_prefix(""))
+    }
+
+    #[test]
+    fn test_get_s3_prefix_converts_root_directory() {
+        assert_eq!("", get_s3_prefix("/"))
+    }
+
+    #[test]
+    fn test_get_s3_prefix_converts_relative_directory() {
+        assert_eq!("test/dir/", get_s3_prefix("test/dir"))
+    }
+
+    #[test]
+    fn test_get_s3_copy_source() {
+        assert_eq!("bucket/key", get_s3_copy_source("bucket", "key"))
+    }
+
+    #[test]
+    fn test_requires_multipart_copy_above_copy_limit() {
+        assert!(requires_multipart_copy(MAX_COPY_OBJECT_SIZE + 1));
+    }
+
+    #[test]
+    fn test_requires_multipart_copy_at_copy_limit() {
+        assert!(!requires_multipart_copy(MAX_COPY_OBJECT_SIZE));
+        assert!(!requires_multipart_copy(0));
+    }
+
+    #[test]
+    fn test_get_read_range() {
+        assert_eq!("bytes=0-9", get_read_range(0, 10));
+        assert_eq!("bytes=100-100", get_read_range(100, 1));
+    }
+
+    #[test]
+    fn test_get_copy_part_ranges() {
+        assert_eq!(
+            vec!["bytes=0-9", "bytes=10-19", "bytes=20-24"],
+            get_copy_part_ranges(25, 10)
+        );
+    }
+
+    #[test]
+    fn test_get_copy_part_ranges_with_exact_parts() {
+        assert_eq!(
+            vec!["bytes=0-9", "bytes=10-19"],
+            get_copy_part_ranges(20, 10)
+        );
+    }
+
+    #[test]
+    fn test_get_copy_part_ranges_above_copy_limit() {
+        let ranges = get_copy_part_ranges(MAX_COPY_OBJECT_SIZE + 1, COPY_PART_SIZE);
+
+        assert_eq!(6, ranges.len());
+        assert_eq!("bytes=0-1073741823", ranges[0]);
+        assert_eq!("bytes=5368709120-5368709120", ranges[5]);
+    }
+
+    #[test]
+    fn test_get_append_copy_part_ranges() {
+        let size = 2 * COPY_PART_SIZE + MIN_PART_SIZE as u64;
+
+        assert_eq!(
+            vec![
+                "bytes=0-1073741823",
+                "bytes=1073741824-2147483647",
+                "bytes=2147483648-2152726527"
+            ],
+            get_append_copy_part_ranges(size, COPY_PART_SIZE)
+        );
+    }
+
+    #[test]
+    fn test_get_append_copy_part_ranges_merges_small_final_range() {
+        let size = 2 * COPY_PART_SIZE + 1;
+
+        assert_eq!(
+            vec!["bytes=0-1073741823", "bytes=1073741824-2147483648"],
+            get_append_copy_part_ranges(size, COPY_PART_SIZE)
+        );
+    }
+
+    #[test]
+    fn test_get_append_copy_part_ranges_with_single_part() {
+        assert_eq!(
+            vec!["bytes=0-5242879"],
+            get_append_copy_part_ranges(MIN_PART_SIZE as u64, COPY_PART_SIZE)
+        );
+    }
+
+    #[test]
+    fn test_get_s3_folder_marker_appends_folder_marker() {
+        assert_eq!(
+            "folder/subfolder/_$folder$",
+            get_s3_folder_marker("/folder/subfolder")
+        );
+    }
+
+    #[test]
+    fn test_map_list_objects_to_files() {
+        let list_objects = ListObjectsV2Output::builder()
+            .common_prefixes(
+                CommonPrefix::builder()
+                    .prefix("users/test/subfolder/")
+                    .build(),
+            )
+            .contents(
+                Object::builder()
+                    .key("users/test/file.txt")
+                    .size(1)
+                    .last_modified(DateTime::from_millis(1417176009000))
+                    .build(),
+            )
+            .continuation_token("token")
+            .build();
+
+        let result = map_list_objects_to_files(list_objects, DefaultModes::default());
+
+        assert_eq!(2, result.len());
+        assert_eq!(
+            File {
+                file_name: "subfolder".to_owned(),
+                file_attributes: FileAttributes {
+                    size: None,
+                    gid: None,
+                    uid: None,
+                    permissions: Some(0o40755),
+                    atime: None,
+                    mtime: None,
+                }
+            },
+            result[1]
+        );
+        assert_eq!(
+            File {
+                file_name: "file.txt".to_owned(),
+                file_attributes: FileAttributes {
+                    size: Some(1),
+                    gid: None,
+                    uid: None,
+                    permissions: Some(0o100644),
+                    atime: None,
+                    mtime: Some(1417176009),
+                }
+            },
+            result[0]
+        );
+    }
+
+    #[test]
+    fn test_dir_handle_read_page() {
+        let mut dir_handle = DirHandle {
+            prefix: String::from("users/test/"),
+            continuation_token: None,
+            is_eof: false,
+        };
+
+        let first_page = (0..1000).fold(
+            ListObjectsV2Output::builder()
+                .common_prefixes(CommonPrefix::builder().prefix("users/test/a/").build())
+                .next_continuation_token("token"),
+            |builder, i| {
+                builder.contents(
+                    Object::builder()
+                        .key(format!("users/test/file{}.txt", i))
+                        .build(),
+                )
+            },
+        );
+        let second_page = (1000..1500).fold(
+            ListObjectsV2Output::builder()
+                .common_prefixes(CommonPrefix::builder().prefix("users/test/b/").build())
+                .continuation_token("token"),
+            |builder, i| {
+                builder.contents(
+                    Object::builder()
+                        .key(format!("users/test/file{}.txt", i))
+                        .build(),
+                )
+            },
+        );
+
+        let mut files = dir_handle.read_page(first_page.build(), DefaultModes::default());
+
+        assert_eq!(Some(String::from("token")), dir_handle.continuation_token);
+        assert!(!dir_handle.is_eof);
+
+        files.extend(dir_handle.read_page(second_page.build(), DefaultModes::default()));
+
+        assert_eq!(None, dir_handle.continuation_token);
+        assert!(dir_handle.is_eof);
+
+        let mut expected_file_names: Vec<String> =
+            (0..1500).map(|i| format!("file{}.txt", i)).collect();
+        expected_file_names.push(String::from("a"));
+        expected_file_names.push(String::from("b"));
+        expected_file_names.sort();
+
+        let mut file_names: Vec<String> = files.into_iter().map(|file| file.file_name).collect();
+        file_names.sort();
+
+        assert_eq!(expected_file_names, file_names);
+    }
+
+    #[tokio::test]
+    async fn test_open_dir_handle_normalizes_prefix() {
+        let s3_storage = create_s3_storage();
+
+        let handle = s3_storage
+            .open_dir_handle(String::from("/users/test"))
+            .await
+            .unwrap();
+        let dir_handle = s3_storage
+            .handle_manager
+            .get_dir_handle(&handle)
+            .await
+            .unwrap();
+
+        assert_eq!("users/test/", dir_handle.lock().await.prefix);
+    }
+
+    #[tokio::test]
+    async fn test_read_dir_after_eof() {
+        let s3_storage = create_s3_storage();
+
+        let handle = s3_storage
+            .open_dir_handle(String::from("/users/test"))
+            .await
+            .unwrap();
+        s3_storage
+            .handle_manager
+            .get_dir_handle(&handle)
+            .await
+            .unwrap()
+            .lock()
+            .await
+            .is_eof = true;
+
+        assert!(s3_storage.read_dir(&handle).await.unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_map_list_objects_to_files_with_directory_marker() {
+        let list_objects = ListObjectsV2Output::builder()
+            .contents(Object::builder().key("users/test/file.txt").build())
+            .contents(Object::builder().key("users/test/_$folder$").build())
+            .build();
+
+        let result = map_list_objects_to_files(list_objects, DefaultModes::default());
+
+        assert_eq!(1, result.len());
+        assert_eq!("file.txt", &result[0].file_name);
+    }
+
+    #[test]
+    fn test_dir_handle_read_page_with_single_page() {
+        let mut dir_handle = DirHandle {
+            prefix: String::from("users/test/"),
+            continuation_token: None,
+            is_eof: false,
+        };
+
+        let files = dir_handle.read_page(
+            ListObjectsV2Output::builder()
+                .contents(Object::builder().key("users/test/file.txt").build())
+                .build(),
+            DefaultModes::default(),
+        );
+
+        assert_eq!(1, files.len());
+        assert!(dir_handle.is_eof);
+    }
+
+    #[test]
+    fn test_dir_handle_read_page_with_hidden_objects_is_not_eof() {
+        let mut dir_handle = DirHandle {
+            prefix: String::from("users/test/"),
+            continuation_token: None,
+            is_eof: false,
+        };
+
+        let files = dir_handle.read_page(
+            ListObjectsV2Output::builder()
+                .contents(Object::builder().key("users/test/_$folder$").build())
+                .next_continuation_token("token")
+                .build(),
+            DefaultModes::default(),
+        );
+
+        assert!(files.is_empty());
+        assert!(!dir_handle.is_eof);
+        assert_eq!(Some(String::from("token")), dir_handle.continuation_token);
+    }
+
+    #[test]
+    fn test_map_list_objects_to_files_sorts_directories_and_files() {
+        let list_objects = ListObjectsV2Output::builder()
+            .common_prefixes(CommonPrefix::builder().prefix("users/test/b/").build())
+            .common_prefixes(CommonPrefix::builder().prefix("users/test/d/").build())
+            .contents(Object::builder().key("users/test/a.txt").build())
+            .contents(Object::builder().key("users/test/c.txt").build())
+            .contents(Object::builder().key("users/test/e.txt").build())
+            .build();
+
+        let result = map_list_objects_to_files(list_objects, DefaultModes::default());
+
+        assert_eq!(
+            vec!["a.txt", "b", "c.txt", "d", "e.txt"],
+            result
+                .iter()
+                .map(|file| file.file_name.as_str())
+                .collect::<Vec<&str>>()
+        );
+    }
+
+    #[test]
+    fn test_map_list_objects_to_files_with_only_directory_marker() {
+        let list_objects = ListObjectsV2Output::builder()
+            .contents(Object::builder().key("users/test/_$folder$").build())
+            .build();
+
+        assert!(map_list_objects_to_files(list_objects, DefaultModes::default()).is_empty());
+    }
+
+    #[test]
+    fn test_map_list_objects_to_files_with_marker_suffix() {
+        let list_objects = ListObjectsV2Output::builder()
+            .contents(Object::builder().key("users/test/report_$folder$").build())
+            .build();
+
+        let result = map_list_objects_to_files(list_objects, DefaultModes::default());
+
+        assert_eq!(1, result.len());
+        assert_eq!("report_$folder$", &result[0].file_name);
+    }
+
+    #[test]
+    fn test_is_folder_marker() {
+        assert!(is_folder_marker("users/test/_$folder$"));
+        assert!(is_folder_marker("/_$folder$"));
+        assert!(!is_folder_marker("users/test/report_$folder$"));
+        assert!(!is_folder_marker("users/test/_$folder$/file.txt"));
+    }
+
+    #[tokio::test]
+    async fn test_get_file_metadata_traces_key_translation() {
+        let s3_storage = create_s3_storage();
+        let writer = TestWriter::default();
+        let subscriber = build_debug_subscriber(writer.clone());
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        // Folder markers are hidden, so the request fails without sending a request to S3.
+        assert_eq!(
+            Err(Error::NoSuchFile),
+            s3_storage
+                .get_file_metadata(String::from("/home/test/_$folder$"))
+                .await
+        );
+
+        let output = String::from_utf8(writer.0.lock().unwrap().clone()).unwrap();
+
+        assert!(output.contains("Translated path to S3 key"));
+        assert!(output.contains("key=\"/home/test/_$folder$\""));
+    }
+
+    #[test]
+    fn test_get_s3_folder_marker_traces_key_translation() {
+        let writer = TestWriter::default();
+        let subscriber = build_debug_subscriber(writer.clone());
+
+        tracing::subscriber::with_default(subscriber, || get_s3_folder_marker("/home/test"));
+
+        let output = String::from_utf8(writer.0.lock().unwrap().clone()).unwrap();
+
+        assert!(output.contains("prefix=home/test/"));
+        assert!(output.contains("key=home/test/_$folder$"));
+    }
+
+    #[test]
+    fn test_check_not_folder_marker() {
+        assert_eq!(
+            Err(Error::PermissionDenied),
+            check_not_folder_marker("users/test/_$folder$")
+        );
+        assert_eq!(Ok(()), check_not_folder_marker("users/test/file.txt"));
+    }
+
+    #[tokio::test]
+    async fn test_create_dir_skips_markers_for_known_dirs() {
+        let (s3_storage, requests) = create_recording_s3_storage();
+
+        for dir_name in ["/a/b/c", "/a/b", "/a", "/a/b/c/"] {
+            s3_storage.create_dir(String::from(dir_name)).await.unwrap();
+        }
+
+        assert_eq!(
+            vec![String::from("PUT /bucket/a/b/c/_%24folder%24")],
+            *requests.lock().unwrap()
+        );
+
+        s3_storage.create_dir(String::from("/a/d")).await.unwrap();
+
+        assert_eq!(
+            String::from("PUT /bucket/a/d/_%24folder%24"),
+            requests.lock().unwrap()[1]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_create_dir_after_remove_dir_writes_markers() {
+        let (s3_storage, requests) = create_recording_s3_storage();
+
+        s3_storage.create_dir(String::from("/a/b")).await.unwrap();
+        s3_storage.create_dir(String::from("/c")).await.unwrap();
+        s3_storage.remove_dir(String::from("/a/b")).await.unwrap();
+        requests.lock().unwrap().clear();
+
+        for dir_name in ["/a", "/a/b", "/c"] {
+            s3_storage.create_dir(String::from(dir_name)).await.unwrap();
+        }
+
+        assert_eq!(
+            vec![
+                String::from("PUT /bucket/a/_%24folder%24"),
+                String::from("PUT /bucket/a/b/_%24folder%24"),
+            ],
+            *requests.lock().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_read_dir_lists_bucket_root() {
+        let s3_storage = create_listing_s3_storage(vec![
+            "file.txt",
+            "home/_$folder$",
+            "home/test/file.txt",
+            "other/file.txt",
+        ]);
+
+        for dir_name in ["/", ""] {
+            assert_eq!(
+                vec!["file.txt", "home", "other"],
+                read_dir_names(&s3_storage, dir_name).await,
+                "{}",
+                dir_name
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_read_dir_lists_subdirectories() {
+        let s3_storage = create_listing_s3_storage(vec![
+            "file.txt",
+            "home/_$folder$",
+            "home/test/file.txt",
+            "home/test/dir/file.txt",
+        ]);
+
+        assert_eq!(vec!["test"], read_dir_names(&s3_storage, "/home").await);
+        assert_eq!(
+            vec!["dir", "file.txt"],
+            read_dir_names(&s3_storage, "/home/test/").await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dir_operations_reject_bucket_root() {
+        let (s3_storage, requests) = create_recording_s3_storage();
+
+        assert_eq!(
+            Err(Error::PermissionDenied),
+            s3_storage.create_dir(String::from("/")).await
+        );
+        assert_eq!(
+            Err(Error::PermissionDenied),
+            s3_storage.remove_dir(String::from("/")).await
+        );
+        assert_eq!(
+            Err(Error::PermissionDenied),
+            s3_storage
+                .rename_dir(String::from("/"), String::from("/new"))
+                .await
+        );
+        assert_eq!(
+            Err(Error::PermissionDenied),
+            s3_storage
+                .rename_dir(String::from("/dir"), String::from(""))
+                .await
+        );
+        assert!(requests.lock().unwrap().is_empty());
+    }
+
+    async fn read_dir_names(s3_storage: &S3Storage, dir_name: &str) -> Vec<String> {
+        let handle = s3_storage
+            .open_dir_handle(String::from(dir_name))
+            .await
+            .unwrap();
+        let mut file_names = vec![];
+
+        loop {
+            let files = s3_storage.read_dir(&handle).await.unwrap();
+
+            if files.is_empty() {
+                return file_names;
+            }
+
+            file_names.extend(files.into_iter().map(|file| file.file_name));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rename_dir_renames_every_page() {
+        let (s3_storage, uploads) = create_multipart_s3_storage(1000);
+
+        for key in ["dir/a", "dir/b", "dir/dir/c", "dir/sub/d", "dir/e", "dirt"] {
+            insert_object(&uploads, key, key.as_bytes().to_vec(), "text/plain");
+        }
+
+        s3_storage
+            .rename_dir(String::from("/dir"), String::from("/new"))
+            .await
+            .unwrap();
+
+        let uploads = uploads.lock().unwrap();
+        let mut keys: Vec<&str> = uploads.objects.keys().map(String::as_str).collect();
+        keys.sort();
+
+        assert_eq!(
+            vec!["dirt", "new/a", "new/b", "new/dir/c", "new/e", "new/sub/d"],
+            keys
+        );
+        assert_eq!(b"dir/dir/c".to_vec(), uploads.objects["new/dir/c"]);
+    }
+
+    #[tokio::test]
+    async fn test_create_dir_after_rename_dir_writes_markers() {
+        let (s3_storage, requests) = create_recording_s3_storage();
+
+        s3_storage.create_dir(String::from("/a/b")).await.unwrap();
+        s3_storage
+            .rename_dir(String::from("/a/b"), String::from("/c"))
+            .await
+            .unwrap();
+        requests.lock().unwrap().clear();
+
+        s3_storage.create_dir(String::from("/a/b")).await.unwrap();
+
+        assert_eq!(
+            vec![String::from("PUT /bucket/a/b/_%24folder%24")],
+            *requests.lock().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_folder_markers_are_reserved() {
+        let s3_storage = create_s3_storage();
+
+        assert_eq!(
+            Err(Error::NoSuchFile),
+            s3_storage
+                .get_file_metadata(String::from("users/test/_$folder$"))
+                .await
+        );
+        assert_eq!(
+            Err(Error::PermissionDenied),
+            s3_storage
+                .open_write_handle(String::from("users/test/_$folder$"))
+                .await
+        );
+        assert_eq!(
+            Err(Error::PermissionDenied),
+            s3_storage
+                .create_dir(String::from("users/test/_$folder$"))
+                .await
+        );
+        assert_eq!(
+            Err(Error::PermissionDenied),
+            s3_storage
+                .rename(
+                    String::from("users/test/file.txt"),
+                    String::from("users/test/_$folder$")
+                )
+                .await
+        );
+    }
+
+    #[test]
+    fn test_map_list_objects_to_files_with_overlapping_names() {
+        let list_objects = ListObjectsV2Output::builder()
+            .common_prefixes(CommonPrefix::builder().prefix("users/test/b/").build())
+            .contents(Object::builder().key("users/test/b").size(1).build())
+            .contents(Object::builder().key("users/test/c").size(1).build())
+            .build();
+
+        let result = map_list_objects_to_files(list_objects, DefaultModes::default());
+
+        assert_eq!(2, result.len());
+        assert_eq!("b", result[0].file_name);
+        assert_eq!(Some(0o40755), result[0].file_attributes.permissions);
+        assert_eq!(None, result[0].file_attributes.size);
+        assert_eq!("c", result[1].file_name);
+        assert_eq!(Some(0o100644), result[1].file_attributes.permissions);
+    }
+
+    #[test]
+    fn test_map_list_objects_to_files_with_default_modes() {
+        let list_objects = ListObjectsV2Output::builder()
+            .common_prefixes(CommonPrefix::builder().prefix("users/test/a/").build())
+            .contents(Object::builder().key("users/test/b").build())
+            .build();
+        let default_modes = DefaultModes {
+            file_mode: 0o600,
+            dir_mode: 0o700,
+        };
+
+        let result = map_list_objects_to_files(list_objects, default_modes);
+
+        assert_eq!(Some(0o40700), result[0].file_attributes.permissions);
+        assert_eq!(Some(0o100600), result[1].file_attributes.permissions);
+    }
+
+    #[test]
+    fn test_map_list_objects_to_files_with_symlink_sentinel() {
+        let list_objects = ListObjectsV2Output::builder()
+            .contents(
+                Object::builder()
+                    .key("users/test/link.draylink")
+                    .size(20)
+                    .build(),
+            )
+            .build();
+
+        let result = map_list_objects_to_files(list_objects, DefaultModes::default());
+
+        assert_eq!(1, result.len());
+        assert_eq!("link", &result[0].file_name);
+        assert!(result[0].file_attributes.is_symlink());
+    }
+
+    #[test]
+    fn test_map_list_objects_to_files_with_missing_data() {
+        let list_objects = ListObjectsV2Output::builder().build();
+
+        let result = map_list_objects_to_files(list_objects, DefaultModes::default());
+
+        assert_eq!(0, result.len());
+    }
+
+    #[test]
+    fn test_map_object_to_file_with_missing_data() {
+        let object = Object::builder().build();
+
+        assert_eq!(
+            File {
+                file_name: "".to_owned(),
+                file_attributes: FileAttributes {
+                    size: None,
+                    gid: None,
+                    uid: None,
+                    permissions: Some(0o100644),
+                    atime: None,
+                    mtime: None,
+                }
+            },
+            map_object_to_file(&object, DEFAULT_FILE_MODE)
+        );
+    }
+
+    #[test]
+    fn test_map_list_objects_to_directory() {
+        let list_objects = ListObjectsV2Output::builder()
+            .prefix("directory/subdirectory/")
+            .contents(Object::builder().build())
+            .build();
+
+        let directory = map_list_objects_to_directory(list_objects, DEFAULT_DIR_MODE);
+
+        assert_eq!(
+            Ok(File {
+                file_name: "subdirectory".to_string(),
+                file_attributes: FileAttributes {
+                    size: None,
+                    gid: None,
+                    uid: None,
+                    permissions: Some(0o40755),
+                    atime: None,
+                    mtime: None,
+                }
+            }),
+            directory
+        );
+    }
+
+    #[test]
+    fn test_map_list_objects_to_directory_with_none_contents() {
+        let list_objects = ListObjectsV2Output::builder()
+            .prefix("directory/subdirectory/")
+            .set_contents(None)
+            .build();
+
+        let directory = map_list_objects_to_directory(list_objects, DEFAULT_DIR_MODE);
+
+        assert_eq!(Err(Error::NoSuchFile), directory);
+    }
+
+    #[test]
+    fn test_map_list_objects_to_directory_with_0_contents() {
+        let list_objects = ListObjectsV2Output::builder()
+            .prefix("directory/subdirectory/")
+            .set_contents(Some(vec![]))
+            .build();
+
+        let directory = map_list_objects_to_directory(list_objects, DEFAULT_DIR_MODE);
+
+        assert_eq!(Err(Error::NoSuchFile), directory);
+    }
+
+    #[test]
+    fn test_map_list_objects_to_directory_with_no_prefix() {
+        let list_objects = ListObjectsV2Output::builder()
+            .set_prefix(None)
+            .set_contents(Some(vec![]))
+            .build();
+
+        let directory = map_list_objects_to_directory(list_objects, DEFAULT_DIR_MODE);
+
+        assert_eq!(Err(Error::NoSuchFile), directory);
+    }
+
+    #[test]
+    fn test_map_prefix_to_file_with_missing_data() {
+        let prefix = CommonPrefix::builder().build();
+
+        assert_eq!(
+            File {
+                file_name: "".to_owned(),
+                file_attributes: FileAttributes {
+                    size: None,
+                    gid: None,
+                    uid: None,
+                    permissions: Some(0o40755),
+                    atime: None,
+                    mtime: None,
+                }
+            },
+            map_prefix_to_file(&prefix, DEFAULT_DIR_MODE)
+        );
+    }
+
+    #[test]
+    fn test_map_head_object_to_file() {
+        let head_object = HeadObjectOutput::builder().build();
+
+        assert_eq!(
+            File {
+                file_name: "file".to_owned(),
+                file_attributes: FileAttributes {
+                    size: None,
+                    gid: None,
+                    uid: None,
+                    permissions: Some(0o100644),
+                    atime: None,
+                    mtime: None,
+                }
+            },
+            map_head_object_to_file("file", &head_object, DEFAULT_FILE_MODE)
+        );
+    }
+
+    #[test]
+    fn test_map_head_object_to_file_with_default_mode() {
+        let head_object = HeadObjectOutput::builder().build();
+
+        let file = map_head_object_to_file("file", &head_object, 0o600);
+
+        assert_eq!(Some(0o100600), file.file_attributes.permissions);
+    }
+
+    #[test]
+    fn test_map_list_objects_to_directory_with_default_mode() {
+        let list_objects = ListObjectsV2Output::builder()
+            .prefix("users/test/dir/")
+            .contents(Object::builder().key("users/test/dir/file").build())
+            .build();
+
+        let directory = map_list_objects_to_directory(list_objects, 0o700).unwrap();
+
+        assert_eq!(Some(0o40700), directory.file_attributes.permissions);
+    }
+
+    #[test]
+    fn test_map_head_object_to_file_with_metadata() {
+        let head_object = HeadObjectOutput::builder()
+            .content_length(100)
+            .metadata(METADATA_PERMISSIONS, "640")
+            .metadata(METADATA_UID, "1000")
+            .metadata(METADATA_GID, "2000")
+            .build();
+
+        assert_eq!(
+            File {
+                file_name: "file".to_owned(),
+                file_attributes: FileAttributes {
+                    size: Some(100),
+                    gid: Some(2000),
+                    uid: Some(1000),
+                    permissions: Some(0o100640),
+                    atime: None,
+                    mtime: None,
+                }
+            },
+            map_head_object_to_file("dir/file", &head_object, DEFAULT_FILE_MODE)
+        );
+    }
+
+    #[test]
+    fn test_map_head_object_to_file_with_last_modified() {
+        let head_object = HeadObjectOutput::builder()
+            .last_modified(DateTime::from_millis(1417176009000))
+            .build();
+
+        assert_eq!(
+            Some(1417176009),
+            map_head_object_to_file("file", &head_object, DEFAULT_FILE_MODE)
+                .file_attributes
+                .mtime
+        );
+    }
+
+    #[test]
+    fn test_map_head_object_to_file_without_last_modified() {
+        let head_object = HeadObjectOutput::builder().build();
+
+        assert_eq!(
+            None,
+            map_head_object_to_file("file", &head_object, DEFAULT_FILE_MODE)
+                .file_attributes
+                .mtime
+        );
+    }
+
+    #[test]
+    fn test_map_head_object_to_file_with_invalid_metadata() {
+        let head_object = HeadObjectOutput::builder()
+            .metadata(METADATA_PERMISSIONS, "rwx")
+            .metadata(METADATA_UID, "-1")
+            .metadata("other", "value")
+            .build();
+
+        let file = map_head_object_to_file("file", &head_object, DEFAULT_FILE_MODE);
+
+        assert_eq!(Some(0o100644), file.file_attributes.permissions);
+        assert_eq!(None, file.file_attributes.uid);
+        assert_eq!(None, file.file_attributes.gid);
+    }
+
+    #[test]
+    fn test_map_head_object_to_file_round_trips_file_attributes() {
+        let file_attributes = FileAttributes {
+            permissions: Some(0o100600),
+            uid: Some(1000),
+            gid: Some(2000),
+            ..Default::default()
+        };
+
+        let head_object = HeadObjectOutput::builder()
+            .set_metadata(Some(map_file_attributes_to_metadata(
+                HashMap::new(),
+                &file_attributes,
+            )))
+            .build();
+
+        assert_eq!(
+            file_attributes,
+            map_head_object_to_file("file", &head_object, DEFAULT_FILE_MODE).file_attributes
+        );
+    }
+
+    #[test]
+    fn test_map_file_attributes_to_metadata() {
+        let file_attributes = FileAttributes {
+            permissions: Some(0o100600),
+            uid: Some(1000),
+            gid: Some(2000),
+            ..Default::default()
+        };
+
+        let metadata = map_file_attributes_to_metadata(HashMap::new(), &file_attributes);
+
+        assert_eq!(3, metadata.len());
+        assert_eq!("600", metadata[METADATA_PERMISSIONS]);
+        assert_eq!("1000", metadata[METADATA_UID]);
+        assert_eq!("2000", metadata[METADATA_GID]);
+    }
+
+    #[test]
+    fn test_map_file_attributes_to_metadata_preserves_existing_metadata() {
+        let existing_metadata = HashMap::from([
+            ("other".to_string(), "value".to_string()),
+            (METADATA_PERMISSIONS.to_string(), "777".to_string()),
+            (METADATA_UID.to_string(), "1000".to_string()),
+        ]);
+
+        let file_attributes = FileAttributes {
+            permissions: Some(0o644),
+            ..Default::default()
+        };
+
+        let metadata = map_file_attributes_to_metadata(existing_metadata, &file_attributes);
+
+        assert_eq!(3, metadata.len());
+        assert_eq!("value", metadata["other"]);
+        assert_eq!("644", metadata[METADATA_PERMISSIONS]);
+        assert_eq!("1000", metadata[METADATA_UID]);
+    }
+
+    #[test]
+    fn test_map_create_multipart_response_to_upload_id() {
+        let multipart_response = CreateMultipartUploadOutput::builder()
+            .upload_id("id")
+            .key("key")
+            .build();
+
+        assert_eq!(
+            Ok(String::from("id")),
+            map_create_multipart_response_to_upload_id(multipart_response)
+        );
+    }
+
+    #[test]
+    fn test_map_create_multipart_response_to_upload_id_with_missing_multipart_id() {
+        let multipart_response = CreateMultipartUploadOutput::builder().key("key").build();
+
+        assert!(map_create_multipart_response_to_upload_id(multipart_response).is_err());
+    }
+
+    #[test]
+    fn test_write_handle_new() {
+        let write_handle = WriteHandle::new(String::from("key"));
+
+        assert_eq!("key", &write_handle.key);
+        assert!(!write_handle.is_multipart());
+        assert_eq!(0, write_handle.completed_parts.len());
+        assert_eq!(0, write_handle.offset);
+    }
+
+    #[test]
+    fn test_write_handle_below_threshold_uses_single_put() {
+        let mut write_handle = create_write_handle();
+
+        write_handle
+            .write(0, bytes::Bytes::from("abc"), DEFAULT_PART_SIZE)
+            .unwrap();
+
+        assert!(!write_handle.should_upload_part(2, 10));
+        assert!(!write_handle.should_use_multipart(10));
+    }
+
+    #[test]
+    fn test_write_handle_over_threshold_uses_multipart() {
+        let mut write_handle = create_write_handle();
+
+        write_handle
+            .write(0, bytes::Bytes::from("abcdefghijk"), DEFAULT_PART_SIZE)
+            .unwrap();
+
+        assert!(write_handle.should_upload_part(2, 10));
+        assert!(write_handle.should_use_multipart(10));
+    }
+
+    #[test]
+    fn test_write_handle_waits_for_threshold_before_uploading_parts() {
+        let mut write_handle = create_write_handle();
+
+        write_handle
+            .write(0, bytes::Bytes::from("abcde"), DEFAULT_PART_SIZE)
+            .unwrap();
+
+        assert!(!write_handle.should_upload_part(2, 10));
+    }
+
+    #[test]
+    fn test_write_handle_with_multipart_upload_always_uses_multipart() {
+        let mut write_handle = create_write_handle();
+        write_handle.upload_id = Some(String::from("id"));
+
+        write_handle
+            .write(0, bytes::Bytes::from("abc"), DEFAULT_PART_SIZE)
+            .unwrap();
+
+        assert!(write_handle.should_upload_part(2, 10));
+        assert!(write_handle.should_use_multipart(10));
+    }
+
+    #[test]
+    fn test_write_handle_should_flush_part() {
+        let mut write_handle = create_write_handle();
+
+        write_handle
+            .write(0, bytes::Bytes::from("abcde"), DEFAULT_PART_SIZE)
+            .unwrap();
+
+        assert!(write_handle.should_flush_part(5, 5));
+        assert!(!write_handle.should_flush_part(6, 5));
+        assert!(!write_handle.should_flush_part(5, 10));
+
+        write_handle.upload_id = Some(String::from("id"));
+
+        assert!(write_handle.should_flush_part(5, 10));
+    }
+
+    #[tokio::test]
+    async fn test_fsync_with_missing_handle() {
+        let s3_storage = create_s3_storage();
+
+        assert!(s3_storage.fsync("missing").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_get_authorized_keys_fingerprints_from_cache() {
+        let s3_storage = create_s3_storage();
+
+        s3_storage
+            .authorized_keys_cache
+            .insert("test", vec![String::from("fingerprint")])
+            .await;
+
+        assert_eq!(
+            Ok(vec![String::from("fingerprint")]),
+            s3_storage.get_authorized_keys_fingerprints("test").await
+        );
+    }
+
+    #[test]
+    fn test_write_handle_write_in_order() {
+        let mut write_handle = create_write_handle();
+
+        write_handle
+            .write(0, bytes::Bytes::from("abc"), DEFAULT_PART_SIZE)
+            .unwrap();
+        write_handle
+            .write(3, bytes::Bytes::from("def"), DEFAULT_PART_SIZE)
+            .unwrap();
+
+        assert_eq!(b"abcdef", write_handle.buffer.as_slice());
+        assert_eq!(6, write_handle.offset);
+        assert_eq!(Ok(()), write_handle.check_complete());
+    }
+
+    #[test]
+    fn test_write_handle_write_out_of_order_reassembles_data() {
+        let mut write_handle = create_write_handle();
+
+        write_handle
+            .write(6, bytes::Bytes::from("ghi"), DEFAULT_PART_SIZE)
+            .unwrap();
+        write_handle
+            .write(3, bytes::Bytes::from("def"), DEFAULT_PART_SIZE)
+            .unwrap();
+        assert!(write_handle.buffer.is_empty());
+
+        write_handle
+            .write(0, bytes::Bytes::from("abc"), DEFAULT_PART_SIZE)
+            .unwrap();
+
+        assert_eq!(b"abcdefghi", write_handle.buffer.as_slice());
+        assert_eq!(9, write_handle.offset);
+        assert_eq!(Ok(()), write_handle.check_complete());
+    }
+
+    #[test]
+    fn test_write_handle_write_with_gap_fails_to_complete() {
+        let mut write_handle = create_write_handle();
+
+        write_handle
+            .write(0, bytes::Bytes::from("abc"), DEFAULT_PART_SIZE)
+            .unwrap();
+        write_handle
+            .write(6, bytes::Bytes::from("ghi"), DEFAULT_PART_SIZE)
+            .unwrap();
+
+        assert_eq!(b"abc", write_handle.buffer.as_slice());
+        assert!(write_handle.check_complete().is_err());
+    }
+
+    #[test]
+    fn test_write_handle_write_behind_offset_fails() {
+        let mut write_handle = create_write_handle();
+
+        write_handle
+            .write(0, bytes::Bytes::from("abc"), DEFAULT_PART_SIZE)
+            .unwrap();
+
+        assert!(write_handle
+            .write(1, bytes::Bytes::from("b"), DEFAULT_PART_SIZE)
+            .is_err());
+    }
+
+    #[test]
+    fn test_write_handle_with_data_overwrites_in_place() {
+        let mut write_handle = WriteHandle::with_data(String::from("key"), b"hello world".to_vec());
+
+        write_handle
+            .write(6, bytes::Bytes::from("there"), DEFAULT_PART_SIZE)
+            .unwrap();
+        write_handle
+            .write(0, bytes::Bytes::from("H"), DEFAULT_PART_SIZE)
+            .unwrap();
+
+        assert_eq!(b"Hello there", write_handle.buffer.as_slice());
+        assert!(write_handle.check_complete().is_ok());
+    }
+
+    #[test]
+    fn test_write_handle_with_data_overwrite_extends_data() {
+        let mut write_handle = WriteHandle::with_data(String::from("key"), b"abc".to_vec());
+
+        write_handle
+            .write(2, bytes::Bytes::from("CDE"), DEFAULT_PART_SIZE)
+            .unwrap();
+        write_handle
+            .write(5, bytes::Bytes::from("f"), DEFAULT_PART_SIZE)
+            .unwrap();
+
+        assert_eq!(b"abCDEf", write_handle.buffer.as_slice());
+        assert_eq!(6, write_handle.offset);
+    }
+
+    #[test]
+    fn test_write_handle_with_data_does_not_upload_parts() {
+        let write_handle = WriteHandle::with_data(String::from("key"), vec![0; 100]);
+
+        assert!(!write_handle.should_upload_part(10, 10));
+        assert!(!write_handle.should_flush_part(10, 10));
+        assert!(write_handle.should_use_multipart(10));
+    }
+
+    #[test]
+    fn test_write_handle_for_append_ignores_offsets() {
+        let mut write_handle = WriteHandle::for_append(String::from("key"), b"abc".to_vec());
+
+        write_handle
+            .write(0, bytes::Bytes::from("def"), DEFAULT_PART_SIZE)
+            .unwrap();
+        write_handle
+            .write(100, bytes::Bytes::from("ghi"), DEFAULT_PART_SIZE)
+            .unwrap();
+
+        assert_eq!(b"abcdefghi", write_handle.buffer.as_slice());
+        assert_eq!(9, write_handle.offset);
+        assert!(write_handle.check_complete().is_ok());
+    }
+
+    #[test]
+    fn test_write_handle_write_with_overlap_fails() {
+        let mut write_handle = create_write_handle();
+
+        write_handle
+            .write(2, bytes::Bytes::from("cd"), DEFAULT_PART_SIZE)
+            .unwrap();
+
+        assert!(write_handle
+            .write(0, bytes::Bytes::from("abc"), DEFAULT_PART_SIZE)
+            .is_err());
+    }
+
+    #[test]
+    fn test_write_handle_write_with_repeated_pending_offset_fails() {
+        let mut write_handle = create_write_handle();
+
+        write_handle
+            .write(3, bytes::Bytes::from("def"), DEFAULT_PART_SIZE)
+            .unwrap();
+
+        assert!(matches!(
+            write_handle.write(3, bytes::Bytes::from("xyz"), DEFAULT_PART_SIZE),
+            Err(Error::Failure(_))
+        ));
+
+        write_handle
+            .write(0, bytes::Bytes::from("abc"), DEFAULT_PART_SIZE)
+            .unwrap();
+
+        assert_eq!(b"abcdef", write_handle.buffer.as_slice());
+    }
+
+    #[test]
+    fn test_write_handle_write_limits_pending_size() {
+        let mut write_handle = create_write_handle();
+
+        write_handle.write(3, bytes::Bytes::from("def"), 6).unwrap();
+        write_handle
+            .write(100, bytes::Bytes::from("ghi"), 6)
+            .unwrap();
+
+        assert!(matches!(
+            write_handle.write(200, bytes::Bytes::from("j"), 6),
+            Err(Error::Failure(_))
+        ));
+
+        // Pending writes that are applied no longer count toward the limit.
+        write_handle.write(0, bytes::Bytes::from("abc"), 6).unwrap();
+        write_handle.write(200, bytes::Bytes::from("j"), 6).unwrap();
+
+        assert_eq!(4, write_handle.pending_size);
+    }
+
+    #[test]
+    fn test_write_handle_set_file_attributes_merges_attributes() {
+        let mut write_handle = create_write_handle();
+
+        write_handle.set_file_attributes(FileAttributes {
+            permissions: Some(0o600),
+            mtime: Some(1000),
+            ..Default::default()
+        });
+        write_handle.set_file_attributes(FileAttributes {
+            mtime: Some(2000),
+            size: Some(10),
+            ..Default::default()
+        });
+
+        assert_eq!(
+            Some(FileAttributes {
+                permissions: Some(0o600),
+                mtime: Some(2000),
+                size: Some(10),
+                ..Default::default()
+            }),
+            write_handle.file_attributes
+        );
+    }
+
+    #[tokio::test]
+    async fn test_set_handle_metadata_stores_attributes_on_write_handle() {
+        let s3_storage = create_s3_storage();
+
+        let handle = s3_storage
+            .handle_manager
+            .create_write_handle(create_write_handle())
+            .await
+            .unwrap();
+
+        s3_storage
+            .set_handle_metadata(
+                &handle,
+                FileAttributes {
+                    permissions: Some(0o600),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+
+        let write_handle = s3_storage
+            .handle_manager
+            .get_write_handle(&handle)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            Some(0o600),
+            write_handle
+                .lock()
+                .await
+                .file_attributes
+                .as_ref()
+                .and_then(|file_attributes| file_attributes.permissions)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_set_handle_metadata_ignores_read_handle() {
+        let s3_storage = create_s3_storage();
+
+        let handle = s3_storage
+            .handle_manager
+            .create_read_handle(ReadHandle::new(
+                "key".to_string(),
+                Box::pin(tokio::io::empty()),
+                DEFAULT_READ_AHEAD_SIZE,
+            ))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            Ok(()),
+            s3_storage
+                .set_handle_metadata(&handle, FileAttributes::default())
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_set_handle_metadata_ignores_dir_handle() {
+        let s3_storage = create_s3_storage();
+
+        let handle = s3_storage
+            .handle_manager
+            .create_dir_handle(DirHandle {
+                prefix: "prefix/".to_string(),
+                continuation_token: None,
+                is_eof: false,
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(
+            Ok(()),
+            s3_storage
+                .set_handle_metadata(&handle, FileAttributes::default())
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_set_handle_metadata_with_missing_handle() {
+        let s3_storage = create_s3_storage();
+
+        assert!(s3_storage
+            .set_handle_metadata("missing_handle", FileAttributes::default())
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn test_map_err_maps_not_found_to_no_such_file() {
+        assert_eq!(
+            Error::NoSuchFile,
+            map_err(aws_sdk_s3::Error::NotFound(NotFound::builder().build()))
+        );
+    }
+
+    #[test]
+    fn test_map_err_maps_missing_key_to_no_such_file() {
+        assert_eq!(
+            Error::NoSuchFile,
+            map_err(aws_sdk_s3::Error::NoSuchKey(NoSuchKey::builder().build()))
+        );
+    }
+
+    #[test]
+    fn test_map_err_maps_unmodeled_missing_key_to_no_such_file() {
+        let get_object_error = GetObjectError::generic(
+            aws_sdk_s3::error::ErrorMetadata::builder()
+                .code("NoSuchKey")
+                .build(),
+        );
+
+        assert_eq!(
+            Error::NoSuchFile,
+            map_err(aws_sdk_s3::Error::from(get_object_error))
+        );
+    }
+
+    #[test]
+    fn test_map_err_maps_unmodeled_error_to_storage_error() {
+        let get_object_error = GetObjectError::generic(
+            aws_sdk_s3::error::ErrorMetadata::builder()
+                .code("InternalError")
+                .build(),
+        );
+
+        assert!(matches!(
+            map_err(aws_sdk_s3::Error::from(get_object_error)),
+            Error::Storage(_)
+        ));
+    }
+
+    #[test]
+    fn test_map_error_maps_generic_error_to_storage_error() {
+        assert_eq!(
+            Error::Storage("BucketAlreadyOwnedByYou".to_string()),
+            map_err(aws_sdk_s3::Error::BucketAlreadyOwnedByYou(
+                BucketAlreadyOwnedByYou::builder().build()
+            ))
+        );
     }
-}
 
-fn map_list_objects_to_directory(
-    list_objects: aws_sdk_s3::operation::list_objects_v2::ListObjectsV2Output,
-) -> Result<File, Error> {
-    let contents = list_objects.contents.unwrap_or_default();
+    #[tokio::test]
+    async fn test_read_stream_read_with_read_ahead() {
+        let data: Vec<u8> = (0..10000).map(|index| (index % 251) as u8).collect();
 
-    let prefix = match list_objects.prefix {
-        Some(prefix) => prefix,
-        None => return Err(Error::NoSuchFile),
-    };
+        // Each chunk is returned by a separate read, like the chunks of an S3 response body.
+        let async_read = std::io::Cursor::new(data[..3000].to_vec())
+            .chain(std::io::Cursor::new(data[3000..6000].to_vec()))
+            .chain(std::io::Cursor::new(data[6000..].to_vec()));
 
-    if contents.is_empty() {
-        Err(Error::NoSuchFile)
-    } else {
-        Ok(map_prefix_to_file(
-            &CommonPrefix::builder().prefix(prefix).build(),
-        ))
-    }
-}
+        let mut read_stream = ReadStream::new(Box::pin(async_read), 4096);
 
-fn map_prefix_to_file(prefix: &CommonPrefix) -> File {
-    let prefix = match prefix.prefix {
-        Some(ref prefix) => {
-            let mut prefix = prefix.to_string();
-            prefix.pop(); // strip trailing /
-            format!("/{}", prefix)
+        let mut read_data = Vec::new();
+
+        loop {
+            let chunk = read_stream.read(700).await.unwrap();
+
+            if chunk.is_empty() {
+                break;
+            }
+
+            assert!(chunk.len() <= 700);
+            read_data.extend(chunk);
         }
-        None => "".to_owned(),
-    };
 
-    let mut prefix_pieces = prefix.rsplit('/');
-    let file_name = prefix_pieces.next().unwrap_or("");
+        assert_eq!(data, read_data);
+        assert!(read_stream.read(700).await.unwrap().is_empty());
+    }
 
-    File {
-        file_name: file_name.to_string(),
-        file_attributes: FileAttributes {
-            size: None,
-            uid: None,
-            gid: None,
-            permissions: Some(0o40777),
-            atime: None,
-            mtime: None,
-        },
+    #[tokio::test]
+    async fn test_read_stream_read_prefetches_read_ahead_size() {
+        let async_read = std::io::Cursor::new(vec![1; 100])
+            .chain(std::io::Cursor::new(vec![2; 100]))
+            .chain(std::io::Cursor::new(vec![3; 100]));
+
+        let mut read_stream = ReadStream::new(Box::pin(async_read), 200);
+
+        assert_eq!(vec![1; 10], read_stream.read(10).await.unwrap());
+        assert_eq!(190, read_stream.read_ahead.len());
+        assert!(!read_stream.is_eof);
     }
-}
 
-fn map_head_object_to_file(
-    key: &str,
-    head_object: &aws_sdk_s3::operation::head_object::HeadObjectOutput,
-) -> File {
-    let mut key_pieces = key.rsplit('/');
-    let file_name = key_pieces.next().unwrap_or("");
+    #[tokio::test]
+    async fn test_read_stream_read_larger_than_read_ahead_size() {
+        let async_read = std::io::Cursor::new(vec![1; 30])
+            .chain(std::io::Cursor::new(vec![2; 30]))
+            .chain(std::io::Cursor::new(vec![3; 30]));
 
-    File {
-        file_name: file_name.to_string(),
-        file_attributes: FileAttributes {
-            size: head_object
-                .content_length
-                .map(|content_length| content_length as u64),
-            uid: None,
-            gid: None,
-            permissions: Some(0o100777),
-            atime: None,
-            mtime: None,
-        },
+        let mut read_stream = ReadStream::new(Box::pin(async_read), 10);
+
+        let mut expected_data = vec![1; 30];
+        expected_data.extend(vec![2; 20]);
+
+        assert_eq!(expected_data, read_stream.read(50).await.unwrap());
     }
-}
 
-fn map_create_multipart_response_to_write_handle(
-    create_multipart_response: aws_sdk_s3::operation::create_multipart_upload::CreateMultipartUploadOutput,
-) -> Result<WriteHandle, Error> {
-    let upload_id = match create_multipart_response.upload_id {
-        Some(upload_id) => Ok(upload_id),
-        None => Err(Error::Storage("Missing upload id.".to_string())),
-    }?;
-
-    let key = match create_multipart_response.key {
-        Some(key) => Ok(key),
-        None => Err(Error::Storage("Missing key.".to_string())),
-    }?;
-
-    Ok(WriteHandle {
-        key,
-        upload_id,
-        completed_parts: Vec::new(),
-        buffer: Vec::with_capacity(5000000),
-    })
-}
+    #[tokio::test]
+    async fn test_read_stream_seek_within_read_ahead() {
+        let async_read = std::io::Cursor::new((0..100).collect::<Vec<u8>>());
 
-fn get_default_endpoint_region() -> String {
-    String::from("custom")
-}
+        let mut read_stream = ReadStream::new(Box::pin(async_read), 50);
 
-fn map_err(s3_sdk_error: aws_sdk_s3::Error) -> Error {
-    match s3_sdk_error {
-        aws_sdk_s3::Error::NoSuchKey(_) => Error::NoSuchFile,
-        aws_sdk_s3::Error::NotFound(_) => Error::NoSuchFile,
-        _ => Error::Storage(s3_sdk_error.to_string()),
+        assert_eq!(vec![0, 1], read_stream.read(2).await.unwrap());
+        assert!(read_stream.seek(2));
+        assert!(read_stream.seek(10));
+        assert_eq!(vec![10, 11], read_stream.read(2).await.unwrap());
+        assert_eq!(12, read_stream.position);
     }
-}
 
-#[cfg(test)]
-mod test {
-    use aws_sdk_s3::{
-        operation::{
-            create_multipart_upload::CreateMultipartUploadOutput, head_object::HeadObjectOutput,
-            list_objects_v2::ListObjectsV2Output,
-        },
-        primitives::DateTime,
-        types::error::{BucketAlreadyOwnedByYou, NoSuchKey, NotFound},
-    };
+    #[tokio::test]
+    async fn test_read_stream_seek_outside_read_ahead() {
+        let async_read = std::io::Cursor::new((0..100).collect::<Vec<u8>>());
 
-    use super::*;
+        let mut read_stream = ReadStream::new(Box::pin(async_read), 50);
 
-    #[test]
-    fn test_get_home_returns_users_home_directory() {
-        assert_eq!("/home/test", get_home("test"));
+        assert_eq!(vec![0, 1], read_stream.read(2).await.unwrap());
+        assert!(!read_stream.seek(0));
+        assert!(!read_stream.seek(51));
+        assert_eq!(2, read_stream.position);
+    }
+
+    #[tokio::test]
+    async fn test_read_stream_reset() {
+        let async_read = std::io::Cursor::new((0..100).collect::<Vec<u8>>());
+
+        let mut read_stream = ReadStream::new(Box::pin(async_read), 50);
+        read_stream.read(2).await.unwrap();
+
+        read_stream.reset(Box::pin(std::io::Cursor::new(vec![60, 61])), 60);
+
+        assert_eq!(vec![60, 61], read_stream.read(10).await.unwrap());
+        assert_eq!(62, read_stream.position);
+        assert!(read_stream.read(10).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_read_data_past_end_of_object() {
+        let s3_storage = create_s3_storage();
+
+        let handle = s3_storage
+            .handle_manager
+            .create_read_handle(ReadHandle {
+                object: Arc::new(ReadObject {
+                    key: "key".to_string(),
+                    size: Some(4),
+                    e_tag: None,
+                }),
+                ..ReadHandle::new(
+                    "key".to_string(),
+                    Box::pin(std::io::Cursor::new(b"data".to_vec())),
+                    DEFAULT_READ_AHEAD_SIZE,
+                )
+            })
+            .await
+            .unwrap();
+
+        // The offset is past the end of the object, so no request is sent to S3.
+        assert!(s3_storage
+            .read_data(&handle, 10, 4)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_read_stream_is_past_end() {
+        let async_read = std::io::Cursor::new((0..10).collect::<Vec<u8>>());
+
+        let mut read_stream = ReadStream::new(Box::pin(async_read), 50);
+
+        assert!(!read_stream.is_past_end(10));
+        assert_eq!(vec![0, 1], read_stream.read(2).await.unwrap());
+        assert!(!read_stream.is_past_end(9));
+        assert!(read_stream.is_past_end(10));
+        assert!(read_stream.is_past_end(100));
+    }
+
+    #[tokio::test]
+    async fn test_read_data_after_end_of_object() {
+        let s3_storage = create_s3_storage();
+
+        let handle = s3_storage
+            .handle_manager
+            .create_read_handle(ReadHandle::new(
+                "key".to_string(),
+                Box::pin(std::io::Cursor::new(b"data".to_vec())),
+                DEFAULT_READ_AHEAD_SIZE,
+            ))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            b"data".to_vec(),
+            s3_storage.read_data(&handle, 0, 10).await.unwrap()
+        );
+
+        // The size of the object is not known, but the stream has ended, so no request is sent to
+        // S3 for these reads.
+        for offset in [4, 4, 100] {
+            assert!(s3_storage
+                .read_data(&handle, offset, 10)
+                .await
+                .unwrap()
+                .is_empty());
+        }
     }
 
     #[test]
-    fn test_get_default_endpoint_region() {
-        assert_eq!("custom", get_default_endpoint_region());
+    fn test_is_invalid_range() {
+        let invalid_range_error = GetObjectError::generic(
+            aws_sdk_s3::error::ErrorMetadata::builder()
+                .code("InvalidRange")
+                .build(),
+        );
+        let internal_error = GetObjectError::generic(
+            aws_sdk_s3::error::ErrorMetadata::builder()
+                .code("InternalError")
+                .build(),
+        );
+
+        assert!(is_invalid_range(&aws_sdk_s3::Error::from(
+            invalid_range_error
+        )));
+        assert!(!is_invalid_range(&aws_sdk_s3::Error::from(internal_error)));
+    }
+
+    #[tokio::test]
+    async fn test_read_data_does_not_hold_handle_lock() {
+        let s3_storage = create_s3_storage();
+
+        let handle = s3_storage
+            .handle_manager
+            .create_read_handle(ReadHandle::new(
+                "key".to_string(),
+                Box::pin(std::io::Cursor::new(b"data".to_vec())),
+                DEFAULT_READ_AHEAD_SIZE,
+            ))
+            .await
+            .unwrap();
+
+        let read_handle = s3_storage
+            .handle_manager
+            .get_read_handle(&handle)
+            .await
+            .unwrap();
+        let read_stream = read_handle.lock().await.stream.clone();
+        let read_stream = read_stream.lock().await;
+
+        // A read in progress holds the stream, but the handle itself remains available.
+        assert!(read_handle.try_lock().is_ok());
+        drop(read_stream);
+
+        assert_eq!(
+            b"da".to_vec(),
+            s3_storage.read_data(&handle, 0, 2).await.unwrap()
+        );
+        assert_eq!(
+            b"ta".to_vec(),
+            s3_storage.read_data(&handle, 2, 2).await.unwrap()
+        );
+    }
+
+    #[derive(Clone, Default)]
+    struct TestWriter(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for TestWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn build_debug_subscriber(writer: TestWriter) -> impl tracing::Subscriber {
+        tracing_subscriber::registry().with(
+            tracing_subscriber::fmt::layer()
+                .with_ansi(false)
+                .with_writer(move || writer.clone())
+                .with_filter(LevelFilter::DEBUG),
+        )
+    }
+
+    /// Sends a request with a client built from the config and returns the URI it was sent to.
+    async fn get_request_uri(
+        endpoint_url: Option<&str>,
+        force_path_style: ForcePathStyle,
+    ) -> String {
+        let request_uri = Arc::new(Mutex::new(None));
+        let connector = SharedHttpConnector::new(CapturingHttpConnector(request_uri.clone()));
+
+        let mut config = SdkConfig::builder()
+            .behavior_version(BehaviorVersion::latest())
+            .credentials_provider(SharedCredentialsProvider::new(Credentials::new(
+                "access_key",
+                "secret_key",
+                None,
+                None,
+                "test",
+            )))
+            .http_client(http_client_fn(move |_, _| connector.clone()));
+
+        if let Some(endpoint_url) = endpoint_url {
+            config = config.endpoint_url(endpoint_url);
+        }
+
+        let s3_config = S3Config {
+            endpoint_region: Some(String::from("us-east-1")),
+            aws_profile: None,
+            aws_region: None,
+            force_path_style,
+            ..create_s3_config()
+        };
+
+        let s3_client =
+            aws_sdk_s3::Client::from_conf(build_s3_sdk_config(&config.build(), &s3_config));
+
+        assert!(s3_client
+            .get_object()
+            .bucket("bucket")
+            .key("key")
+            .send()
+            .await
+            .is_err());
+
+        let request_uri = request_uri.lock().unwrap().clone();
+        request_uri.unwrap()
+    }
+
+    /// Records the URI of a request, without the query, and fails it without sending it.
+    #[derive(Debug)]
+    struct CapturingHttpConnector(Arc<Mutex<Option<String>>>);
+
+    impl HttpConnector for CapturingHttpConnector {
+        fn call(&self, request: HttpRequest) -> HttpConnectorFuture {
+            *self.0.lock().unwrap() = request.uri().split('?').next().map(String::from);
+            HttpConnectorFuture::ready(Err(ConnectorError::other("test".into(), None)))
+        }
+    }
+
+    /// Records the method and path of each request, and responds with an empty listing.
+    #[derive(Debug)]
+    struct RecordingHttpConnector(Arc<Mutex<Vec<String>>>);
+
+    impl HttpConnector for RecordingHttpConnector {
+        fn call(&self, request: HttpRequest) -> HttpConnectorFuture {
+            let path = request.uri().split('?').next().unwrap_or_default();
+            let path = path.trim_start_matches("http://localhost:9000");
+
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("{} {}", request.method(), path));
+
+            HttpConnectorFuture::ready(Ok(HttpResponse::new(
+                StatusCode::try_from(200).unwrap(),
+                SdkBody::from("<ListBucketResult></ListBucketResult>"),
+            )))
+        }
+    }
+
+    fn create_recording_s3_storage() -> (S3Storage, Arc<Mutex<Vec<String>>>) {
+        let requests = Arc::new(Mutex::new(vec![]));
+        let connector = SharedHttpConnector::new(RecordingHttpConnector(requests.clone()));
+
+        let s3_sdk_config = aws_sdk_s3::Config::builder()
+            .behavior_version(BehaviorVersion::latest())
+            .region(Region::new("custom"))
+            .endpoint_url("http://localhost:9000")
+            .force_path_style(true)
+            .credentials_provider(Credentials::new(
+                "access_key",
+                "secret_key",
+                None,
+                None,
+                "test",
+            ))
+            .http_client(http_client_fn(move |_, _| connector.clone()))
+            .build();
+
+        let s3_storage = S3Storage::new(
+            aws_sdk_s3::Client::from_conf(s3_sdk_config),
+            "bucket".to_string(),
+            crate::storage::DEFAULT_MAX_HANDLES,
+            DEFAULT_PART_SIZE,
+            DEFAULT_MULTIPART_THRESHOLD,
+            DEFAULT_READ_AHEAD_SIZE,
+            Arc::new(AuthorizedKeysCache::new(Duration::from_secs(60))),
+        );
+
+        (s3_storage, requests)
+    }
+
+    /// Lists a fixed set of keys, filtered by the prefix and delimiter of each request like S3.
+    #[derive(Debug)]
+    struct ListingHttpConnector(Vec<&'static str>);
+
+    impl HttpConnector for ListingHttpConnector {
+        fn call(&self, request: HttpRequest) -> HttpConnectorFuture {
+            let query = request.uri().split_once('?').unwrap_or_default().1;
+            let get_param = |name: &str| {
+                query
+                    .split('&')
+                    .find_map(|param| param.strip_prefix(name))
+                    .map(|value| value.replace("%2F", "/"))
+            };
+            let prefix = get_param("prefix=").unwrap_or_default();
+            let delimiter = get_param("delimiter=").unwrap_or_default();
+
+            let mut contents = String::new();
+            let mut common_prefixes = vec![];
+
+            for key in self.0.iter().filter(|key| key.starts_with(&prefix)) {
+                match key[prefix.len()..].find(&delimiter) {
+                    Some(index) if !delimiter.is_empty() => {
+                        let common_prefix = &key[..prefix.len() + index + 1];
+
+                        if !common_prefixes.contains(&common_prefix) {
+                            common_prefixes.push(common_prefix);
+                        }
+                    }
+                    _ => contents.push_str(&format!(
+                        "<Contents><Key>{}</Key><Size>4</Size></Contents>",
+                        key
+                    )),
+                }
+            }
+
+            let common_prefixes: String = common_prefixes
+                .iter()
+                .map(|prefix| {
+                    format!(
+                        "<CommonPrefixes><Prefix>{}</Prefix></CommonPrefixes>",
+                        prefix
+                    )
+                })
+                .collect();
+
+            HttpConnectorFuture::ready(Ok(HttpResponse::new(
+                StatusCode::try_from(200).unwrap(),
+                SdkBody::from(format!(
+                    "<ListBucketResult><Prefix>{}</Prefix>{}{}</ListBucketResult>",
+                    prefix, contents, common_prefixes
+                )),
+            )))
+        }
+    }
+
+    fn create_listing_s3_storage(keys: Vec<&'static str>) -> S3Storage {
+        let connector = SharedHttpConnector::new(ListingHttpConnector(keys));
+
+        let s3_sdk_config = aws_sdk_s3::Config::builder()
+            .behavior_version(BehaviorVersion::latest())
+            .region(Region::new("custom"))
+            .endpoint_url("http://localhost:9000")
+            .force_path_style(true)
+            .credentials_provider(Credentials::new(
+                "access_key",
+                "secret_key",
+                None,
+                None,
+                "test",
+            ))
+            .http_client(http_client_fn(move |_, _| connector.clone()))
+            .build();
+
+        S3Storage::new(
+            aws_sdk_s3::Client::from_conf(s3_sdk_config),
+            "bucket".to_string(),
+            crate::storage::DEFAULT_MAX_HANDLES,
+            DEFAULT_PART_SIZE,
+            DEFAULT_MULTIPART_THRESHOLD,
+            DEFAULT_READ_AHEAD_SIZE,
+            Arc::new(AuthorizedKeysCache::new(Duration::from_secs(60))),
+        )
+    }
+
+    /// Stores the parts of multipart uploads in memory, and assembles them in the order listed
+    /// when the upload is completed. Parts and objects may be copied from ranges of stored
+    /// objects, and objects are read back whole or by range. Listings return pages of
+    /// `LIST_PAGE_SIZE` keys. The largest part received and the storage classes and encryption requested are
+    /// recorded, and a part can be dropped from the assembled object to simulate a lost part.
+    #[derive(Debug, Default)]
+    struct MultipartUploads {
+        parts: HashMap<String, Vec<u8>>,
+        objects: HashMap<String, Vec<u8>>,
+        content_types: HashMap<String, String>,
+        max_part_size: usize,
+        dropped_part: Option<String>,
+        head_requests: usize,
+        storage_classes: Vec<String>,
+        encryption: Vec<String>,
+        acls: Vec<String>,
+    }
+
+    #[derive(Debug)]
+    struct MultipartHttpConnector(Arc<Mutex<MultipartUploads>>);
+
+    impl HttpConnector for MultipartHttpConnector {
+        fn call(&self, request: HttpRequest) -> HttpConnectorFuture {
+            let uri = request
+                .uri()
+                .trim_start_matches("http://localhost:9000/bucket/");
+            let (key, query) = uri.split_once('?').unwrap_or((uri, ""));
+            let body = request.body().bytes().unwrap_or_default();
+            let mut uploads = self.0.lock().unwrap();
+
+            if let Some(storage_class) = request.headers().get("x-amz-storage-class") {
+                uploads.storage_classes.push(storage_class.to_string());
+            }
+
+            if let Some(acl) = request.headers().get("x-amz-acl") {
+                uploads.acls.push(format!("{} {}", key, acl));
+            }
+
+            if let Some(sse) = request.headers().get("x-amz-server-side-encryption") {
+                let kms_key_id = request
+                    .headers()
+                    .get("x-amz-server-side-encryption-aws-kms-key-id")
+                    .unwrap_or_default();
+
+                uploads
+                    .encryption
+                    .push(format!("{} {} {}", request.method(), sse, kms_key_id));
+            }
+
+            let content_type = request
+                .headers()
+                .get("Content-Type")
+                .map(|content_type| content_type.to_string());
+            let copy_source = request
+                .headers()
+                .get("x-amz-copy-source")
+                .map(|copy_source| {
+                    let object = &uploads.objects[copy_source.trim_start_matches("bucket/")];
+
+                    match request.headers().get("x-amz-copy-source-range") {
+                        Some(range) => {
+                            let (start, end) =
+                                range.trim_start_matches("bytes=").split_once('-').unwrap();
+                            object[start.parse().unwrap()..=end.parse().unwrap()].to_vec()
+                        }
+                        None => object.clone(),
+                    }
+                });
+
+            let mut content_length = None;
+            let mut head_content_type = None;
+
+            let response = if request.method() == "HEAD" {
+                uploads.head_requests += 1;
+                content_length = uploads.objects.get(key).map(|object| object.len());
+                head_content_type = uploads.content_types.get(key).cloned();
+                String::new()
+            } else if query.contains("list-type") {
+                list_objects(&uploads, query)
+            } else if request.method() == "DELETE" {
+                uploads.objects.remove(key);
+                uploads.content_types.remove(key);
+                String::new()
+            } else if request.method() == "GET" {
+                let object = uploads.objects.get(key).cloned().unwrap_or_default();
+                let object = match request.headers().get("Range") {
+                    Some(range) => {
+                        let (start, end) =
+                            range.trim_start_matches("bytes=").split_once('-').unwrap();
+                        let end = end.parse::<usize>().unwrap().min(object.len() - 1);
+                        object[start.parse().unwrap()..=end].to_vec()
+                    }
+                    None => object,
+                };
+
+                return HttpConnectorFuture::ready(Ok(HttpResponse::new(
+                    StatusCode::try_from(200).unwrap(),
+                    SdkBody::from(object),
+                )));
+            } else if query.starts_with("uploads") {
+                set_content_type(&mut uploads, key, content_type);
+                "<InitiateMultipartUploadResult><UploadId>upload</UploadId></InitiateMultipartUploadResult>"
+                    .to_string()
+            } else if let Some(part_number) = query
+                .split('&')
+                .find_map(|param| param.strip_prefix("partNumber="))
+            {
+                let (part, response) = match copy_source {
+                    Some(copy_source) => (
+                        copy_source,
+                        "<CopyPartResult><ETag>\"etag\"</ETag></CopyPartResult>".to_string(),
+                    ),
+                    None => (body.to_vec(), String::new()),
+                };
+
+                uploads.max_part_size = uploads.max_part_size.max(part.len());
+                uploads.parts.insert(part_number.to_string(), part);
+                response
+            } else if let Some(copy_source) = copy_source {
+                uploads.objects.insert(key.to_string(), copy_source);
+                set_content_type(&mut uploads, key, content_type);
+                "<CopyObjectResult><ETag>\"etag\"</ETag></CopyObjectResult>".to_string()
+            } else if request.method() == "POST" {
+                let body = String::from_utf8(body.to_vec()).unwrap();
+                let object = body
+                    .split("<PartNumber>")
+                    .skip(1)
+                    .filter_map(|part| part.split('<').next())
+                    .filter(|part_number| uploads.dropped_part.as_deref() != Some(part_number))
+                    .flat_map(|part_number| uploads.parts[part_number].clone())
+                    .collect();
+
+                uploads.objects.insert(key.to_string(), object);
+                "<CompleteMultipartUploadResult></CompleteMultipartUploadResult>".to_string()
+            } else {
+                uploads.objects.insert(key.to_string(), body.to_vec());
+                set_content_type(&mut uploads, key, content_type);
+                String::new()
+            };
+
+            let mut response =
+                HttpResponse::new(StatusCode::try_from(200).unwrap(), SdkBody::from(response));
+            response.headers_mut().insert("ETag", "\"etag\"");
+
+            if let Some(content_length) = content_length {
+                response
+                    .headers_mut()
+                    .insert("Content-Length", content_length.to_string());
+            }
+
+            if let Some(content_type) = head_content_type {
+                response.headers_mut().insert("Content-Type", content_type);
+            }
+
+            HttpConnectorFuture::ready(Ok(response))
+        }
+    }
+
+    const LIST_PAGE_SIZE: usize = 2;
+
+    /// Lists a page of the stored objects under the prefix of a query, continuing after the key in
+    /// the query's continuation token.
+    fn list_objects(uploads: &MultipartUploads, query: &str) -> String {
+        let get_param = |name: &str| {
+            query
+                .split('&')
+                .find_map(|param| param.strip_prefix(name))
+                .map(|value| value.replace("%2F", "/"))
+        };
+        let prefix = get_param("prefix=").unwrap_or_default();
+        let continuation_token = get_param("continuation-token=").unwrap_or_default();
+
+        let mut keys: Vec<&String> = uploads
+            .objects
+            .keys()
+            .filter(|key| key.starts_with(&prefix) && **key > continuation_token)
+            .collect();
+        keys.sort();
+
+        let next_continuation_token = match keys.len() > LIST_PAGE_SIZE {
+            true => format!(
+                "<NextContinuationToken>{}</NextContinuationToken>",
+                keys[LIST_PAGE_SIZE - 1]
+            ),
+            false => String::new(),
+        };
+
+        let contents: String = keys
+            .iter()
+            .take(LIST_PAGE_SIZE)
+            .map(|key| format!("<Contents><Key>{}</Key><Size>4</Size></Contents>", key))
+            .collect();
+
+        format!(
+            "<ListBucketResult><Prefix>{}</Prefix><IsTruncated>{}</IsTruncated>{}{}</ListBucketResult>",
+            prefix,
+            !next_continuation_token.is_empty(),
+            contents,
+            next_continuation_token
+        )
+    }
+
+    /// Records the content type an object is written with. Objects written without a content
+    /// type have none, like objects that S3 replaces without one.
+    fn set_content_type(uploads: &mut MultipartUploads, key: &str, content_type: Option<String>) {
+        match content_type {
+            Some(content_type) => uploads.content_types.insert(key.to_string(), content_type),
+            None => uploads.content_types.remove(key),
+        };
+    }
+
+    fn create_multipart_s3_storage(part_size: usize) -> (S3Storage, Arc<Mutex<MultipartUploads>>) {
+        let uploads = Arc::new(Mutex::new(MultipartUploads::default()));
+        let connector = SharedHttpConnector::new(MultipartHttpConnector(uploads.clone()));
+
+        let s3_sdk_config = aws_sdk_s3::Config::builder()
+            .behavior_version(BehaviorVersion::latest())
+            .region(Region::new("custom"))
+            .endpoint_url("http://localhost:9000")
+            .force_path_style(true)
+            .credentials_provider(Credentials::new(
+                "access_key",
+                "secret_key",
+                None,
+                None,
+                "test",
+            ))
+            .http_client(http_client_fn(move |_, _| connector.clone()))
+            .build();
+
+        let s3_storage = S3Storage::new(
+            aws_sdk_s3::Client::from_conf(s3_sdk_config),
+            "bucket".to_string(),
+            crate::storage::DEFAULT_MAX_HANDLES,
+            part_size,
+            part_size,
+            DEFAULT_READ_AHEAD_SIZE,
+            Arc::new(AuthorizedKeysCache::new(Duration::from_secs(60))),
+        );
+
+        (s3_storage, uploads)
+    }
+
+    #[tokio::test]
+    async fn test_write_data_streams_parts_to_multipart_upload() {
+        let part_size = 1000;
+        let chunk_size = 100;
+        let (s3_storage, uploads) = create_multipart_s3_storage(part_size);
+        let data: Vec<u8> = (0..(part_size * 5 + 50)).map(|i| (i % 251) as u8).collect();
+
+        let handle = s3_storage
+            .open_write_handle(String::from("/file"))
+            .await
+            .unwrap();
+
+        for (index, chunk) in data.chunks(chunk_size).enumerate() {
+            s3_storage
+                .write_data(
+                    &handle,
+                    (index * chunk_size) as u64,
+                    bytes::Bytes::copy_from_slice(chunk),
+                )
+                .await
+                .unwrap();
+
+            // Full parts are handed off to the upload, so the handle never buffers much more
+            // than a single part.
+            let write_handle = s3_storage.handle_manager.get_write_handle(&handle).await;
+            let buffered = write_handle.unwrap().lock().await.buffer.len();
+            assert!(buffered <= part_size + chunk_size);
+        }
+
+        s3_storage.close_handle(&handle).await.unwrap();
+
+        let uploads = uploads.lock().unwrap();
+        assert_eq!(5, uploads.parts.len());
+        assert!(uploads.max_part_size <= part_size + chunk_size);
+        assert_eq!(1, uploads.objects.len());
+        assert!(uploads.objects.values().all(|object| *object == data));
+    }
+
+    #[tokio::test]
+    async fn test_close_handle_after_interleaved_writes_completes_parts_in_order() {
+        let (s3_storage, uploads) = create_multipart_s3_storage(1000);
+        let s3_storage = Arc::new(s3_storage);
+        let data: Vec<u8> = (0..5500).map(|i| (i % 251) as u8).collect();
+
+        let handle = s3_storage
+            .open_write_handle(String::from("/file"))
+            .await
+            .unwrap();
+
+        // Chunks are written concurrently, so writes are interleaved with the parts that earlier
+        // writes started uploading.
+        let writes = data.chunks(100).enumerate().map(|(index, chunk)| {
+            let s3_storage = s3_storage.clone();
+            let handle = handle.clone();
+            let chunk = bytes::Bytes::copy_from_slice(chunk);
+
+            tokio::spawn(async move {
+                s3_storage
+                    .write_data(&handle, (index * 100) as u64, chunk)
+                    .await
+            })
+        });
+
+        for write in futures::future::join_all(writes).await {
+            write.unwrap().unwrap();
+        }
+
+        s3_storage.close_handle(&handle).await.unwrap();
+
+        // The object is assembled in the order parts are listed when the upload is completed.
+        let uploads = uploads.lock().unwrap();
+        assert!(uploads.parts.len() > 1);
+        assert_eq!(1, uploads.objects.len());
+        assert!(uploads.objects.values().all(|object| *object == data));
     }
 
-    #[test]
-    fn test_get_s3_prefix_converts_unix_absolute_directory() {
-        assert_eq!(String::from("test/"), get_s3_prefix("/test"))
+    #[tokio::test]
+    async fn test_finish_part_uploads_orders_completed_parts() {
+        let mut write_handle = WriteHandle::new(String::from("file"));
+        write_handle.completed_parts.push(build_completed_part(3));
+
+        // The first part takes the longest to upload.
+        for (part_number, delay) in [(1, 50), (2, 0), (4, 10)] {
+            write_handle.part_uploads.push(tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_millis(delay)).await;
+                Ok(build_completed_part(part_number))
+            }));
+        }
+
+        assert_eq!(Ok(()), finish_part_uploads(&mut write_handle).await);
+        assert!(write_handle.part_uploads.is_empty());
+        assert_eq!(
+            vec![Some(1), Some(2), Some(3), Some(4)],
+            write_handle
+                .completed_parts
+                .iter()
+                .map(|completed_part| completed_part.part_number())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_finish_part_uploads_waits_for_all_parts_after_failure() {
+        let mut write_handle = WriteHandle::new(String::from("file"));
+
+        write_handle.part_uploads.push(tokio::spawn(async {
+            Err(Error::Failure(String::from("failed")))
+        }));
+        write_handle.part_uploads.push(tokio::spawn(async {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            Ok(build_completed_part(2))
+        }));
+
+        assert_eq!(
+            Err(Error::Failure(String::from("failed"))),
+            finish_part_uploads(&mut write_handle).await
+        );
+        assert!(write_handle.part_uploads.is_empty());
+        assert_eq!(1, write_handle.completed_parts.len());
+    }
+
+    fn build_completed_part(part_number: i32) -> CompletedPart {
+        CompletedPart::builder()
+            .e_tag("etag")
+            .part_number(part_number)
+            .build()
+    }
+
+    #[tokio::test]
+    async fn test_close_handle_with_verify_uploads_checks_size() {
+        let (s3_storage, uploads) = create_multipart_s3_storage(1000);
+        let s3_storage = s3_storage.with_verify_uploads(true);
+
+        let handle = write_multipart_file(&s3_storage, 3500).await;
+
+        assert_eq!(Ok(()), s3_storage.close_handle(&handle).await);
+        assert_eq!(1, uploads.lock().unwrap().head_requests);
     }
 
-    #[test]
-    fn test_get_s3_prefix_converts_unix_absolute_directory_with_trailing_slash() {
-        assert_eq!(String::from("test/"), get_s3_prefix("/test/"))
+    #[tokio::test]
+    async fn test_close_handle_with_verify_uploads_fails_for_dropped_part() {
+        let (s3_storage, uploads) = create_multipart_s3_storage(1000);
+        let s3_storage = s3_storage.with_verify_uploads(true);
+        uploads.lock().unwrap().dropped_part = Some(String::from("2"));
+
+        let handle = write_multipart_file(&s3_storage, 3500).await;
+
+        assert!(matches!(
+            s3_storage.close_handle(&handle).await,
+            Err(Error::Failure(_))
+        ));
+        assert!(s3_storage
+            .handle_manager
+            .get_write_handle(&handle)
+            .await
+            .is_none());
     }
 
-    #[test]
-    fn test_get_s3_prefix_converts_blank_directory() {
-        assert_eq!("/", get_s3_prefix(""))
+    #[tokio::test]
+    async fn test_close_handle_without_verify_uploads_skips_check() {
+        let (s3_storage, uploads) = create_multipart_s3_storage(1000);
+        uploads.lock().unwrap().dropped_part = Some(String::from("2"));
+
+        let handle = write_multipart_file(&s3_storage, 3500).await;
+
+        assert_eq!(Ok(()), s3_storage.close_handle(&handle).await);
+        assert_eq!(0, uploads.lock().unwrap().head_requests);
     }
 
-    #[test]
-    fn test_get_s3_copy_source() {
-        assert_eq!("bucket/key", get_s3_copy_source("bucket", "key"))
+    #[tokio::test]
+    async fn test_close_handle_with_storage_class_sets_class_on_put() {
+        let (s3_storage, uploads) = create_multipart_s3_storage(1000);
+        let s3_storage = s3_storage.with_storage_class(Some(StorageClass::IntelligentTiering));
+
+        let handle = write_multipart_file(&s3_storage, 500).await;
+        s3_storage.close_handle(&handle).await.unwrap();
+
+        assert_eq!(
+            vec![String::from("INTELLIGENT_TIERING")],
+            uploads.lock().unwrap().storage_classes
+        );
     }
 
-    #[test]
-    fn test_get_s3_folder_marker_appends_folder_marker() {
+    #[tokio::test]
+    async fn test_close_handle_with_storage_class_sets_class_on_multipart_upload() {
+        let (s3_storage, uploads) = create_multipart_s3_storage(1000);
+        let s3_storage = s3_storage.with_storage_class(Some(StorageClass::StandardIa));
+
+        let handle = write_multipart_file(&s3_storage, 3500).await;
+        s3_storage.close_handle(&handle).await.unwrap();
+
+        // The class is set when the upload is created, not on each part.
         assert_eq!(
-            "folder/subfolder/_$folder$",
-            get_s3_folder_marker("/folder/subfolder")
+            vec![String::from("STANDARD_IA")],
+            uploads.lock().unwrap().storage_classes
         );
     }
 
-    #[test]
-    fn test_map_list_objects_to_files() {
-        let list_objects = ListObjectsV2Output::builder()
-            .common_prefixes(
-                CommonPrefix::builder()
-                    .prefix("users/test/subfolder/")
-                    .build(),
-            )
-            .contents(
-                Object::builder()
-                    .key("users/test/file.txt")
-                    .size(1)
-                    .last_modified(DateTime::from_millis(1417176009000))
-                    .build(),
-            )
-            .continuation_token("token")
-            .build();
+    #[tokio::test]
+    async fn test_close_handle_without_storage_class_uses_bucket_default() {
+        let (s3_storage, uploads) = create_multipart_s3_storage(1000);
 
-        let result = map_list_objects_to_files(list_objects);
+        let handle = write_multipart_file(&s3_storage, 500).await;
+        s3_storage.close_handle(&handle).await.unwrap();
 
-        assert_eq!(2, result.len());
+        assert!(uploads.lock().unwrap().storage_classes.is_empty());
+    }
+
+    #[test]
+    fn test_map_permissions_to_acl() {
+        assert_eq!(ObjectCannedAcl::PublicRead, map_permissions_to_acl(0o644));
+        assert_eq!(ObjectCannedAcl::PublicRead, map_permissions_to_acl(0o755));
         assert_eq!(
-            File {
-                file_name: "subfolder".to_owned(),
-                file_attributes: FileAttributes {
-                    size: None,
-                    gid: None,
-                    uid: None,
-                    permissions: Some(0o40777),
-                    atime: None,
-                    mtime: None,
-                }
-            },
-            result[0]
+            ObjectCannedAcl::PublicRead,
+            map_permissions_to_acl(0o100604)
         );
+        assert_eq!(ObjectCannedAcl::Private, map_permissions_to_acl(0o640));
+        assert_eq!(ObjectCannedAcl::Private, map_permissions_to_acl(0o600));
+        assert_eq!(ObjectCannedAcl::Private, map_permissions_to_acl(0o100750));
+    }
+
+    #[tokio::test]
+    async fn test_set_file_metadata_with_map_chmod_to_acl_puts_acl() {
+        let (s3_storage, uploads) = create_multipart_s3_storage(1000);
+        let s3_storage = s3_storage.with_map_chmod_to_acl(true);
+        uploads
+            .lock()
+            .unwrap()
+            .objects
+            .insert(String::from("file"), vec![0; 10]);
+
+        for permissions in [0o644, 0o600] {
+            let file_attributes = FileAttributes {
+                permissions: Some(permissions),
+                ..FileAttributes::default()
+            };
+
+            s3_storage
+                .set_file_metadata(String::from("file"), file_attributes)
+                .await
+                .unwrap();
+        }
+
         assert_eq!(
-            File {
-                file_name: "file.txt".to_owned(),
-                file_attributes: FileAttributes {
-                    size: Some(1),
-                    gid: None,
-                    uid: None,
-                    permissions: Some(0o100777),
-                    atime: None,
-                    mtime: Some(1417176009),
-                }
-            },
-            result[1]
+            vec![
+                String::from("file public-read"),
+                String::from("file private")
+            ],
+            uploads.lock().unwrap().acls
         );
     }
 
-    #[test]
-    fn test_map_list_objects_to_files_with_directory_marker() {
-        let list_objects = ListObjectsV2Output::builder()
-            .contents(Object::builder().key("users/test/file.txt").build())
-            .contents(Object::builder().key("users/test/_$folder$").build())
-            .build();
+    #[tokio::test]
+    async fn test_set_file_metadata_without_map_chmod_to_acl_keeps_acl() {
+        let (s3_storage, uploads) = create_multipart_s3_storage(1000);
+        uploads
+            .lock()
+            .unwrap()
+            .objects
+            .insert(String::from("file"), vec![0; 10]);
 
-        let result = map_list_objects_to_files(list_objects);
+        let file_attributes = FileAttributes {
+            permissions: Some(0o644),
+            ..FileAttributes::default()
+        };
 
-        assert_eq!(1, result.len());
-        assert_eq!("file.txt", &result[0].file_name);
+        s3_storage
+            .set_file_metadata(String::from("file"), file_attributes)
+            .await
+            .unwrap();
+
+        // Without permissions, there is nothing to map even when the mode is enabled.
+        let s3_storage = s3_storage.with_map_chmod_to_acl(true);
+
+        s3_storage
+            .set_file_metadata(String::from("file"), FileAttributes::default())
+            .await
+            .unwrap();
+
+        assert!(uploads.lock().unwrap().acls.is_empty());
     }
 
-    #[test]
-    fn test_map_list_objects_to_files_with_missing_data() {
-        let list_objects = ListObjectsV2Output::builder().build();
+    #[tokio::test]
+    async fn test_write_data_past_multipart_threshold_in_place_fails() {
+        let (s3_storage, uploads) = create_multipart_s3_storage(1000);
+        insert_object(&uploads, "file", vec![0; 10], "text/plain");
 
-        let result = map_list_objects_to_files(list_objects);
+        let handle = s3_storage
+            .open_read_write_handle(String::from("file"), false)
+            .await
+            .unwrap();
 
-        assert_eq!(0, result.len());
+        s3_storage
+            .write_data(&handle, 0, bytes::Bytes::from(vec![1; 1000]))
+            .await
+            .unwrap();
+
+        // The data is held until the handle is closed, so it cannot grow past a single put.
+        assert!(matches!(
+            s3_storage
+                .write_data(&handle, 995, bytes::Bytes::from(vec![2; 10]))
+                .await,
+            Err(Error::Failure(_))
+        ));
+
+        s3_storage.close_handle(&handle).await.unwrap();
+
+        let uploads = uploads.lock().unwrap();
+        assert_eq!(vec![1; 1000], uploads.objects["file"]);
     }
 
-    #[test]
-    fn test_map_object_to_file_with_missing_data() {
-        let object = Object::builder().build();
+    #[tokio::test]
+    async fn test_set_file_metadata_truncates_with_multipart_copy() {
+        let (s3_storage, uploads) = create_multipart_s3_storage(1000);
+        insert_object(&uploads, "file", (0..10).collect(), "text/plain");
+
+        s3_storage
+            .set_file_metadata(
+                String::from("file"),
+                FileAttributes {
+                    size: Some(4),
+                    ..FileAttributes::default()
+                },
+            )
+            .await
+            .unwrap();
+
+        // The kept data is copied within the bucket rather than downloaded and uploaded again.
+        let uploads = uploads.lock().unwrap();
+        assert_eq!(vec![0, 1, 2, 3], uploads.objects["file"]);
+        assert_eq!(1, uploads.parts.len());
+        assert_eq!("text/plain", uploads.content_types["file"]);
+    }
+
+    #[tokio::test]
+    async fn test_set_file_metadata_truncates_without_part_copy() {
+        let (s3_storage, uploads) = create_multipart_s3_storage(1000);
+        let s3_storage = s3_storage.with_part_copy(false);
+        insert_object(&uploads, "file", (0..10).collect(), "text/plain");
+
+        s3_storage
+            .set_file_metadata(
+                String::from("file"),
+                FileAttributes {
+                    size: Some(4),
+                    ..FileAttributes::default()
+                },
+            )
+            .await
+            .unwrap();
+
+        let uploads = uploads.lock().unwrap();
+        assert_eq!(vec![0, 1, 2, 3], uploads.objects["file"]);
+        assert!(uploads.parts.is_empty());
+        assert_eq!("text/plain", uploads.content_types["file"]);
+    }
+
+    #[tokio::test]
+    async fn test_set_file_metadata_truncate_past_multipart_threshold_without_part_copy_is_unsupported(
+    ) {
+        let (s3_storage, uploads) = create_multipart_s3_storage(4);
+        let s3_storage = s3_storage.with_part_copy(false);
+        insert_object(&uploads, "file", (0..10).collect(), "text/plain");
 
         assert_eq!(
-            File {
-                file_name: "".to_owned(),
-                file_attributes: FileAttributes {
-                    size: None,
-                    gid: None,
-                    uid: None,
-                    permissions: Some(0o100777),
-                    atime: None,
-                    mtime: None,
-                }
-            },
-            map_object_to_file(&object)
+            Err(Error::Unimplemented),
+            s3_storage
+                .set_file_metadata(
+                    String::from("file"),
+                    FileAttributes {
+                        size: Some(5),
+                        ..FileAttributes::default()
+                    },
+                )
+                .await
         );
+
+        let uploads = uploads.lock().unwrap();
+        assert_eq!((0..10).collect::<Vec<u8>>(), uploads.objects["file"]);
+        assert!(uploads.parts.is_empty());
     }
 
-    #[test]
-    fn test_map_list_objects_to_directory() {
-        let list_objects = ListObjectsV2Output::builder()
-            .prefix("directory/subdirectory/")
-            .contents(Object::builder().build())
-            .build();
+    #[tokio::test]
+    async fn test_open_append_handle_without_part_copy_loads_object() {
+        let (s3_storage, uploads) = create_multipart_s3_storage(MIN_PART_SIZE);
+        let s3_storage = s3_storage.with_part_copy(false);
+        let data: Vec<u8> = (0..MIN_PART_SIZE).map(|i| (i % 251) as u8).collect();
+        insert_object(&uploads, "file", data.clone(), "text/plain");
+
+        let handle = s3_storage
+            .open_append_handle(String::from("file"), false)
+            .await
+            .unwrap();
+
+        s3_storage
+            .write_data(&handle, 0, bytes::Bytes::from("end"))
+            .await
+            .unwrap();
+        s3_storage.close_handle(&handle).await.unwrap();
+
+        let uploads = uploads.lock().unwrap();
+        assert_eq!([data, b"end".to_vec()].concat(), uploads.objects["file"]);
+    }
 
-        let directory = map_list_objects_to_directory(list_objects);
+    #[tokio::test]
+    async fn test_open_append_handle_past_multipart_threshold_without_part_copy_is_unsupported() {
+        let (s3_storage, uploads) = create_multipart_s3_storage(1000);
+        let s3_storage = s3_storage.with_part_copy(false);
+        insert_object(&uploads, "file", vec![0; MIN_PART_SIZE], "text/plain");
 
         assert_eq!(
-            Ok(File {
-                file_name: "subdirectory".to_string(),
-                file_attributes: FileAttributes {
-                    size: None,
-                    gid: None,
-                    uid: None,
-                    permissions: Some(0o40777),
-                    atime: None,
-                    mtime: None,
-                }
-            }),
-            directory
+            Err(Error::Unimplemented),
+            s3_storage
+                .open_append_handle(String::from("file"), false)
+                .await
         );
+        assert!(uploads.lock().unwrap().parts.is_empty());
     }
 
-    #[test]
-    fn test_map_list_objects_to_directory_with_none_contents() {
-        let list_objects = ListObjectsV2Output::builder()
-            .prefix("directory/subdirectory/")
-            .set_contents(None)
-            .build();
+    #[tokio::test]
+    async fn test_copy_object_multipart_without_part_copy_is_unsupported() {
+        let (s3_storage, uploads) = create_multipart_s3_storage(1000);
+        let s3_storage = s3_storage.with_part_copy(false);
+        insert_object(&uploads, "file", (0..10).collect(), "text/plain");
 
-        let directory = map_list_objects_to_directory(list_objects);
+        assert_eq!(
+            Err(Error::Unimplemented),
+            s3_storage
+                .copy_object_multipart("file", "copy", 10, None, None)
+                .await
+        );
+        assert!(!uploads.lock().unwrap().objects.contains_key("copy"));
+    }
 
-        assert_eq!(Err(Error::NoSuchFile), directory);
+    #[tokio::test]
+    async fn test_set_file_metadata_truncates_to_empty() {
+        let (s3_storage, uploads) = create_multipart_s3_storage(1000);
+        insert_object(&uploads, "file", (0..10).collect(), "text/plain");
+
+        s3_storage
+            .set_file_metadata(
+                String::from("file"),
+                FileAttributes {
+                    size: Some(0),
+                    ..FileAttributes::default()
+                },
+            )
+            .await
+            .unwrap();
+
+        let uploads = uploads.lock().unwrap();
+        assert!(uploads.objects["file"].is_empty());
+        assert!(uploads.parts.is_empty());
+        assert_eq!("text/plain", uploads.content_types["file"]);
     }
 
-    #[test]
-    fn test_map_list_objects_to_directory_with_0_contents() {
-        let list_objects = ListObjectsV2Output::builder()
-            .prefix("directory/subdirectory/")
-            .set_contents(Some(vec![]))
-            .build();
+    #[tokio::test]
+    async fn test_set_file_metadata_keeps_content_type() {
+        let (s3_storage, uploads) = create_multipart_s3_storage(1000);
+        insert_object(&uploads, "file", (0..10).collect(), "image/png");
+
+        s3_storage
+            .set_file_metadata(
+                String::from("file"),
+                FileAttributes {
+                    permissions: Some(0o600),
+                    ..FileAttributes::default()
+                },
+            )
+            .await
+            .unwrap();
 
-        let directory = map_list_objects_to_directory(list_objects);
+        let uploads = uploads.lock().unwrap();
+        assert_eq!((0..10).collect::<Vec<u8>>(), uploads.objects["file"]);
+        assert_eq!("image/png", uploads.content_types["file"]);
+    }
 
-        assert_eq!(Err(Error::NoSuchFile), directory);
+    fn insert_object(
+        uploads: &Arc<Mutex<MultipartUploads>>,
+        key: &str,
+        data: Vec<u8>,
+        content_type: &str,
+    ) {
+        let mut uploads = uploads.lock().unwrap();
+        uploads.objects.insert(key.to_string(), data);
+        uploads
+            .content_types
+            .insert(key.to_string(), content_type.to_string());
     }
 
-    #[test]
-    fn test_map_list_objects_to_directory_with_no_prefix() {
-        let list_objects = ListObjectsV2Output::builder()
-            .set_prefix(None)
-            .set_contents(Some(vec![]))
-            .build();
+    #[tokio::test]
+    async fn test_close_handle_with_server_side_encryption_encrypts_put() {
+        let (s3_storage, uploads) = create_multipart_s3_storage(1000);
+        let s3_storage = s3_storage.with_server_side_encryption(
+            Some(ServerSideEncryption::AwsKms),
+            Some(String::from("key")),
+        );
 
-        let directory = map_list_objects_to_directory(list_objects);
+        let handle = write_multipart_file(&s3_storage, 500).await;
+        s3_storage.close_handle(&handle).await.unwrap();
 
-        assert_eq!(Err(Error::NoSuchFile), directory);
+        assert_eq!(
+            vec![String::from("PUT aws:kms key")],
+            uploads.lock().unwrap().encryption
+        );
     }
 
-    #[test]
-    fn test_map_prefix_to_file_with_missing_data() {
-        let prefix = CommonPrefix::builder().build();
+    #[tokio::test]
+    async fn test_close_handle_with_server_side_encryption_encrypts_multipart_upload() {
+        let (s3_storage, uploads) = create_multipart_s3_storage(1000);
+        let s3_storage =
+            s3_storage.with_server_side_encryption(Some(ServerSideEncryption::Aes256), None);
 
+        let handle = write_multipart_file(&s3_storage, 3500).await;
+        s3_storage.close_handle(&handle).await.unwrap();
+
+        // Encryption is set when the upload is created, not on each part.
         assert_eq!(
-            File {
-                file_name: "".to_owned(),
-                file_attributes: FileAttributes {
-                    size: None,
-                    gid: None,
-                    uid: None,
-                    permissions: Some(0o40777),
-                    atime: None,
-                    mtime: None,
-                }
-            },
-            map_prefix_to_file(&prefix)
+            vec![String::from("POST AES256 ")],
+            uploads.lock().unwrap().encryption
         );
     }
 
-    #[test]
-    fn test_map_head_object_to_file() {
-        let head_object = HeadObjectOutput::builder().build();
+    #[tokio::test]
+    async fn test_rename_with_server_side_encryption_encrypts_copy() {
+        let (s3_storage, uploads) = create_multipart_s3_storage(1000);
+        let s3_storage =
+            s3_storage.with_server_side_encryption(Some(ServerSideEncryption::Aes256), None);
+
+        let handle = write_multipart_file(&s3_storage, 500).await;
+        s3_storage.close_handle(&handle).await.unwrap();
+        uploads.lock().unwrap().encryption.clear();
+
+        s3_storage
+            .rename(String::from("/file"), String::from("/renamed"))
+            .await
+            .unwrap();
 
         assert_eq!(
-            File {
-                file_name: "file".to_owned(),
-                file_attributes: FileAttributes {
-                    size: None,
-                    gid: None,
-                    uid: None,
-                    permissions: Some(0o100777),
-                    atime: None,
-                    mtime: None,
-                }
-            },
-            map_head_object_to_file("file", &head_object)
+            vec![String::from("PUT AES256 ")],
+            uploads.lock().unwrap().encryption
         );
     }
 
-    #[test]
-    fn test_map_create_multipart_response_to_write_handle() {
-        let multipart_response = CreateMultipartUploadOutput::builder()
-            .upload_id("id")
-            .key("key")
-            .build();
+    async fn write_multipart_file(s3_storage: &S3Storage, size: usize) -> String {
+        let handle = s3_storage
+            .open_write_handle(String::from("/file"))
+            .await
+            .unwrap();
 
-        let write_handle =
-            map_create_multipart_response_to_write_handle(multipart_response).unwrap();
+        for offset in (0..size).step_by(100) {
+            s3_storage
+                .write_data(&handle, offset as u64, bytes::Bytes::from(vec![1; 100]))
+                .await
+                .unwrap();
+        }
 
-        assert_eq!("id", &write_handle.upload_id);
-        assert_eq!("key", &write_handle.key);
-        assert_eq!(0, write_handle.completed_parts.len());
-        assert_eq!(5000000, write_handle.buffer.capacity());
+        handle
     }
 
-    #[test]
-    fn test_map_create_multipart_response_to_write_handle_with_missing_multipart_id() {
-        let multipart_response = CreateMultipartUploadOutput::builder().key("key").build();
-
-        assert!(map_create_multipart_response_to_write_handle(multipart_response).is_err());
+    fn create_s3_config() -> S3Config {
+        S3Config {
+            endpoint_name: Some(String::from("https://localhost:9000")),
+            endpoint_region: Some(String::from("custom")),
+            aws_profile: None,
+            aws_region: None,
+            bucket: String::from("bucket"),
+            part_size: DEFAULT_PART_SIZE,
+            multipart_threshold: DEFAULT_MULTIPART_THRESHOLD,
+            read_ahead_size: DEFAULT_READ_AHEAD_SIZE,
+            ca_bundle: None,
+            tls_insecure: false,
+            tls_insecure_dev_override: false,
+            force_path_style: ForcePathStyle::Auto,
+            verify_uploads: false,
+            storage_class: None,
+            sse: None,
+            sse_kms_key_id: None,
+            map_chmod_to_acl: false,
+        }
     }
 
-    #[test]
-    fn test_map_create_multipart_response_to_write_handle_with_missing_key() {
-        let multipart_response = CreateMultipartUploadOutput::builder()
-            .upload_id("id")
+    fn create_s3_storage() -> S3Storage {
+        let s3_sdk_config = aws_sdk_s3::Config::builder()
+            .behavior_version(BehaviorVersion::latest())
+            .region(Region::new("custom"))
             .build();
 
-        assert!(map_create_multipart_response_to_write_handle(multipart_response).is_err());
+        S3Storage::new(
+            aws_sdk_s3::Client::from_conf(s3_sdk_config),
+            "bucket".to_string(),
+            crate::storage::DEFAULT_MAX_HANDLES,
+            DEFAULT_PART_SIZE,
+            DEFAULT_MULTIPART_THRESHOLD,
+            DEFAULT_READ_AHEAD_SIZE,
+            Arc::new(AuthorizedKeysCache::new(Duration::from_secs(60))),
+        )
     }
 
     #[test]
-    fn test_map_err_maps_not_found_to_no_such_file() {
+    fn test_write_handle_check_failure() {
+        let mut write_handle = create_write_handle();
+
+        assert_eq!(Ok(()), write_handle.check_failure());
+
+        write_handle.failure = Some(String::from("Storage error"));
+
         assert_eq!(
-            Error::NoSuchFile,
-            map_err(aws_sdk_s3::Error::NotFound(NotFound::builder().build()))
+            Err(Error::Failure(String::from(
+                "A previous write to key failed: Storage error"
+            ))),
+            write_handle.check_failure()
         );
     }
 
-    #[test]
-    fn test_map_err_maps_missing_key_to_no_such_file() {
+    #[tokio::test]
+    async fn test_write_data_with_failed_part_fails() {
+        let s3_storage = create_s3_storage();
+        let handle = create_failed_write_handle(&s3_storage).await;
+
+        assert!(s3_storage
+            .write_data(&handle, 0, bytes::Bytes::from("data"))
+            .await
+            .is_err());
+        assert!(s3_storage.fsync(&handle).await.is_err());
         assert_eq!(
-            Error::NoSuchFile,
-            map_err(aws_sdk_s3::Error::NoSuchKey(NoSuchKey::builder().build()))
+            Vec::<u8>::new(),
+            s3_storage
+                .handle_manager
+                .get_write_handle(&handle)
+                .await
+                .unwrap()
+                .lock()
+                .await
+                .buffer
         );
     }
 
-    #[test]
-    fn test_map_error_maps_generic_error_to_storage_error() {
+    #[tokio::test]
+    async fn test_close_handle_with_failed_part_fails_and_removes_handle() {
+        let s3_storage = create_s3_storage();
+        let handle = create_failed_write_handle(&s3_storage).await;
+
         assert_eq!(
-            Error::Storage("BucketAlreadyOwnedByYou".to_string()),
-            map_err(aws_sdk_s3::Error::BucketAlreadyOwnedByYou(
-                BucketAlreadyOwnedByYou::builder().build()
-            ))
+            Err(Error::Failure(String::from(
+                "A previous write to key failed: Storage error"
+            ))),
+            s3_storage.close_handle(&handle).await
         );
+        assert!(s3_storage
+            .handle_manager
+            .get_write_handle(&handle)
+            .await
+            .is_none());
+    }
+
+    async fn create_failed_write_handle(s3_storage: &S3Storage) -> String {
+        let mut write_handle = create_write_handle();
+        write_handle.failure = Some(String::from("Storage error"));
+
+        s3_storage
+            .handle_manager
+            .create_write_handle(write_handle)
+            .await
+            .unwrap()
+    }
+
+    fn create_write_handle() -> WriteHandle {
+        WriteHandle::new(String::from("key"))
     }
 }
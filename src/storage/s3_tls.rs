@@ -0,0 +1,253 @@
+use crate::error::Error;
+use aws_smithy_http_client::tls::{self, rustls_provider::CryptoMode, TlsContext, TrustStore};
+use aws_smithy_runtime_api::client::http::{
+    http_client_fn, HttpConnector, HttpConnectorFuture, SharedHttpClient, SharedHttpConnector,
+};
+use aws_smithy_runtime_api::client::orchestrator::{HttpRequest, HttpResponse};
+use aws_smithy_runtime_api::client::result::ConnectorError;
+use aws_smithy_types::body::SdkBody;
+use hyper_rustls::HttpsConnector;
+use hyper_util::client::legacy::connect::HttpConnector as TcpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{aws_lc_rs, CryptoProvider};
+use rustls::{DigitallySignedStruct, SignatureScheme};
+use rustls_pki_types::pem::PemObject;
+use rustls_pki_types::{CertificateDer, ServerName, UnixTime};
+use std::convert::TryFrom;
+use std::sync::Arc;
+use tracing::warn;
+
+/// Builds an HTTP client that trusts the CA certificates in a PEM bundle, in addition to the
+/// system's trusted certificates, for S3 endpoints with certificates issued by a private CA.
+pub fn build_ca_bundle_http_client(ca_bundle_path: &str) -> Result<SharedHttpClient, Error> {
+    let ca_bundle = std::fs::read(ca_bundle_path).map_err(|err| {
+        Error::Configuration(format!(
+            "Failed to read DRAY_S3_CA_BUNDLE {}: {}",
+            ca_bundle_path, err
+        ))
+    })?;
+
+    // The certificates are not parsed until the first connection is made, so they are checked
+    // here to report an invalid bundle at startup.
+    let certificates = CertificateDer::pem_slice_iter(&ca_bundle)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| {
+            Error::Configuration(format!(
+                "Failed to parse DRAY_S3_CA_BUNDLE {}: {}",
+                ca_bundle_path, err
+            ))
+        })?;
+
+    if certificates.is_empty() {
+        return Err(Error::Configuration(format!(
+            "DRAY_S3_CA_BUNDLE {} does not contain any certificates.",
+            ca_bundle_path
+        )));
+    }
+
+    let tls_context = TlsContext::builder()
+        .with_trust_store(TrustStore::default().with_pem_certificate(ca_bundle))
+        .build()
+        .map_err(|err| Error::Configuration(err.to_string()))?;
+
+    Ok(aws_smithy_http_client::Builder::new()
+        .tls_provider(tls::Provider::Rustls(CryptoMode::AwsLc))
+        .tls_context(tls_context)
+        .build_https())
+}
+
+/// Builds an HTTP client that does not verify the certificates of S3 endpoints. This is only
+/// intended for development against endpoints with self-signed certificates.
+pub fn build_insecure_http_client() -> SharedHttpClient {
+    warn!("TLS certificate verification is disabled for S3 endpoints");
+
+    let crypto_provider = Arc::new(aws_lc_rs::default_provider());
+
+    let tls_config = rustls::ClientConfig::builder_with_provider(crypto_provider.clone())
+        .with_safe_default_protocol_versions()
+        .expect("The default TLS protocol versions are supported.")
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(InsecureCertificateVerifier(crypto_provider)))
+        .with_no_client_auth();
+
+    let https_connector = hyper_rustls::HttpsConnectorBuilder::new()
+        .with_tls_config(tls_config)
+        .https_or_http()
+        .enable_http1()
+        .enable_http2()
+        .build();
+
+    let connector = SharedHttpConnector::new(InsecureHttpConnector {
+        client: Client::builder(TokioExecutor::new()).build(https_connector),
+    });
+
+    http_client_fn(move |_, _| connector.clone())
+}
+
+#[derive(Debug)]
+struct InsecureHttpConnector {
+    client: Client<HttpsConnector<TcpConnector>, SdkBody>,
+}
+
+impl HttpConnector for InsecureHttpConnector {
+    fn call(&self, request: HttpRequest) -> HttpConnectorFuture {
+        let request = match request.try_into_http1x() {
+            Ok(request) => request,
+            Err(err) => return HttpConnectorFuture::ready(Err(ConnectorError::user(err.into()))),
+        };
+
+        let response = self.client.request(request);
+
+        HttpConnectorFuture::new(async move {
+            let response = response
+                .await
+                .map_err(|err| ConnectorError::io(err.into()))?;
+
+            HttpResponse::try_from(response.map(SdkBody::from_body_1_x))
+                .map_err(|err| ConnectorError::other(err.into(), None))
+        })
+    }
+}
+
+/// Accepts any server certificate. Signatures are still checked, so that the connection is made
+/// with the holder of the certificate's key.
+#[derive(Debug)]
+struct InsecureCertificateVerifier(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for InsecureCertificateVerifier {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::storage::s3::{
+        ForcePathStyle, S3Config, S3StorageFactory, DEFAULT_MULTIPART_THRESHOLD, DEFAULT_PART_SIZE,
+        DEFAULT_READ_AHEAD_SIZE,
+    };
+    use crate::storage::{StorageFactory, DEFAULT_MAX_HANDLES};
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    const TEST_CA_CERTIFICATE: &str = "-----BEGIN CERTIFICATE-----
+MIIBhTCCASugAwIBAgIUXyJdw49Mut2gpmndDhkpRfHKav4wCgYIKoZIzj0EAwIw
+FzEVMBMGA1UEAwwMRHJheSBUZXN0IENBMCAXDTI2MTAxNjEyMDQxMloYDzIxMjYw
+OTIyMTIwNDEyWjAXMRUwEwYDVQQDDAxEcmF5IFRlc3QgQ0EwWTATBgcqhkjOPQIB
+BggqhkjOPQMBBwNCAATtza54JtQygtgtGnq/60Xvi75mWUTA2X5byxtAgGMZ8D0G
+bCtEVKmEXlr4BZddysmyHkNu0eLuHriaq8EGlT/9o1MwUTAdBgNVHQ4EFgQU9nXa
+z36LlIVq544SbPXozK8NiyIwHwYDVR0jBBgwFoAU9nXaz36LlIVq544SbPXozK8N
+iyIwDwYDVR0TAQH/BAUwAwEB/zAKBggqhkjOPQQDAgNIADBFAiBijSIbDQtQkUZK
+INHw95xybhOtW7S8a2hOP1MimTrsdgIhANTQN4fJlU6arnKtiJT1or4k7H+ylvaG
+ZGUXn8wNatpD
+-----END CERTIFICATE-----
+";
+
+    #[test]
+    fn test_build_ca_bundle_http_client() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("ca.pem");
+        std::fs::write(&path, TEST_CA_CERTIFICATE).unwrap();
+
+        assert!(build_ca_bundle_http_client(path.to_str().unwrap()).is_ok());
+    }
+
+    #[test]
+    fn test_build_ca_bundle_http_client_with_missing_file() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("missing.pem");
+
+        assert!(matches!(
+            build_ca_bundle_http_client(path.to_str().unwrap()),
+            Err(Error::Configuration(_))
+        ));
+    }
+
+    #[test]
+    fn test_build_ca_bundle_http_client_without_certificates() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("ca.pem");
+        std::fs::write(&path, "not a certificate").unwrap();
+
+        assert!(matches!(
+            build_ca_bundle_http_client(path.to_str().unwrap()),
+            Err(Error::Configuration(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_s3_storage_factory_with_ca_bundle() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("ca.pem");
+        std::fs::write(&path, TEST_CA_CERTIFICATE).unwrap();
+
+        let s3_config = S3Config {
+            endpoint_name: Some(String::from("https://localhost:9000")),
+            endpoint_region: Some(String::from("custom")),
+            aws_profile: None,
+            aws_region: None,
+            bucket: String::from("bucket"),
+            part_size: DEFAULT_PART_SIZE,
+            multipart_threshold: DEFAULT_MULTIPART_THRESHOLD,
+            read_ahead_size: DEFAULT_READ_AHEAD_SIZE,
+            ca_bundle: Some(path.to_str().unwrap().to_string()),
+            tls_insecure: false,
+            tls_insecure_dev_override: false,
+            force_path_style: ForcePathStyle::Auto,
+            verify_uploads: false,
+            storage_class: None,
+            sse: None,
+            sse_kms_key_id: None,
+            map_chmod_to_acl: false,
+        };
+
+        let factory =
+            S3StorageFactory::new(&s3_config, DEFAULT_MAX_HANDLES, Duration::from_secs(60))
+                .await
+                .unwrap();
+        factory.create_storage();
+    }
+}
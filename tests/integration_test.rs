@@ -1,11 +1,15 @@
 use std::{env, net::TcpListener, process::Stdio, sync::Once};
 
-use aws_config::{BehaviorVersion, Region};
+use aws_config::BehaviorVersion;
 use aws_sdk_s3::primitives::ByteStream;
 use dray::{
-    config::{DrayConfig, S3Config},
+    config::{DrayConfig, S3Config, StorageBackend},
     error::Error,
     ssh_server::DraySshServer,
+    storage::{
+        s3::{ForcePathStyle, S3StorageFactory},
+        StorageFactory,
+    },
 };
 use rand::Rng;
 use std::sync::OnceLock;
@@ -27,6 +31,7 @@ use tokio::{
 struct TestClient {
     host: String,
     s3_client: aws_sdk_s3::Client,
+    s3_config: S3Config,
     bucket: String,
 }
 
@@ -55,7 +60,7 @@ async fn setup() -> TestClient {
 
     let s3_client = create_s3_client(&dray_config).await;
 
-    let dray_server = DraySshServer::new(dray_config.clone()).await;
+    let dray_server = DraySshServer::new(dray_config.clone()).await.unwrap();
 
     dray_server.health_check().await.unwrap();
 
@@ -63,9 +68,10 @@ async fn setup() -> TestClient {
     wait_for_server_listening(&dray_config).await;
 
     let test_client = TestClient {
+        bucket: dray_config.get_s3_config().unwrap().bucket.clone(),
+        s3_config: dray_config.get_s3_config().unwrap().clone(),
         host: dray_config.host,
         s3_client,
-        bucket: dray_config.s3.bucket,
     };
 
     put_object(
@@ -93,14 +99,60 @@ async fn get_config(minio: &Container<'_, MinIO>) -> DrayConfig {
     DrayConfig {
         host: format!("127.0.0.1:{}", port),
         ssh_key_paths: ".ssh/id_ed25519".to_string(),
-        s3: S3Config {
+        max_handles: 64,
+        max_write_handles: 16,
+        max_read_length: 261120,
+        home_template: "/home/{user}".to_string(),
+        read_only_prefixes: vec![],
+        auto_create_home: false,
+        read_only_users: vec![],
+        allowed_users: None,
+        storage_backend: StorageBackend::S3,
+        user_quota_bytes: None,
+        max_upload_bytes: None,
+        max_path_depth: 64,
+        metrics_addr: None,
+        health_addr: None,
+        shutdown_timeout: 10,
+        authorized_keys_ttl: 60,
+        max_sessions: None,
+        max_conn_per_ip: None,
+        session_rps: None,
+        idle_timeout_secs: None,
+        storage_op_timeout: None,
+        listen_backlog: 1024,
+        tcp_keepalive_secs: None,
+        ssh_window_size: 16777216,
+        ssh_max_packet: 32768,
+        audit_log: None,
+        check_config: false,
+        login_banner: None,
+        default_file_mode: 0o644,
+        default_dir_mode: 0o755,
+        s3: Some(S3Config {
             endpoint_name: Some(format!(
                 "http://localhost:{}",
                 minio.get_host_port_ipv4(9000)
             )),
-            endpoint_region: "custom".to_string(),
+            endpoint_region: Some("custom".to_string()),
+            aws_profile: None,
+            aws_region: None,
             bucket: format!("integration-test-{}", rng.gen::<u32>()),
-        },
+            part_size: 10000000,
+            multipart_threshold: 10000000,
+            read_ahead_size: 1048576,
+            ca_bundle: None,
+            tls_insecure: false,
+            tls_insecure_dev_override: false,
+            force_path_style: ForcePathStyle::Auto,
+            verify_uploads: true,
+            storage_class: None,
+            sse: None,
+            sse_kms_key_id: None,
+            map_chmod_to_acl: false,
+        }),
+        filesystem: None,
+        gcs: None,
     }
 }
 
@@ -124,9 +176,11 @@ async fn get_free_port() -> Option<u16> {
 }
 
 async fn create_s3_client(dray_config: &DrayConfig) -> aws_sdk_s3::Client {
+    let s3_config = dray_config.get_s3_config().unwrap();
+
     let mut config_loader = aws_config::defaults(BehaviorVersion::latest());
 
-    if let Some(endpoint_name) = &dray_config.s3.endpoint_name {
+    if let Some(endpoint_name) = &s3_config.endpoint_name {
         config_loader = config_loader.endpoint_url(endpoint_name);
     };
 
@@ -138,26 +192,26 @@ async fn create_s3_client(dray_config: &DrayConfig) -> aws_sdk_s3::Client {
         s3_client_builder.force_path_style(true);
     }
 
-    let mut s3_config = aws_sdk_s3::config::Builder::from(&config);
+    let mut s3_sdk_config = aws_sdk_s3::config::Builder::from(&config);
 
     if config.endpoint_url().is_some() {
-        s3_config = s3_config.force_path_style(true);
+        s3_sdk_config = s3_sdk_config.force_path_style(true);
     }
 
-    s3_config = s3_config.region(Region::new(dray_config.s3.endpoint_region.clone()));
+    s3_sdk_config = s3_sdk_config.region(s3_config.get_region());
 
-    let s3_client = aws_sdk_s3::Client::from_conf(s3_config.build());
+    let s3_client = aws_sdk_s3::Client::from_conf(s3_sdk_config.build());
 
     let head_bucket_result = s3_client
         .head_bucket()
-        .bucket(&dray_config.s3.bucket)
+        .bucket(&s3_config.bucket)
         .send()
         .await;
 
     if head_bucket_result.is_err() {
         let create_bucket_result = s3_client
             .create_bucket()
-            .bucket(&dray_config.s3.bucket)
+            .bucket(&s3_config.bucket)
             .send()
             .await;
 
@@ -174,7 +228,7 @@ async fn create_s3_client(dray_config: &DrayConfig) -> aws_sdk_s3::Client {
 
     let objects_to_delete = s3_client
         .list_objects_v2()
-        .bucket(&dray_config.s3.bucket)
+        .bucket(&s3_config.bucket)
         .max_keys(i32::MAX)
         .send()
         .await
@@ -185,7 +239,7 @@ async fn create_s3_client(dray_config: &DrayConfig) -> aws_sdk_s3::Client {
         for object in objects_to_delete {
             s3_client
                 .delete_object()
-                .bucket(&dray_config.s3.bucket)
+                .bucket(&s3_config.bucket)
                 .key(object.key.unwrap())
                 .send()
                 .await
@@ -232,6 +286,19 @@ async fn get_object(test_client: &TestClient, key: &str) -> Vec<u8> {
     object_data
 }
 
+async fn count_multipart_uploads(test_client: &TestClient) -> usize {
+    test_client
+        .s3_client
+        .list_multipart_uploads()
+        .bucket(&test_client.bucket)
+        .send()
+        .await
+        .unwrap()
+        .uploads
+        .unwrap_or_default()
+        .len()
+}
+
 async fn execute_sftp_command(test_client: &TestClient, command: &str) -> Result<String, Error> {
     let host_pieces: Vec<&str> = test_client.host.split(':').collect();
     let host_name = host_pieces[0];
@@ -281,6 +348,25 @@ async fn wait_for_server_listening(dray_config: &DrayConfig) {
     }
 }
 
+#[tokio::test]
+async fn test_validate_full() {
+    let test_client = setup().await;
+
+    let mut dray_config = get_config(MINIO.get().unwrap()).await;
+    dray_config.s3 = Some(test_client.s3_config.clone());
+
+    assert!(dray_config.validate_full().await.is_ok());
+}
+
+#[tokio::test]
+async fn test_validate_full_with_missing_bucket() {
+    setup().await;
+
+    let dray_config = get_config(MINIO.get().unwrap()).await;
+
+    assert!(dray_config.validate_full().await.is_err());
+}
+
 #[tokio::test]
 async fn test_list_directory() {
     let test_client = setup().await;
@@ -299,6 +385,23 @@ async fn test_list_directory() {
     assert!(!sftp_output.contains("file1"));
 }
 
+#[tokio::test]
+async fn test_list_current_directory() {
+    let test_client = setup().await;
+
+    put_object(&test_client, "home/test/file1", "1".as_bytes().to_vec()).await;
+    put_object(&test_client, "home/other/file2", "2".as_bytes().to_vec()).await;
+    put_object(&test_client, "file3", "3".as_bytes().to_vec()).await;
+
+    let sftp_output = execute_sftp_command(&test_client, "cd .\nls .")
+        .await
+        .unwrap();
+
+    assert!(sftp_output.contains("file1"));
+    assert!(!sftp_output.contains("file2"));
+    assert!(!sftp_output.contains("file3"));
+}
+
 #[tokio::test]
 #[should_panic(expected = "Can't ls")]
 async fn test_list_directory_with_permission_error() {
@@ -375,6 +478,295 @@ async fn test_write_file() {
     assert_eq!(b"Test write data!", file_data.as_slice());
 }
 
+#[tokio::test]
+async fn test_write_file_over_multipart_threshold() {
+    let test_client = setup().await;
+
+    let temp_file = NamedTempFile::new().unwrap().into_temp_path();
+
+    let mut rng = rand::thread_rng();
+    let data: Vec<u8> = (0..12000000).map(|_| rng.gen()).collect();
+
+    fs::write(&temp_file, &data).await.unwrap();
+
+    execute_sftp_command(
+        &test_client,
+        &format!(
+            "PUT {} /home/test/multipart-test.bin",
+            temp_file.to_string_lossy()
+        ),
+    )
+    .await
+    .unwrap();
+
+    let file_data = get_object(&test_client, "home/test/multipart-test.bin").await;
+
+    assert_eq!(data, file_data);
+}
+
+#[tokio::test]
+async fn test_abort_write_handles_aborts_multipart_upload() {
+    let test_client = setup().await;
+
+    let storage = S3StorageFactory::new(&test_client.s3_config, 64, Duration::from_secs(60))
+        .await
+        .unwrap()
+        .create_storage();
+
+    let handle = storage
+        .open_write_handle("/home/test/abort-test.bin".to_string())
+        .await
+        .unwrap();
+
+    let mut rng = rand::thread_rng();
+    let data: Vec<u8> = (0..12000000).map(|_| rng.gen()).collect();
+
+    storage
+        .write_data(&handle, 0, bytes::Bytes::from(data))
+        .await
+        .unwrap();
+
+    assert_eq!(1, count_multipart_uploads(&test_client).await);
+
+    storage.abort_write_handles().await.unwrap();
+
+    assert_eq!(0, count_multipart_uploads(&test_client).await);
+}
+
+#[tokio::test]
+async fn test_read_data_at_offset() {
+    let test_client = setup().await;
+
+    let storage = S3StorageFactory::new(&test_client.s3_config, 64, Duration::from_secs(60))
+        .await
+        .unwrap()
+        .create_storage();
+
+    let handle = storage
+        .open_write_handle("/home/test/resume-test.txt".to_string())
+        .await
+        .unwrap();
+    storage
+        .write_data(&handle, 0, bytes::Bytes::from("hello world"))
+        .await
+        .unwrap();
+    storage.close_handle(&handle).await.unwrap();
+
+    let handle = storage
+        .open_read_handle("/home/test/resume-test.txt".to_string())
+        .await
+        .unwrap();
+
+    assert_eq!(
+        b"world".to_vec(),
+        storage.read_data(&handle, 6, 100).await.unwrap()
+    );
+    assert_eq!(
+        b"hello".to_vec(),
+        storage.read_data(&handle, 0, 5).await.unwrap()
+    );
+    assert!(storage
+        .read_data(&handle, 11, 100)
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+#[tokio::test]
+async fn test_read_data_concurrently_at_overlapping_offsets() {
+    let test_client = setup().await;
+
+    let storage = S3StorageFactory::new(&test_client.s3_config, 64, Duration::from_secs(60))
+        .await
+        .unwrap()
+        .create_storage();
+
+    let data: Vec<u8> = (0..10000).map(|index| (index % 251) as u8).collect();
+
+    let handle = storage
+        .open_write_handle("/home/test/concurrent-read-test.txt".to_string())
+        .await
+        .unwrap();
+    storage
+        .write_data(&handle, 0, bytes::Bytes::from(data.clone()))
+        .await
+        .unwrap();
+    storage.close_handle(&handle).await.unwrap();
+
+    let handle = storage
+        .open_read_handle("/home/test/concurrent-read-test.txt".to_string())
+        .await
+        .unwrap();
+
+    let reads = [
+        (0, 4000),
+        (2000, 4000),
+        (9000, 2000),
+        (500, 100),
+        (6000, 3500),
+    ];
+
+    let results = futures::future::join_all(
+        reads
+            .iter()
+            .map(|(offset, len)| storage.read_data(&handle, *offset, *len)),
+    )
+    .await;
+
+    for ((offset, len), result) in reads.iter().zip(results) {
+        let start = *offset as usize;
+        let end = (start + *len as usize).min(data.len());
+
+        assert_eq!(data[start..end].to_vec(), result.unwrap());
+    }
+}
+
+#[tokio::test]
+async fn test_open_read_write_handle_modifies_object_in_place() {
+    let test_client = setup().await;
+
+    let storage = S3StorageFactory::new(&test_client.s3_config, 64, Duration::from_secs(60))
+        .await
+        .unwrap()
+        .create_storage();
+
+    let handle = storage
+        .open_write_handle("/home/test/edit-test.txt".to_string())
+        .await
+        .unwrap();
+    storage
+        .write_data(&handle, 0, bytes::Bytes::from("hello world"))
+        .await
+        .unwrap();
+    storage.close_handle(&handle).await.unwrap();
+
+    let handle = storage
+        .open_read_write_handle("/home/test/edit-test.txt".to_string(), false)
+        .await
+        .unwrap();
+
+    assert_eq!(
+        b"hello world".to_vec(),
+        storage.read_data(&handle, 0, 100).await.unwrap()
+    );
+
+    storage
+        .write_data(&handle, 6, bytes::Bytes::from("there"))
+        .await
+        .unwrap();
+    storage.close_handle(&handle).await.unwrap();
+
+    let handle = storage
+        .open_read_handle("/home/test/edit-test.txt".to_string())
+        .await
+        .unwrap();
+
+    assert_eq!(
+        b"hello there".to_vec(),
+        storage.read_data(&handle, 0, 100).await.unwrap()
+    );
+}
+
+#[tokio::test]
+async fn test_open_append_handle_appends_to_object() {
+    let test_client = setup().await;
+
+    let storage = S3StorageFactory::new(&test_client.s3_config, 64, Duration::from_secs(60))
+        .await
+        .unwrap()
+        .create_storage();
+
+    let mut rng = rand::thread_rng();
+    let mut data: Vec<u8> = (0..6000000).map(|_| rng.gen()).collect();
+
+    let handle = storage
+        .open_write_handle("/home/test/append-test.bin".to_string())
+        .await
+        .unwrap();
+    storage
+        .write_data(&handle, 0, bytes::Bytes::from(data.clone()))
+        .await
+        .unwrap();
+    storage.close_handle(&handle).await.unwrap();
+
+    // The existing object is large enough to be copied into a multipart upload.
+    let appended_data: Vec<u8> = (0..1000).map(|_| rng.gen()).collect();
+
+    let handle = storage
+        .open_append_handle("/home/test/append-test.bin".to_string(), false)
+        .await
+        .unwrap();
+    storage
+        .write_data(&handle, 0, bytes::Bytes::from(appended_data.clone()))
+        .await
+        .unwrap();
+    storage.close_handle(&handle).await.unwrap();
+
+    data.extend(appended_data);
+
+    let handle = storage
+        .open_read_handle("/home/test/append-test.bin".to_string())
+        .await
+        .unwrap();
+
+    let mut object_data = Vec::new();
+
+    loop {
+        let read_data = storage
+            .read_data(&handle, object_data.len() as u64, 1000000)
+            .await
+            .unwrap();
+
+        if read_data.is_empty() {
+            break;
+        }
+
+        object_data.extend(read_data);
+    }
+
+    assert_eq!(data, object_data);
+}
+
+#[tokio::test]
+async fn test_open_append_handle_appends_to_small_object() {
+    let test_client = setup().await;
+
+    let storage = S3StorageFactory::new(&test_client.s3_config, 64, Duration::from_secs(60))
+        .await
+        .unwrap()
+        .create_storage();
+
+    let handle = storage
+        .open_append_handle("/home/test/append-test.txt".to_string(), true)
+        .await
+        .unwrap();
+    storage
+        .write_data(&handle, 0, bytes::Bytes::from("hello"))
+        .await
+        .unwrap();
+    storage.close_handle(&handle).await.unwrap();
+
+    let handle = storage
+        .open_append_handle("/home/test/append-test.txt".to_string(), false)
+        .await
+        .unwrap();
+    storage
+        .write_data(&handle, 0, bytes::Bytes::from(" world"))
+        .await
+        .unwrap();
+    storage.close_handle(&handle).await.unwrap();
+
+    let handle = storage
+        .open_read_handle("/home/test/append-test.txt".to_string())
+        .await
+        .unwrap();
+
+    assert_eq!(
+        b"hello world".to_vec(),
+        storage.read_data(&handle, 0, 100).await.unwrap()
+    );
+}
+
 #[tokio::test]
 #[should_panic(expected = "Permission denied")]
 async fn test_write_file_with_permission_error() {
@@ -471,6 +863,40 @@ async fn test_create_directory() {
         .unwrap();
 }
 
+#[tokio::test]
+async fn test_create_nested_directories() {
+    let test_client = setup().await;
+
+    execute_sftp_command(
+        &test_client,
+        "MKDIR /home/test/nested/a/b\nMKDIR /home/test/nested/a\nMKDIR /home/test/nested",
+    )
+    .await
+    .unwrap();
+
+    let keys: Vec<String> = test_client
+        .s3_client
+        .list_objects_v2()
+        .bucket(&test_client.bucket)
+        .prefix("home/test/nested/")
+        .send()
+        .await
+        .unwrap()
+        .contents
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|object| object.key)
+        .collect();
+
+    assert_eq!(vec!["home/test/nested/a/b/_$folder$"], keys);
+
+    let output = execute_sftp_command(&test_client, "LS /home/test/nested/a")
+        .await
+        .unwrap();
+
+    assert!(output.contains("/home/test/nested/a/b"));
+}
+
 #[tokio::test]
 #[should_panic(expected = "Permission denied")]
 async fn test_create_directory_with_permission_error() {
@@ -502,6 +928,39 @@ async fn test_rename_file() {
     get_object(&test_client, "home/test/rename/test2.txt").await;
 }
 
+#[tokio::test]
+async fn test_rename_file_over_existing_file() {
+    let test_client = setup().await;
+
+    put_object(
+        &test_client,
+        "home/test/rename-existing/test1.txt",
+        b"New data!".to_vec(),
+    )
+    .await;
+
+    put_object(
+        &test_client,
+        "home/test/rename-existing/test2.txt",
+        b"Old data!".to_vec(),
+    )
+    .await;
+
+    execute_sftp_command(
+        &test_client,
+        "RENAME /home/test/rename-existing/test1.txt /home/test/rename-existing/test2.txt",
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        b"New data!",
+        get_object(&test_client, "home/test/rename-existing/test2.txt")
+            .await
+            .as_slice()
+    );
+}
+
 #[tokio::test]
 async fn test_rename_folder() {
     let test_client = setup().await;
@@ -550,14 +1009,113 @@ async fn test_rename_file_with_permission_error_destination() {
 }
 
 #[tokio::test]
-#[should_panic(expected = "Operation unsupported")]
+async fn test_chmod_file() {
+    let test_client = setup().await;
+
+    put_object(&test_client, "home/test/chmod.txt", b"Test data!".to_vec()).await;
+
+    execute_sftp_command(&test_client, "CHMOD 600 /home/test/chmod.txt")
+        .await
+        .unwrap();
+
+    let head_object_result = test_client
+        .s3_client
+        .head_object()
+        .bucket(&test_client.bucket)
+        .key("home/test/chmod.txt")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(
+        Some(&"600".to_string()),
+        head_object_result.metadata().unwrap().get("permissions")
+    );
+    assert_eq!(
+        b"Test data!",
+        get_object(&test_client, "home/test/chmod.txt")
+            .await
+            .as_slice()
+    );
+}
+
+#[tokio::test]
+#[should_panic(expected = "Permission denied")]
+async fn test_chmod_file_with_permission_error() {
+    let test_client = setup().await;
+
+    execute_sftp_command(&test_client, "CHMOD 600 /home/other/chmod.txt")
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
 async fn test_symlink() {
     let test_client = setup().await;
 
+    put_object(
+        &test_client,
+        "home/test/link/source",
+        b"Test data!".to_vec(),
+    )
+    .await;
+
     execute_sftp_command(
         &test_client,
         "LN -s /home/test/link/source /home/test/link/destination",
     )
     .await
     .unwrap();
+
+    assert_eq!(
+        b"/home/test/link/source",
+        get_object(&test_client, "home/test/link/destination.draylink")
+            .await
+            .as_slice()
+    );
+
+    let sftp_output = execute_sftp_command(&test_client, "ls -l /home/test/link")
+        .await
+        .unwrap();
+
+    assert!(sftp_output
+        .lines()
+        .any(|line| line.starts_with("lrwxrwxrwx") && line.ends_with("destination")));
+}
+
+#[tokio::test]
+async fn test_hardlink() {
+    let test_client = setup().await;
+
+    put_object(
+        &test_client,
+        "home/test/hardlink/source",
+        b"Test data!".to_vec(),
+    )
+    .await;
+
+    execute_sftp_command(
+        &test_client,
+        "LN /home/test/hardlink/source /home/test/hardlink/destination",
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        get_object(&test_client, "home/test/hardlink/source").await,
+        get_object(&test_client, "home/test/hardlink/destination").await
+    );
+}
+
+#[tokio::test]
+#[should_panic(expected = "Permission denied")]
+async fn test_symlink_with_permission_error() {
+    let test_client = setup().await;
+
+    execute_sftp_command(
+        &test_client,
+        "LN -s /home/other/source /home/test/link/destination",
+    )
+    .await
+    .unwrap();
 }
@@ -0,0 +1,324 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use dray::{
+    config::DrayConfig,
+    ssh_server::DraySshServer,
+    storage::{memory::InMemoryStorageFactory, DEFAULT_MAX_HANDLES},
+};
+use russh::{client, ChannelMsg, ChannelStream};
+use russh_keys::{
+    key::{KeyPair, PublicKey},
+    PublicKeyBase64,
+};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+const SSH_FXP_INIT: u8 = 1;
+const SSH_FXP_VERSION: u8 = 2;
+const SSH_FXP_OPEN: u8 = 3;
+const SSH_FXP_CLOSE: u8 = 4;
+const SSH_FXP_READ: u8 = 5;
+const SSH_FXP_WRITE: u8 = 6;
+const SSH_FXP_REALPATH: u8 = 16;
+const SSH_FXP_STATUS: u8 = 101;
+const SSH_FXP_HANDLE: u8 = 102;
+const SSH_FXP_DATA: u8 = 103;
+const SSH_FXP_NAME: u8 = 104;
+
+const SSH_FXF_READ: u32 = 0x00000001;
+const SSH_FXF_WRITE: u32 = 0x00000002;
+const SSH_FXF_CREAT: u32 = 0x00000008;
+
+#[tokio::test]
+async fn test_realpath_with_split_frame() {
+    let mut harness = SftpHarness::connect().await;
+    let frame = build_realpath_frame(1, ".");
+
+    // Some clients, such as Windows OpenSSH, send the length of a request in a separate packet
+    // from its data, or split the data across packets.
+    harness.send_split(&frame, &[2, 9]).await;
+
+    assert_eq!(
+        vec![String::from("/home/test")],
+        read_names(&mut harness.read_response().await, 1)
+    );
+}
+
+#[tokio::test]
+async fn test_realpath_with_frames_in_one_packet() {
+    let mut harness = SftpHarness::connect().await;
+
+    let mut frames = build_realpath_frame(1, ".");
+    frames.extend_from_slice(&build_realpath_frame(2, "/home/test/../other"));
+    harness.send(&frames).await;
+
+    assert_eq!(
+        vec![String::from("/home/test")],
+        read_names(&mut harness.read_response().await, 1)
+    );
+    assert_eq!(
+        vec![String::from("/home/other")],
+        read_names(&mut harness.read_response().await, 2)
+    );
+}
+
+#[tokio::test]
+async fn test_write_larger_than_ssh_packet_is_read_back() {
+    let mut harness = SftpHarness::connect().await;
+    let data: Vec<u8> = (0..100000).map(|i| (i % 251) as u8).collect();
+
+    let handle = harness
+        .open("/home/test/file.bin", SSH_FXF_WRITE | SSH_FXF_CREAT)
+        .await;
+
+    // The request is larger than the maximum SSH packet, so it arrives in several packets.
+    let mut payload = BytesMut::new();
+    put_string(&mut payload, &handle);
+    payload.put_u64(0);
+    put_string(&mut payload, &data);
+    harness.send(&build_frame(SSH_FXP_WRITE, 3, &payload)).await;
+    assert_status(&mut harness.read_response().await, 3, 0);
+
+    harness.close(&handle).await;
+
+    let handle = harness.open("/home/test/file.bin", SSH_FXF_READ).await;
+    let mut read_data = Vec::new();
+
+    while read_data.len() < data.len() {
+        let mut payload = BytesMut::new();
+        put_string(&mut payload, &handle);
+        payload.put_u64(read_data.len() as u64);
+        payload.put_u32(32768);
+        harness.send(&build_frame(SSH_FXP_READ, 5, &payload)).await;
+
+        let mut response = harness.read_response().await;
+        assert_eq!(SSH_FXP_DATA, response.get_u8());
+        assert_eq!(5, response.get_u32());
+        read_data.extend_from_slice(&get_string(&mut response));
+    }
+
+    assert_eq!(data, read_data);
+}
+
+/// An SFTP client that connects to Dray over SSH and sends hand-crafted requests, so that tests
+/// can control exactly how requests are framed and split across SSH packets.
+struct SftpHarness {
+    _client: client::Handle<HarnessClient>,
+    stream: ChannelStream<client::Msg>,
+}
+
+impl SftpHarness {
+    /// Starts a server with in-memory storage on a random port, authenticates as `test`, starts
+    /// the sftp subsystem, and negotiates version 3.
+    async fn connect() -> SftpHarness {
+        let client_key = KeyPair::generate_ed25519().unwrap();
+
+        let dray_config: DrayConfig = envy::prefixed("DRAY_")
+            .from_iter(vec![
+                (String::from("DRAY_HOST"), String::from("127.0.0.1:0")),
+                (String::from("DRAY_SSH_KEY_PATHS"), String::new()),
+            ])
+            .unwrap();
+
+        let storage_factory = InMemoryStorageFactory::new(DEFAULT_MAX_HANDLES);
+        storage_factory
+            .put_file(
+                "/.ssh/test/authorized_keys",
+                format!(
+                    "ssh-ed25519 {} test",
+                    client_key.clone_public_key().unwrap().public_key_base64()
+                )
+                .into_bytes(),
+            )
+            .await
+            .unwrap();
+
+        let ssh_config = Arc::new(russh::server::Config {
+            keys: vec![KeyPair::generate_ed25519().unwrap()],
+            ..Default::default()
+        });
+        let server = DraySshServer::with_storage_factory(dray_config, Arc::new(storage_factory));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move { server.run_on_listener(ssh_config, &listener).await });
+
+        let mut client = client::connect(Arc::new(client::Config::default()), addr, HarnessClient)
+            .await
+            .unwrap();
+
+        assert!(client
+            .authenticate_publickey("test", Arc::new(client_key))
+            .await
+            .unwrap());
+
+        let mut channel = client.channel_open_session().await.unwrap();
+        channel.request_subsystem(true, "sftp").await.unwrap();
+
+        loop {
+            match channel.wait().await.unwrap() {
+                ChannelMsg::Success => break,
+                ChannelMsg::Failure => panic!("The sftp subsystem was refused"),
+                _ => {}
+            }
+        }
+
+        let mut harness = SftpHarness {
+            _client: client,
+            stream: channel.into_stream(),
+        };
+
+        let mut init = BytesMut::new();
+        init.put_u32(5);
+        init.put_u8(SSH_FXP_INIT);
+        init.put_u32(3);
+        harness.send(&init).await;
+
+        let mut response = harness.read_response().await;
+        assert_eq!(SSH_FXP_VERSION, response.get_u8());
+        assert_eq!(3, response.get_u32());
+
+        harness
+    }
+
+    async fn send(&mut self, data: &[u8]) {
+        self.stream.write_all(data).await.unwrap();
+        self.stream.flush().await.unwrap();
+    }
+
+    /// Sends data in separate SSH packets, split at each offset. The server is given time to
+    /// read each packet before the next is sent.
+    async fn send_split(&mut self, data: &[u8], offsets: &[usize]) {
+        let mut start = 0;
+
+        for &offset in offsets {
+            self.send(&data[start..offset]).await;
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            start = offset;
+        }
+
+        self.send(&data[start..]).await;
+    }
+
+    /// Reads the next response, without its length.
+    async fn read_response(&mut self) -> Bytes {
+        let length = self.stream.read_u32().await.unwrap() as usize;
+        let mut response = vec![0; length];
+        self.stream.read_exact(&mut response).await.unwrap();
+
+        Bytes::from(response)
+    }
+
+    async fn open(&mut self, path: &str, pflags: u32) -> Vec<u8> {
+        let mut payload = BytesMut::new();
+        put_string(&mut payload, path.as_bytes());
+        payload.put_u32(pflags);
+        payload.put_u32(0); // attribute flags
+        self.send(&build_frame(SSH_FXP_OPEN, 1, &payload)).await;
+
+        let mut response = self.read_response().await;
+        assert_eq!(SSH_FXP_HANDLE, response.get_u8());
+        assert_eq!(1, response.get_u32());
+
+        get_string(&mut response)
+    }
+
+    async fn close(&mut self, handle: &[u8]) {
+        let mut payload = BytesMut::new();
+        put_string(&mut payload, handle);
+        self.send(&build_frame(SSH_FXP_CLOSE, 4, &payload)).await;
+
+        assert_status(&mut self.read_response().await, 4, 0);
+    }
+}
+
+struct HarnessClient;
+
+#[async_trait]
+impl client::Handler for HarnessClient {
+    type Error = russh::Error;
+
+    async fn check_server_key(
+        &mut self,
+        _server_public_key: &PublicKey,
+    ) -> Result<bool, Self::Error> {
+        Ok(true)
+    }
+}
+
+/// Builds a request frame: the length, the request type, the request id, and the payload.
+fn build_frame(request_type: u8, id: u32, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::new();
+
+    frame.put_u32(5 + payload.len() as u32);
+    frame.put_u8(request_type);
+    frame.put_u32(id);
+    frame.put_slice(payload);
+
+    frame
+}
+
+fn build_realpath_frame(id: u32, path: &str) -> Vec<u8> {
+    let mut payload = BytesMut::new();
+    put_string(&mut payload, path.as_bytes());
+
+    build_frame(SSH_FXP_REALPATH, id, &payload)
+}
+
+fn put_string(buffer: &mut BytesMut, data: &[u8]) {
+    buffer.put_u32(data.len() as u32);
+    buffer.put_slice(data);
+}
+
+fn get_string(response: &mut Bytes) -> Vec<u8> {
+    let length = response.get_u32() as usize;
+    response.split_to(length).to_vec()
+}
+
+/// Reads the file names from a name response, skipping the long names and attributes.
+fn read_names(response: &mut Bytes, id: u32) -> Vec<String> {
+    assert_eq!(SSH_FXP_NAME, response.get_u8());
+    assert_eq!(id, response.get_u32());
+
+    let count = response.get_u32();
+    let mut names = Vec::new();
+
+    for _ in 0..count {
+        names.push(String::from_utf8(get_string(response)).unwrap());
+        get_string(response); // long name
+        skip_attributes(response);
+    }
+
+    names
+}
+
+fn skip_attributes(response: &mut Bytes) {
+    let flags = response.get_u32();
+
+    if flags & 0x00000001 != 0 {
+        response.get_u64(); // size
+    }
+
+    if flags & 0x00000002 != 0 {
+        response.get_u32(); // uid
+        response.get_u32(); // gid
+    }
+
+    if flags & 0x00000004 != 0 {
+        response.get_u32(); // permissions
+    }
+
+    if flags & 0x00000008 != 0 {
+        response.get_u32(); // atime
+        response.get_u32(); // mtime
+    }
+}
+
+fn assert_status(response: &mut Bytes, id: u32, status_code: u32) {
+    assert_eq!(SSH_FXP_STATUS, response.get_u8());
+    assert_eq!(id, response.get_u32());
+    assert_eq!(status_code, response.get_u32());
+}
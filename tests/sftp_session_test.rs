@@ -0,0 +1,149 @@
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use dray::protocol::file_attributes::FileAttributes;
+use dray::protocol::request::{
+    handle::Handle, init::Init, open::Open, open::OpenOptions, path::Path, read::Read,
+    write::Write, Request,
+};
+use dray::protocol::response::{status::StatusCode, Response};
+use dray::sftp_session::{SftpSession, SftpSessionConfig};
+use dray::sftp_stream::SftpStream;
+use dray::storage::{memory::InMemoryStorageFactory, StorageFactory, DEFAULT_MAX_HANDLES};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio_util::sync::CancellationToken;
+
+#[tokio::test]
+async fn test_sftp_session_handles_requests_without_ssh() {
+    let session = create_sftp_session();
+
+    assert!(matches!(
+        session
+            .handle_request(Request::Init(Init { version: 3 }))
+            .await,
+        Response::Version(_)
+    ));
+
+    let handle = match session
+        .handle_request(Request::Open(Open {
+            id: 1,
+            filename: String::from("/home/test/hello.txt"),
+            file_attributes: FileAttributes::default(),
+            open_options: OpenOptions {
+                read: false,
+                write: true,
+                create: true,
+                create_new_only: false,
+                append: false,
+                truncate: true,
+            },
+        }))
+        .await
+    {
+        Response::Handle(handle) => handle.handle,
+        response => panic!("Unexpected response {:?}", response),
+    };
+
+    assert_status(
+        StatusCode::Ok,
+        session
+            .handle_request(Request::Write(Write {
+                id: 2,
+                handle: handle.clone(),
+                offset: 0,
+                data: Bytes::from("hello"),
+            }))
+            .await,
+    );
+    assert_status(
+        StatusCode::Ok,
+        session
+            .handle_request(Request::Close(Handle { id: 3, handle }))
+            .await,
+    );
+
+    let handle = match session
+        .handle_request(Request::Open(Open {
+            id: 4,
+            filename: String::from("/home/test/hello.txt"),
+            file_attributes: FileAttributes::default(),
+            open_options: OpenOptions {
+                read: true,
+                write: false,
+                create: false,
+                create_new_only: false,
+                append: false,
+                truncate: false,
+            },
+        }))
+        .await
+    {
+        Response::Handle(handle) => handle.handle,
+        response => panic!("Unexpected response {:?}", response),
+    };
+
+    match session
+        .handle_request(Request::Read(Read {
+            id: 5,
+            handle,
+            offset: 0,
+            len: 1024,
+        }))
+        .await
+    {
+        Response::Data(data) => assert_eq!(b"hello", data.data.as_slice()),
+        response => panic!("Unexpected response {:?}", response),
+    }
+
+    assert_status(
+        StatusCode::NoSuchFile,
+        session
+            .handle_request(Request::Stat(Path {
+                id: 6,
+                path: String::from("/home/test/missing.txt"),
+            }))
+            .await,
+    );
+}
+
+#[tokio::test]
+async fn test_sftp_stream_processes_requests_from_any_transport() {
+    let (mut client, server) = tokio::io::duplex(64 * 1024);
+    let stream = SftpStream::new(create_sftp_session(), None, None);
+
+    let server = tokio::spawn(async move {
+        stream
+            .process_stream(server, CancellationToken::new())
+            .await
+    });
+
+    // An init request for version 3: the length, the init data type, and the version.
+    let mut request = BytesMut::new();
+    request.put_u32(5);
+    request.put_u8(1);
+    request.put_u32(3);
+    client.write_all(&request).await.unwrap();
+
+    let length = client.read_u32().await.unwrap();
+    let mut response = vec![0; length as usize];
+    client.read_exact(&mut response).await.unwrap();
+    let mut response = Bytes::from(response);
+
+    // The version response data type, followed by the negotiated version.
+    assert_eq!(2, response.get_u8());
+    assert_eq!(3, response.get_u32());
+
+    drop(client);
+    assert!(server.await.is_ok());
+}
+
+fn create_sftp_session() -> SftpSession {
+    let storage = InMemoryStorageFactory::new(DEFAULT_MAX_HANDLES).create_storage();
+
+    SftpSession::new(storage, String::from("test"), &SftpSessionConfig::default()).unwrap()
+}
+
+fn assert_status(status_code: StatusCode, response: Response) {
+    match response {
+        Response::Status(status) => assert_eq!(status_code, status.status_code),
+        response => panic!("Unexpected response {:?}", response),
+    }
+}